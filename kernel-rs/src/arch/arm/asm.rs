@@ -76,6 +76,16 @@ pub fn tlbi_vmalle1() {
     unsafe { asm!("tlbi vmalle1") }
 }
 
+/// flush the TLBs of all CPUs in the inner shareable domain
+pub fn tlbi_vmalle1is() {
+    unsafe {
+        asm!("dsb ishst");
+        asm!("tlbi vmalle1is");
+        asm!("dsb ish");
+        asm!("isb");
+    }
+}
+
 /// Instruction Synchronization Barrier.
 pub fn isb() {
     unsafe { asm!("isb") }
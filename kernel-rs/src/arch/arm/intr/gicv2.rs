@@ -17,6 +17,7 @@ use crate::arch::{
     memlayout::TIMER0_IRQ,
     Armv8,
 };
+use crate::param::NDISK;
 
 const GIC_INTERRUPT_NUM: usize = 1024;
 const GIC_SGI_NUM: usize = 16;
@@ -333,7 +334,9 @@ pub unsafe fn intr_init_core() {
         // IRQ numbers are valid
         unsafe {
             // virtio_blk
-            INTERRUPT_CONTROLLER.enable(Armv8::VIRTIO0_IRQ);
+            for i in 0..NDISK {
+                INTERRUPT_CONTROLLER.enable(Armv8::VIRTIO0_IRQ + i);
+            }
             // pl011 uart
             INTERRUPT_CONTROLLER.enable(Armv8::UART0_IRQ);
         }
@@ -16,7 +16,7 @@ use crate::arch::{
     timer::udelay,
     Armv8,
 };
-use crate::param::NCPU;
+use crate::param::{NCPU, NDISK};
 
 // TODO: group all the constants properly as did in `gicv2.rs`,
 // using `regiter_structs` macro.
@@ -1213,7 +1213,9 @@ pub unsafe fn intr_init_core() {
         // SAFETY: enable valid irq numbers after calling `gic.init`.
         unsafe {
             // virtio_blk
-            intr_controller.enable(Armv8::VIRTIO0_IRQ);
+            for i in 0..NDISK {
+                intr_controller.enable(Armv8::VIRTIO0_IRQ + i);
+            }
 
             // pl011 uart
             intr_controller.enable(Armv8::UART0_IRQ);
@@ -6,6 +6,7 @@
 //! 00000000 -- boot ROM, provided by qemu, space up to 0x8000000 is reserved.
 //! 08000000 -- GIC
 //! 09000000 -- uart0
+//! 09010000 -- pl031 rtc
 //! 0a000000 -- virtio disk
 //! 40010000 -- boot ROM jumps here in machine mode
 //!             -kernel loads the kernel here
@@ -26,12 +27,15 @@ impl MemLayout for Armv8 {
     /// for use by the kernel and user pages
     /// from physical address 0x80000000 to PHYSTOP.
     const KERNBASE: usize = 0x40000000;
+    /// PL031 real-time clock.
+    const RTC0: usize = 0x09010000;
     /// qemu puts UART registers here in physical memory.
     const UART0: usize = 0x09000000;
     const UART0_IRQ: usize = 33;
     /// virtio mmio interface
     const VIRTIO0: usize = 0x0a000000;
     const VIRTIO0_IRQ: usize = 48;
+    const VIRTIO_STRIDE: usize = 0x200;
 }
 
 // TODO: Find counterpart of this in ARM, seems that it doesn't exist.
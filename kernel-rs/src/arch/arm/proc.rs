@@ -1,3 +1,5 @@
+use zerocopy::{AsBytes, FromBytes};
+
 use crate::arch::interface::{ContextManager, ProcManager, TrapFrameManager};
 use crate::arch::{asm, Armv8};
 use crate::proc::RegNum;
@@ -24,7 +26,8 @@ impl ProcManager for Armv8 {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, AsBytes, FromBytes)]
+#[repr(C)]
 pub struct TrapFrame {
     /// kernel page table (satp: Supervisor Address Translation and Protection)
     pub kernel_satp: usize,
@@ -140,14 +143,116 @@ pub struct Context {
 }
 
 impl const TrapFrameManager for TrapFrame {
+    type UserRegs = [usize; 66];
+
     fn set_pc(&mut self, val: usize) {
         self.pc = val;
     }
 
+    fn get_pc(&self) -> usize {
+        self.pc
+    }
+
     fn set_sp(&mut self, val: usize) {
         self.sp = val;
     }
 
+    fn get_sp(&self) -> usize {
+        self.sp
+    }
+
+    fn set_ra(&mut self, val: usize) {
+        self.r30 = val;
+    }
+
+    fn user_regs(&self) -> Self::UserRegs {
+        [
+            self.pc, self.sp, self.fpsr, self.r0, self.r1, self.r2, self.r3, self.r4, self.r5,
+            self.r6, self.r7, self.r8, self.r9, self.r10, self.r11, self.r12, self.r13, self.r14,
+            self.r15, self.r16, self.r17, self.r18, self.r19, self.r20, self.r21, self.r22,
+            self.r23, self.r24, self.r25, self.r26, self.r27, self.r28, self.r29, self.r30,
+            self.s0, self.s1, self.s2, self.s3, self.s4, self.s5, self.s6, self.s7, self.s8,
+            self.s9, self.s10, self.s11, self.s12, self.s13, self.s14, self.s15, self.s16,
+            self.s17, self.s18, self.s19, self.s20, self.s21, self.s22, self.s23, self.s24,
+            self.s25, self.s26, self.s27, self.s28, self.s29, self.s30, self.s31,
+        ]
+    }
+
+    fn set_user_regs(&mut self, regs: &Self::UserRegs) {
+        // spsr is not a user register, since a modified one could return to EL1.
+        let [pc, sp, fpsr, r0, r1, r2, r3, r4, r5, r6, r7, r8, r9, r10, r11, r12, r13, r14, r15, r16, r17, r18, r19, r20, r21, r22, r23, r24, r25, r26, r27, r28, r29, r30, s0, s1, s2, s3, s4, s5, s6, s7, s8, s9, s10, s11, s12, s13, s14, s15, s16, s17, s18, s19, s20, s21, s22, s23, s24, s25, s26, s27, s28, s29, s30, s31] =
+            *regs;
+        *self = Self {
+            pc,
+            sp,
+            fpsr,
+            r0,
+            r1,
+            r2,
+            r3,
+            r4,
+            r5,
+            r6,
+            r7,
+            r8,
+            r9,
+            r10,
+            r11,
+            r12,
+            r13,
+            r14,
+            r15,
+            r16,
+            r17,
+            r18,
+            r19,
+            r20,
+            r21,
+            r22,
+            r23,
+            r24,
+            r25,
+            r26,
+            r27,
+            r28,
+            r29,
+            r30,
+            s0,
+            s1,
+            s2,
+            s3,
+            s4,
+            s5,
+            s6,
+            s7,
+            s8,
+            s9,
+            s10,
+            s11,
+            s12,
+            s13,
+            s14,
+            s15,
+            s16,
+            s17,
+            s18,
+            s19,
+            s20,
+            s21,
+            s22,
+            s23,
+            s24,
+            s25,
+            s26,
+            s27,
+            s28,
+            s29,
+            s30,
+            s31,
+            ..*self
+        };
+    }
+
     /// Set the value of return value register
     fn set_ret_val(&mut self, val: usize) {
         self.r0 = val;
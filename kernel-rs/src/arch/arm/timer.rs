@@ -1,10 +1,20 @@
+use core::ptr;
+
 use cortex_a::{asm::barrier, registers::*};
 use tock_registers::interfaces::{Readable, Writeable};
 
-use crate::arch::{interface::TimeManager, Armv8};
+use crate::arch::{
+    interface::{MemLayout, TimeManager},
+    Armv8,
+};
 
 const US_PER_S: u64 = 1_000_000;
 
+const NS_PER_S: u64 = 1_000_000_000;
+
+/// PL031 data register, which holds the time in seconds.
+const RTCDR: usize = 0x00;
+
 const TIMER_TICK_MS: u64 = 100;
 
 // pub struct Timer;
@@ -18,6 +28,12 @@ impl TimeManager for Armv8 {
         Ok((read_cntpct() * US_PER_S / read_freq()) as usize)
     }
 
+    fn rtc_time_as_nanos() -> u64 {
+        // SAFETY: the kernel page table maps the RTC registers.
+        let secs = unsafe { ptr::read_volatile((Self::RTC0 + RTCDR) as *const u32) };
+        u64::from(secs) * NS_PER_S
+    }
+
     fn r_cycle() -> usize {
         read_cntpct() as usize
     }
@@ -16,8 +16,10 @@ use crate::{
         timer::set_next_timer,
         Armv8,
     },
-    memlayout::{TRAMPOLINE, TRAPFRAME},
+    memlayout::TRAMPOLINE,
+    param::NDISK,
     trap::{IrqNum, IrqTypes, TrapTypes},
+    vm::AccessFlags,
 };
 
 /// In ARM.v8 architecture, interrupts are part
@@ -53,7 +55,7 @@ impl From<&IrqTypes> for IrqNum {
     fn from(item: &IrqTypes) -> Self {
         match item {
             IrqTypes::Uart => Armv8::UART0_IRQ,
-            IrqTypes::Virtio => Armv8::VIRTIO0_IRQ,
+            IrqTypes::Virtio(i) => Armv8::VIRTIO0_IRQ + i,
             IrqTypes::Unknown(i) => *i,
             IrqTypes::Others(i) => *i,
         }
@@ -84,11 +86,22 @@ impl TrapManager for Armv8 {
             ExceptionTypes::SyncException => {
                 if ESR_EL1.matches_all(ESR_EL1::EC::SVC64) {
                     TrapTypes::Syscall
-                } else if ESR_EL1
-                    .matches_any(ESR_EL1::EC::DataAbortLowerEL + ESR_EL1::EC::InstrAbortLowerEL)
-                {
-                    // TODO: Should handle these exceptions?
-                    TrapTypes::BadTrap
+                } else if ESR_EL1.matches_all(ESR_EL1::EC::DataAbortLowerEL) {
+                    // The WnR bit of ISS tells whether the abort was caused by a write.
+                    let access = if ESR_EL1.read(ESR_EL1::ISS) & (1 << 6) != 0 {
+                        AccessFlags::W
+                    } else {
+                        AccessFlags::R
+                    };
+                    TrapTypes::PageFault {
+                        addr: FAR_EL1.get() as usize,
+                        access,
+                    }
+                } else if ESR_EL1.matches_all(ESR_EL1::EC::InstrAbortLowerEL) {
+                    TrapTypes::PageFault {
+                        addr: FAR_EL1.get() as usize,
+                        access: AccessFlags::X,
+                    }
                 } else {
                     TrapTypes::BadTrap
                 }
@@ -103,7 +116,9 @@ impl TrapManager for Armv8 {
                                 return TrapTypes::TimerInterrupt;
                             }
                             Armv8::UART0_IRQ => IrqTypes::Uart,
-                            Armv8::VIRTIO0_IRQ => IrqTypes::Virtio,
+                            i if (Armv8::VIRTIO0_IRQ..Armv8::VIRTIO0_IRQ + NDISK).contains(&i) => {
+                                IrqTypes::Virtio(i - Armv8::VIRTIO0_IRQ)
+                            }
                             _ => IrqTypes::Unknown(i),
                         }
                     }
@@ -194,6 +209,7 @@ impl TrapManager for Armv8 {
     unsafe fn user_trap_ret(
         user_pagetable_addr: usize,
         trapframe: &mut TrapFrame,
+        trapframe_va: usize,
         kernel_stack: usize,
         usertrap: usize,
     ) -> ! {
@@ -219,7 +235,7 @@ impl TrapManager for Armv8 {
         let fn_0: usize =
             TRAMPOLINE + unsafe { userret.as_ptr().offset_from(trampoline.as_ptr()) } as usize;
         let fn_0 = unsafe { mem::transmute::<_, unsafe extern "C" fn(usize, usize) -> !>(fn_0) };
-        unsafe { fn_0(TRAPFRAME, user_pagetable_addr) }
+        unsafe { fn_0(trapframe_va, user_pagetable_addr) }
     }
 
     fn save_trap_regs(store: &mut [usize; 10]) {
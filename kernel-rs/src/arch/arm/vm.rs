@@ -7,7 +7,7 @@ use crate::{
     arch::Armv8,
     arch::{
         addr::{pa2pte, pte2pa, PLNUM},
-        asm::{isb, tlbi_vmalle1},
+        asm::{isb, tlbi_vmalle1, tlbi_vmalle1is},
         interface::{IPageTableEntry, MemLayout, PageTableManager},
         memlayout::GIC,
     },
@@ -36,6 +36,8 @@ bitflags! {
         const UXN = 1 << 54;
         /// Privileged execute-never, stage 1 only
         const PXN = 1 << 53;
+        /// Copy-on-write, reserved for software use
+        const COW = 1 << 55;
 
         // Could be used for set_entry function and MAIR_EL1 register
         const MEM_ATTR_IDX_0 = (0 << 2);
@@ -138,6 +140,23 @@ impl IPageTableEntry for PageTableEntry {
             && self.flag_intersects(Self::EntryFlags::PAGE | Self::EntryFlags::ACCESS_FLAG)
     }
 
+    fn is_writable(&self) -> bool {
+        // AP[2] makes the entry read-only.
+        !self.flag_intersects(Self::EntryFlags::RO_P)
+    }
+
+    fn is_cow(&self) -> bool {
+        self.flag_intersects(Self::EntryFlags::COW)
+    }
+
+    fn set_cow(&mut self) {
+        self.inner |= (Self::EntryFlags::RO_P | Self::EntryFlags::COW).bits();
+    }
+
+    fn clear_cow(&mut self) {
+        self.inner &= !(Self::EntryFlags::RO_P | Self::EntryFlags::COW).bits();
+    }
+
     /// Make the entry refer to a given page-table page.
     fn set_table(&mut self, page: *mut RawPageTable) {
         self.inner = pa2pte((page as usize).into())
@@ -205,4 +224,11 @@ impl PageTableManager for Armv8 {
         isb();
         tlbi_vmalle1();
     }
+
+    /// TLB maintenance on the inner shareable domain reaches the other CPUs without an
+    /// interrupt, so this flushes the TLBs of all CPUs at once.
+    fn shootdown_tlb(_cpus: usize) -> bool {
+        tlbi_vmalle1is();
+        true
+    }
 }
@@ -1,5 +1,7 @@
 use core::fmt;
 
+use zerocopy::{AsBytes, FromBytes};
+
 use crate::{
     addr::{Addr, PAddr},
     arch::TargetArch,
@@ -28,6 +30,13 @@ pub trait MemLayout {
     /// virtio mmio interface
     const VIRTIO0: usize;
 
+    /// Distance between the virtio mmio interfaces. The interface `i` is at
+    /// `VIRTIO0 + i * VIRTIO_STRIDE`, and its irq is `VIRTIO0_IRQ + i`.
+    const VIRTIO_STRIDE: usize;
+
+    /// The real-time clock, which `TimeManager::rtc_time_as_nanos` reads.
+    const RTC0: usize;
+
     /// the kernel expects there to be RAM
     /// for use by the kernel and user pages
     /// from physical address KERNBASE to PHYSTOP.
@@ -44,6 +53,10 @@ pub trait TimeManager {
     /// This includes time consumed by firmware and bootloaders.
     fn uptime_as_micro() -> Result<usize, ()>;
 
+    /// The wall-clock time that the real-time clock keeps, in nanoseconds since the
+    /// Unix epoch.
+    fn rtc_time_as_nanos() -> u64;
+
     fn r_cycle() -> usize;
 }
 
@@ -126,6 +139,7 @@ pub trait TrapManager {
     unsafe fn switch_to_user_vec();
 
     /// Go back to the user space after handling user trap.
+    /// `trap` is mapped at `trap_va` in the user page table.
     ///
     /// # Safety
     ///
@@ -133,6 +147,7 @@ pub trait TrapManager {
     unsafe fn user_trap_ret(
         user_pagetable_addr: usize,
         trap: &mut <TargetArch as ProcManager>::TrapFrame,
+        trap_va: usize,
         kernel_stack: usize,
         usertrap: usize,
     ) -> !;
@@ -183,13 +198,32 @@ pub trait ProcManager {
     fn cpu_id() -> usize;
 }
 
-pub trait TrapFrameManager: Copy + Clone {
+pub trait TrapFrameManager: Copy + Clone + AsBytes + FromBytes {
     /// Set user pc.
     fn set_pc(&mut self, val: usize);
 
+    /// Get user pc.
+    fn get_pc(&self) -> usize;
+
     /// Set the value of user stack pointer.
     fn set_sp(&mut self, val: usize);
 
+    /// Get the value of user stack pointer.
+    fn get_sp(&self) -> usize;
+
+    /// Set the value of return address register.
+    fn set_ra(&mut self, val: usize);
+
+    /// The user registers in a trap frame, which may be exposed to the user.
+    type UserRegs: Copy + AsBytes + FromBytes;
+
+    /// Get the user registers.
+    fn user_regs(&self) -> Self::UserRegs;
+
+    /// Set the user registers to `regs`, which may have been modified by the
+    /// user. Kernel fields and the privilege level are left untouched.
+    fn set_user_regs(&mut self, regs: &Self::UserRegs);
+
     /// Set the value of return value register.
     fn set_ret_val(&mut self, val: usize);
 
@@ -226,6 +260,11 @@ pub trait PageTableManager {
     ///
     /// `page_table_base` must contain base address for a valid page table, containing mapping for current pc.
     unsafe fn switch_page_table_and_enable_mmu(page_table_base: usize);
+
+    /// Makes the CPUs in `cpus`, a bitmask of CPU ids, drop the TLB entries of user page tables.
+    /// Returns true if they have already dropped them. Otherwise, they have only been
+    /// interrupted, and drop them when they return to user mode.
+    fn shootdown_tlb(cpus: usize) -> bool;
 }
 
 /// # Safety
@@ -250,6 +289,17 @@ pub trait IPageTableEntry: Default {
 
     fn is_data(&self) -> bool;
 
+    fn is_writable(&self) -> bool;
+
+    /// Return true if the entry is a read-only copy-on-write mapping.
+    fn is_cow(&self) -> bool;
+
+    /// Make a writable entry read-only and mark it as copy-on-write.
+    fn set_cow(&mut self);
+
+    /// Make a copy-on-write entry writable again and clear the mark.
+    fn clear_cow(&mut self);
+
     /// Make the entry refer to a given page-table page.
     fn set_table(&mut self, page: *mut RawPageTable);
 
@@ -6,22 +6,23 @@ use crate::arch::{
     memlayout::{plic_sclaim, plic_senable, plic_spriority, PLIC},
     RiscV,
 };
+use crate::param::NDISK;
 
 impl InterruptManager for RiscV {
     unsafe fn intr_init() {
         // set desired IRQ priorities non-zero (otherwise disabled).
         unsafe { *((PLIC.wrapping_add(RiscV::UART0_IRQ.wrapping_mul(4))) as *mut u32) = 1 };
-        unsafe { *((PLIC + RiscV::VIRTIO0_IRQ * 4) as *mut u32) = 1 };
+        for i in 0..NDISK {
+            unsafe { *((PLIC + (RiscV::VIRTIO0_IRQ + i) * 4) as *mut u32) = 1 };
+        }
     }
 
     unsafe fn intr_init_core() {
         let hart: usize = r_tp();
 
-        // set uart's enable bit for this hart's S-mode.
-        unsafe {
-            *(plic_senable(hart) as *mut u32) =
-                (1 << RiscV::UART0_IRQ | 1 << RiscV::VIRTIO0_IRQ) as u32
-        };
+        // set uart's and virtio disks' enable bits for this hart's S-mode.
+        let virtio_mask = ((1 << NDISK) - 1) << RiscV::VIRTIO0_IRQ;
+        unsafe { *(plic_senable(hart) as *mut u32) = (1 << RiscV::UART0_IRQ | virtio_mask) as u32 };
 
         // set this hart's S-mode priority threshold to 0.
         unsafe { *(plic_spriority(hart) as *mut u32) = 0 };
@@ -4,6 +4,7 @@
 //! based on qemu's hw/riscv/virt.c:
 //!
 //! 00001000 -- boot ROM, provided by qemu
+//! 00101000 -- goldfish rtc
 //! 02000000 -- CLINT
 //! 0C000000 -- PLIC
 //! 10000000 -- uart0
@@ -27,12 +28,15 @@ impl MemLayout for RiscV {
     /// for use by the kernel and user pages
     /// from physical address 0x80000000 to PHYSTOP.
     const KERNBASE: usize = 0x80000000;
+    /// Goldfish real-time clock.
+    const RTC0: usize = 0x101000;
     /// qemu puts UART registers here in physical memory.
     const UART0: usize = 0x10000000;
     const UART0_IRQ: usize = 10;
     /// virtio mmio interface
     const VIRTIO0: usize = 0x10001000;
     const VIRTIO0_IRQ: usize = 1;
+    const VIRTIO_STRIDE: usize = 0x1000;
 }
 
 /// SiFive Test Finisher. (virt device only)
@@ -40,6 +44,10 @@ pub const FINISHER: usize = 0x100000;
 
 /// core local interruptor (CLINT), which contains the timer.
 pub const CLINT: usize = 0x2000000;
+/// Writing 1 to a hart's MSIP register raises a machine-mode software interrupt on it.
+pub const fn clint_msip(hartid: usize) -> usize {
+    CLINT.wrapping_add(hartid.wrapping_mul(4))
+}
 pub const fn clint_mtimecmp(hartid: usize) -> usize {
     CLINT
         .wrapping_add(0x4000)
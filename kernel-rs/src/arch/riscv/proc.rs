@@ -1,3 +1,5 @@
+use zerocopy::{AsBytes, FromBytes};
+
 use crate::arch::interface::{ContextManager, ProcManager, TrapFrameManager};
 use crate::arch::{asm, RiscV};
 use crate::proc::RegNum;
@@ -58,7 +60,8 @@ pub struct Context {
 /// The trapframe includes callee-saved user registers like s0-s11 because the
 /// return-to-user path via usertrapret() doesn't return through
 /// the entire kernel call stack.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, AsBytes, FromBytes)]
+#[repr(C)]
 pub struct TrapFrame {
     /// 0 - kernel page table (satp: Supervisor Address Translation and Protection)
     pub kernel_satp: usize,
@@ -170,14 +173,77 @@ pub struct TrapFrame {
 }
 
 impl const TrapFrameManager for TrapFrame {
+    type UserRegs = [usize; 32];
+
     fn set_pc(&mut self, val: usize) {
         self.epc = val;
     }
 
+    fn get_pc(&self) -> usize {
+        self.epc
+    }
+
     fn set_sp(&mut self, val: usize) {
         self.sp = val;
     }
 
+    fn get_sp(&self) -> usize {
+        self.sp
+    }
+
+    fn set_ra(&mut self, val: usize) {
+        self.ra = val;
+    }
+
+    fn user_regs(&self) -> Self::UserRegs {
+        [
+            self.epc, self.ra, self.sp, self.gp, self.tp, self.t0, self.t1, self.t2, self.s0,
+            self.s1, self.a0, self.a1, self.a2, self.a3, self.a4, self.a5, self.a6, self.a7,
+            self.s2, self.s3, self.s4, self.s5, self.s6, self.s7, self.s8, self.s9, self.s10,
+            self.s11, self.t3, self.t4, self.t5, self.t6,
+        ]
+    }
+
+    fn set_user_regs(&mut self, regs: &Self::UserRegs) {
+        let [epc, ra, sp, gp, tp, t0, t1, t2, s0, s1, a0, a1, a2, a3, a4, a5, a6, a7, s2, s3, s4, s5, s6, s7, s8, s9, s10, s11, t3, t4, t5, t6] =
+            *regs;
+        *self = Self {
+            epc,
+            ra,
+            sp,
+            gp,
+            tp,
+            t0,
+            t1,
+            t2,
+            s0,
+            s1,
+            a0,
+            a1,
+            a2,
+            a3,
+            a4,
+            a5,
+            a6,
+            a7,
+            s2,
+            s3,
+            s4,
+            s5,
+            s6,
+            s7,
+            s8,
+            s9,
+            s10,
+            s11,
+            t3,
+            t4,
+            t5,
+            t6,
+            ..*self
+        };
+    }
+
     /// Set the value of return value register
     fn set_ret_val(&mut self, val: usize) {
         self.a0 = val;
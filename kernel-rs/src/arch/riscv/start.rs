@@ -1,9 +1,11 @@
 use core::arch::asm;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::{
     arch::asm::{
-        r_mhartid, w_medeleg, w_mepc, w_mideleg, w_mscratch, w_mtvec, w_satp, w_tp, Mstatus, Pmp,
-        MIE, SIE,
+        r_mhartid, r_tp, w_medeleg, w_mepc, w_mideleg, w_mscratch, w_mtvec, w_satp, w_tp, Mstatus,
+        Pmp, MIE, SIE,
     },
     arch::memlayout::{clint_mtimecmp, CLINT_MTIME},
     kernel::main,
@@ -30,7 +32,17 @@ impl Stack {
 pub static mut stack0: Stack = Stack::new();
 
 /// A scratch area per CPU for machine-mode timer interrupts.
-static mut TIMER_SCRATCH: [[usize; NCPU]; 5] = [[0; NCPU]; 5];
+static mut TIMER_SCRATCH: [[usize; 6]; NCPU] = [[0; 6]; NCPU];
+
+/// Returns true if a timer interrupt has arrived at the current CPU since the last call.
+/// Otherwise, the supervisor software interrupt was raised by another CPU's interrupt.
+pub fn take_timer_tick() -> bool {
+    let id = r_tp();
+    // SAFETY: `AtomicUsize` has the same layout as `usize`, and timervec on this CPU is the
+    // only other code that accesses this flag.
+    let fired = unsafe { &*(ptr::addr_of!(TIMER_SCRATCH[id][5]) as *const AtomicUsize) };
+    fired.swap(0, Ordering::Relaxed) != 0
+}
 
 /// Configures the Pmp registers so that we can trivally boot.
 /// See section 3.7.1 "Physical Memory Protection CSRs" in the RISC-V privileged specification for details.
@@ -98,6 +110,7 @@ unsafe fn timerinit() {
     // scratch[0..2] : space for timervec to save registers.
     // scratch[3] : address of CLINT MTIMECMP register.
     // scratch[4] : desired interval (in cycles) between timer interrupts.
+    // scratch[5] : set by timervec when the timer fires.
     let scratch = unsafe { &mut TIMER_SCRATCH[id][..] };
     *unsafe { scratch.get_unchecked_mut(3) } = clint_mtimecmp(id);
     *unsafe { scratch.get_unchecked_mut(4) } = interval;
@@ -111,8 +124,9 @@ unsafe fn timerinit() {
     x.insert(Mstatus::MIE);
     unsafe { x.write() };
 
-    // enable machine-mode timer interrupts.
+    // enable machine-mode timer and software interrupts.
     let mut y = MIE::read();
     y.insert(MIE::MTIE);
+    y.insert(MIE::MSIE);
     unsafe { y.write() };
 }
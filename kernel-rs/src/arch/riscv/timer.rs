@@ -1,7 +1,12 @@
-use core::arch::asm;
+use core::{arch::asm, ptr};
 
 use super::RiscV;
-use crate::arch::interface::TimeManager;
+use crate::arch::interface::{MemLayout, TimeManager};
+
+/// Goldfish RTC registers, which hold the time in nanoseconds. Reading `TIME_LOW` latches
+/// the high half of the time into `TIME_HIGH`.
+const TIME_LOW: usize = 0x00;
+const TIME_HIGH: usize = 0x04;
 
 impl TimeManager for RiscV {
     fn timer_init() {
@@ -14,6 +19,16 @@ impl TimeManager for RiscV {
         todo!()
     }
 
+    fn rtc_time_as_nanos() -> u64 {
+        // SAFETY: the kernel page table maps the RTC registers.
+        let (low, high) = unsafe {
+            let low = ptr::read_volatile((Self::RTC0 + TIME_LOW) as *const u32);
+            let high = ptr::read_volatile((Self::RTC0 + TIME_HIGH) as *const u32);
+            (low, high)
+        };
+        u64::from(high) << 32 | u64::from(low)
+    }
+
     fn r_cycle() -> usize {
         let mut x;
         unsafe {
@@ -11,9 +11,12 @@ use crate::{
     arch::interface::{MemLayout, TrapManager},
     arch::intr::{plic_claim, plic_complete},
     arch::proc::TrapFrame,
+    arch::start::take_timer_tick,
     arch::RiscV,
-    memlayout::{TRAMPOLINE, TRAPFRAME},
+    memlayout::TRAMPOLINE,
+    param::NDISK,
     trap::{IrqNum, IrqTypes, TrapTypes},
+    vm::AccessFlags,
 };
 
 extern "C" {
@@ -32,7 +35,7 @@ impl From<&IrqTypes> for IrqNum {
     fn from(item: &IrqTypes) -> Self {
         match item {
             IrqTypes::Uart => RiscV::UART0_IRQ,
-            IrqTypes::Virtio => RiscV::VIRTIO0_IRQ,
+            IrqTypes::Virtio(i) => RiscV::VIRTIO0_IRQ + i,
             IrqTypes::Unknown(i) => *i,
             IrqTypes::Others(_) => 0,
         }
@@ -70,7 +73,9 @@ impl TrapManager for RiscV {
 
             match irq {
                 RiscV::UART0_IRQ => TrapTypes::Irq(IrqTypes::Uart),
-                RiscV::VIRTIO0_IRQ => TrapTypes::Irq(IrqTypes::Virtio),
+                irq if (RiscV::VIRTIO0_IRQ..RiscV::VIRTIO0_IRQ + NDISK).contains(&irq) => {
+                    TrapTypes::Irq(IrqTypes::Virtio(irq - RiscV::VIRTIO0_IRQ))
+                }
                 0 => {
                     // TODO: should we handle this?
                     TrapTypes::Irq(IrqTypes::Others(0))
@@ -78,10 +83,24 @@ impl TrapManager for RiscV {
                 _ => TrapTypes::Irq(IrqTypes::Unknown(irq)),
             }
         } else if scause == 0x8000000000000001 {
-            // Software interrupt from a machine-mode timer interrupt,
-            // forwarded by timervec in selfvec.S.
-
-            TrapTypes::TimerInterrupt
+            // Software interrupt from a machine-mode timer or software interrupt,
+            // forwarded by timervec in kernelvec.S.
+            if take_timer_tick() {
+                TrapTypes::TimerInterrupt
+            } else {
+                TrapTypes::Ipi
+            }
+        } else if scause == 12 || scause == 13 || scause == 15 {
+            // Instruction, load, or store/AMO page fault.
+            let access = match scause {
+                12 => AccessFlags::X,
+                13 => AccessFlags::R,
+                _ => AccessFlags::W,
+            };
+            TrapTypes::PageFault {
+                addr: r_stval(),
+                access,
+            }
         } else {
             TrapTypes::BadTrap
         }
@@ -123,7 +142,7 @@ impl TrapManager for RiscV {
                     }
                 }
             }
-            TrapTypes::TimerInterrupt => {
+            TrapTypes::TimerInterrupt | TrapTypes::Ipi => {
                 // Acknowledge the software interrupt by clearing
                 // the SSIP bit in sip.
                 unsafe { w_sip(r_sip() & !2) };
@@ -176,6 +195,7 @@ impl TrapManager for RiscV {
     unsafe fn user_trap_ret(
         user_pagetable_addr: usize,
         trapframe: &mut TrapFrame,
+        trapframe_va: usize,
         kernel_stack: usize,
         usertrap: usize,
     ) -> ! {
@@ -232,7 +252,7 @@ impl TrapManager for RiscV {
         let fn_0: usize =
             TRAMPOLINE + unsafe { userret.as_ptr().offset_from(trampoline.as_ptr()) } as usize;
         let fn_0 = unsafe { mem::transmute::<_, unsafe extern "C" fn(usize, usize) -> !>(fn_0) };
-        unsafe { fn_0(TRAPFRAME, satp) }
+        unsafe { fn_0(trapframe_va, satp) }
     }
 
     /// Save trap registers in `store`.
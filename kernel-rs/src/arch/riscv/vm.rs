@@ -1,10 +1,12 @@
+use core::ptr;
+
 use bitflags::bitflags;
 
 use super::RiscV;
 use crate::{
     addr::{PAddr, PGSIZE},
     arch::interface::{IPageTableEntry, PageTableManager},
-    arch::memlayout::{FINISHER, PLIC},
+    arch::memlayout::{clint_msip, CLINT, FINISHER, PLIC},
     arch::{
         addr::{pa2pte, pte2pa, PLNUM},
         asm::{make_satp, sfence_vma, w_satp},
@@ -24,6 +26,8 @@ bitflags! {
         const X = 1 << 3;
         /// user-accessible
         const U = 1 << 4;
+        /// copy-on-write (reserved for software)
+        const C = 1 << 8;
     }
 }
 
@@ -94,6 +98,22 @@ impl IPageTableEntry for PageTableEntry {
             && self.flag_intersects(Self::EntryFlags::R | Self::EntryFlags::W | Self::EntryFlags::X)
     }
 
+    fn is_writable(&self) -> bool {
+        self.flag_intersects(Self::EntryFlags::W)
+    }
+
+    fn is_cow(&self) -> bool {
+        self.flag_intersects(Self::EntryFlags::C)
+    }
+
+    fn set_cow(&mut self) {
+        self.inner = (self.inner & !Self::EntryFlags::W.bits()) | Self::EntryFlags::C.bits();
+    }
+
+    fn clear_cow(&mut self) {
+        self.inner = (self.inner & !Self::EntryFlags::C.bits()) | Self::EntryFlags::W.bits();
+    }
+
     /// Make the entry refer to a given page-table page.
     fn set_table(&mut self, page: *mut RawPageTable) {
         self.inner = pa2pte((page as usize).into()) | Self::EntryFlags::V.bits();
@@ -120,8 +140,9 @@ impl IPageTableEntry for PageTableEntry {
 
 impl RiscV {
     // Device mappings in memory.
-    // SiFive Test Finisher MMIO, PLIC.
-    const DEV_MAPPING: [(usize, usize); 2] = [(FINISHER, PGSIZE), (PLIC, 0x400000)];
+    // SiFive Test Finisher MMIO, CLINT, PLIC.
+    const DEV_MAPPING: [(usize, usize); 3] =
+        [(FINISHER, PGSIZE), (CLINT, 0x10000), (PLIC, 0x400000)];
 }
 
 impl PageTableManager for RiscV {
@@ -130,7 +151,7 @@ impl PageTableManager for RiscV {
     const PLNUM: usize = PLNUM;
 
     fn kernel_page_dev_mappings() -> &'static [(usize, usize)] {
-        &Self::DEV_MAPPING[0..2]
+        &Self::DEV_MAPPING
     }
 
     /// Switch the page table to `page_table_base` and enable paging.
@@ -145,4 +166,14 @@ impl PageTableManager for RiscV {
             sfence_vma();
         }
     }
+
+    /// Sends a software interrupt to each CPU in `cpus`. An interrupted CPU traps into the
+    /// kernel, and executes sfence.vma in trampoline.S when it returns to user mode.
+    fn shootdown_tlb(cpus: usize) -> bool {
+        for id in (0..usize::BITS as usize).filter(|id| cpus & (1 << id) != 0) {
+            // SAFETY: the kernel page table maps the CLINT, and `id` is a valid hart id.
+            unsafe { ptr::write_volatile(clint_msip(id) as *mut u32, 1) };
+        }
+        false
+    }
 }
@@ -53,6 +53,13 @@ impl<T, const CAPACITY: usize> ArrayArena<T, CAPACITY> {
     ) -> StrongPin<'s, SpinLock<ArrayArenaInner<T, CAPACITY>>> {
         unsafe { StrongPin::new_unchecked(&(*self.ptr()).inner) }
     }
+
+    /// Returns the total number of references to the entries.
+    pub fn refs(self: StrongPin<'_, Self>) -> usize {
+        let mut guard = self.inner().strong_pinned_lock();
+        let this = guard.get_strong_pinned_mut();
+        this.entries().iter_mut().map(|entry| entry.refcnt()).sum()
+    }
 }
 
 impl<T, const CAPACITY: usize> ArrayArenaInner<T, CAPACITY> {
@@ -108,6 +108,24 @@ impl<T, const CAPACITY: usize> MruArena<T, CAPACITY> {
             .init();
     }
 
+    /// Calls `f` for each entry, from the most recently used one, with the number of
+    /// references to it and, if there is none, its data. The data of a referenced entry
+    /// is not given, because it may be under finalization.
+    pub fn for_each<F: FnMut(usize, Option<&T>)>(self: StrongPin<'_, Self>, mut f: F) {
+        let mut guard = self.inner().strong_pinned_lock();
+        let this = guard.get_strong_pinned_mut();
+
+        // SAFETY: the whole `MruArena` is protected by a lock.
+        for entry in unsafe { this.list().iter_strong_pin_mut_unchecked() } {
+            let mut entry = entry.data();
+            match entry.as_mut().refcnt() {
+                // No one else can borrow the entry while we hold the lock.
+                0 => f(0, entry.try_borrow().as_deref()),
+                refcnt => f(refcnt, None),
+            }
+        }
+    }
+
     #[allow(clippy::needless_lifetimes)]
     fn inner<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, SpinLock<MruArenaInner<T, CAPACITY>>> {
         unsafe { StrongPin::new_unchecked(&(*self.ptr()).inner) }
@@ -27,7 +27,7 @@ use crate::{
 };
 
 pub struct BufEntry {
-    dev: u32,
+    pub dev: u32,
     pub blockno: u32,
 
     /// WaitChannel saying virtio_disk request is done.
@@ -72,10 +72,10 @@ pub struct BufInner {
     data: BufData,
 }
 
-// Data in Buf may be assumed to be u32, so the data field in Buf must have
-// an alignment of 4 bytes. Due to the align(4) modifier, BufData has an
-// alignment of 4 bytes.
-#[repr(align(4))]
+// Data in Buf may be assumed to be u32 or u64 (e.g. the size in a Dinode),
+// so the data field in Buf must have an alignment of 8 bytes. Due to the
+// align(8) modifier, BufData has an alignment of 8 bytes.
+#[repr(align(8))]
 #[derive(Deref, DerefMut)]
 pub struct BufData {
     pub inner: [u8; BSIZE],
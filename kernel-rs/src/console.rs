@@ -13,9 +13,13 @@ use crate::{
     addr::UVAddr,
     arch::interface::{Arch, UartManager, UartManagerConst},
     arch::TargetArch,
+    errno::Errno,
+    file::SelectEvent,
+    fs::FcntlFlags,
     hal::hal,
     kernel::{Kernel, KernelRef},
     lock::{SleepableLock, SleepableLockGuard, SpinLock, SpinLockGuard},
+    poll::PollQueue,
     proc::KernelCtx,
     util::spin_loop,
 };
@@ -70,6 +74,9 @@ pub struct Console {
     uart: Uart,
     input_buffer: SleepableLock<InputBuffer>,
     output_buffer: SleepableLock<OutputBuffer>,
+
+    /// Notified when a line of input arrives or the output buffer has room.
+    queue: PollQueue,
 }
 
 impl Console {
@@ -81,6 +88,7 @@ impl Console {
             uart: unsafe { Uart::new(uart) },
             input_buffer: SleepableLock::new("console_input", InputBuffer::new()),
             output_buffer: SleepableLock::new("console_output", OutputBuffer::new()),
+            queue: PollQueue::new(),
         }
     }
 
@@ -157,28 +165,48 @@ impl Console {
 
             // Maybe uart.putc() is waiting for space in the buffer.
             guard.wakeup(kernel);
+            self.queue.notify(kernel);
 
             self.uart.putc(c);
         }
     }
 
-    fn write(&self, src: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+    fn write(&self, src: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, Errno> {
         for i in 0..n {
             let mut c = [0u8];
-            if ctx
-                .proc_mut()
-                .memory_mut()
-                .copy_in_bytes(&mut c, src + i as usize)
-                .is_err()
-            {
-                return i;
+            if let Err(err) = ctx.copy_in_bytes(&mut c, src + i as usize) {
+                return if i == 0 { Err(err) } else { Ok(i as usize) };
             }
             self.putc_sleep(c[0], ctx);
         }
-        n
+        Ok(n as usize)
+    }
+
+    /// Returns whether reading or writing the console would not block.
+    fn is_ready(&self, event: SelectEvent) -> bool {
+        match event {
+            SelectEvent::Read => {
+                let guard = self.input_buffer.lock();
+                guard.r != guard.w
+            }
+            SelectEvent::Write => {
+                let guard = self.output_buffer.lock();
+                guard.w != guard.r.wrapping_add(OUTPUT_BUF)
+            }
+            SelectEvent::Error => false,
+        }
     }
 
-    fn read(&self, mut dst: UVAddr, mut n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+    /// Reads up to a line of at most `n` bytes into `dst`. If `nonblock` is true and there is
+    /// no input, returns what it has read so far or Err(EAGAIN) instead of waiting. A pending
+    /// signal stops the wait in the same way, with Err(EINTR).
+    fn read(
+        &self,
+        mut dst: UVAddr,
+        mut n: i32,
+        nonblock: bool,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, Errno> {
         let mut guard = self.input_buffer.lock();
         let target = n;
         while n > 0 {
@@ -186,7 +214,18 @@ impl Console {
             // input into CONS.buffer.
             while guard.r == guard.w {
                 if ctx.proc().killed() {
-                    return -1;
+                    return Err(Errno::EINTR);
+                }
+                // A signal interrupts the read, but keeps what it has read so far.
+                let interrupted = ctx.proc().signal_pending();
+                if nonblock || interrupted {
+                    return if n < target {
+                        Ok((target - n) as usize)
+                    } else if interrupted {
+                        Err(Errno::EINTR)
+                    } else {
+                        Err(Errno::EAGAIN)
+                    };
                 }
                 guard.sleep(ctx);
             }
@@ -204,12 +243,7 @@ impl Console {
             } else {
                 // Copy the input byte to the user-space buffer.
                 let cbuf = [cin as u8];
-                if ctx
-                    .proc_mut()
-                    .memory_mut()
-                    .copy_out_bytes(dst, &cbuf)
-                    .is_err()
-                {
+                if ctx.proc().memory().copy_out_bytes(dst, &cbuf).is_err() {
                     break;
                 }
                 dst = dst + 1;
@@ -221,7 +255,7 @@ impl Console {
                 }
             }
         }
-        target - n
+        Ok((target - n) as usize)
     }
 
     /// Handle a uart interrupt, raised because input has arrived, or the uart is ready for more
@@ -277,6 +311,7 @@ impl Console {
                             // Wake up read() if a whole line (or end-of-file) has arrived.
                             guard.w = guard.e;
                             guard.wakeup(kernel);
+                            self.queue.notify(kernel);
                         }
                     }
                 }
@@ -330,13 +365,38 @@ const fn ctrl(x: char) -> i32 {
 }
 
 /// User write()s to the console go here.
-pub fn console_write(src: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+pub fn console_write(
+    _minor: u16,
+    src: UVAddr,
+    n: i32,
+    _off: u64,
+    _flags: FcntlFlags,
+    ctx: &mut KernelCtx<'_, '_>,
+) -> Result<usize, Errno> {
     hal().console().write(src, n, ctx)
 }
 
 /// User read()s from the console go here.
 /// Copy (up to) a whole input line to dst.
 /// User_dist indicates whether dst is a user or kernel address.
-pub fn console_read(dst: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
-    hal().console().read(dst, n, ctx)
+pub fn console_read(
+    _minor: u16,
+    dst: UVAddr,
+    n: i32,
+    _off: u64,
+    flags: FcntlFlags,
+    ctx: &mut KernelCtx<'_, '_>,
+) -> Result<usize, Errno> {
+    let nonblock = flags.contains(FcntlFlags::O_NONBLOCK);
+    hal().console().read(dst, n, nonblock, ctx)
+}
+
+/// `select`s on the console go here.
+pub fn console_poll(_minor: u16, event: SelectEvent) -> bool {
+    hal().console().is_ready(event)
+}
+
+/// The queue of `select`s on the console.
+pub fn console_queue(_minor: u16) -> &'static PollQueue {
+    &hal().get_ref().console().queue
 }
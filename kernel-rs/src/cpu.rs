@@ -1,13 +1,15 @@
 use core::{
     cell::{Cell, UnsafeCell},
     marker::PhantomData,
+    mem,
     ptr::{self, NonNull},
+    sync::atomic::{fence, AtomicUsize, Ordering},
 };
 
 use array_macro::array;
 
 use crate::{
-    arch::interface::{ContextManager, ProcManager, TrapManager},
+    arch::interface::{ContextManager, PageTableManager, ProcManager, TrapManager},
     arch::TargetArch,
     param::NCPU,
     proc::Proc,
@@ -32,6 +34,13 @@ impl HeldInterrupts {
     }
 }
 
+/// # Safety
+///
+/// Preemption of the current thread is disabled, so it stays on the current CPU unless it sleeps.
+// One private field prevents `PreemptDisabled` from being constructed outside this module,
+// and `*const ()` prevents it from being sent to another thread.
+pub struct PreemptDisabled(PhantomData<*const ()>);
+
 // SAFETY: each thread access the cpu struct of the cpu on which it's running.
 unsafe impl Sync for Cpus {}
 
@@ -100,6 +109,105 @@ impl Cpus {
             cpu.pop_off();
         }
     }
+
+    /// preempt_disable/preempt_enable prevent a timer interrupt from preempting the current
+    /// thread in the kernel, while leaving interrupts enabled. They are matched like
+    /// push_off/pop_off. Holding a spinlock, or any other push_off, also disables preemption.
+    ///
+    /// The current thread stays on the current CPU until the matching preempt_enable unless it
+    /// sleeps. A sleeping thread keeps its preemption disabled, as `sched` saves and restores
+    /// the count.
+    pub fn preempt_disable(&self) -> PreemptDisabled {
+        let intr = self.push_off();
+        let cpu = self.current(&intr);
+        cpu.set_preempt_count(cpu.get_preempt_count() + 1);
+        // SAFETY: it restores interrupts to the state before `push_off`.
+        unsafe { self.pop_off(intr) };
+        PreemptDisabled(PhantomData)
+    }
+
+    /// preempt_enable() should be paired with preempt_disable().
+    /// See preempt_disable() for more details. Returns true if preemption is enabled now and
+    /// a timer interrupt arrived in the meantime, in which case the caller should yield the CPU.
+    pub fn preempt_enable(&self, _: PreemptDisabled) -> bool {
+        let intr = self.push_off();
+        let cpu = self.current(&intr);
+        let count = cpu.get_preempt_count();
+        assert!(count >= 1, "preempt_enable");
+        cpu.set_preempt_count(count - 1);
+        // Our own push_off is the only one.
+        let resched = count == 1 && cpu.get_noff() == 1 && cpu.take_missed_tick();
+        // SAFETY: it restores interrupts to the state before `push_off`.
+        unsafe { self.pop_off(intr) };
+        resched
+    }
+}
+
+impl Cpus {
+    /// Returns the user state of the CPU `id`. Unlike the rest of `Cpu`, other CPUs may read it.
+    fn user(&self, id: usize) -> &UserState {
+        // SAFETY: `user` is only accessed through shared references.
+        unsafe { &*ptr::addr_of!((*self.0[id].get()).user) }
+    }
+
+    /// Makes the other CPUs drop their TLB entries of the user page table `page_table`,
+    /// which has had some of its entries removed or write-protected. It returns after every
+    /// CPU that could still use a removed entry has stopped running user code on it, so the
+    /// pages of the removed entries can be freed.
+    ///
+    /// It does not sleep, and can be called while holding a spinlock.
+    pub fn shootdown(&self, page_table: usize) {
+        // Pairs with the store in `enter_user`: a CPU that has not published `page_table`
+        // yet will see the updated entries.
+        fence(Ordering::SeqCst);
+
+        let mut cpus = 0;
+        let mut traps = [0; NCPU];
+        for (i, t) in traps.iter_mut().enumerate() {
+            let user = self.user(i);
+            if user.page_table.load(Ordering::SeqCst) == page_table {
+                cpus |= 1 << i;
+                *t = user.traps.load(Ordering::SeqCst);
+            }
+        }
+        if cpus == 0 || TargetArch::shootdown_tlb(cpus) {
+            return;
+        }
+
+        // Each interrupted CPU drops its TLB entries when it returns to user mode, so it is
+        // enough to wait until it has trapped into the kernel.
+        for (i, t) in traps
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| cpus & (1 << i) != 0)
+        {
+            let user = self.user(i);
+            while user.page_table.load(Ordering::SeqCst) == page_table
+                && user.traps.load(Ordering::SeqCst) == *t
+            {
+                ::core::hint::spin_loop();
+            }
+        }
+    }
+}
+
+/// The user page table that a CPU runs on. It is shared with the other CPUs for TLB
+/// shootdowns.
+struct UserState {
+    /// The address of the user page table, or 0 if the CPU is not in user mode.
+    page_table: AtomicUsize,
+
+    /// The number of traps from user mode.
+    traps: AtomicUsize,
+}
+
+impl UserState {
+    const fn new() -> Self {
+        Self {
+            page_table: AtomicUsize::new(0),
+            traps: AtomicUsize::new(0),
+        }
+    }
 }
 
 /// Per-CPU-state.
@@ -115,6 +223,15 @@ pub struct Cpu {
 
     /// Were interrupts enabled before push_off()?
     interrupt_enabled: bool,
+
+    /// Depth of preempt_disable() nesting.
+    preempt_count: u32,
+
+    /// Did a timer interrupt arrive while preemption was disabled?
+    missed_tick: bool,
+
+    /// The user page table that this CPU runs on.
+    user: UserState,
 }
 
 impl Cpu {
@@ -124,6 +241,9 @@ impl Cpu {
             context: <TargetArch as ProcManager>::Context::new(),
             noff: 0,
             interrupt_enabled: false,
+            preempt_count: 0,
+            missed_tick: false,
+            user: UserState::new(),
         }
     }
 }
@@ -183,6 +303,38 @@ impl CpuMut<'_> {
         }
     }
 
+    pub fn get_preempt_count(&self) -> u32 {
+        // SAFETY: invariant of `CpuMut`
+        unsafe { (*self.ptr()).preempt_count }
+    }
+
+    pub fn set_preempt_count(&self, count: u32) {
+        // SAFETY: invariant of `CpuMut`
+        unsafe {
+            (*self.ptr()).preempt_count = count;
+        }
+    }
+
+    /// Records that a timer interrupt arrived while preemption was disabled.
+    pub fn miss_tick(&self) {
+        // SAFETY: invariant of `CpuMut`
+        unsafe {
+            (*self.ptr()).missed_tick = true;
+        }
+    }
+
+    /// Clears the record of `miss_tick`, and returns whether there was one.
+    pub fn take_missed_tick(&self) -> bool {
+        // SAFETY: invariant of `CpuMut`
+        unsafe { mem::replace(&mut (*self.ptr()).missed_tick, false) }
+    }
+
+    /// Returns true if a timer interrupt can preempt the kernel code that was running on
+    /// this CPU, i.e., it holds no spinlock and has not disabled preemption.
+    pub fn is_preemptible(&self) -> bool {
+        self.get_noff() == 0 && self.get_preempt_count() == 0
+    }
+
     pub fn get_interrupt(&self) -> bool {
         // SAFETY: invariant of `CpuMut`
         unsafe { (*self.ptr()).interrupt_enabled }
@@ -195,6 +347,21 @@ impl CpuMut<'_> {
         }
     }
 
+    /// Records that this CPU is about to run user code on `page_table`.
+    pub fn enter_user(&self, page_table: usize) {
+        // SAFETY: invariant of `CpuMut`
+        let user = unsafe { &*ptr::addr_of!((*self.ptr()).user) };
+        user.page_table.store(page_table, Ordering::SeqCst);
+    }
+
+    /// Records that this CPU has trapped from user mode into the kernel.
+    pub fn leave_user(&self) {
+        // SAFETY: invariant of `CpuMut`
+        let user = unsafe { &*ptr::addr_of!((*self.ptr()).user) };
+        user.page_table.store(0, Ordering::SeqCst);
+        let _ = user.traps.fetch_add(1, Ordering::SeqCst);
+    }
+
     fn push_off(&self, old: bool) {
         let noff = self.get_noff();
         if noff == 0 {
@@ -0,0 +1,322 @@
+//! The device registry.
+//!
+//! A driver registers the callbacks of its major device number with `Devices::register`,
+//! and a node for each of its devices with `Devices::add_node`. The read, write, and
+//! ioctl system calls on a device file call the callbacks of its major number with its
+//! minor number, `select` asks the poll callback whether the device is ready, and the
+//! devfs shows the registered nodes under `/dev`.
+//!
+//! Besides the console, there are the memory devices (`null`, `zero`, and `random`) and
+//! the disks, which are read-only and read through the buffer cache.
+
+use core::cmp;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{
+    addr::UVAddr,
+    console::{console_poll, console_queue, console_read, console_write},
+    errno::Errno,
+    file::SelectEvent,
+    fs::{FcntlFlags, DIRSIZ},
+    hal::hal,
+    param::{BSIZE, NDEV, NDEVNODE, NDISK},
+    poll::PollQueue,
+    proc::KernelCtx,
+};
+
+/// Major device number of the console.
+pub const CONSOLE: u16 = 1;
+
+/// Major device number of the memory devices.
+pub const MEM: u16 = 2;
+
+/// Major device number of the disks. The minor number is the device number of the disk.
+pub const DISK: u16 = 3;
+
+/// Minor device numbers of the memory devices.
+const NULL: u16 = 0;
+const ZERO: u16 = 1;
+const RANDOM: u16 = 2;
+
+/// ioctl request of the disks: writes the number of blocks of the disk as an int to `arg`.
+const DIOCGBLOCKS: i32 = 1;
+
+/// Reads or writes `n` bytes at the user address `addr`, at the offset `off` of the device
+/// of the minor number `minor`, through a file with the flags `flags`. If `flags` has
+/// O_NONBLOCK, returns Err(EAGAIN) instead of waiting for the device.
+/// Returns Ok(number of bytes read or written) on success.
+pub type DevRw =
+    fn(u16, UVAddr, i32, u64, FcntlFlags, &mut KernelCtx<'_, '_>) -> Result<usize, Errno>;
+
+/// Runs the request `req` with the user address `arg` on the device of the minor number
+/// `minor`. Returns Ok(request-specific value) on success.
+pub type DevIoctl = fn(u16, i32, UVAddr, &mut KernelCtx<'_, '_>) -> Result<usize, Errno>;
+
+/// Returns whether the device of the minor number `minor` is ready for `event`, i.e.,
+/// whether reading or writing it would not block. A device without this callback never
+/// blocks. A driver with this callback has a `DevQueue` callback as well.
+pub type DevPoll = fn(u16, SelectEvent) -> bool;
+
+/// Returns the queue that the driver notifies when the device of the minor number `minor`
+/// may have become ready.
+pub type DevQueue = fn(u16) -> &'static PollQueue;
+
+/// map major device number to device functions.
+#[derive(Copy, Clone)]
+pub struct Devsw {
+    pub read: Option<DevRw>,
+    pub write: Option<DevRw>,
+    pub ioctl: Option<DevIoctl>,
+    pub poll: Option<DevPoll>,
+    pub queue: Option<DevQueue>,
+}
+
+/// A device that the devfs shows.
+#[derive(Copy, Clone)]
+pub struct DevNode {
+    name: [u8; DIRSIZ],
+    len: usize,
+    pub major: u16,
+    pub minor: u16,
+    /// The permission bits of the node, which the superuser owns.
+    pub mode: u16,
+}
+
+pub struct Devices {
+    devsw: [Option<Devsw>; NDEV],
+    nodes: [DevNode; NDEVNODE],
+    nnodes: usize,
+
+    /// State of the xorshift generator of `/dev/random`.
+    seed: AtomicU64,
+}
+
+impl DevNode {
+    const fn new() -> Self {
+        Self {
+            name: [0; DIRSIZ],
+            len: 0,
+            major: 0,
+            minor: 0,
+            mode: 0,
+        }
+    }
+
+    pub fn name(&self) -> &[u8] {
+        &self.name[..self.len]
+    }
+}
+
+impl Devices {
+    pub const fn new() -> Self {
+        Self {
+            devsw: [None; NDEV],
+            nodes: [DevNode::new(); NDEVNODE],
+            nnodes: 0,
+            seed: AtomicU64::new(0x2545_f491_4f6c_dd1d),
+        }
+    }
+
+    /// Registers the drivers of the console, the memory devices, and the present disks.
+    pub fn init(&mut self) {
+        self.register(
+            CONSOLE,
+            Devsw {
+                read: Some(console_read),
+                write: Some(console_write),
+                ioctl: None,
+                poll: Some(console_poll),
+                queue: Some(console_queue),
+            },
+        );
+        self.add_node(b"console", CONSOLE, 0, 0o666);
+
+        self.register(
+            MEM,
+            Devsw {
+                read: Some(mem_read),
+                write: Some(mem_write),
+                ioctl: None,
+                poll: None,
+                queue: None,
+            },
+        );
+        self.add_node(b"null", MEM, NULL, 0o666);
+        self.add_node(b"zero", MEM, ZERO, 0o666);
+        self.add_node(b"random", MEM, RANDOM, 0o666);
+
+        self.register(
+            DISK,
+            Devsw {
+                read: Some(disk_read),
+                write: None,
+                ioctl: Some(disk_ioctl),
+                poll: None,
+                queue: None,
+            },
+        );
+        for dev in 1..=NDISK as u16 {
+            if hal().disk().is_present(dev as u32) {
+                let mut name = *b"disk0";
+                name[4] += dev as u8;
+                // Only the superuser may read a whole disk.
+                self.add_node(&name, DISK, dev, 0o600);
+            }
+        }
+    }
+
+    /// Registers the callbacks of the major device number `major`.
+    pub fn register(&mut self, major: u16, devsw: Devsw) {
+        let slot = &mut self.devsw[major as usize];
+        assert!(
+            slot.is_none(),
+            "Devices::register: major {} is taken",
+            major
+        );
+        *slot = Some(devsw);
+    }
+
+    /// Adds a node named `name` of the device `major`:`minor` with the permission bits
+    /// `mode`, which the devfs shows.
+    pub fn add_node(&mut self, name: &[u8], major: u16, minor: u16, mode: u16) {
+        assert!(self.nnodes < NDEVNODE, "Devices::add_node: no nodes");
+        let node = &mut self.nodes[self.nnodes];
+        node.name[..name.len()].copy_from_slice(name);
+        node.len = name.len();
+        node.major = major;
+        node.minor = minor;
+        node.mode = mode;
+        self.nnodes += 1;
+    }
+
+    /// Returns the callbacks of the major device number `major`, or Err(ENODEV) if there
+    /// is no such driver.
+    pub fn get(&self, major: u16) -> Result<&Devsw, Errno> {
+        self.devsw
+            .get(major as usize)
+            .and_then(Option::as_ref)
+            .ok_or(Errno::ENODEV)
+    }
+
+    /// Returns the registered nodes.
+    pub fn nodes(&self) -> &[DevNode] {
+        &self.nodes[..self.nnodes]
+    }
+
+    /// Returns the next pseudo-random number.
+    fn random(&self) -> u64 {
+        // A racy update only makes two readers see the same number.
+        let mut x = self.seed.load(Ordering::Relaxed);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.seed.store(x, Ordering::Relaxed);
+        x
+    }
+}
+
+/// Reads from `/dev/null`, which is always at its end, `/dev/zero`, which gives zeros,
+/// and `/dev/random`, which gives pseudo-random bytes.
+fn mem_read(
+    minor: u16,
+    mut dst: UVAddr,
+    n: i32,
+    _off: u64,
+    _flags: FcntlFlags,
+    ctx: &mut KernelCtx<'_, '_>,
+) -> Result<usize, Errno> {
+    if minor == NULL {
+        return Ok(0);
+    }
+    if minor != ZERO && minor != RANDOM {
+        return Err(Errno::ENXIO);
+    }
+    if minor == RANDOM {
+        // Let the time choose where the sequence continues.
+        let ticks = *ctx.kernel().ticks().lock();
+        let devices = ctx.kernel().devices();
+        let _ = devices.seed.fetch_xor(ticks as u64, Ordering::Relaxed);
+    }
+
+    let n = cmp::max(n, 0) as usize;
+    let mut buf = [0u8; 64];
+    let mut done = 0;
+    while done < n {
+        let m = cmp::min(n - done, buf.len());
+        if minor == RANDOM {
+            for chunk in buf[..m].chunks_mut(8) {
+                let x = ctx.kernel().devices().random().to_le_bytes();
+                chunk.copy_from_slice(&x[..chunk.len()]);
+            }
+        }
+        ctx.copy_out_bytes(dst, &buf[..m])?;
+        dst = dst + m;
+        done += m;
+    }
+    Ok(n)
+}
+
+/// Writes to the memory devices, which discard what is written.
+fn mem_write(
+    _minor: u16,
+    _src: UVAddr,
+    n: i32,
+    _off: u64,
+    _flags: FcntlFlags,
+    _ctx: &mut KernelCtx<'_, '_>,
+) -> Result<usize, Errno> {
+    Ok(cmp::max(n, 0) as usize)
+}
+
+/// Reads the disk of the device `minor` from the byte offset `off`.
+fn disk_read(
+    minor: u16,
+    mut dst: UVAddr,
+    n: i32,
+    mut off: u64,
+    _flags: FcntlFlags,
+    ctx: &mut KernelCtx<'_, '_>,
+) -> Result<usize, Errno> {
+    let dev = minor as u32;
+    if !hal().disk().is_present(dev) {
+        return Err(Errno::ENXIO);
+    }
+    let size = hal().disk().nblocks(dev) as usize * BSIZE;
+    let end = cmp::min(off as usize + cmp::max(n, 0) as usize, size);
+    let mut done = 0;
+    while (off as usize) < end {
+        let m = cmp::min(end - off as usize, BSIZE - off as usize % BSIZE);
+        let buf = hal().disk().read(dev, (off / BSIZE as u64) as u32, ctx);
+        let begin = off as usize % BSIZE;
+        let res = ctx
+            .proc()
+            .memory()
+            .copy_out_bytes(dst, &buf.data()[begin..begin + m]);
+        buf.free(ctx);
+        res?;
+        dst = dst + m;
+        off += m as u64;
+        done += m;
+    }
+    Ok(done)
+}
+
+fn disk_ioctl(
+    minor: u16,
+    req: i32,
+    arg: UVAddr,
+    ctx: &mut KernelCtx<'_, '_>,
+) -> Result<usize, Errno> {
+    let dev = minor as u32;
+    if !hal().disk().is_present(dev) {
+        return Err(Errno::ENXIO);
+    }
+    match req {
+        DIOCGBLOCKS => {
+            let nblocks = hal().disk().nblocks(dev) as i32;
+            ctx.copy_out(arg, &nblocks)?;
+            Ok(0)
+        }
+        _ => Err(Errno::EINVAL),
+    }
+}
@@ -9,8 +9,10 @@ use zerocopy::{AsBytes, FromBytes};
 use crate::{
     addr::{pgroundup, PAddr, PGSIZE},
     arch::interface::TrapFrameManager,
-    fs::{FileSystem, FileSystemExt, Path},
+    errno::Errno,
+    fs::{dispatch, Access, FileSystem, Path, RcInode},
     hal::hal,
+    memlayout::USERTOP,
     page::Page,
     param::MAXARG,
     proc::{KernelCtx, RegNum},
@@ -97,48 +99,30 @@ impl ProgHdr {
 }
 
 impl KernelCtx<'_, '_> {
-    pub fn exec(&mut self, path: &Path, args: &[Page]) -> Result<usize, ()> {
+    pub fn exec(&mut self, path: &Path, args: &[Page]) -> Result<usize, Errno> {
         if args.len() > MAXARG {
-            return Err(());
+            return Err(Errno::E2BIG);
         }
 
-        let allocator = hal().kmem();
-
-        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let tx = scopeguard::guard(tx, |t| t.end(self));
-        let ptr = self.kernel().fs().namei(path, &tx, self)?;
-        let ptr = scopeguard::guard(ptr, |ptr| ptr.free((&tx, self)));
-        let ip = ptr.lock(self);
-        let mut ip = scopeguard::guard(ip, |ip| ip.free(self));
-
-        // Check ELF header
-        let mut elf: ElfHdr = Default::default();
-        ip.read_kernel(&mut elf, 0, self)?;
-        if !elf.is_valid() {
-            return Err(());
+        // Only the main thread can replace the memory shared by the threads.
+        if !self.proc().is_main_thread() {
+            return Err(Errno::EINVAL);
         }
+        // The other threads cannot run without the old memory.
+        self.kernel().procs().exit_threads(self);
 
-        let trap_frame: PAddr = (self.proc().trap_frame() as *const _ as usize).into();
-        let mem = UserMemory::new(trap_frame, None, allocator).ok_or(())?;
-        let mut mem = scopeguard::guard(mem, |mem| mem.free(allocator));
-
-        // Load program into memory.
-        for i in 0..elf.phnum as usize {
-            let off = elf.phoff + i * mem::size_of::<ProgHdr>();
+        let allocator = hal().kmem();
 
-            let mut ph: ProgHdr = Default::default();
-            ip.read_kernel(&mut ph, off as _, self)?;
-            if ph.is_prog_load() {
-                if ph.memsz < ph.filesz || ph.vaddr % PGSIZE != 0 {
-                    return Err(());
-                }
-                let _ = mem.alloc(ph.vaddr.checked_add(ph.memsz).ok_or(())?, allocator)?;
-                mem.load_file(ph.vaddr.into(), &mut ip, ph.off as _, ph.filesz as _, self)?;
-            }
+        let fs = self.kernel().fs();
+        let ptr = fs.with_tx(self, |tx| fs.namei(path, tx, self))?;
+        if let Err(err) = ptr.permit(Access::EXEC, self) {
+            ptr.put(self);
+            return Err(err);
         }
-        drop(ip);
-        drop(ptr);
-        drop(tx);
+        let res = dispatch!(&ptr, ip => self.load_elf(ip));
+        ptr.put(self);
+        let (mem, entry) = res?;
+        let mut mem = scopeguard::guard(mem, |mem| mem.free(allocator));
 
         // Allocate two pages at the next page boundary.
         // Use the second as the user stack.
@@ -161,7 +145,7 @@ impl KernelCtx<'_, '_> {
             // riscv sp must be 16-byte aligned
             sp &= !0xf;
             if sp < stackbase {
-                return Err(());
+                return Err(Errno::E2BIG);
             }
 
             mem.copy_out_bytes(sp.into(), bytes)?;
@@ -175,7 +159,7 @@ impl KernelCtx<'_, '_> {
         sp -= argv_size;
         sp &= !0xf;
         if sp < stackbase {
-            return Err(());
+            return Err(Errno::E2BIG);
         }
         // SAFETY: any byte can be considered as a valid u8.
         let (_, ustack, _) = unsafe { ustack.align_to::<u8>() };
@@ -196,11 +180,20 @@ impl KernelCtx<'_, '_> {
         }
 
         // Commit to the user image.
-        mem::replace(
-            self.proc_mut().memory_mut(),
+        self.write_back(0, USERTOP);
+        let mut old_mem = mem::replace(
+            &mut *self.proc().memory(),
             scopeguard::ScopeGuard::into_inner(mem),
-        )
-        .free(allocator);
+        );
+        for f in old_mem.munmap_all(allocator) {
+            f.free(self);
+        }
+        old_mem.free(allocator);
+        self.proc_mut().reset_signal_actions();
+        let files = self.proc().open_files().take_cloexec();
+        for f in files.into_iter().flatten() {
+            f.free(self);
+        }
 
         // arguments to user main(argc, argv)
         // argc is returned via the system call return
@@ -208,7 +201,7 @@ impl KernelCtx<'_, '_> {
         *self.proc_mut().trap_frame_mut().param_reg_mut(RegNum::R1) = sp;
 
         // initial program counter = main
-        self.proc_mut().trap_frame_mut().set_pc(elf.entry);
+        self.proc_mut().trap_frame_mut().set_pc(entry);
 
         // initial stack pointer
         self.proc_mut().trap_frame_mut().sp = sp;
@@ -216,4 +209,40 @@ impl KernelCtx<'_, '_> {
         // this ends up in a0, the first argument to main(argc, argv)
         Ok(argc)
     }
+
+    /// Load the ELF executable `ip` into a new user memory.
+    /// Returns Ok((the memory, the entry point)) on success, Err(errno) on error.
+    fn load_elf<FS: FileSystem>(&self, ip: &RcInode<FS>) -> Result<(UserMemory, usize), Errno> {
+        let allocator = hal().kmem();
+        let ip = ip.lock(self);
+        let mut ip = scopeguard::guard(ip, |ip| ip.free(self));
+
+        // Check ELF header
+        let mut elf: ElfHdr = Default::default();
+        ip.read_kernel(&mut elf, 0, self)?;
+        if !elf.is_valid() {
+            return Err(Errno::ENOEXEC);
+        }
+
+        let trap_frame: PAddr = (self.proc().trap_frame() as *const _ as usize).into();
+        let mem = UserMemory::new(trap_frame, None, allocator).ok_or(Errno::ENOMEM)?;
+        let mut mem = scopeguard::guard(mem, |mem| mem.free(allocator));
+
+        // Load program into memory.
+        for i in 0..elf.phnum as usize {
+            let off = elf.phoff + i * mem::size_of::<ProgHdr>();
+
+            let mut ph: ProgHdr = Default::default();
+            ip.read_kernel(&mut ph, off as _, self)?;
+            if ph.is_prog_load() {
+                if ph.memsz < ph.filesz || ph.vaddr % PGSIZE != 0 {
+                    return Err(Errno::ENOEXEC);
+                }
+                let end = ph.vaddr.checked_add(ph.memsz).ok_or(Errno::ENOEXEC)?;
+                let _ = mem.alloc(end, allocator)?;
+                mem.load_file(ph.vaddr.into(), &mut ip, ph.off as _, ph.filesz as _, self)?;
+            }
+        }
+        Ok((scopeguard::ScopeGuard::into_inner(mem), elf.entry))
+    }
 }
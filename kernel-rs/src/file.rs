@@ -2,30 +2,45 @@
 
 use core::{
     cell::UnsafeCell,
-    cmp,
+    cmp, fmt,
     mem::{self, ManuallyDrop},
     ops::Deref,
     ops::DerefMut,
+    sync::atomic::{AtomicI32, AtomicU64, Ordering},
 };
 
-use cfg_if::cfg_if;
-
 use crate::{
     addr::UVAddr,
     arena::{Arena, ArenaObject, ArenaRc, ArrayArena},
-    fs::{DefaultFs, FileSystem, FileSystemExt, InodeGuard, RcInode},
+    errno::Errno,
+    fs::{
+        dispatch, FcntlFlags, FileSystem, FileSystemExt, InodeGuard, InodeType, RcInode, Vnode,
+        MAXRECLEN,
+    },
     hal::hal,
-    param::{BSIZE, MAXOPBLOCKS, NFILE},
+    param::{BSIZE, MAXOPBLOCKS, NFILE, NOFILE},
     pipe::AllocatedPipe,
+    poll::{AllocatedEpoll, PollQueue},
     proc::KernelCtx,
     util::strong_pin::StrongPin,
 };
 
+/// The maximum number of bytes written to an inode in a transaction.
+///
+/// Write a few blocks at a time to avoid exceeding the maximum log transaction
+/// size, including i-node, up to three levels of indirect blocks, allocation
+/// blocks, and 2 blocks of slop for non-aligned writes.
+/// lfs needs one more block for the imap, which is also small enough for ufs.
+// This really belongs lower down, since write() might be writing a device like
+// the console.
+const MAXWRITE: usize = (MAXOPBLOCKS - 1 - 3 - 2 - 1) / 2 * BSIZE;
+
 pub enum FileType {
     None,
     Pipe { pipe: AllocatedPipe },
     Inode { inner: InodeFileType },
-    Device { ip: RcInode<DefaultFs>, major: u16 },
+    Device { inner: DeviceFileType },
+    Epoll { epoll: AllocatedEpoll },
 }
 
 /// It has an inode and an offset.
@@ -34,9 +49,17 @@ pub enum FileType {
 ///
 /// The offset should be accessed only when the inode is locked.
 pub struct InodeFileType {
-    pub ip: RcInode<DefaultFs>,
+    pub ip: Vnode,
     // It should be accessed only when `ip` is locked.
-    pub off: UnsafeCell<u32>,
+    pub off: UnsafeCell<u64>,
+}
+
+/// It has an inode of a device file, the device numbers, and an offset.
+pub struct DeviceFileType {
+    pub ip: Vnode,
+    pub major: u16,
+    pub minor: u16,
+    pub off: AtomicU64,
 }
 
 /// It can be acquired when the inode of `InodeFileType` is locked. `ip` is the guard of the locked
@@ -44,27 +67,38 @@ pub struct InodeFileType {
 /// since the inode is locked.
 struct InodeFileTypeGuard<'a, FS: FileSystem> {
     ip: ManuallyDrop<InodeGuard<'a, FS>>,
-    off: &'a mut u32,
+    off: &'a mut u64,
 }
 
 pub struct File {
+    /// Identifies the open file, as its entry in the file table is reused after it is closed.
+    id: u64,
     pub typ: FileType,
     readable: bool,
     writable: bool,
+
+    /// The status flags, O_NONBLOCK and O_APPEND, which `fcntl` can change.
+    flags: AtomicI32,
 }
 
 pub type FileTable = ArrayArena<File, NFILE>;
 
-/// map major device number to device functions.
-#[derive(Copy, Clone)]
-pub struct Devsw {
-    pub read: Option<fn(UVAddr, i32, &mut KernelCtx<'_, '_>) -> i32>,
-    pub write: Option<fn(UVAddr, i32, &mut KernelCtx<'_, '_>) -> i32>,
-}
-
 /// A reference counted smart pointer to a `File`.
 pub type RcFile = ArenaRc<FileTable>;
 
+/// `cmd` of `fcntl`.
+pub const F_DUPFD: i32 = 0;
+pub const F_GETFD: i32 = 1;
+pub const F_SETFD: i32 = 2;
+pub const F_GETFL: i32 = 3;
+pub const F_SETFL: i32 = 4;
+
+/// The file descriptor flag of `fcntl` that makes `exec` close the file descriptor.
+pub const FD_CLOEXEC: i32 = 1;
+
+/// The number of file descriptors that an fd set of `select` holds.
+pub const FD_SETSIZE: usize = 1024;
+
 // Events for `select`
 #[derive(Copy, Clone)]
 pub enum SelectEvent {
@@ -85,9 +119,34 @@ impl Default for FileType {
     }
 }
 
+/// Shows what the file refers to and whether it is readable and writable,
+/// e.g. "inode 1:23 r-" or "device 2:0 rw".
+impl fmt::Display for File {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.typ {
+            FileType::None => f.write_str("none")?,
+            FileType::Pipe { .. } => f.write_str("pipe")?,
+            FileType::Inode { inner } => write!(f, "inode {}:{}", inner.ip.dev(), inner.ip.inum())?,
+            FileType::Device { inner } => write!(f, "device {}:{}", inner.major, inner.minor)?,
+            FileType::Epoll { .. } => f.write_str("epoll")?,
+        }
+        write!(
+            f,
+            " {}{}",
+            if self.readable { 'r' } else { '-' },
+            if self.writable { 'w' } else { '-' }
+        )
+    }
+}
+
 impl InodeFileType {
-    fn lock(&self, ctx: &KernelCtx<'_, '_>) -> InodeFileTypeGuard<'_, DefaultFs> {
-        let ip = self.ip.lock(ctx);
+    /// Locks `ip`, which must be the inode of `self.ip`.
+    fn lock<'a, FS: FileSystem>(
+        &'a self,
+        ip: &'a RcInode<FS>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> InodeFileTypeGuard<'a, FS> {
+        let ip = ip.lock(ctx);
         // SAFETY: `ip` is locked and `off` can be exclusively accessed.
         let off = unsafe { &mut *self.off.get() };
         InodeFileTypeGuard {
@@ -127,195 +186,414 @@ impl<FS: FileSystem> Drop for InodeFileTypeGuard<'_, FS> {
     }
 }
 
+/// Returns the offset `n` bytes away from `base`.
+/// Returns Err(EINVAL) if it is negative, and Err(EOVERFLOW) if it exceeds `i64::MAX`,
+/// which the user could not tell from an error.
+fn seek_offset(base: u64, n: i64) -> Result<u64, Errno> {
+    let off = i64::try_from(base)
+        .ok()
+        .and_then(|base| base.checked_add(n))
+        .ok_or(Errno::EOVERFLOW)?;
+    if off < 0 {
+        Err(Errno::EINVAL)
+    } else {
+        Ok(off as u64)
+    }
+}
+
 impl File {
-    pub const fn new(typ: FileType, readable: bool, writable: bool) -> Self {
+    pub const fn new(id: u64, typ: FileType, readable: bool, writable: bool) -> Self {
         Self {
+            id,
             typ,
             readable,
             writable,
+            flags: AtomicI32::new(0),
         }
     }
 
+    /// Returns the id of the file, which no other file opened since boot has.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns the access mode and the status flags of the file.
+    pub fn flags(&self) -> FcntlFlags {
+        let mode = match (self.readable, self.writable) {
+            (true, true) => FcntlFlags::O_RDWR,
+            (false, true) => FcntlFlags::O_WRONLY,
+            _ => FcntlFlags::O_RDONLY,
+        };
+        mode | FcntlFlags::from_bits_truncate(self.flags.load(Ordering::Relaxed))
+    }
+
+    /// Sets the status flags of the file to those in `flags`, ignoring the other flags.
+    pub fn set_flags(&self, flags: FcntlFlags) {
+        let flags = flags & (FcntlFlags::O_NONBLOCK | FcntlFlags::O_APPEND);
+        self.flags.store(flags.bits(), Ordering::Relaxed);
+    }
+
+    fn is_nonblocking(&self) -> bool {
+        self.flags().contains(FcntlFlags::O_NONBLOCK)
+    }
+
     /// Get metadata about file self.
     /// addr is a user virtual address, pointing to a struct stat.
-    pub fn stat(&self, addr: UVAddr, ctx: &mut KernelCtx<'_, '_>) -> Result<(), ()> {
+    /// Returns Err(EBADF) if the file is not an inode.
+    pub fn stat(&self, addr: UVAddr, ctx: &mut KernelCtx<'_, '_>) -> Result<(), Errno> {
         match &self.typ {
             FileType::Inode {
                 inner: InodeFileType { ip, .. },
             }
-            | FileType::Device { ip, .. } => {
+            | FileType::Device {
+                inner: DeviceFileType { ip, .. },
+            } => {
                 let st = ip.stat(ctx);
-                ctx.proc_mut().memory_mut().copy_out(addr, &st)
+                ctx.copy_out(addr, &st)
             }
-            _ => Err(()),
+            _ => Err(Errno::EBADF),
         }
     }
 
     /// Read from file self.
     /// addr is a user virtual address.
-    pub fn read(&self, addr: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, ()> {
+    pub fn read(&self, addr: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, Errno> {
         if !self.readable {
-            return Err(());
+            return Err(Errno::EBADF);
         }
 
         match &self.typ {
-            FileType::Pipe { pipe } => pipe.read(addr, n as usize, ctx),
+            FileType::Pipe { pipe } => pipe.read(addr, n as usize, self.is_nonblocking(), ctx),
             FileType::Inode { inner } => {
-                let mut ip = inner.lock(ctx);
-                let curr_off = *ip.off;
-                let ret = ip.read_user(addr, curr_off, n as u32, ctx);
+                dispatch!(&inner.ip, ip => {
+                    let mut ip = inner.lock(ip, ctx);
+                    let curr_off = *ip.off;
+                    let ret = ip.read_user(addr, curr_off, n as u32, ctx);
+                    if let Ok(v) = ret {
+                        *ip.off += v as u64;
+                    }
+                    ip.free(ctx);
+                    ret
+                })
+            }
+            FileType::Device { inner } => {
+                let devsw = ctx.kernel().devices().get(inner.major)?;
+                let read = devsw.read.ok_or(Errno::EINVAL)?;
+                let off = inner.off.load(Ordering::Relaxed);
+                let ret = read(inner.minor, addr, n, off, self.flags(), ctx);
                 if let Ok(v) = ret {
-                    *ip.off += v as u32;
+                    let _ = inner.off.fetch_add(v as u64, Ordering::Relaxed);
                 }
-                ip.free(ctx);
                 ret
             }
-            FileType::Device { major, .. } => {
-                let major = ctx.kernel().devsw().get(*major as usize).ok_or(())?;
-                let read = major.read.ok_or(())?;
-                Ok(read(addr, n, ctx) as usize)
-            }
+            FileType::Epoll { .. } => Err(Errno::EINVAL),
             FileType::None => panic!("File::read"),
         }
     }
 
     /// Write to file self.
     /// addr is a user virtual address.
-    pub fn write(&self, addr: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, ()> {
+    pub fn write(&self, addr: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, Errno> {
         if !self.writable {
-            return Err(());
+            return Err(Errno::EBADF);
         }
 
         match &self.typ {
-            FileType::Pipe { pipe } => pipe.write(addr, n as usize, ctx),
+            FileType::Pipe { pipe } => pipe.write(addr, n as usize, self.is_nonblocking(), ctx),
             FileType::Inode { inner } => {
-                let n = n as usize;
-
-                // Use a different `max` value depending on the file system.
-                // TODO: We may want to use a different way when we use
-                // multiple file systems at the same time.
-                cfg_if! {
-                    if #[cfg(feature = "lfs")] {
-                        // need to subtract one more compared to `ufs` because of the imap.
-                        let max = (MAXOPBLOCKS - 1 - 1 - 2 - 1) / 2 * BSIZE;
-                    } else {
-                        // write a few blocks at a time to avoid exceeding
-                        // the maximum log transaction size, including
-                        // i-node, indirect block, allocation blocks,
-                        // and 2 blocks of slop for non-aligned writes.
-                        // this really belongs lower down, since write()
-                        // might be writing a device like the console.
-                        let max = (MAXOPBLOCKS - 1 - 1 - 2) / 2 * BSIZE;
+                dispatch!(&inner.ip, FS, ip => {
+                    let n = n as usize;
+                    let max = MAXWRITE;
+                    // Each write of an appending file starts at its end.
+                    let append = self.flags().contains(FcntlFlags::O_APPEND);
+
+                    let mut bytes_written: usize = 0;
+                    while bytes_written < n {
+                        let bytes_to_write = cmp::min(n - bytes_written, max);
+                        let tx = FS::get(ctx.kernel().fs()).as_pin().get_ref().begin_tx(ctx);
+                        let mut ip = inner.lock(ip, ctx);
+                        let curr_off = if append { FS::inode_size(&ip) } else { *ip.off };
+                        let r = ip.write_user(
+                            addr + bytes_written,
+                            curr_off,
+                            bytes_to_write as u32,
+                            ctx,
+                            &tx,
+                        );
+                        if let Ok(r) = r {
+                            *ip.off = curr_off + r as u64;
+                        }
+                        // Drop the `InodeFileTypeGuard` before completing the transacton
+                        // to prevent deadlocks (e.g. during the lfs segment cleaner).
+                        ip.free(ctx);
+                        tx.end(ctx);
+                        let r = match r {
+                            Ok(r) => r,
+                            Err(err) if bytes_written == 0 => return Err(err),
+                            // Report the error on the next write.
+                            Err(_) => break,
+                        };
+                        bytes_written += r;
+                        if r != bytes_to_write {
+                            // write_user stopped early, e.g., because the disk is full.
+                            break;
+                        }
                     }
+                    Ok(bytes_written)
+                })
+            }
+            FileType::Device { inner } => {
+                let devsw = ctx.kernel().devices().get(inner.major)?;
+                let write = devsw.write.ok_or(Errno::EINVAL)?;
+                let off = inner.off.load(Ordering::Relaxed);
+                let ret = write(inner.minor, addr, n, off, self.flags(), ctx);
+                if let Ok(v) = ret {
+                    let _ = inner.off.fetch_add(v as u64, Ordering::Relaxed);
                 }
+                ret
+            }
+            FileType::Epoll { .. } => Err(Errno::EINVAL),
+            FileType::None => panic!("File::read"),
+        }
+    }
 
-                let mut bytes_written: usize = 0;
-                while bytes_written < n {
-                    let bytes_to_write = cmp::min(n - bytes_written, max);
-                    let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
-                    let mut ip = inner.lock(ctx);
-                    let curr_off = *ip.off;
-                    let r = ip.write_user(
-                        addr + bytes_written,
-                        curr_off,
-                        bytes_to_write as u32,
-                        ctx,
-                        &tx,
-                    );
-                    if let Ok(r) = r {
-                        *ip.off += r as u32;
-                    }
-                    // Drop the `InodeFileTypeGuard` before completing the transacton
-                    // to prevent deadlocks (e.g. during the lfs segment cleaner).
-                    ip.free(ctx);
-                    tx.end(ctx);
-                    let r = r?;
-                    if r != bytes_to_write {
-                        // error from write_user
-                        break;
+    /// Read the entries of the directory file self from its offset into the user buffer
+    /// `addr` of `n` bytes, as `struct direntry`s, and advance the offset past them.
+    /// Returns Ok(number of bytes read) on success, which is 0 at the end of the directory,
+    /// Err(ENOTDIR) if the file is not a directory, and Err(EINVAL) if the buffer cannot
+    /// hold the next entry.
+    pub fn getdents(
+        &self,
+        addr: UVAddr,
+        n: i32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, Errno> {
+        if !self.readable {
+            return Err(Errno::EBADF);
+        }
+
+        let inner = match &self.typ {
+            FileType::Inode { inner } => inner,
+            _ => return Err(Errno::ENOTDIR),
+        };
+        dispatch!(&inner.ip, FS, ip => {
+            let ip = inner.lock(ip, ctx);
+            let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+            if FS::inode_type(&ip) != InodeType::Dir {
+                return Err(Errno::ENOTDIR);
+            }
+
+            let n = cmp::max(n, 0) as usize;
+            let mut record = [0; MAXRECLEN];
+            let mut done = 0;
+            loop {
+                let off = *ip.off;
+                let (entry, next) = match FS::readdir(&mut ip, off, ctx)? {
+                    Some(found) => found,
+                    None => break,
+                };
+                let reclen = entry.to_record(&mut record);
+                if done + reclen > n {
+                    if done == 0 {
+                        return Err(Errno::EINVAL);
                     }
-                    bytes_written += r;
-                }
-                if bytes_written != n {
-                    return Err(());
+                    break;
                 }
-                Ok(n)
-            }
-            FileType::Device { major, .. } => {
-                let major = ctx.kernel().devsw().get(*major as usize).ok_or(())?;
-                let write = major.write.ok_or(())?;
-                Ok(write(addr, n, ctx) as usize)
+                ctx.proc()
+                    .memory()
+                    .copy_out_bytes(addr + done, &record[..reclen])?;
+                done += reclen;
+                *ip.off = next;
             }
-            FileType::None => panic!("File::read"),
+            Ok(done)
+        })
+    }
+
+    /// Returns true if the file can be mapped to memory by `mmap`, and
+    /// written back from memory as well if `writable` is true.
+    pub fn is_mappable(&self, writable: bool) -> bool {
+        self.readable && (!writable || self.writable) && matches!(self.typ, FileType::Inode { .. })
+    }
+
+    /// Read from file self at offset `off` into the kernel buffer `dst`,
+    /// without changing the file offset. The rest of `dst` is left untouched
+    /// if the file ends before `off + dst.len()`.
+    /// Returns Ok(number read) on success, Err(EBADF) if the file is not readable,
+    /// and Err(ENODEV) if it is not an inode.
+    pub fn read_kernel_at(
+        &self,
+        dst: &mut [u8],
+        off: u64,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, Errno> {
+        if !self.readable {
+            return Err(Errno::EBADF);
+        }
+
+        if let FileType::Inode { inner } = &self.typ {
+            Ok(inner.ip.read_bytes_kernel(dst, off, ctx))
+        } else {
+            Err(Errno::ENODEV)
+        }
+    }
+
+    /// Write the kernel buffer `src` to file self at offset `off`, without
+    /// changing the file offset or the file size. The part of `src` past the end
+    /// of the file is not written.
+    /// Returns Ok(number written) on success, Err(EBADF) if the file is not writable,
+    /// and Err(ENODEV) if it is not an inode.
+    pub fn write_kernel_at(
+        &self,
+        src: &[u8],
+        off: u64,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, Errno> {
+        if !self.writable {
+            return Err(Errno::EBADF);
         }
+
+        let inner = match &self.typ {
+            FileType::Inode { inner } => inner,
+            _ => return Err(Errno::ENODEV),
+        };
+        dispatch!(&inner.ip, FS, ip => {
+            let mut written = 0;
+            while written < src.len() {
+                let tx = FS::get(ctx.kernel().fs()).as_pin().get_ref().begin_tx(ctx);
+                let mut ip = ip.lock(ctx);
+                let off = off + written as u64;
+                let n = cmp::min(
+                    cmp::min(src.len() - written, MAXWRITE) as u64,
+                    FS::inode_size(&ip).saturating_sub(off),
+                ) as usize;
+                let r = ip.write_bytes_kernel(&src[written..written + n], off, &tx, ctx);
+                ip.free(ctx);
+                tx.end(ctx);
+                let r = r?;
+                written += r;
+                if n == 0 || r != n {
+                    break;
+                }
+            }
+            Ok(written)
+        })
     }
 
     /// Repositions the file offset of the open file description
     /// associated with the file descriptor fd to the `n` according
     /// to the directive `option`.
+    /// Returns Ok(the new offset) on success, Err(EINVAL) if it would be negative, and
+    /// Err(EOVERFLOW) if it does not fit in an `off_t`.
     pub fn lseek(
         &self,
-        n: i32,
+        n: i64,
         option: SeekWhence,
         ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, Errno> {
         if !self.readable {
-            return Err(());
+            return Err(Errno::EBADF);
         }
 
-        if let FileType::Inode { inner } = &self.typ {
-            let ip = inner.lock(ctx);
-            let off = match option {
-                SeekWhence::Set => n as u32,
-                SeekWhence::Cur => *ip.off + n as u32,
-                SeekWhence::End => {
-                    let ip_inner = ip.deref_inner();
-                    ip_inner.size + n as u32
-                }
-            };
-            *ip.off = off;
-            ip.free(ctx);
-            Ok(off as usize)
+        match &self.typ {
+            FileType::Inode { inner } => {
+                dispatch!(&inner.ip, FS, ip => {
+                    let ip = inner.lock(ip, ctx);
+                    let base = match option {
+                        SeekWhence::Set => 0,
+                        SeekWhence::Cur => *ip.off,
+                        SeekWhence::End => FS::inode_size(&ip),
+                    };
+                    let off = seek_offset(base, n);
+                    if let Ok(off) = off {
+                        *ip.off = off;
+                    }
+                    ip.free(ctx);
+                    Ok(off? as usize)
+                })
+            }
+            // A device has no size to seek from its end.
+            FileType::Device { inner } => {
+                let base = match option {
+                    SeekWhence::Set => 0,
+                    SeekWhence::Cur => inner.off.load(Ordering::Relaxed),
+                    SeekWhence::End => return Err(Errno::EINVAL),
+                };
+                let off = seek_offset(base, n)?;
+                inner.off.store(off, Ordering::Relaxed);
+                Ok(off as usize)
+            }
+            _ => Err(Errno::ESPIPE),
+        }
+    }
+
+    /// Runs the device-specific request `req` with the argument `arg` on file self.
+    /// Returns Err(ENOTTY) if the file is not a device or its driver takes no requests.
+    pub fn ioctl(
+        &self,
+        req: i32,
+        arg: UVAddr,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, Errno> {
+        if let FileType::Device { inner } = &self.typ {
+            let devsw = ctx.kernel().devices().get(inner.major)?;
+            let ioctl = devsw.ioctl.ok_or(Errno::ENOTTY)?;
+            ioctl(inner.minor, req, arg, ctx)
         } else {
-            Err(())
+            Err(Errno::ENOTTY)
         }
     }
 
-    /// Check file is ready for specified select event.
-    /// It only supports pipe now.
-    /// TODO: support other type of files
-    pub fn is_ready(&self, event: SelectEvent) -> Result<bool, ()> {
+    /// Check file is ready for specified select event, i.e., whether reading or
+    /// writing it would not block, or whether it has an exceptional condition.
+    /// As in POSIX, a file is ready even if reading or writing it would fail, e.g.,
+    /// because it was not opened for that.
+    pub fn is_ready(&self, event: SelectEvent, ctx: &KernelCtx<'_, '_>) -> Result<bool, Errno> {
         match event {
-            SelectEvent::Read => {
-                if !self.readable {
-                    return Err(());
-                }
+            SelectEvent::Read if !self.readable => return Ok(true),
+            SelectEvent::Write if !self.writable => return Ok(true),
+            _ => (),
+        }
 
-                match &self.typ {
-                    FileType::Pipe { pipe } => {
-                        // pipe-empty
-                        if pipe.is_ready(event) {
-                            return Ok(true);
-                        }
-                    }
-                    FileType::Inode { .. } => {
-                        unimplemented!()
-                    }
-                    FileType::Device { .. } => unimplemented!(""),
-                    FileType::None => panic!("Syscall::sys_select"),
+        match &self.typ {
+            FileType::Pipe { pipe } => Ok(pipe.is_ready(event)),
+            // Reading or writing an inode never waits for anything but the disk.
+            FileType::Inode { .. } => Ok(!matches!(event, SelectEvent::Error)),
+            FileType::Device { inner } => {
+                let devsw = ctx.kernel().devices().get(inner.major)?;
+                match devsw.poll {
+                    Some(poll) => Ok(poll(inner.minor, event)),
+                    None => Ok(!matches!(event, SelectEvent::Error)),
                 }
-                Ok(false)
             }
-            _ => {
-                todo!("Select for write and error is not implemented yet")
+            // An epoll instance is ready for reading if any of its files is ready.
+            FileType::Epoll { epoll } => {
+                match event {
+                    SelectEvent::Read => epoll.is_ready(ctx),
+                    _ => Ok(false),
+                }
             }
+            FileType::None => panic!("File::is_ready"),
+        }
+    }
+
+    /// Returns the queue that the file notifies when it may have become ready, or None if
+    /// reading or writing it never waits for anything but the disk.
+    pub fn poll_queue(&self, ctx: &KernelCtx<'_, '_>) -> Option<&PollQueue> {
+        match &self.typ {
+            FileType::Pipe { pipe } => Some(&pipe.queue),
+            FileType::Device { inner } => {
+                let queue = ctx.kernel().devices().get(inner.major).ok()?.queue?;
+                Some(queue(inner.minor))
+            }
+            FileType::Epoll { epoll } => Some(&epoll.queue),
+            _ => None,
         }
     }
 }
 
 impl const Default for File {
     fn default() -> Self {
-        Self::new(FileType::None, false, false)
+        Self::new(0, FileType::None, false, false)
     }
 }
 
@@ -333,11 +611,10 @@ impl ArenaObject for File {
             FileType::Inode {
                 inner: InodeFileType { ip, .. },
             }
-            | FileType::Device { ip, .. } => {
-                let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
-                ip.free((&tx, ctx));
-                tx.end(ctx);
-            }
+            | FileType::Device {
+                inner: DeviceFileType { ip, .. },
+            } => ip.put(ctx),
+            FileType::Epoll { epoll } => hal().kmem().free(epoll.close()),
             _ => (),
         }
     }
@@ -354,23 +631,39 @@ impl FileTable {
         typ: FileType,
         readable: bool,
         writable: bool,
-    ) -> Result<RcFile, ()> {
-        self.alloc(|| File::new(typ, readable, writable)).ok_or(())
+    ) -> Result<RcFile, Errno> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        self.alloc(|| File::new(id, typ, readable, writable))
+            .ok_or(Errno::ENFILE)
     }
 }
 
 impl RcFile {
     /// Allocate a file descriptor for the given file.
     /// Takes over file reference from caller on success.
-    pub fn fdalloc(self, ctx: &mut KernelCtx<'_, '_>) -> Result<i32, ()> {
-        let proc_data = ctx.proc_mut().deref_mut_data();
-        for (fd, f) in proc_data.open_files.iter_mut().enumerate() {
-            if f.is_none() {
-                *f = Some(self);
-                return Ok(fd as i32);
-            }
+    pub fn fdalloc(self, ctx: &mut KernelCtx<'_, '_>) -> Result<i32, Errno> {
+        self.fdalloc_from(0, false, ctx)
+    }
+
+    /// Allocate the lowest file descriptor not less than `min` for the given file, which
+    /// `exec` closes if `cloexec` is true.
+    /// Takes over file reference from caller on success.
+    pub fn fdalloc_from(
+        self,
+        min: usize,
+        cloexec: bool,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<i32, Errno> {
+        let mut open_files = ctx.proc().open_files();
+        if let Some(fd) = (min..NOFILE).find(|&fd| open_files[fd].is_none()) {
+            open_files[fd] = Some(self);
+            open_files.set_cloexec(fd, cloexec);
+            return Ok(fd as i32);
         }
+        drop(open_files);
         self.free(ctx);
-        Err(())
+        Err(Errno::EMFILE)
     }
 }
@@ -0,0 +1,83 @@
+//! Inodes of the devfs.
+//!
+//! The root directory has the inode number `ROOTINO`, and the `i`th node of the device
+//! registry has the inode number `NODEBASE + i`. The type of an inode, which holds the
+//! device numbers of a node, is filled in when the inode is looked up.
+
+use zerocopy::{AsBytes, FromBytes};
+
+use super::Devfs;
+use crate::{
+    arena::{Arena, ArrayArena},
+    fs::{Inode, InodeType, Itable, RcInode, DIRSIZ},
+    lock::SleepLock,
+    param::NINODE,
+    util::strong_pin::StrongPin,
+};
+
+/// root i-number
+pub const ROOTINO: u32 = 1;
+
+/// The inode number of the first node.
+pub const NODEBASE: u32 = 2;
+
+#[repr(C)]
+#[derive(Default, AsBytes, FromBytes)]
+pub struct Dirent {
+    pub inum: u16,
+    name: [u8; DIRSIZ],
+}
+
+impl Dirent {
+    /// Returns a `Dirent` of the inode `inum` named `name`, which must not be longer than
+    /// DIRSIZ.
+    pub fn new(name: &[u8], inum: u32) -> Self {
+        let mut de = Dirent {
+            inum: inum as u16,
+            ..Default::default()
+        };
+        de.name[..name.len()].copy_from_slice(name);
+        de
+    }
+}
+
+impl const Default for Inode<Devfs> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Inode<Devfs> {
+    pub const fn new() -> Self {
+        Self {
+            dev: 0,
+            inum: 0,
+            inner: SleepLock::new("inode", InodeType::None),
+        }
+    }
+}
+
+impl Itable<Devfs> {
+    pub const fn new_itable() -> Self {
+        ArrayArena::<Inode<Devfs>, NINODE>::new("ITABLE")
+    }
+
+    /// Find the inode with number inum on device dev
+    /// and return the in-memory copy, whose type is `typ`.
+    pub fn get_inode(
+        self: StrongPin<'_, Self>,
+        dev: u32,
+        inum: u32,
+        typ: InodeType,
+    ) -> RcInode<Devfs> {
+        self.find_or_alloc(
+            |inode| inode.dev == dev && inode.inum == inum,
+            |inode| {
+                inode.dev = dev;
+                inode.inum = inum;
+                *inode.inner.get_mut() = typ;
+            },
+        )
+        .expect("[Itable::get_inode] no inodes")
+    }
+}
@@ -0,0 +1,318 @@
+//! A synthetic file system that shows the devices of the device registry.
+//!
+//! The devfs has a single directory, which holds a device file for each node that a
+//! driver has added to `Devices`, such as `console`, `null`, `zero`, `random`, and the
+//! disks. Since the drivers register their devices while the kernel boots, nothing
+//! needs to be created by mkfs or mknod. Nothing can be written, created, or removed.
+
+use core::ops::Deref;
+use core::{cmp, mem};
+
+use pin_project::pin_project;
+use zerocopy::AsBytes;
+
+use super::{
+    AnyTx, DirEntry, FileName, FileSystem, Inode, InodeGuard, InodeType, Itable, RcInode, Stat, Tx,
+    Vfs, Vnode, NAME_MAX, ROOT_UID,
+};
+use crate::{
+    errno::Errno,
+    param::{DEVFSDEV, NDEVNODE},
+    proc::KernelCtx,
+    time::Timespec,
+    util::strong_pin::StrongPin,
+};
+
+mod inode;
+
+use inode::{Dirent, NODEBASE, ROOTINO};
+
+/// dirent size
+const DIRENT_SIZE: usize = mem::size_of::<Dirent>();
+
+#[pin_project]
+pub struct Devfs {
+    #[pin]
+    itable: Itable<Self>,
+}
+
+impl Devfs {
+    pub const fn new() -> Self {
+        Self {
+            itable: Itable::<Self>::new_itable(),
+        }
+    }
+}
+
+impl FileSystem for Devfs {
+    type Dirent = Dirent;
+    type InodeInner = InodeType;
+
+    const NAME: &'static str = "devfs";
+
+    fn get(vfs: StrongPin<'_, Vfs>) -> StrongPin<'_, Self> {
+        vfs.devfs()
+    }
+
+    fn vnode(inode: RcInode<Self>) -> Vnode {
+        Vnode::Devfs(inode)
+    }
+
+    fn any_tx(tx: Tx<'_, Self>) -> AnyTx<'_> {
+        AnyTx::Devfs(tx)
+    }
+
+    fn tx<'a, 's>(tx: &'a AnyTx<'s>) -> Option<&'a Tx<'s, Self>> {
+        match tx {
+            AnyTx::Devfs(tx) => Some(tx),
+            _ => None,
+        }
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn itable<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Itable<Self>> {
+        unsafe { StrongPin::new_unchecked(&self.as_pin().get_ref().itable) }
+    }
+
+    fn mount_dev(_dev: u32) -> Result<u32, Errno> {
+        Ok(DEVFSDEV)
+    }
+
+    fn init(&self, _dev: u32, _ctx: &KernelCtx<'_, '_>) -> Result<(), Errno> {
+        Ok(())
+    }
+
+    fn root(self: StrongPin<'_, Self>, dev: u32) -> RcInode<Self> {
+        self.itable().get_inode(dev, ROOTINO, InodeType::Dir)
+    }
+
+    fn lookup(
+        dp: &mut InodeGuard<'_, Self>,
+        name: &FileName<NAME_MAX>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcInode<Self>, Errno> {
+        let itable = ctx.kernel().fs().devfs().itable();
+        if name.as_bytes() == b"." || name.as_bytes() == b".." {
+            return Ok(itable.get_inode(dp.dev, ROOTINO, InodeType::Dir));
+        }
+        let nodes = ctx.kernel().devices().nodes();
+        let i = nodes
+            .iter()
+            .position(|node| node.name() == name.as_bytes())
+            .ok_or(Errno::ENOENT)?;
+        let typ = InodeType::Device {
+            major: nodes[i].major,
+            minor: nodes[i].minor,
+        };
+        Ok(itable.get_inode(dp.dev, NODEBASE + i as u32, typ))
+    }
+
+    fn readdir(
+        dp: &mut InodeGuard<'_, Self>,
+        off: u64,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Option<(DirEntry, u64)>, Errno> {
+        // The `i`th entry is at the offset `i * DIRENT_SIZE`, as in a read.
+        let index = (off as usize + DIRENT_SIZE - 1) / DIRENT_SIZE;
+        let entry = match index {
+            0 => DirEntry::new(dp.inum, InodeType::Dir, b"."),
+            1 => DirEntry::new(dp.inum, InodeType::Dir, b".."),
+            _ => {
+                match ctx.kernel().devices().nodes().get(index - 2) {
+                    Some(node) => {
+                        DirEntry::new(
+                            NODEBASE + (index - 2) as u32,
+                            InodeType::Device {
+                                major: node.major,
+                                minor: node.minor,
+                            },
+                            node.name(),
+                        )
+                    }
+                    None => return Ok(None),
+                }
+            }
+        };
+        Ok(Some((entry, ((index + 1) * DIRENT_SIZE) as u64)))
+    }
+
+    fn link(
+        self: StrongPin<'_, Self>,
+        _dp: &RcInode<Self>,
+        _name: &FileName<NAME_MAX>,
+        _inode: &RcInode<Self>,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        Err(Errno::EACCES)
+    }
+
+    fn unlink(
+        self: StrongPin<'_, Self>,
+        _dp: &RcInode<Self>,
+        _name: &FileName<NAME_MAX>,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        Err(Errno::EACCES)
+    }
+
+    fn rename(
+        self: StrongPin<'_, Self>,
+        _dp: &RcInode<Self>,
+        _name: &FileName<NAME_MAX>,
+        _newdp: &RcInode<Self>,
+        _newname: &FileName<NAME_MAX>,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        Err(Errno::EACCES)
+    }
+
+    fn create(
+        self: StrongPin<'_, Self>,
+        _dp: &RcInode<Self>,
+        _name: &FileName<NAME_MAX>,
+        _typ: InodeType,
+        _excl: bool,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcInode<Self>, Errno> {
+        Err(Errno::EACCES)
+    }
+
+    fn tx_begin(&self, _ctx: &KernelCtx<'_, '_>) {}
+
+    unsafe fn tx_end(&self, _tx: &mut Tx<'_, Self>, _ctx: &KernelCtx<'_, '_>) {}
+
+    fn inode_read<
+        'id,
+        's,
+        K: Deref<Target = KernelCtx<'id, 's>>,
+        F: FnMut(u32, &[u8], &mut K) -> Result<(), Errno>,
+    >(
+        guard: &mut InodeGuard<'_, Self>,
+        off: u64,
+        n: u32,
+        mut f: F,
+        mut k: K,
+    ) -> Result<usize, Errno> {
+        // The content of a device is read through its driver, not its inode.
+        if guard.inum != ROOTINO {
+            return Ok(0);
+        }
+
+        let mut dirents = [0; (NDEVNODE + 2) * DIRENT_SIZE];
+        let mut len = 0;
+        let mut push = |de: Dirent| {
+            dirents[len..len + DIRENT_SIZE].copy_from_slice(de.as_bytes());
+            len += DIRENT_SIZE;
+        };
+        push(Dirent::new(b".", ROOTINO));
+        push(Dirent::new(b"..", ROOTINO));
+        for (i, node) in k.kernel().devices().nodes().iter().enumerate() {
+            push(Dirent::new(node.name(), NODEBASE + i as u32));
+        }
+
+        let begin = cmp::min(off as usize, len);
+        let end = cmp::min(begin + n as usize, len);
+        if begin < end {
+            f(0, &dirents[begin..end], &mut k)?;
+        }
+        Ok(end - begin)
+    }
+
+    fn inode_write<
+        'id,
+        's,
+        K: Deref<Target = KernelCtx<'id, 's>>,
+        F: FnMut(u32, &mut [u8], &mut K) -> Result<(), Errno>,
+    >(
+        _guard: &mut InodeGuard<'_, Self>,
+        _off: u64,
+        _n: u32,
+        _f: F,
+        _tx: &Tx<'_, Self>,
+        _k: K,
+    ) -> Result<usize, Errno> {
+        Err(Errno::EACCES)
+    }
+
+    fn inode_trunc(
+        _guard: &mut InodeGuard<'_, Self>,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) {
+    }
+
+    fn inode_lock<'a>(inode: &'a Inode<Self>, ctx: &KernelCtx<'_, '_>) -> InodeGuard<'a, Self> {
+        mem::forget(inode.inner.lock(ctx));
+        InodeGuard { inode }
+    }
+
+    fn inode_finalize<'a, 'id: 'a>(
+        _inode: &mut Inode<Self>,
+        _tx: &'a Tx<'a, Self>,
+        _ctx: &'a KernelCtx<'id, 'a>,
+    ) {
+    }
+
+    fn inode_type(guard: &InodeGuard<'_, Self>) -> InodeType {
+        *guard.deref_inner()
+    }
+
+    fn inode_size(_guard: &InodeGuard<'_, Self>) -> u64 {
+        0
+    }
+
+    fn inode_stat(inode: &Inode<Self>, ctx: &KernelCtx<'_, '_>) -> Stat {
+        let mode = if inode.inum == ROOTINO {
+            0o755
+        } else {
+            ctx.kernel().devices().nodes()[(inode.inum - NODEBASE) as usize].mode
+        };
+        Stat {
+            dev: inode.dev as i32,
+            ino: inode.inum,
+            typ: if inode.inum == ROOTINO { 1 } else { 3 },
+            nlink: 1,
+            mode,
+            _padding: 0,
+            uid: ROOT_UID,
+            gid: 0,
+            size: 0,
+            atime: Timespec::ZERO,
+            mtime: Timespec::ZERO,
+            ctime: Timespec::ZERO,
+        }
+    }
+
+    fn inode_set_times(
+        _guard: &mut InodeGuard<'_, Self>,
+        _atime: Option<Timespec>,
+        _mtime: Option<Timespec>,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        Err(Errno::EACCES)
+    }
+
+    fn inode_set_mode(
+        _guard: &mut InodeGuard<'_, Self>,
+        _mode: u16,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        Err(Errno::EACCES)
+    }
+
+    fn inode_set_owner(
+        _guard: &mut InodeGuard<'_, Self>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        Err(Errno::EACCES)
+    }
+}
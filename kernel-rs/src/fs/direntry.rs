@@ -0,0 +1,73 @@
+use core::mem;
+
+use zerocopy::AsBytes;
+
+use super::{InodeType, NAME_MAX};
+
+/// A directory entry of any file system, which `FileSystem::readdir` returns.
+pub struct DirEntry {
+    /// Inode number
+    pub inum: u32,
+
+    /// Type of the inode
+    pub typ: InodeType,
+
+    name: [u8; NAME_MAX],
+    len: usize,
+}
+
+/// The header of a `struct direntry` of user programs, which the name follows.
+#[derive(AsBytes)]
+#[repr(C)]
+struct DirEntryHeader {
+    ino: u32,
+    reclen: u16,
+    typ: u8,
+    namelen: u8,
+}
+
+const HEADER_SIZE: usize = mem::size_of::<DirEntryHeader>();
+
+/// Maximum length of a `struct direntry`.
+pub const MAXRECLEN: usize = (HEADER_SIZE + NAME_MAX + 1 + 3) & !3;
+
+impl DirEntry {
+    /// Returns an entry of the inode `inum` of type `typ`, named `name`, which must not be
+    /// longer than NAME_MAX.
+    pub fn new(inum: u32, typ: InodeType, name: &[u8]) -> Self {
+        let mut entry = Self {
+            inum,
+            typ,
+            name: [0; NAME_MAX],
+            len: name.len(),
+        };
+        entry.name[..name.len()].copy_from_slice(name);
+        entry
+    }
+
+    pub fn name(&self) -> &[u8] {
+        &self.name[..self.len]
+    }
+
+    /// Writes the entry into `buf` as a `struct direntry`, whose length is a multiple of 4
+    /// so that the next one is aligned. Returns the length.
+    pub fn to_record(&self, buf: &mut [u8; MAXRECLEN]) -> usize {
+        let reclen = (HEADER_SIZE + self.len + 1 + 3) & !3;
+        let header = DirEntryHeader {
+            ino: self.inum,
+            reclen: reclen as u16,
+            typ: match self.typ {
+                InodeType::Dir => 1,
+                InodeType::File => 2,
+                InodeType::Device { .. } => 3,
+                InodeType::Symlink => 4,
+                InodeType::None => 0,
+            },
+            namelen: self.len as u8,
+        };
+        buf[..HEADER_SIZE].copy_from_slice(header.as_bytes());
+        buf[HEADER_SIZE..HEADER_SIZE + self.len].copy_from_slice(self.name());
+        buf[HEADER_SIZE + self.len..reclen].fill(0);
+        reclen
+    }
+}
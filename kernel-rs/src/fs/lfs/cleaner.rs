@@ -7,6 +7,7 @@ use arrayvec::ArrayVec;
 use static_assertions::const_assert;
 
 use super::{
+    inode::IndirectId,
     segment::{BlockType, DSegSum, DSegSumEntry},
     tx::CLEANING_THRES,
     Lfs, SegManager, Tx,
@@ -96,10 +97,10 @@ impl Lfs {
                 // and is stored at `bno`
                 let inode = itable.get_inode(dev, entry.inum);
                 let ip = inode.lock(ctx);
-                let addr = ip.deref_inner().addr_indirect;
+                let addr = ip.indirect_addr(IndirectId::from_u32(entry.block_no), ctx);
                 ip.free(ctx);
                 inode.free((tx, ctx));
-                bno == addr
+                addr == Some(bno)
             }
             BlockType::Imap => {
                 let imap = self.imap(ctx);
@@ -233,10 +234,9 @@ impl Lfs {
 
                     // copy to end of segment
                     ip.writable_data_block(entry.block_no as usize, seg, tx, ctx)
+                        .unwrap()
                         .free(ctx);
-                    if seg.is_full() {
-                        seg.commit(true, ctx);
-                    }
+                    seg.commit_if_full(ctx);
 
                     // update inode
                     ip.update(tx, ctx);
@@ -248,10 +248,10 @@ impl Lfs {
                     let mut ip = inode.lock(ctx);
 
                     // copy to end of segment
-                    ip.writable_indirect_block(seg, ctx).free(ctx);
-                    if seg.is_full() {
-                        seg.commit(true, ctx);
-                    }
+                    ip.writable_indirect_block(IndirectId::from_u32(entry.block_no), seg, ctx)
+                        .unwrap()
+                        .free(ctx);
+                    seg.commit_if_full(ctx);
 
                     // update inode
                     ip.update(tx, ctx);
@@ -261,9 +261,7 @@ impl Lfs {
                 BlockType::Imap => {
                     let mut imap = tx.imap(ctx);
                     imap.update(entry.block_no, seg, ctx).unwrap().free(ctx);
-                    if seg.is_full() {
-                        seg.commit(true, ctx);
-                    }
+                    seg.commit_if_full(ctx);
                     imap.free(ctx);
                 }
             };
@@ -106,7 +106,7 @@ impl Imap {
     /// For the inode with inode number `inum`, returns the disk_block_no of it.
     pub fn get(&self, inum: u32, ctx: &KernelCtx<'_, '_>) -> u32 {
         assert!(
-            0 < inum && inum < ctx.kernel().fs().superblock().ninodes(),
+            0 < inum && inum < ctx.kernel().fs().lfs().superblock().ninodes(),
             "invalid inum"
         );
         let (block_no, offset) = self.get_imap_block_no(inum);
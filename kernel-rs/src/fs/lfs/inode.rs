@@ -3,21 +3,23 @@ use core::{iter::StepBy, mem, ops::Range};
 use static_assertions::const_assert;
 use zerocopy::{AsBytes, FromBytes};
 
-use super::{FileName, Lfs, Path, SegManager, NDIRECT, NINDIRECT, ROOTINO};
+use super::{FileName, Lfs, SegManager, NDIRECT, NINDIRECT, NLEVEL};
 use crate::{
     arena::{Arena, ArrayArena},
     bio::{Buf, BufData},
-    fs::{DInodeType, Inode, InodeGuard, InodeType, Itable, RcInode, Tx},
+    errno::Errno,
+    fs::{
+        longdir, DInodeType, DirEntry, DirFileSystem, Inode, InodeGuard, InodeType, Itable,
+        RcInode, Tx, DIRSIZ, NAME_MAX,
+    },
     hal::hal,
     lock::SleepLock,
-    param::{NINODE, ROOTDEV},
+    param::NINODE,
     proc::KernelCtx,
+    time::Timespec,
     util::{memset, strong_pin::StrongPin},
 };
 
-/// Directory is a file containing a sequence of Dirent structures.
-pub const DIRSIZ: usize = 14;
-
 /// dirent size
 pub const DIRENT_SIZE: usize = mem::size_of::<Dirent>();
 
@@ -29,11 +31,19 @@ pub struct InodeInner {
     /// the number of links to this inode
     pub nlink: i16,
     // the size of this inode
-    pub size: u32,
+    pub size: u64,
+    /// the last access, modification, and status change times
+    pub atime: Timespec,
+    pub mtime: Timespec,
+    pub ctime: Timespec,
+    /// the owner, its group, and the permission bits
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u16,
     /// direct addresses of disk data
     pub addr_direct: [u32; NDIRECT],
-    /// indirect address
-    pub addr_indirect: u32,
+    /// single, double, and triple indirect addresses
+    pub addr_indirect: [u32; NLEVEL],
 }
 
 /// On-disk inode structure
@@ -57,13 +67,116 @@ pub struct Dinode {
     pub nlink: i16,
 
     /// Size of file (bytes)
-    pub size: u32,
+    pub size: u64,
+
+    /// Time of last access
+    pub atime: Timespec,
+
+    /// Time of last modification
+    pub mtime: Timespec,
+
+    /// Time of last status change
+    pub ctime: Timespec,
+
+    /// Owner's user id
+    pub uid: u32,
+
+    /// Owner's group id
+    pub gid: u32,
+
+    /// Permission bits
+    pub mode: u16,
+
+    _pad: u16,
 
     /// Direct data block addresses
     pub addr_direct: [u32; NDIRECT],
 
-    /// Indirect data block address
-    pub addr_indirect: u32,
+    /// Single, double, and triple indirect block addresses
+    pub addr_indirect: [u32; NLEVEL],
+}
+
+impl Dinode {
+    /// Returns the type of the inode, with its device numbers.
+    pub fn inode_type(&self) -> InodeType {
+        match self.typ {
+            DInodeType::None => InodeType::None,
+            DInodeType::Dir => InodeType::Dir,
+            DInodeType::File => InodeType::File,
+            DInodeType::Symlink => InodeType::Symlink,
+            DInodeType::Device => {
+                InodeType::Device {
+                    major: self.major,
+                    minor: self.minor,
+                }
+            }
+        }
+    }
+}
+
+/// Identifies an indirect block of an inode by the `level`s of the indirect blocks below
+/// its root, its `depth` below the root, and its `index` among the blocks of that depth.
+#[derive(Clone, Copy)]
+pub struct IndirectId {
+    level: usize,
+    depth: usize,
+    index: usize,
+}
+
+impl IndirectId {
+    /// Returns the id of the root of the indirect blocks of `level` levels.
+    const fn root(level: usize) -> Self {
+        Self {
+            level,
+            depth: 0,
+            index: 0,
+        }
+    }
+
+    /// Returns the id of the indirect block that maps the `bn`th data block, which must
+    /// not be direct, and the index of the data block in it.
+    fn of_data_block(bn: usize) -> (Self, usize) {
+        let mut bn = bn - NDIRECT;
+        let mut level = 1;
+        while bn >= NINDIRECT.pow(level as u32) {
+            bn -= NINDIRECT.pow(level as u32);
+            level += 1;
+            assert!(level <= NLEVEL, "bmap: out of range");
+        }
+        let id = Self {
+            level,
+            depth: level - 1,
+            index: bn / NINDIRECT,
+        };
+        (id, bn % NINDIRECT)
+    }
+
+    /// Returns the id of the block at `depth` on the way from the root to `self`, and
+    /// the index of its entry in its parent.
+    fn ancestor(self, depth: usize) -> (Self, usize) {
+        let index = self.index / NINDIRECT.pow((self.depth - depth) as u32);
+        let id = Self {
+            level: self.level,
+            depth,
+            index,
+        };
+        (id, index % NINDIRECT)
+    }
+
+    /// Encodes the id as a segment summary entry's `block_no`. The root of the single
+    /// indirect blocks is 0.
+    pub fn to_u32(self) -> u32 {
+        (self.index << 4 | self.depth << 2 | (self.level - 1)) as u32
+    }
+
+    pub fn from_u32(block_no: u32) -> Self {
+        let block_no = block_no as usize;
+        Self {
+            level: (block_no & 3) + 1,
+            depth: block_no >> 2 & 3,
+            index: block_no >> 4,
+        }
+    }
 }
 
 impl<'s> TryFrom<&'s BufData> for &'s Dinode {
@@ -110,7 +223,11 @@ pub struct Dirent {
 }
 
 impl Dirent {
-    fn new(ip: &mut InodeGuard<'_, Lfs>, off: u32, ctx: &KernelCtx<'_, '_>) -> Result<Dirent, ()> {
+    fn new(
+        ip: &mut InodeGuard<'_, Lfs>,
+        off: u64,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Dirent, Errno> {
         let mut dirent = Dirent::default();
         ip.read_kernel(&mut dirent, off, ctx)?;
         Ok(dirent)
@@ -133,10 +250,10 @@ impl Dirent {
     /// Returns slice which exactly contains `name`.
     ///
     /// It contains no NUL characters.
-    fn get_name(&self) -> &FileName<{ DIRSIZ }> {
+    pub fn get_name(&self) -> &FileName<{ DIRSIZ }> {
         let len = self.name.iter().position(|ch| *ch == 0).unwrap_or(DIRSIZ);
         // SAFETY: self.name[..len] doesn't contain '\0', and len must be <= DIRSIZ.
-        unsafe { FileName::from_bytes(&self.name[..len]) }
+        unsafe { FileName::from_bytes_unchecked(&self.name[..len]) }
     }
 }
 
@@ -146,12 +263,12 @@ impl Dirent {
 /// `'s` is a lifetime for the guard and ctx
 struct DirentIter<'id, 's, 't> {
     guard: &'s mut InodeGuard<'t, Lfs>,
-    iter: StepBy<Range<u32>>,
+    iter: StepBy<Range<u64>>,
     ctx: &'s KernelCtx<'id, 's>,
 }
 
 impl Iterator for DirentIter<'_, '_, '_> {
-    type Item = (Dirent, u32);
+    type Item = (Dirent, u64);
 
     fn next(&mut self) -> Option<Self::Item> {
         let off = self.iter.next()?;
@@ -162,7 +279,17 @@ impl Iterator for DirentIter<'_, '_, '_> {
 
 impl<'t> InodeGuard<'t, Lfs> {
     fn iter_dirents<'id, 's>(&'s mut self, ctx: &'s KernelCtx<'id, 's>) -> DirentIter<'id, 's, 't> {
-        let iter = (0..self.deref_inner().size).step_by(DIRENT_SIZE);
+        self.iter_dirents_from(0, ctx)
+    }
+
+    /// Iterates the `Dirent`s from the offset `off`, which is rounded up to a `Dirent`.
+    fn iter_dirents_from<'id, 's>(
+        &'s mut self,
+        off: u64,
+        ctx: &'s KernelCtx<'id, 's>,
+    ) -> DirentIter<'id, 's, 't> {
+        let off = (off + DIRENT_SIZE as u64 - 1) / DIRENT_SIZE as u64 * DIRENT_SIZE as u64;
+        let iter = (off..self.deref_inner().size).step_by(DIRENT_SIZE);
         DirentIter {
             guard: self,
             iter,
@@ -175,52 +302,137 @@ impl<'t> InodeGuard<'t, Lfs> {
 ///
 /// Handling directories
 impl InodeGuard<'_, Lfs> {
-    /// Write a new directory entry (name, inum) into the directory dp.
-    pub fn dirlink(
-        &mut self,
-        name: &FileName<DIRSIZ>,
+    /// Returns whether the directory has entries of variable length, which `longdir`
+    /// manages, instead of `Dirent`s.
+    fn has_long_names(&self, ctx: &KernelCtx<'_, '_>) -> bool {
+        ctx.kernel().fs().lfs().superblock().long_names()
+    }
+
+    /// Returns the first used directory entry at the offset `off` or after it, and the
+    /// offset right after it. The type of the returned entry is `InodeType::None`.
+    pub fn next_dirent(&mut self, off: u64, ctx: &KernelCtx<'_, '_>) -> Option<(DirEntry, u64)> {
+        if self.has_long_names(ctx) {
+            return longdir::next(self, off, ctx);
+        }
+        self.iter_dirents_from(off, ctx)
+            .find(|(de, _)| de.inum != 0)
+            .map(|(de, off)| {
+                let entry =
+                    DirEntry::new(de.inum as u32, InodeType::None, de.get_name().as_bytes());
+                (entry, off + DIRENT_SIZE as u64)
+            })
+    }
+}
+
+impl DirFileSystem for Lfs {
+    fn dirlookup(
+        dp: &mut InodeGuard<'_, Self>,
+        name: &FileName<NAME_MAX>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(RcInode<Self>, u64), Errno> {
+        assert_eq!(dp.deref_inner().typ, InodeType::Dir, "dirlookup not DIR");
+
+        let found = if dp.has_long_names(ctx) {
+            longdir::lookup(dp, name, ctx)
+        } else {
+            dp.iter_dirents(ctx)
+                .find(|(de, _)| de.inum != 0 && de.get_name() == name.truncate::<DIRSIZ>())
+                .map(|(de, off)| (de.inum as u32, off))
+        };
+        found
+            .map(|(inum, off)| {
+                (
+                    ctx.kernel().fs().lfs().itable().get_inode(dp.dev, inum),
+                    off,
+                )
+            })
+            .ok_or(Errno::ENOENT)
+    }
+
+    fn dirlink(
+        dp: &mut InodeGuard<'_, Self>,
+        name: &FileName<NAME_MAX>,
         inum: u32,
-        tx: &Tx<'_, Lfs>,
+        tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), Errno> {
         // Check that name is not present.
-        if let Ok((ip, _)) = self.dirlookup(name, ctx) {
+        if let Ok((ip, _)) = dp.dirlookup(name, ctx) {
             ip.free((tx, ctx));
-            return Err(());
+            return Err(Errno::EEXIST);
         };
 
+        if dp.has_long_names(ctx) {
+            return longdir::link(dp, name, inum, tx, ctx);
+        }
+
         // Look for an empty Dirent.
-        let (mut de, off) = self
+        let (mut de, off) = dp
             .iter_dirents(ctx)
             .find(|(de, _)| de.inum == 0)
-            .unwrap_or((Default::default(), self.deref_inner().size));
+            .unwrap_or((Default::default(), dp.deref_inner().size));
         de.inum = inum as _;
-        de.set_name(name);
-        self.write_kernel(&de, off, tx, ctx).expect("dirlink");
+        de.set_name(name.truncate());
+        dp.write_kernel(&de, off, tx, ctx)
+    }
+
+    fn dirrelink(
+        dp: &mut InodeGuard<'_, Self>,
+        name: &FileName<NAME_MAX>,
+        inum: u32,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        let (ip, off) = dp.dirlookup(name, ctx)?;
+        ip.free((tx, ctx));
+        if dp.has_long_names(ctx) {
+            longdir::relink(dp, off, inum, tx, ctx).expect("dirrelink");
+            return Ok(());
+        }
+        let mut de = Dirent {
+            inum: inum as _,
+            ..Default::default()
+        };
+        de.set_name(name.truncate());
+        dp.write_kernel(&de, off, tx, ctx).expect("dirrelink");
         Ok(())
     }
 
-    /// Look for a directory entry in a directory.
-    /// If found, return the entry and byte offset of entry.
-    pub fn dirlookup(
-        &mut self,
-        name: &FileName<DIRSIZ>,
+    fn dirunlink(
+        dp: &mut InodeGuard<'_, Self>,
+        off: u64,
+        tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(RcInode<Lfs>, u32), ()> {
-        assert_eq!(self.deref_inner().typ, InodeType::Dir, "dirlookup not DIR");
+    ) {
+        if dp.has_long_names(ctx) {
+            longdir::unlink(dp, off, tx, ctx)
+        } else {
+            dp.write_kernel(&Dirent::default(), off, tx, ctx)
+        }
+        .expect("dirunlink");
+    }
 
-        self.iter_dirents(ctx)
-            .find(|(de, _)| de.inum != 0 && de.get_name() == name)
-            .map(|(de, off)| {
-                (
-                    ctx.kernel()
-                        .fs()
-                        .itable()
-                        .get_inode(self.dev, de.inum as u32),
-                    off,
-                )
-            })
-            .ok_or(())
+    fn is_dir_empty(dp: &mut InodeGuard<'_, Self>, ctx: &KernelCtx<'_, '_>) -> bool {
+        if dp.has_long_names(ctx) {
+            return longdir::is_empty(dp, ctx);
+        }
+        let mut de: Dirent = Default::default();
+        for off in (2 * DIRENT_SIZE as u64..dp.deref_inner().size).step_by(DIRENT_SIZE) {
+            dp.read_kernel(&mut de, off, ctx)
+                .expect("is_dir_empty: read_kernel");
+            if de.inum != 0 {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn inode_nlink<'a>(guard: &'a mut InodeGuard<'_, Self>) -> &'a mut i16 {
+        &mut guard.deref_inner_mut().nlink
+    }
+
+    fn inode_update(guard: &InodeGuard<'_, Self>, tx: &Tx<'_, Self>, ctx: &KernelCtx<'_, '_>) {
+        guard.update(tx, ctx);
     }
 }
 
@@ -263,26 +475,33 @@ impl InodeGuard<'_, Lfs> {
                 dip.major = 0;
                 dip.minor = 0;
             }
+            InodeType::Symlink => {
+                dip.typ = DInodeType::Symlink;
+                dip.major = 0;
+                dip.minor = 0;
+            }
         }
 
         (*dip).nlink = inner.nlink;
         (*dip).size = inner.size;
+        (*dip).atime = inner.atime;
+        (*dip).mtime = inner.mtime;
+        (*dip).ctime = inner.ctime;
+        (*dip).uid = inner.uid;
+        (*dip).gid = inner.gid;
+        (*dip).mode = inner.mode;
         for (d, s) in (*dip).addr_direct.iter_mut().zip(&inner.addr_direct) {
             *d = *s;
         }
         (*dip).addr_indirect = inner.addr_indirect;
 
         bp.free(ctx);
-        if seg.is_full() {
-            seg.commit(true, ctx);
-        }
+        seg.commit_if_full(ctx);
 
         // 2. Write the imap to segment.
         let mut imap = tx.imap(ctx);
         assert!(imap.set(self.inum, disk_block_no, &mut seg, ctx));
-        if seg.is_full() {
-            seg.commit(true, ctx);
-        }
+        seg.commit_if_full(ctx);
         imap.free(ctx);
         seg.free(ctx);
     }
@@ -297,14 +516,14 @@ impl InodeGuard<'_, Lfs> {
             } else {
                 None
             }
-        } else if inner.addr_indirect == 0 || bn - NDIRECT > NINDIRECT {
-            None
         } else {
+            let (id, i) = IndirectId::of_data_block(bn);
+            let indirect = self.indirect_addr(id, ctx)?;
             // Read the indirect block.
-            let bp = hal().disk().read(self.dev, inner.addr_indirect, ctx);
+            let bp = hal().disk().read(self.dev, indirect, ctx);
             // Get the address.
             let data: &[u32; NINDIRECT] = bp.data().into();
-            let addr = data[bn - NDIRECT];
+            let addr = data[i];
             bp.free(ctx);
             if addr != 0 {
                 Some(addr)
@@ -314,6 +533,27 @@ impl InodeGuard<'_, Lfs> {
         }
     }
 
+    /// Returns the disk block number of the inode's indirect block `id` if exists.
+    /// Otherwise, returns `None`.
+    pub fn indirect_addr(&self, id: IndirectId, ctx: &KernelCtx<'_, '_>) -> Option<u32> {
+        let mut addr = self.deref_inner().addr_indirect[id.level - 1];
+        for depth in 1..=id.depth {
+            if addr == 0 {
+                return None;
+            }
+            let (_, i) = id.ancestor(depth);
+            let bp = hal().disk().read(self.dev, addr, ctx);
+            let data: &[u32; NINDIRECT] = bp.data().into();
+            addr = data[i];
+            bp.free(ctx);
+        }
+        if addr != 0 {
+            Some(addr)
+        } else {
+            None
+        }
+    }
+
     /// Returns a `Buf` that has the inode's `bn`th data block content.
     ///
     /// # Note
@@ -340,10 +580,13 @@ impl InodeGuard<'_, Lfs> {
     ///
     /// The content (data) associated with each inode is stored
     /// in blocks on the disk. The first NDIRECT block numbers
-    /// are listed in self->addrs[].  The next NINDIRECT blocks are
-    /// listed in block self->addr_indirect.
+    /// are listed in self->addr_direct[].  The next NINDIRECT blocks are
+    /// listed in block self->addr_indirect[0], the next NINDIRECT^2 blocks
+    /// in the blocks that self->addr_indirect[1] lists, and the next
+    /// NINDIRECT^3 blocks below self->addr_indirect[2].
     /// Return the disk block address of the nth block in inode self.
     /// If there is no such block, bmap allocates one.
+    /// Returns Err(ENOSPC) if the segment is full and there is no empty segment.
     // TODO: Is the `segment` argument necessary? Seems like `fourfiles` deadlocks if not added.
     pub fn writable_data_block(
         &mut self,
@@ -351,30 +594,26 @@ impl InodeGuard<'_, Lfs> {
         seg: &mut SegManager,
         _tx: &Tx<'_, Lfs>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Buf {
+    ) -> Result<Buf, Errno> {
         if bn < NDIRECT {
+            if seg.is_full() {
+                seg.commit(true, ctx)?;
+            }
             let addr = self.deref_inner().addr_direct[bn];
             let (buf, new_addr) = self.writable_data_block_inner(bn, addr, seg, ctx);
             self.deref_inner_mut().addr_direct[bn] = new_addr;
-            buf
+            Ok(buf)
         } else {
-            let bn = bn - NDIRECT;
-            assert!(bn < NINDIRECT, "bmap: out of range");
-
-            // We need two `Buf`. Hence, we flush the segment early if we need to
-            // and maintain the lock on the `SegManager` until we're done.
-            if seg.remaining() < 2 {
-                seg.commit(true, ctx);
-            }
+            let (id, i) = IndirectId::of_data_block(bn);
 
             // Get the indirect block and the address of the indirect data block.
-            let mut bp = self.writable_indirect_block(seg, ctx);
+            let mut bp = self.writable_indirect_block(id, seg, ctx)?;
             let data: &mut [u32; NINDIRECT] = bp.data_mut().into();
             // Get the indirect data block and update the indirect block.
-            let (buf, new_addr) = self.writable_data_block_inner(bn + NDIRECT, data[bn], seg, ctx);
-            data[bn] = new_addr;
+            let (buf, new_addr) = self.writable_data_block_inner(bn, data[i], seg, ctx);
+            data[i] = new_addr;
             bp.free(ctx);
-            buf
+            Ok(buf)
         }
     }
 
@@ -410,50 +649,68 @@ impl InodeGuard<'_, Lfs> {
         }
     }
 
-    /// Returns the `Buf` and the disk block number for the block
-    /// that stores the indirect mappings for the indirect data blocks of the inode.
-    /// The `indirect` field of the inode may be updated after calling this.
+    /// Returns the `Buf` for the indirect block `id` of the inode.
+    /// The blocks on the way from the root to it, and the `indirect` field of the inode,
+    /// may be updated after calling this.
     ///
     /// # Note
     ///
-    /// You should make sure the segment has an empty block before calling this.
+    /// This needs a block on the segment for each block on the way, and the caller may
+    /// need one more. Hence, this flushes the segment early if it does not have them,
+    /// and returns Err(ENOSPC) if there is no empty segment to continue on.
     pub fn writable_indirect_block(
         &mut self,
+        id: IndirectId,
+        seg: &mut SegManager,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Buf, Errno> {
+        if seg.remaining() < id.depth + 2 {
+            seg.commit(true, ctx)?;
+        }
+
+        let root = self.deref_inner().addr_indirect[id.level - 1];
+        let (mut bp, new_root) =
+            self.writable_indirect_block_inner(IndirectId::root(id.level), root, seg, ctx);
+        self.deref_inner_mut().addr_indirect[id.level - 1] = new_root;
+        for depth in 1..=id.depth {
+            let (child, i) = id.ancestor(depth);
+            let data: &mut [u32; NINDIRECT] = bp.data_mut().into();
+            let (child_bp, new_addr) = self.writable_indirect_block_inner(child, data[i], seg, ctx);
+            data[i] = new_addr;
+            bp.free(ctx);
+            bp = child_bp;
+        }
+        Ok(bp)
+    }
+
+    /// Returns the indirect block `id` of the inode and its (possibly new) disk block number.
+    /// The given `addr` is the (possibly old) disk block number of the block.
+    ///
+    /// # Note
+    ///
+    /// You should make sure the segment has an empty block before calling this.
+    fn writable_indirect_block_inner(
+        &self,
+        id: IndirectId,
+        addr: u32,
         seg: &mut SegManager,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Buf {
-        let indirect = self.deref_inner().addr_indirect;
-        if indirect == 0 {
-            let (bp, new_indirect) = seg.add_new_indirect_block(self.inum, ctx).unwrap();
-            self.deref_inner_mut().addr_indirect = new_indirect;
-            bp
+    ) -> (Buf, u32) {
+        if addr == 0 {
+            seg.add_new_indirect_block(self.inum, id.to_u32(), ctx)
+                .unwrap()
         } else {
-            let (mut bp, new_indirect) = seg
-                .get_or_add_updated_indirect_block(self.inum, ctx)
+            let (mut bp, new_addr) = seg
+                .get_or_add_updated_indirect_block(self.inum, id.to_u32(), ctx)
                 .unwrap();
-            if indirect != new_indirect {
+            if new_addr != addr {
                 // Copy from old block to new block.
-                let old_bp = hal().disk().read(self.dev, indirect, ctx);
+                let old_bp = hal().disk().read(self.dev, addr, ctx);
                 bp.data_mut().copy_from(old_bp.data());
                 old_bp.free(ctx);
-                self.deref_inner_mut().addr_indirect = new_indirect;
-            }
-            bp
-        }
-    }
-
-    /// Is the directory dp empty except for "." and ".." ?
-    #[allow(clippy::wrong_self_convention)]
-    pub fn is_dir_empty(&mut self, ctx: &KernelCtx<'_, '_>) -> bool {
-        let mut de: Dirent = Default::default();
-        for off in (2 * DIRENT_SIZE as u32..self.deref_inner().size).step_by(DIRENT_SIZE) {
-            self.read_kernel(&mut de, off, ctx)
-                .expect("is_dir_empty: read_kernel");
-            if de.inum != 0 {
-                return false;
             }
+            (bp, new_addr)
         }
-        true
     }
 }
 
@@ -475,8 +732,14 @@ impl Inode<Lfs> {
                     typ: InodeType::None,
                     nlink: 0,
                     size: 0,
+                    atime: Timespec::ZERO,
+                    mtime: Timespec::ZERO,
+                    ctime: Timespec::ZERO,
+                    uid: 0,
+                    gid: 0,
+                    mode: 0,
                     addr_direct: [0; NDIRECT],
-                    addr_indirect: 0,
+                    addr_indirect: [0; NLEVEL],
                 },
             ),
         }
@@ -505,21 +768,30 @@ impl Itable<Lfs> {
 
     /// Allocate an inode on device dev.
     /// Mark it as allocated by giving it type.
-    /// Returns an unlocked but allocated and referenced inode.
+    /// Returns Ok(an unlocked but allocated and referenced inode), or
+    /// Err(ENOSPC) if there is no free inode or segment.
     pub fn alloc_inode(
         self: StrongPin<'_, Self>,
         dev: u32,
         typ: InodeType,
         tx: &Tx<'_, Lfs>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> RcInode<Lfs> {
+    ) -> Result<RcInode<Lfs>, Errno> {
         let mut seg = tx.segmanager(ctx);
         let mut imap = tx.imap(ctx);
 
         // 1. Write the inode.
-        let inum = imap.get_empty_inum(ctx).unwrap();
-        let (mut bp, disk_block_no) = seg.add_new_inode_block(inum, ctx).unwrap();
-
+        let block = imap
+            .get_empty_inum(ctx)
+            .and_then(|inum| Some((inum, seg.add_new_inode_block(inum, ctx)?)));
+        let (inum, (mut bp, disk_block_no)) = match block {
+            Some(block) => block,
+            None => {
+                imap.free(ctx);
+                seg.free(ctx);
+                return Err(Errno::ENOSPC);
+            }
+        };
         let dip = unsafe { &mut *(bp.data_mut().as_mut_ptr() as *mut Dinode) };
         // SAFETY: DInode does not have any invariant.
         unsafe { memset(dip, 0u32) };
@@ -527,88 +799,29 @@ impl Itable<Lfs> {
             InodeType::None => dip.typ = DInodeType::None,
             InodeType::Dir => dip.typ = DInodeType::Dir,
             InodeType::File => dip.typ = DInodeType::File,
+            InodeType::Symlink => dip.typ = DInodeType::Symlink,
             InodeType::Device { major, minor } => {
                 dip.typ = DInodeType::Device;
                 dip.major = major;
                 dip.minor = minor;
             }
         }
+        let now = Timespec::now();
+        dip.atime = now;
+        dip.mtime = now;
+        dip.ctime = now;
+        dip.uid = ctx.proc().creds().uid;
+        dip.gid = ctx.proc().creds().gid;
+        dip.mode = typ.default_mode();
         bp.free(ctx);
-        if seg.is_full() {
-            seg.commit(true, ctx);
-        }
+        seg.commit_if_full(ctx);
 
         // 2. Now write the imap.
         assert!(imap.set(inum, disk_block_no, &mut seg, ctx));
-        if seg.is_full() {
-            seg.commit(true, ctx);
-        }
+        seg.commit_if_full(ctx);
         imap.free(ctx);
         seg.free(ctx);
 
-        self.get_inode(dev, inum)
-    }
-
-    pub fn root(self: StrongPin<'_, Self>) -> RcInode<Lfs> {
-        self.get_inode(ROOTDEV, ROOTINO)
-    }
-
-    pub fn namei(
-        self: StrongPin<'_, Self>,
-        path: &Path,
-        tx: &Tx<'_, Lfs>,
-        proc: &KernelCtx<'_, '_>,
-    ) -> Result<RcInode<Lfs>, ()> {
-        Ok(self.namex(path, false, tx, proc)?.0)
-    }
-
-    pub fn nameiparent<'s>(
-        self: StrongPin<'_, Self>,
-        path: &'s Path,
-        tx: &Tx<'_, Lfs>,
-        ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(RcInode<Lfs>, &'s FileName<{ DIRSIZ }>), ()> {
-        let (ip, name_in_path) = self.namex(path, true, tx, ctx)?;
-        let name_in_path = name_in_path.ok_or(())?;
-        Ok((ip, name_in_path))
-    }
-
-    fn namex<'s>(
-        self: StrongPin<'_, Self>,
-        mut path: &'s Path,
-        parent: bool,
-        tx: &Tx<'_, Lfs>,
-        ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(RcInode<Lfs>, Option<&'s FileName<{ DIRSIZ }>>), ()> {
-        let mut ptr = if path.is_absolute() {
-            self.root()
-        } else {
-            ctx.proc().cwd().clone()
-        };
-
-        while let Some((new_path, name)) = path.skipelem() {
-            path = new_path;
-
-            let mut ip = ptr.lock(ctx);
-            if ip.deref_inner().typ != InodeType::Dir {
-                ip.free(ctx);
-                ptr.free((tx, ctx));
-                return Err(());
-            }
-            if parent && path.is_empty_string() {
-                // Stop one level early.
-                ip.free(ctx);
-                return Ok((ptr, Some(name)));
-            }
-            let next = ip.dirlookup(name, ctx);
-            ip.free(ctx);
-            ptr.free((tx, ctx));
-            ptr = next?.0
-        }
-        if parent {
-            ptr.free((tx, ctx));
-            return Err(());
-        }
-        Ok((ptr, None))
+        Ok(self.get_inode(dev, inum))
     }
 }
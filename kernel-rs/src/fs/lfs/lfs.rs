@@ -11,6 +11,7 @@ use static_assertions::const_assert;
 use super::{Imap, Itable, SegManager, SegTable, Superblock, Tx, TxManager};
 use crate::{
     bio::BufData,
+    errno::Errno,
     hal::hal,
     lock::{SleepLock, SleepLockGuard, SleepableLock},
     param::IMAPSIZE,
@@ -20,8 +21,10 @@ use crate::{
 
 #[pin_project]
 pub struct Lfs {
+    /// The disk device that the file system is on.
+    dev: Once<u32>,
+
     /// Initializing superblock should run only once because forkret() calls FileSystem::init().
-    /// There should be one superblock per disk device, but we run with only one device.
     superblock: Once<Superblock>,
 
     /// In-memory inodes.
@@ -108,6 +111,7 @@ impl Tx<'_, Lfs> {
 impl Lfs {
     pub const fn new() -> Self {
         Self {
+            dev: Once::new(),
             superblock: Once::new(),
             itable: Itable::<Self>::new_itable(),
             segmanager: Once::new(),
@@ -116,6 +120,11 @@ impl Lfs {
         }
     }
 
+    /// Returns true if `self` has been initialized on a disk.
+    pub fn is_initialized(&self) -> bool {
+        self.segmanager.is_completed()
+    }
+
     /// Returns a reference to the `Superblock`.
     ///
     /// # Panic
@@ -188,9 +197,12 @@ impl Lfs {
         self.tx_manager.get().expect("tx_manager")
     }
 
-    /// Initializes `self`.
-    /// Does nothing if already initialized.
-    pub fn initialize(&self, dev: u32, ctx: &KernelCtx<'_, '_>) {
+    /// Initializes `self` on the disk `dev`.
+    /// Does nothing if already initialized, and returns Err(EBUSY) if it was on another disk.
+    pub fn initialize(&self, dev: u32, ctx: &KernelCtx<'_, '_>) -> Result<(), Errno> {
+        if *self.dev.call_once(|| dev) != dev {
+            return Err(Errno::EBUSY);
+        }
         if !self.superblock.is_completed() {
             // Load the superblock.
             let buf = hal().disk().read(dev, 1, ctx);
@@ -233,6 +245,7 @@ impl Lfs {
                 )
             });
         }
+        Ok(())
     }
 
     /// Commits the checkpoint at the checkpoint region.
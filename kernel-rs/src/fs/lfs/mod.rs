@@ -1,16 +1,12 @@
-use core::cell::UnsafeCell;
 use core::mem;
 use core::ops::Deref;
 
 use super::{
-    DInodeType, FcntlFlags, FileName, FileSystem, Inode, InodeGuard, InodeType, Itable, Path,
-    RcInode, Stat, Tx,
+    AnyTx, DirEntry, FileName, FileSystem, Inode, InodeGuard, InodeType, Itable, RcInode, Stat, Tx,
+    Vfs, Vnode, NAME_MAX,
 };
 use crate::{
-    file::{FileType, InodeFileType},
-    hal::hal,
-    param::BSIZE,
-    proc::KernelCtx,
+    errno::Errno, hal::hal, param::BSIZE, proc::KernelCtx, time::Timespec,
     util::strong_pin::StrongPin,
 };
 
@@ -32,81 +28,152 @@ use tx::TxManager;
 /// root i-number
 const ROOTINO: u32 = 1;
 
-const NDIRECT: usize = 12;
+const NDIRECT: usize = 10;
 const NINDIRECT: usize = BSIZE.wrapping_div(mem::size_of::<u32>());
-const MAXFILE: usize = NDIRECT.wrapping_add(NINDIRECT);
+/// Levels of indirect blocks: single, double, and triple.
+const NLEVEL: usize = 3;
+const MAXFILE: usize =
+    NDIRECT + NINDIRECT + NINDIRECT * NINDIRECT + NINDIRECT * NINDIRECT * NINDIRECT;
+
+impl Lfs {
+    /// Reads the inode `inum` of the disk `dev` from the disk, and returns `f` applied to it.
+    fn read_dinode<R>(
+        &self,
+        dev: u32,
+        inum: u32,
+        ctx: &KernelCtx<'_, '_>,
+        f: impl FnOnce(&Dinode) -> R,
+    ) -> R {
+        let imap = self.imap(ctx);
+        let bp = hal().disk().read(dev, imap.get(inum, ctx), ctx);
+        imap.free(ctx);
+
+        let dip: &Dinode = bp.data().try_into().unwrap();
+        let ret = f(dip);
+        bp.free(ctx);
+        ret
+    }
+}
 
 impl FileSystem for Lfs {
     type Dirent = Dirent;
     type InodeInner = InodeInner;
 
-    fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>) {
-        self.initialize(dev, ctx);
+    const NAME: &'static str = "lfs";
+
+    fn get(vfs: StrongPin<'_, Vfs>) -> StrongPin<'_, Self> {
+        vfs.lfs()
     }
 
-    fn root(self: StrongPin<'_, Self>) -> RcInode<Self> {
-        self.itable().root()
+    fn vnode(inode: RcInode<Self>) -> Vnode {
+        Vnode::Lfs(inode)
     }
 
-    fn namei(
-        self: StrongPin<'_, Self>,
-        path: &Path,
-        tx: &Tx<'_, Self>,
+    fn any_tx(tx: Tx<'_, Self>) -> AnyTx<'_> {
+        AnyTx::Lfs(tx)
+    }
+
+    fn tx<'a, 's>(tx: &'a AnyTx<'s>) -> Option<&'a Tx<'s, Self>> {
+        match tx {
+            AnyTx::Lfs(tx) => Some(tx),
+            _ => None,
+        }
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn itable<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Itable<Self>> {
+        Lfs::itable(self)
+    }
+
+    fn mount_dev(dev: u32) -> Result<u32, Errno> {
+        if hal().disk().is_present(dev) {
+            Ok(dev)
+        } else {
+            Err(Errno::ENXIO)
+        }
+    }
+
+    fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>) -> Result<(), Errno> {
+        self.initialize(dev, ctx)
+    }
+
+    fn root(self: StrongPin<'_, Self>, dev: u32) -> RcInode<Self> {
+        self.itable().get_inode(dev, ROOTINO)
+    }
+
+    fn lookup(
+        dp: &mut InodeGuard<'_, Self>,
+        name: &FileName<NAME_MAX>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<RcInode<Self>, ()> {
-        // name-to-inode translation
-        self.itable().namei(path, tx, ctx)
+    ) -> Result<RcInode<Self>, Errno> {
+        Ok(dp.dirlookup(name, ctx)?.0)
+    }
+
+    fn readdir(
+        dp: &mut InodeGuard<'_, Self>,
+        off: u64,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Option<(DirEntry, u64)>, Errno> {
+        Ok(dp.next_dirent(off, ctx).map(|(mut entry, off)| {
+            // Read the type from the disk, as locking the inode of "." or ".." may deadlock.
+            let lfs = ctx.kernel().fs().lfs();
+            entry.typ = lfs.read_dinode(dp.dev, entry.inum, ctx, Dinode::inode_type);
+            (entry, off)
+        }))
     }
 
     fn link(
         self: StrongPin<'_, Self>,
-        inode: RcInode<Self>,
-        path: &Path,
+        dp: &RcInode<Self>,
+        name: &FileName<NAME_MAX>,
+        inode: &RcInode<Self>,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
-        // Create another name `path` by linking to inode
-        let inode = scopeguard::guard(inode, |ptr| ptr.free((tx, ctx)));
+    ) -> Result<(), Errno> {
+        // Create another name `name` in `dp` by linking to inode
         let ip = inode.lock(ctx);
         let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
         if ip.deref_inner().typ == InodeType::Dir {
-            return Err(());
+            return Err(Errno::EPERM);
         }
         ip.deref_inner_mut().nlink += 1;
+        ip.deref_inner_mut().ctime = Timespec::now();
         ip.update(tx, ctx);
         drop(ip);
 
-        if let Ok((ptr2, name)) = self.itable().nameiparent(path, tx, ctx) {
-            let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
-            let dp = ptr2.lock(ctx);
-            let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
-            if dp.dev == inode.dev && dp.dirlink(name, inode.inum, tx, ctx).is_ok() {
-                return Ok(());
+        let dp = dp.lock(ctx);
+        let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
+        let err = if dp.dev != inode.dev {
+            Errno::EXDEV
+        } else {
+            match dp.dirlink(name, inode.inum, tx, ctx) {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
             }
-        }
+        };
+        drop(dp);
 
         let ip = inode.lock(ctx);
         let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
         ip.deref_inner_mut().nlink -= 1;
         ip.update(tx, ctx);
-        Err(())
+        Err(err)
     }
 
     fn unlink(
         self: StrongPin<'_, Self>,
-        path: &Path,
+        dp: &RcInode<Self>,
+        name: &FileName<NAME_MAX>,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
-        // remove a file with `path`
-        let (ptr, name) = self.itable().nameiparent(path, tx, ctx)?;
-        let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
-        let dp = ptr.lock(ctx);
+    ) -> Result<(), Errno> {
+        // remove the file `name` in `dp`
+        let dp = dp.lock(ctx);
         let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
 
         // Cannot unlink "." or "..".
         if name.as_bytes() == b"." || name.as_bytes() == b".." {
-            return Err(());
+            return Err(Errno::EINVAL);
         }
 
         let (ptr2, off) = dp.dirlookup(name, ctx)?;
@@ -116,154 +183,97 @@ impl FileSystem for Lfs {
         assert!(ip.deref_inner().nlink >= 1, "unlink: nlink < 1");
 
         if ip.deref_inner().typ == InodeType::Dir && !ip.is_dir_empty(ctx) {
-            return Err(());
+            return Err(Errno::ENOTEMPTY);
         }
 
-        dp.write_kernel(&Dirent::default(), off, tx, ctx)
-            .expect("unlink: writei");
+        dp.dirunlink(off, tx, ctx);
         if ip.deref_inner().typ == InodeType::Dir {
             dp.deref_inner_mut().nlink -= 1;
             dp.update(tx, ctx);
         }
         drop(dp);
-        drop(ptr);
         ip.deref_inner_mut().nlink -= 1;
+        ip.deref_inner_mut().ctime = Timespec::now();
         ip.update(tx, ctx);
         Ok(())
     }
 
-    fn create<F, T>(
+    fn rename(
+        self: StrongPin<'_, Self>,
+        dp: &RcInode<Self>,
+        name: &FileName<NAME_MAX>,
+        newdp: &RcInode<Self>,
+        newname: &FileName<NAME_MAX>,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        super::rename(dp, name, newdp, newname, tx, ctx)
+    }
+
+    fn create(
         self: StrongPin<'_, Self>,
-        path: &Path,
+        dp: &RcInode<Self>,
+        name: &FileName<NAME_MAX>,
         typ: InodeType,
+        excl: bool,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
-        f: F,
-    ) -> Result<(RcInode<Self>, T), ()>
-    where
-        F: FnOnce(&mut InodeGuard<'_, Self>) -> T,
-    {
-        let (ptr, name) = self.itable().nameiparent(path, tx, ctx)?;
-        let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
-        let dp = ptr.lock(ctx);
+    ) -> Result<RcInode<Self>, Errno> {
+        let dp = dp.lock(ctx);
         let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
         if let Ok((ptr2, _)) = dp.dirlookup(name, ctx) {
             let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
             drop(dp);
-            if typ != InodeType::File {
-                return Err(());
+            if typ != InodeType::File || excl {
+                return Err(Errno::EEXIST);
             }
             let ip = ptr2.lock(ctx);
-            let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
-            if let InodeType::None | InodeType::Dir = ip.deref_inner().typ {
-                return Err(());
+            let ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+            match ip.deref_inner().typ {
+                InodeType::None => return Err(Errno::ENOENT),
+                InodeType::Dir => return Err(Errno::EISDIR),
+                _ => (),
             }
-            let ret = f(&mut ip);
             drop(ip);
-            return Ok((scopeguard::ScopeGuard::into_inner(ptr2), ret));
+            return Ok(scopeguard::ScopeGuard::into_inner(ptr2));
         }
-        let ptr2 = self.itable().alloc_inode(dp.dev, typ, tx, ctx);
+        let ptr2 = self.itable().alloc_inode(dp.dev, typ, tx, ctx)?;
+        let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
         let ip = ptr2.lock(ctx);
         let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
         ip.deref_inner_mut().nlink = 1;
         ip.update(tx, ctx);
 
-        // Create . and .. entries.
+        let inum = ip.inum;
+        let res = if typ == InodeType::Dir {
+            // Create . and .. entries.
+            // No ip->nlink++ for ".": avoid cyclic ref count.
+            // SAFETY: b"." and b".." do not contain any NUL characters, and are short enough.
+            let (dot, dotdot) = unsafe {
+                (
+                    FileName::from_bytes_unchecked(b"."),
+                    FileName::from_bytes_unchecked(b".."),
+                )
+            };
+            ip.dirlink(dot, inum, tx, ctx)
+                .and_then(|_| ip.dirlink(dotdot, dp.inum, tx, ctx))
+        } else {
+            Ok(())
+        }
+        .and_then(|_| dp.dirlink(name, inum, tx, ctx));
+        if let Err(err) = res {
+            // The inode is freed when its last reference is dropped.
+            ip.deref_inner_mut().nlink = 0;
+            ip.update(tx, ctx);
+            return Err(err);
+        }
         if typ == InodeType::Dir {
             // for ".."
             dp.deref_inner_mut().nlink += 1;
             dp.update(tx, ctx);
-
-            let inum = ip.inum;
-            // No ip->nlink++ for ".": avoid cyclic ref count.
-            // SAFETY: b"." does not contain any NUL characters.
-            ip.dirlink(unsafe { FileName::from_bytes(b".") }, inum, tx, ctx)
-                // SAFETY: b".." does not contain any NUL characters.
-                .and_then(|_| ip.dirlink(unsafe { FileName::from_bytes(b"..") }, dp.inum, tx, ctx))
-                .expect("create dots");
         }
-        dp.dirlink(name, ip.inum, tx, ctx).expect("create: dirlink");
-        let ret = f(&mut ip);
         drop(ip);
-        Ok((ptr2, ret))
-    }
-
-    fn open(
-        self: StrongPin<'_, Self>,
-        path: &Path,
-        omode: FcntlFlags,
-        tx: &Tx<'_, Self>,
-        ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<usize, ()> {
-        let (ip, typ) = if omode.contains(FcntlFlags::O_CREATE) {
-            self.create(path, InodeType::File, tx, ctx, |ip| ip.deref_inner().typ)?
-        } else {
-            let ptr = self.itable().namei(path, tx, ctx)?;
-            let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
-            let ip = ptr.lock(ctx);
-            let ip = scopeguard::guard(ip, |ip| ip.free(ctx));
-            let typ = ip.deref_inner().typ;
-
-            if typ == InodeType::Dir && omode != FcntlFlags::O_RDONLY {
-                return Err(());
-            }
-            drop(ip);
-            (scopeguard::ScopeGuard::into_inner(ptr), typ)
-        };
-
-        let filetype = match typ {
-            InodeType::Device { major, .. } => FileType::Device { ip, major },
-            _ => {
-                FileType::Inode {
-                    inner: InodeFileType {
-                        ip,
-                        off: UnsafeCell::new(0),
-                    },
-                }
-            }
-        };
-
-        let f = ctx.kernel().ftable().alloc_file(
-            filetype,
-            !omode.intersects(FcntlFlags::O_WRONLY),
-            omode.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR),
-        )?;
-
-        if omode.contains(FcntlFlags::O_TRUNC) && typ == InodeType::File {
-            match &f.typ {
-                // It is safe to call itrunc because ip.lock() is held
-                FileType::Device { ip, .. }
-                | FileType::Inode {
-                    inner: InodeFileType { ip, .. },
-                } => {
-                    let mut ip = ip.lock(ctx);
-                    ip.trunc(tx, ctx);
-                    ip.free(ctx);
-                }
-                _ => panic!("sys_open : Not reach"),
-            };
-        }
-        let fd = f.fdalloc(ctx)?;
-        Ok(fd as usize)
-    }
-
-    fn chdir(
-        self: StrongPin<'_, Self>,
-        inode: RcInode<Self>,
-        tx: &Tx<'_, Self>,
-        ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
-        // change the current directory
-        let ip = inode.lock(ctx);
-        let typ = ip.deref_inner().typ;
-        ip.free(ctx);
-        if typ != InodeType::Dir {
-            inode.free((tx, ctx));
-            return Err(());
-        }
-
-        mem::replace(ctx.proc_mut().cwd_mut(), inode).free((tx, ctx));
-        Ok(())
+        Ok(scopeguard::ScopeGuard::into_inner(ptr2))
     }
 
     fn tx_begin(&self, ctx: &KernelCtx<'_, '_>) {
@@ -280,33 +290,33 @@ impl FileSystem for Lfs {
         'id,
         's,
         K: Deref<Target = KernelCtx<'id, 's>>,
-        F: FnMut(u32, &[u8], &mut K) -> Result<(), ()>,
+        F: FnMut(u32, &[u8], &mut K) -> Result<(), Errno>,
     >(
         guard: &mut InodeGuard<'_, Self>,
-        mut off: u32,
+        mut off: u64,
         mut n: u32,
         mut f: F,
         mut k: K,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, Errno> {
         // read inode
         let inner = guard.deref_inner();
-        if off > inner.size || off.wrapping_add(n) < off {
+        if off > inner.size || off.wrapping_add(u64::from(n)) < off {
             return Ok(0);
         }
-        if off + n > inner.size {
-            n = inner.size - off;
+        if off + u64::from(n) > inner.size {
+            n = (inner.size - off) as u32;
         }
         let mut tot: u32 = 0;
         while tot < n {
             let bp = guard.readable_data_block(off as usize / BSIZE, &k);
-            let m = core::cmp::min(n - tot, BSIZE as u32 - off % BSIZE as u32);
-            let begin = (off % BSIZE as u32) as usize;
+            let m = core::cmp::min(n - tot, BSIZE as u32 - (off % BSIZE as u64) as u32);
+            let begin = (off % BSIZE as u64) as usize;
             let end = begin + m as usize;
             let res = f(tot, &bp.data()[begin..end], &mut k);
             bp.free(&k);
             res?;
             tot += m;
-            off += m;
+            off += u64::from(m);
         }
         Ok(tot as usize)
     }
@@ -315,87 +325,96 @@ impl FileSystem for Lfs {
         'id,
         's,
         K: Deref<Target = KernelCtx<'id, 's>>,
-        F: FnMut(u32, &mut [u8], &mut K) -> Result<(), ()>,
+        F: FnMut(u32, &mut [u8], &mut K) -> Result<(), Errno>,
     >(
         guard: &mut InodeGuard<'_, Self>,
-        mut off: u32,
+        mut off: u64,
         n: u32,
         mut f: F,
         tx: &Tx<'_, Lfs>,
         mut k: K,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, Errno> {
         // write the inode
         if off > guard.deref_inner().size {
-            return Err(());
+            return Err(Errno::EINVAL);
         }
-        if off.checked_add(n).ok_or(())? as usize > MAXFILE * BSIZE {
-            return Err(());
+        if off.checked_add(u64::from(n)).ok_or(Errno::EFBIG)? > (MAXFILE * BSIZE) as u64 {
+            return Err(Errno::EFBIG);
         }
         let mut tot: u32 = 0;
+        let mut res = Ok(());
         while tot < n {
             let mut seg = tx.segmanager(&k);
-            let mut bp = guard.writable_data_block(off as usize / BSIZE, &mut seg, tx, &k);
-            let m = core::cmp::min(n - tot, BSIZE as u32 - off % BSIZE as u32);
-            let begin = (off % BSIZE as u32) as usize;
+            let mut bp = match guard.writable_data_block(off as usize / BSIZE, &mut seg, tx, &k) {
+                Ok(bp) => bp,
+                Err(err) => {
+                    seg.free(&k);
+                    res = Err(err);
+                    break;
+                }
+            };
+            let m = core::cmp::min(n - tot, BSIZE as u32 - (off % BSIZE as u64) as u32);
+            let begin = (off % BSIZE as u64) as usize;
             let end = begin + m as usize;
-            let res = f(tot, &mut bp.data_mut()[begin..end], &mut k);
+            res = f(tot, &mut bp.data_mut()[begin..end], &mut k);
             bp.free(&k);
-            if seg.is_full() {
-                seg.commit(true, &k);
-            }
+            seg.commit_if_full(&k);
             seg.free(&k);
             if res.is_err() {
                 break;
             }
             tot += m;
-            off += m;
+            off += u64::from(m);
         }
 
         if off > guard.deref_inner().size {
             guard.deref_inner_mut().size = off;
         }
+        if tot > 0 {
+            let now = Timespec::now();
+            guard.deref_inner_mut().mtime = now;
+            guard.deref_inner_mut().ctime = now;
+        }
 
         // Write the i-node back to disk even if the size didn't change
         // because the loop above might have called bmap() and added a new
         // block to self->addrs[].
         guard.update(tx, &k);
+        if tot == 0 {
+            res?;
+        }
         Ok(tot as usize)
     }
 
     fn inode_trunc(guard: &mut InodeGuard<'_, Self>, tx: &Tx<'_, Self>, ctx: &KernelCtx<'_, '_>) {
         guard.deref_inner_mut().addr_direct = [0; NDIRECT];
-        guard.deref_inner_mut().addr_indirect = 0;
+        guard.deref_inner_mut().addr_indirect = [0; NLEVEL];
         guard.deref_inner_mut().size = 0;
+        let now = Timespec::now();
+        guard.deref_inner_mut().mtime = now;
+        guard.deref_inner_mut().ctime = now;
         guard.update(tx, ctx);
     }
 
     fn inode_lock<'a>(inode: &'a Inode<Self>, ctx: &KernelCtx<'_, '_>) -> InodeGuard<'a, Self> {
         let mut guard = inode.inner.lock(ctx);
         if !guard.valid {
-            let fs = ctx.kernel().fs();
-            let imap = fs.imap(ctx);
-            let bp = hal().disk().read(inode.dev, imap.get(inode.inum, ctx), ctx);
-            imap.free(ctx);
-
-            let dip: &Dinode = bp.data().try_into().unwrap();
-            match dip.typ {
-                DInodeType::None => guard.typ = InodeType::None,
-                DInodeType::Dir => guard.typ = InodeType::Dir,
-                DInodeType::File => guard.typ = InodeType::File,
-                DInodeType::Device => {
-                    guard.typ = InodeType::Device {
-                        major: dip.major,
-                        minor: dip.minor,
-                    }
+            let lfs = ctx.kernel().fs().lfs();
+            lfs.read_dinode(inode.dev, inode.inum, ctx, |dip| {
+                guard.typ = dip.inode_type();
+                guard.nlink = dip.nlink;
+                guard.size = dip.size;
+                guard.atime = dip.atime;
+                guard.mtime = dip.mtime;
+                guard.ctime = dip.ctime;
+                guard.uid = dip.uid;
+                guard.gid = dip.gid;
+                guard.mode = dip.mode;
+                for (d, s) in guard.addr_direct.iter_mut().zip(&dip.addr_direct) {
+                    *d = *s;
                 }
-            }
-            guard.nlink = dip.nlink;
-            guard.size = dip.size;
-            for (d, s) in guard.addr_direct.iter_mut().zip(&dip.addr_direct) {
-                *d = *s;
-            }
-            guard.addr_indirect = dip.addr_indirect;
-            bp.free(ctx);
+                guard.addr_indirect = dip.addr_indirect;
+            });
             guard.valid = true;
             assert_ne!(guard.typ, InodeType::None, "Inode::lock: no type");
         };
@@ -418,9 +437,7 @@ impl FileSystem for Lfs {
             let mut seg = tx.segmanager(ctx);
             let mut imap = tx.imap(ctx);
             assert!(imap.set(ip.inum, 0, &mut seg, ctx));
-            if seg.is_full() {
-                seg.commit(true, ctx);
-            }
+            seg.commit_if_full(ctx);
             imap.free(ctx);
             seg.free(ctx);
             ip.deref_inner_mut().valid = false;
@@ -429,6 +446,14 @@ impl FileSystem for Lfs {
         }
     }
 
+    fn inode_type(guard: &InodeGuard<'_, Self>) -> InodeType {
+        guard.deref_inner().typ
+    }
+
+    fn inode_size(guard: &InodeGuard<'_, Self>) -> u64 {
+        guard.deref_inner().size
+    }
+
     fn inode_stat(inode: &Inode<Self>, ctx: &KernelCtx<'_, '_>) -> Stat {
         let inner = inode.inner.lock(ctx);
         let st = Stat {
@@ -439,12 +464,70 @@ impl FileSystem for Lfs {
                 InodeType::Dir => 1,
                 InodeType::File => 2,
                 InodeType::Device { .. } => 3,
+                InodeType::Symlink => 4,
             },
             nlink: inner.nlink,
+            mode: inner.mode,
             _padding: 0,
-            size: inner.size as usize,
+            uid: inner.uid,
+            gid: inner.gid,
+            size: inner.size,
+            atime: inner.atime,
+            mtime: inner.mtime,
+            ctime: inner.ctime,
         };
         inner.free(ctx);
         st
     }
+
+    fn inode_set_times(
+        guard: &mut InodeGuard<'_, Self>,
+        atime: Option<Timespec>,
+        mtime: Option<Timespec>,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        let inner = guard.deref_inner_mut();
+        if let Some(atime) = atime {
+            inner.atime = atime;
+        }
+        if let Some(mtime) = mtime {
+            inner.mtime = mtime;
+        }
+        inner.ctime = Timespec::now();
+        guard.update(tx, ctx);
+        Ok(())
+    }
+
+    fn inode_set_mode(
+        guard: &mut InodeGuard<'_, Self>,
+        mode: u16,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        let inner = guard.deref_inner_mut();
+        inner.mode = mode;
+        inner.ctime = Timespec::now();
+        guard.update(tx, ctx);
+        Ok(())
+    }
+
+    fn inode_set_owner(
+        guard: &mut InodeGuard<'_, Self>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        let inner = guard.deref_inner_mut();
+        if let Some(uid) = uid {
+            inner.uid = uid;
+        }
+        if let Some(gid) = gid {
+            inner.gid = gid;
+        }
+        inner.ctime = Timespec::now();
+        guard.update(tx, ctx);
+        Ok(())
+    }
 }
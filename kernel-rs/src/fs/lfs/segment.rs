@@ -39,13 +39,14 @@
 //! When acquiring the lock on the `SegManager`, `Imap`, or `Buf` at the same time, it must always done in the order of
 //! `SegManager` -> `Imap` -> `Buf`. Otherwise, you may encounter a deadlock.
 
-use core::mem;
+use core::{cmp, mem};
 
 use arrayvec::ArrayVec;
 use static_assertions::const_assert;
 
 use crate::{
     bio::{Buf, BufData, BufUnlocked},
+    errno::Errno,
     hal::hal,
     param::{BSIZE, SEGSIZE, SEGTABLESIZE},
     proc::KernelCtx,
@@ -58,8 +59,8 @@ pub enum SegSumEntry {
     Inode { inum: u32 },
     /// Data block of an inode.
     DataBlock { inum: u32, block_no: u32 },
-    /// The block that stores the mapping for all indirect data blocks of an inode.
-    IndirectMap { inum: u32 },
+    /// An indirect block of an inode, which `IndirectId::to_u32` identifies.
+    IndirectMap { inum: u32, block_no: u32 },
     /// Imap.
     Imap { block_no: u32 },
 }
@@ -81,7 +82,7 @@ pub struct DSegSumEntry {
     /// 0: empty, 1: inode, 2: data block, 3: indirect map, 4: imap block
     pub block_type: BlockType,
     pub inum: u32,     // 0 in case of empty or imap block
-    pub block_no: u32, // 0 in case of inode or indirect map
+    pub block_no: u32, // 0 in case of inode
 }
 
 impl Default for DSegSumEntry {
@@ -111,11 +112,11 @@ impl From<SegSumEntry> for DSegSumEntry {
                     block_no,
                 }
             }
-            SegSumEntry::IndirectMap { inum } => {
+            SegSumEntry::IndirectMap { inum, block_no } => {
                 Self {
                     block_type: BlockType::IndirectMap,
                     inum,
-                    block_no: 0,
+                    block_no,
                 }
             }
             SegSumEntry::Imap { block_no } => {
@@ -227,8 +228,11 @@ impl SegManager {
                 this.nfree += 1;
             }
         }
-        // Allocate a segment.
-        this.alloc_segment(None);
+        // Allocate a segment. If the disk is full, the segment stays full until
+        // the cleaner frees one.
+        if this.alloc_segment(None).is_err() {
+            this.start = SEGSIZE - 1;
+        }
         this
     }
 
@@ -237,6 +241,7 @@ impl SegManager {
     fn get_disk_block_no(&self, seg_block_no: usize, ctx: &KernelCtx<'_, '_>) -> u32 {
         ctx.kernel()
             .fs()
+            .lfs()
             .superblock()
             .seg_to_disk_block_no(self.segment_no, seg_block_no as u32)
     }
@@ -262,6 +267,16 @@ impl SegManager {
         SEGSIZE - 1 - self.start - self.segment.len()
     }
 
+    /// Returns the total number of segments on the disk.
+    pub fn nsegments(&self) -> u32 {
+        self.nsegments
+    }
+
+    /// Returns the segment number of the current segment.
+    pub fn segment_no(&self) -> u32 {
+        self.segment_no
+    }
+
     /// Returns the number of free segments on the disk.
     pub fn nfree(&self) -> u32 {
         self.nfree
@@ -313,7 +328,8 @@ impl SegManager {
     /// Traverses the segment usage table to find an empty segment and marks it as used.
     /// Uses that segment from now on.
     /// If a `last_seg_no` was given, starts traversing from `last_seg_no + 1`.
-    fn alloc_segment(&mut self, last_seg_no: Option<u32>) {
+    /// Returns Err(ENOSPC) if there is no empty segment.
+    fn alloc_segment(&mut self, last_seg_no: Option<u32>) -> Result<(), Errno> {
         let start = match last_seg_no {
             None => 0,
             Some(seg_no) => seg_no + 1,
@@ -324,10 +340,10 @@ impl SegManager {
                 self.segtable_alloc(seg_no);
                 self.segment_no = seg_no;
                 self.nfree -= 1;
-                return;
+                return Ok(());
             }
         }
-        panic!("no empty segment");
+        Err(Errno::ENOSPC)
     }
 
     /// Allocates a new zeroed block on the segment to be used by the `entry`.
@@ -369,15 +385,17 @@ impl SegManager {
         self.add_new_block(SegSumEntry::DataBlock { inum, block_no }, ctx)
     }
 
-    /// Provides a new zeroed block on the segment to be used to store the new indirect map of an inode.
+    /// Provides a new zeroed block on the segment to be used to store the new indirect block `block_no`
+    /// of an inode, which `IndirectId::to_u32` returned.
     /// If succeeds, returns a `Buf` of the disk block and the disk block number of it.
     /// Always use this if this is the first time we allocate the indirect block for the inode.
     pub fn add_new_indirect_block(
         &mut self,
         inum: u32,
+        block_no: u32,
         ctx: &KernelCtx<'_, '_>,
     ) -> Option<(Buf, u32)> {
-        self.add_new_block(SegSumEntry::IndirectMap { inum }, ctx)
+        self.add_new_block(SegSumEntry::IndirectMap { inum, block_no }, ctx)
     }
 
     /// Checks if the block for the given `entry` already exists on the segment.
@@ -450,9 +468,10 @@ impl SegManager {
     pub fn get_or_add_updated_indirect_block(
         &mut self,
         inum: u32,
+        block_no: u32,
         ctx: &KernelCtx<'_, '_>,
     ) -> Option<(Buf, u32)> {
-        self.get_or_add_updated_block(SegSumEntry::IndirectMap { inum }, ctx)
+        self.get_or_add_updated_block(SegSumEntry::IndirectMap { inum, block_no }, ctx)
     }
 
     /// Provides a block on the segment to be used to store the updated imap block.
@@ -472,7 +491,10 @@ impl SegManager {
     /// Commits the segment to the disk and allocates a new segment if necessary.
     /// If `alloc` is `true`, always allocates a new segment, unless the segment is empty.
     /// Run this when you need to empty the segment or before committing the checkpoint.
-    pub fn commit(&mut self, alloc: bool, ctx: &KernelCtx<'_, '_>) {
+    ///
+    /// Returns Err(ENOSPC) if a new segment was needed but there is no empty one.
+    /// The blocks are committed even then, and the current segment is kept if it has room.
+    pub fn commit(&mut self, alloc: bool, ctx: &KernelCtx<'_, '_>) -> Result<(), Errno> {
         const_assert!(core::mem::size_of::<DSegSum>() <= BSIZE);
 
         let len = self.segment.len();
@@ -502,8 +524,25 @@ impl SegManager {
 
         // Allocate a new segment if we need to.
         if alloc || self.start >= SEGSIZE - 1 {
-            self.alloc_segment(Some(self.segment_no));
+            if let Err(err) = self.alloc_segment(Some(self.segment_no)) {
+                self.start = cmp::min(self.start, SEGSIZE - 1);
+                return Err(err);
+            }
             self.start = 0;
         }
+        Ok(())
+    }
+
+    /// Commits the segment if it is full.
+    ///
+    /// # Panic
+    ///
+    /// Panics if there is no empty segment. Use this only for the blocks that
+    /// `TxManager::begin_op` or the cleaner has reserved.
+    pub fn commit_if_full(&mut self, ctx: &KernelCtx<'_, '_>) {
+        if self.is_full() {
+            self.commit(true, ctx)
+                .expect("commit_if_full: no empty segment");
+        }
     }
 }
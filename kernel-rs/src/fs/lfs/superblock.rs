@@ -4,6 +4,7 @@ use static_assertions::const_assert;
 
 use crate::{
     bio::{Buf, BufData},
+    fs::longdir::LONGNAMES,
     param::SEGSIZE,
 };
 
@@ -41,6 +42,9 @@ pub struct Superblock {
 
     // Block number of first segment
     segstart: u32,
+
+    /// Optional features of the file system, such as `LONGNAMES`
+    features: u32,
 }
 
 impl<'s> TryFrom<&'s BufData> for &'s Superblock {
@@ -79,6 +83,11 @@ impl Superblock {
         self.nsegments
     }
 
+    /// Returns whether the directories have entries of variable length.
+    pub fn long_names(&self) -> bool {
+        self.features & LONGNAMES != 0
+    }
+
     /// Translates (segment number, segment block number) -> disk block number.
     pub fn seg_to_disk_block_no(&self, seg_no: u32, seg_block_no: u32) -> u32 {
         seg_no
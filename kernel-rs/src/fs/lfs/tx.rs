@@ -132,7 +132,9 @@ impl SleepableLock<TxManager> {
                 // Do checkpointing if necessary.
                 if seg.blocks_written() >= last_blocks_written + CHECKPOINTING_THRES {
                     last_blocks_written = seg.blocks_written();
-                    seg.commit(false, ctx);
+                    // Without an empty segment, the segment stays full until the
+                    // cleaner frees one, and `begin_op` waits for it.
+                    let _ = seg.commit(false, ctx);
 
                     // SAFETY: there is no another transaction, so `Imap` is not mutated.
                     let imap = unsafe { &*fs.imap_raw() };
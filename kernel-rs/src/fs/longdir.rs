@@ -0,0 +1,253 @@
+//! Directories of variable-length entries, which have names of up to NAME_MAX bytes and
+//! 32-bit inode numbers.
+//!
+//! The Ufs and the Lfs use this format instead of fixed-size `Dirent`s if the superblock
+//! has the `LONGNAMES` feature. An entry is a `Header` followed by its name, without a
+//! NUL, and its length is a multiple of 4. Entries never cross a block: the `reclen` of
+//! an entry also covers the unused space after it, so the last entry of a block reaches
+//! the end of the block. An entry whose `inum` is 0 is unused, which only the first entry
+//! of a block can be.
+
+use core::{cmp, mem};
+
+use zerocopy::{AsBytes, FromBytes};
+
+use super::{DirEntry, FileName, FileSystem, InodeGuard, InodeType, Tx, NAME_MAX};
+use crate::{errno::Errno, param::BSIZE, proc::KernelCtx};
+
+/// The superblock feature of directories of variable-length entries.
+pub const LONGNAMES: u32 = 0x1;
+
+#[repr(C)]
+#[derive(Default, AsBytes, FromBytes)]
+struct Header {
+    inum: u32,
+    reclen: u16,
+    namelen: u8,
+    _pad: u8,
+}
+
+const HEADER_SIZE: usize = mem::size_of::<Header>();
+
+/// Returns the length of an entry whose name is `namelen` bytes long.
+const fn entry_len(namelen: usize) -> usize {
+    (HEADER_SIZE + namelen + 3) & !3
+}
+
+/// An entry at the offset `off`.
+struct Entry {
+    off: u64,
+    header: Header,
+}
+
+impl Entry {
+    /// Returns the length that the entry uses, excluding the unused space after it.
+    fn used(&self) -> u64 {
+        if self.header.inum == 0 {
+            0
+        } else {
+            entry_len(self.header.namelen as usize) as u64
+        }
+    }
+
+    /// Returns the offset of the next entry.
+    fn end(&self) -> u64 {
+        self.off + u64::from(self.header.reclen)
+    }
+}
+
+/// Returns the offset of the block that holds the offset `off`.
+fn block_start(off: u64) -> u64 {
+    off / BSIZE as u64 * BSIZE as u64
+}
+
+fn read_entry<FS: FileSystem>(
+    dp: &mut InodeGuard<'_, FS>,
+    off: u64,
+    ctx: &KernelCtx<'_, '_>,
+) -> Entry {
+    let mut header = Header::default();
+    dp.read_kernel(&mut header, off, ctx)
+        .expect("read_entry: read_kernel");
+    let used = entry_len(header.namelen as usize) as u64;
+    let end = off + u64::from(header.reclen);
+    assert!(
+        u64::from(header.reclen) >= used && end <= block_start(off) + BSIZE as u64,
+        "read_entry: bad reclen"
+    );
+    Entry { off, header }
+}
+
+/// Reads the name of `entry` into `buf`, and returns it.
+fn read_name<'b, FS: FileSystem>(
+    dp: &mut InodeGuard<'_, FS>,
+    entry: &Entry,
+    buf: &'b mut [u8; NAME_MAX],
+    ctx: &KernelCtx<'_, '_>,
+) -> &'b [u8] {
+    let len = entry.header.namelen as usize;
+    let n = dp.read_bytes_kernel(&mut buf[..len], entry.off + HEADER_SIZE as u64, ctx);
+    &buf[..n]
+}
+
+fn write_entry<FS: FileSystem>(
+    dp: &mut InodeGuard<'_, FS>,
+    off: u64,
+    reclen: u64,
+    name: &FileName<NAME_MAX>,
+    inum: u32,
+    tx: &Tx<'_, FS>,
+    ctx: &KernelCtx<'_, '_>,
+) -> Result<(), Errno> {
+    let name = name.as_bytes();
+    let header = Header {
+        inum,
+        reclen: reclen as u16,
+        namelen: name.len() as u8,
+        _pad: 0,
+    };
+    dp.write_kernel(&header, off, tx, ctx)?;
+    let _ = dp.write_bytes_kernel(name, off + HEADER_SIZE as u64, tx, ctx)?;
+    Ok(())
+}
+
+/// Looks for the entry `name` in the directory `dp`.
+/// If found, returns the inode number and the offset of the entry.
+pub fn lookup<FS: FileSystem>(
+    dp: &mut InodeGuard<'_, FS>,
+    name: &FileName<NAME_MAX>,
+    ctx: &KernelCtx<'_, '_>,
+) -> Option<(u32, u64)> {
+    let mut buf = [0; NAME_MAX];
+    let mut off = 0;
+    while off < FS::inode_size(dp) {
+        let entry = read_entry(dp, off, ctx);
+        if entry.header.inum != 0
+            && entry.header.namelen as usize == name.as_bytes().len()
+            && read_name(dp, &entry, &mut buf, ctx) == name.as_bytes()
+        {
+            return Some((entry.header.inum, off));
+        }
+        off = entry.end();
+    }
+    None
+}
+
+/// Returns the first used entry of the directory `dp` at the offset `off` or after it,
+/// and the offset right after it. The type of the returned entry is `InodeType::None`.
+pub fn next<FS: FileSystem>(
+    dp: &mut InodeGuard<'_, FS>,
+    off: u64,
+    ctx: &KernelCtx<'_, '_>,
+) -> Option<(DirEntry, u64)> {
+    let mut buf = [0; NAME_MAX];
+    // `off` may be in the middle of an entry, so walk from the start of its block.
+    let mut cur = block_start(off);
+    while cur < FS::inode_size(dp) {
+        let entry = read_entry(dp, cur, ctx);
+        cur = entry.end();
+        if entry.off >= off && entry.header.inum != 0 {
+            let name = read_name(dp, &entry, &mut buf, ctx);
+            return Some((DirEntry::new(entry.header.inum, InodeType::None, name), cur));
+        }
+    }
+    None
+}
+
+/// Writes a new entry (name, inum) into the directory `dp`, which must not have `name`.
+pub fn link<FS: FileSystem>(
+    dp: &mut InodeGuard<'_, FS>,
+    name: &FileName<NAME_MAX>,
+    inum: u32,
+    tx: &Tx<'_, FS>,
+    ctx: &KernelCtx<'_, '_>,
+) -> Result<(), Errno> {
+    let len = entry_len(name.as_bytes().len()) as u64;
+    let size = FS::inode_size(dp);
+
+    // Look for an entry with enough unused space after it, and split it.
+    let mut off = 0;
+    while off < size {
+        let mut entry = read_entry(dp, off, ctx);
+        let used = entry.used();
+        if u64::from(entry.header.reclen) - used >= len {
+            let reclen = u64::from(entry.header.reclen) - used;
+            if used != 0 {
+                entry.header.reclen = used as u16;
+                dp.write_kernel(&entry.header, off, tx, ctx)?;
+            }
+            return write_entry(dp, off + used, reclen, name, inum, tx, ctx);
+        }
+        off = entry.end();
+    }
+
+    // Add a block, whose only entry reaches its end.
+    write_entry(dp, size, BSIZE as u64, name, inum, tx, ctx)?;
+    let zeros = [0; 64];
+    let mut off = size + (HEADER_SIZE + name.as_bytes().len()) as u64;
+    while off < size + BSIZE as u64 {
+        let n = cmp::min(zeros.len(), (size + BSIZE as u64 - off) as usize);
+        off += dp.write_bytes_kernel(&zeros[..n], off, tx, ctx)? as u64;
+    }
+    Ok(())
+}
+
+/// Removes the entry at the offset `off` of the directory `dp`.
+pub fn unlink<FS: FileSystem>(
+    dp: &mut InodeGuard<'_, FS>,
+    off: u64,
+    tx: &Tx<'_, FS>,
+    ctx: &KernelCtx<'_, '_>,
+) -> Result<(), Errno> {
+    let mut prev = None;
+    let mut cur = block_start(off);
+    while cur < off {
+        let entry = read_entry(dp, cur, ctx);
+        cur = entry.end();
+        prev = Some(entry);
+    }
+    assert_eq!(cur, off, "unlink: not an entry");
+
+    let mut entry = read_entry(dp, off, ctx);
+    match prev {
+        // Give the space to the previous entry of the block.
+        Some(mut prev) => {
+            prev.header.reclen += entry.header.reclen;
+            dp.write_kernel(&prev.header, prev.off, tx, ctx)
+        }
+        None => {
+            entry.header.inum = 0;
+            dp.write_kernel(&entry.header, off, tx, ctx)
+        }
+    }
+}
+
+/// Makes the entry at the offset `off` of the directory `dp` refer to the inode `inum`.
+pub fn relink<FS: FileSystem>(
+    dp: &mut InodeGuard<'_, FS>,
+    off: u64,
+    inum: u32,
+    tx: &Tx<'_, FS>,
+    ctx: &KernelCtx<'_, '_>,
+) -> Result<(), Errno> {
+    let mut entry = read_entry(dp, off, ctx);
+    entry.header.inum = inum;
+    dp.write_kernel(&entry.header, off, tx, ctx)
+}
+
+/// Is the directory `dp` empty except for "." and ".." ?
+pub fn is_empty<FS: FileSystem>(dp: &mut InodeGuard<'_, FS>, ctx: &KernelCtx<'_, '_>) -> bool {
+    let mut buf = [0; NAME_MAX];
+    let mut off = 0;
+    while off < FS::inode_size(dp) {
+        let entry = read_entry(dp, off, ctx);
+        if entry.header.inum != 0 {
+            let name = read_name(dp, &entry, &mut buf, ctx);
+            if name != b"." && name != b".." {
+                return false;
+            }
+        }
+        off = entry.end();
+    }
+    true
+}
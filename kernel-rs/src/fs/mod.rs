@@ -8,41 +8,81 @@ use zerocopy::{AsBytes, FromBytes};
 use crate::{
     addr::UVAddr,
     arena::{ArenaObject, ArenaRc, ArrayArena},
+    errno::Errno,
     lock::SleepLock,
     param::NINODE,
     proc::KernelCtx,
+    time::Timespec,
     util::strong_pin::StrongPin,
 };
 
+mod devfs;
+mod direntry;
+mod lfs;
+mod longdir;
 mod path;
+mod procfs;
 mod stat;
+mod tmpfs;
+mod ufs;
+mod vfs;
 
+pub use devfs::Devfs;
+pub use direntry::{DirEntry, MAXRECLEN};
+pub use lfs::Lfs;
 pub use path::{FileName, Path};
+pub use procfs::Procfs;
 pub use stat::Stat;
+pub use tmpfs::Tmpfs;
+pub use ufs::Ufs;
+pub(crate) use vfs::dispatch;
+pub use vfs::{AnyTx, Vfs, VfsTx, Vnode};
 
-// The default file system. Ufs or Lfs
+// The file system of the root disk. Ufs or Lfs
 cfg_if! {
     if #[cfg(feature = "lfs")] {
         pub type DefaultFs = Lfs;
-        mod lfs;
-        pub use lfs::Lfs;
     } else {
         pub type DefaultFs = Ufs;
-        mod ufs;
-        pub use ufs::Ufs;
     }
 }
 
+/// Maximum length of a file name.
+/// A directory is a file containing a sequence of entries, each of which has a name.
+pub const NAME_MAX: usize = 255;
+
+/// Length of the name of a fixed-size directory entry. Longer names are truncated in
+/// such directories.
+pub const DIRSIZ: usize = 14;
+
 bitflags! {
     pub struct FcntlFlags: i32 {
         const O_RDONLY = 0;
         const O_WRONLY = 0x1;
         const O_RDWR = 0x2;
+        const O_NONBLOCK = 0x4;
+        const O_APPEND = 0x8;
         const O_CREATE = 0x200;
         const O_TRUNC = 0x400;
+        const O_NOFOLLOW = 0x800;
+        const O_EXCL = 0x1000;
+        const O_CLOEXEC = 0x2000;
+    }
+}
+
+bitflags! {
+    /// Kinds of access to an inode, as the permission bits of its mode for each class of
+    /// users: the owner, the group of the owner, and the others.
+    pub struct Access: u16 {
+        const EXEC = 0o1;
+        const WRITE = 0o2;
+        const READ = 0o4;
     }
 }
 
+/// The user id of the superuser, who may access any inode.
+pub const ROOT_UID: u32 = 0;
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 #[repr(i16)]
 pub enum InodeType {
@@ -50,6 +90,7 @@ pub enum InodeType {
     Dir,
     File,
     Device { major: u16, minor: u16 },
+    Symlink,
 }
 
 #[derive(Copy, Clone, PartialEq, Debug)]
@@ -59,6 +100,19 @@ pub enum DInodeType {
     Dir,
     File,
     Device,
+    Symlink,
+}
+
+impl InodeType {
+    /// Returns the permission bits of a new inode of the type.
+    pub fn default_mode(self) -> u16 {
+        match self {
+            InodeType::Dir => 0o755,
+            InodeType::Device { .. } => 0o666,
+            InodeType::Symlink => 0o777,
+            InodeType::None | InodeType::File => 0o644,
+        }
+    }
 }
 
 /// InodeGuard implies that `SleepLock<InodeInner>` is held by current thread.
@@ -103,18 +157,18 @@ impl<FS: FileSystem> InodeGuard<'_, FS> {
     }
 
     /// Copy data into `dst` from the content of inode at offset `off`.
-    /// Return Ok(()) on success, Err(()) on failure.
+    /// Return Ok(()) on success, Err(EIO) if the inode ends before `dst` is filled.
     pub fn read_kernel<T: AsBytes + FromBytes>(
         &mut self,
         dst: &mut T,
-        off: u32,
+        off: u64,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), Errno> {
         let bytes = self.read_bytes_kernel(dst.as_bytes_mut(), off, ctx);
         if bytes == mem::size_of::<T>() {
             Ok(())
         } else {
-            Err(())
+            Err(Errno::EIO)
         }
     }
 
@@ -123,7 +177,7 @@ impl<FS: FileSystem> InodeGuard<'_, FS> {
     pub fn read_bytes_kernel(
         &mut self,
         dst: &mut [u8],
-        off: u32,
+        off: u64,
         ctx: &KernelCtx<'_, '_>,
     ) -> usize {
         FS::inode_read(
@@ -136,59 +190,56 @@ impl<FS: FileSystem> InodeGuard<'_, FS> {
             },
             ctx,
         )
-        .expect("read: should never fail")
+        // Copying never fails, but the procfs may fail to generate the content.
+        .unwrap_or(0)
     }
 
     /// Copy data into virtual address `dst` of the current process by `n` bytes
     /// from the content of inode at offset `off`.
-    /// Returns Ok(number of bytes copied) on success, Err(()) on failure due to
+    /// Returns Ok(number of bytes copied) on success, Err(EFAULT) on failure due to
     /// accessing an invalid virtual address.
     pub fn read_user(
         &mut self,
         dst: UVAddr,
-        off: u32,
+        off: u64,
         n: u32,
         ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, Errno> {
         FS::inode_read(
             self,
             off,
             n,
-            |off, src, ctx| {
-                ctx.proc_mut()
-                    .memory_mut()
-                    .copy_out_bytes(dst + off as usize, src)
-            },
+            |off, src, ctx| ctx.proc().memory().copy_out_bytes(dst + off as usize, src),
             ctx,
         )
     }
 
     /// Copy data from `src` into the inode at offset `off`.
-    /// Return Ok(()) on success, Err(()) on failure.
+    /// Return Ok(()) on success, Err(errno) on failure.
     pub fn write_kernel<T: AsBytes>(
         &mut self,
         src: &T,
-        off: u32,
+        off: u64,
         tx: &Tx<'_, FS>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), Errno> {
         let bytes = self.write_bytes_kernel(src.as_bytes(), off, tx, ctx)?;
         if bytes == mem::size_of::<T>() {
             Ok(())
         } else {
-            Err(())
+            Err(Errno::EIO)
         }
     }
 
     /// Copy data from `src` into the inode at offset `off`.
-    /// Returns Ok(number of bytes copied) on success, Err(()) on failure.
+    /// Returns Ok(number of bytes copied) on success, Err(errno) on failure.
     pub fn write_bytes_kernel(
         &mut self,
         src: &[u8],
-        off: u32,
+        off: u64,
         tx: &Tx<'_, FS>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, Errno> {
         FS::inode_write(
             self,
             off,
@@ -204,24 +255,20 @@ impl<FS: FileSystem> InodeGuard<'_, FS> {
 
     /// Copy data from virtual address `src` of the current process by `n` bytes
     /// into the inode at offset `off`.
-    /// Returns Ok(number of bytes copied) on success, Err(()) on failure.
+    /// Returns Ok(number of bytes copied) on success, Err(errno) on failure.
     pub fn write_user(
         &mut self,
         src: UVAddr,
-        off: u32,
+        off: u64,
         n: u32,
         ctx: &mut KernelCtx<'_, '_>,
         tx: &Tx<'_, FS>,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, Errno> {
         FS::inode_write(
             self,
             off,
             n,
-            |off, dst, ctx| {
-                ctx.proc_mut()
-                    .memory_mut()
-                    .copy_in_bytes(dst, src + off as usize)
-            },
+            |off, dst, ctx| ctx.proc().memory().copy_in_bytes(dst, src + off as usize),
             tx,
             ctx,
         )
@@ -234,6 +281,52 @@ impl<FS: FileSystem> InodeGuard<'_, FS> {
     }
 }
 
+impl<FS: DirFileSystem> InodeGuard<'_, FS> {
+    /// Look for a directory entry in a directory.
+    /// If found, return the entry and byte offset of entry.
+    pub fn dirlookup(
+        &mut self,
+        name: &FileName<NAME_MAX>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(RcInode<FS>, u64), Errno> {
+        FS::dirlookup(self, name, ctx)
+    }
+
+    /// Write a new directory entry (name, inum) into the directory dp.
+    pub fn dirlink(
+        &mut self,
+        name: &FileName<NAME_MAX>,
+        inum: u32,
+        tx: &Tx<'_, FS>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        FS::dirlink(self, name, inum, tx, ctx)
+    }
+
+    /// Make the directory entry `name` refer to the inode `inum` instead.
+    /// Returns Err(ENOENT) if there is no such entry.
+    pub fn dirrelink(
+        &mut self,
+        name: &FileName<NAME_MAX>,
+        inum: u32,
+        tx: &Tx<'_, FS>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        FS::dirrelink(self, name, inum, tx, ctx)
+    }
+
+    /// Remove the directory entry at the offset `off`, which `dirlookup` returned.
+    pub fn dirunlink(&mut self, off: u64, tx: &Tx<'_, FS>, ctx: &KernelCtx<'_, '_>) {
+        FS::dirunlink(self, off, tx, ctx)
+    }
+
+    /// Is the directory dp empty except for "." and ".." ?
+    #[allow(clippy::wrong_self_convention)] // for invariant reads
+    pub fn is_dir_empty(&mut self, ctx: &KernelCtx<'_, '_>) -> bool {
+        FS::is_dir_empty(self, ctx)
+    }
+}
+
 impl<FS: FileSystem> Inode<FS> {
     #[inline]
     pub fn lock(&self, ctx: &KernelCtx<'_, '_>) -> InodeGuard<'_, FS> {
@@ -244,6 +337,46 @@ impl<FS: FileSystem> Inode<FS> {
     pub fn stat(&self, ctx: &KernelCtx<'_, '_>) -> Stat {
         FS::inode_stat(self, ctx)
     }
+
+    /// Returns the parent of the directory, which is the directory itself for the root.
+    fn parent(&self, ctx: &KernelCtx<'_, '_>) -> RcInode<FS> {
+        let mut guard = self.lock(ctx);
+        // SAFETY: b".." does not contain any NUL characters, and is short enough.
+        let parent = FS::lookup(
+            &mut guard,
+            unsafe { FileName::from_bytes_unchecked(b"..") },
+            ctx,
+        );
+        guard.free(ctx);
+        parent.expect("parent: no ..")
+    }
+
+    /// Walks up from the directory toward the root, and returns the inode number of the
+    /// directory right below the directory `anc` on the way if `anc` is a proper ancestor.
+    /// Must not be called while holding the lock of a directory on the way.
+    pub fn below_ancestor(
+        &self,
+        anc: u32,
+        tx: &Tx<'_, FS>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Option<u32> {
+        let mut child = self.inum;
+        let mut parent = self.parent(ctx);
+        let ret = loop {
+            if parent.inum == child {
+                break None;
+            }
+            if parent.inum == anc {
+                break Some(child);
+            }
+            let next = parent.parent(ctx);
+            child = parent.inum;
+            parent.free((tx, ctx));
+            parent = next;
+        };
+        parent.free((tx, ctx));
+        ret
+    }
 }
 
 impl<FS: FileSystem> ArenaObject for Inode<FS> {
@@ -313,70 +446,103 @@ pub trait FileSystem: 'static + Sized {
     type Dirent;
     type InodeInner: 'static + Unpin + Send + Sized;
 
-    /// Initializes the file system (loading from the disk).
-    fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>);
+    /// The name of the file system, with which `mount` chooses it.
+    const NAME: &'static str;
 
-    /// Finds the root inode.
-    fn root(self: StrongPin<'_, Self>) -> RcInode<Self>;
+    /// Returns the file system of this type in `vfs`.
+    fn get(vfs: StrongPin<'_, Vfs>) -> StrongPin<'_, Self>;
 
-    /// Finds inode from the given path.
-    fn namei(
-        self: StrongPin<'_, Self>,
-        path: &Path,
-        tx: &Tx<'_, Self>,
+    /// Wraps an inode of this file system in a `Vnode`.
+    fn vnode(inode: RcInode<Self>) -> Vnode;
+
+    /// Wraps a transaction of this file system in an `AnyTx`.
+    fn any_tx(tx: Tx<'_, Self>) -> AnyTx<'_>;
+
+    /// Returns `tx` if it is a transaction of this file system.
+    fn tx<'a, 's>(tx: &'a AnyTx<'s>) -> Option<&'a Tx<'s, Self>>;
+
+    /// Returns the table of in-memory inodes.
+    #[allow(clippy::needless_lifetimes)]
+    fn itable<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Itable<Self>>;
+
+    /// Returns the device that `mount` puts the file system on, given the device `dev`
+    /// that the user asked for.
+    /// Returns Err(ENXIO) if there is no such device.
+    fn mount_dev(dev: u32) -> Result<u32, Errno>;
+
+    /// Initializes the file system on the disk `dev` (loading from the disk).
+    /// Does nothing if already initialized on `dev`.
+    /// Returns Err(EBUSY) if already initialized on another disk.
+    fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>) -> Result<(), Errno>;
+
+    /// Finds the root inode of the file system on the disk `dev`.
+    fn root(self: StrongPin<'_, Self>, dev: u32) -> RcInode<Self>;
+
+    /// Looks for the directory entry `name` in the directory `dp`.
+    /// Returns Ok(the inode of the entry) on success, Err(ENOENT) if there is none.
+    fn lookup(
+        dp: &mut InodeGuard<'_, Self>,
+        name: &FileName<NAME_MAX>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcInode<Self>, Errno>;
+
+    /// Reads the first entry of the directory `dp` at the offset `off` or after it.
+    /// Returns Ok(Some((the entry, the offset right after it))) on success, Ok(None) if
+    /// there are no more entries.
+    fn readdir(
+        dp: &mut InodeGuard<'_, Self>,
+        off: u64,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<RcInode<Self>, ()>;
+    ) -> Result<Option<(DirEntry, u64)>, Errno>;
 
-    /// Create another name(newname) for the file oldname.
-    /// Returns Ok(()) on success, Err(()) on error.
+    /// Create another name `name` in the directory `dp` for the file `inode`.
+    /// Returns Ok(()) on success, Err(errno) on error.
     fn link(
         self: StrongPin<'_, Self>,
-        inode: RcInode<Self>,
-        path: &Path,
+        dp: &RcInode<Self>,
+        name: &FileName<NAME_MAX>,
+        inode: &RcInode<Self>,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()>;
+    ) -> Result<(), Errno>;
 
-    /// Remove a file(filename).
-    /// Returns Ok(()) on success, Err(()) on error.
+    /// Remove the directory entry `name` from the directory `dp`.
+    /// Returns Ok(()) on success, Err(errno) on error.
     fn unlink(
         self: StrongPin<'_, Self>,
-        path: &Path,
+        dp: &RcInode<Self>,
+        name: &FileName<NAME_MAX>,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()>;
-
-    /// Create an inode with given type.
-    /// Returns Ok(created inode, result of given function f) on success, Err(()) on error.
-    fn create<F, T>(
+    ) -> Result<(), Errno>;
+
+    /// Moves the directory entry `name` of the directory `dp` to the entry `newname` of the
+    /// directory `newdp`, replacing the inode that `newname` refers to, if any.
+    /// A directory can replace only an empty directory, and cannot move into itself or
+    /// its subdirectory.
+    /// Returns Ok(()) on success, Err(errno) on error.
+    fn rename(
         self: StrongPin<'_, Self>,
-        path: &Path,
-        typ: InodeType,
+        dp: &RcInode<Self>,
+        name: &FileName<NAME_MAX>,
+        newdp: &RcInode<Self>,
+        newname: &FileName<NAME_MAX>,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
-        f: F,
-    ) -> Result<(RcInode<Self>, T), ()>
-    where
-        F: FnOnce(&mut InodeGuard<'_, Self>) -> T;
+    ) -> Result<(), Errno>;
 
-    /// Open a file; omode indicate read/write.
-    /// Returns Ok(file descriptor) on success, Err(()) on error.
-    fn open(
+    /// Create an inode with given type, named `name` in the directory `dp`.
+    /// If `typ` is `File`, `excl` is false, and a file named `name` exists, returns it instead.
+    /// Returns Ok(the inode) on success, Err(errno) on error.
+    fn create(
         self: StrongPin<'_, Self>,
-        path: &Path,
-        omode: FcntlFlags,
-        tx: &Tx<'_, Self>,
-        ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<usize, ()>;
-
-    /// Change the current directory.
-    /// Returns Ok(()) on success, Err(()) on error.
-    fn chdir(
-        self: StrongPin<'_, Self>,
-        inode: RcInode<Self>,
+        dp: &RcInode<Self>,
+        name: &FileName<NAME_MAX>,
+        typ: InodeType,
+        excl: bool,
         tx: &Tx<'_, Self>,
-        ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<(), ()>;
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcInode<Self>, Errno>;
 
     /// Begins a transaction.
     ///
@@ -408,31 +574,18 @@ pub trait FileSystem: 'static + Sized {
         'id,
         's,
         K: Deref<Target = KernelCtx<'id, 's>>,
-        F: FnMut(u32, &[u8], &mut K) -> Result<(), ()>,
+        F: FnMut(u32, &[u8], &mut K) -> Result<(), Errno>,
     >(
         guard: &mut InodeGuard<'_, Self>,
-        off: u32,
+        off: u64,
         n: u32,
         f: F,
         k: K,
-    ) -> Result<usize, ()>;
+    ) -> Result<usize, Errno>;
 
     /// Write data to inode. Returns the number of bytes successfully written.
     /// If the return value is less than the requested n, there was an error of
-    /// some kind.
-    ///
-    /// `f` takes an offset and a slice as arguments. `f(off, dst)` should copy
-    /// the content beginning at the `off`th byte of the source, which the
-    /// caller of this method knows, to `dst`.
-    // This method takes a function as an argument, because reading kernel
-    // memory and user memory are very different from each other. Reading a
-    // consecutive region in kernel memory can be done at once by simple memcpy.
-    // However, reading user memory needs page table accesses since a single
-    // consecutive region in user memory may split into several pages in
-    // physical memory.
-    /// Write data to inode. Returns the number of bytes successfully written.
-    /// If the return value is less than the requested n, there was an error of
-    /// some kind.
+    /// some kind. Returns the error of `f` if nothing is written.
     ///
     /// `f` takes an offset and a slice as arguments. `f(off, dst)` should copy
     /// the content beginning at the `off`th byte of the source, which the
@@ -447,15 +600,15 @@ pub trait FileSystem: 'static + Sized {
         'id,
         's,
         K: Deref<Target = KernelCtx<'id, 's>>,
-        F: FnMut(u32, &mut [u8], &mut K) -> Result<(), ()>,
+        F: FnMut(u32, &mut [u8], &mut K) -> Result<(), Errno>,
     >(
         guard: &mut InodeGuard<'_, Self>,
-        off: u32,
+        off: u64,
         n: u32,
         f: F,
         tx: &Tx<'_, Self>,
         k: K,
-    ) -> Result<usize, ()>;
+    ) -> Result<usize, Errno>;
 
     /// Truncate inode (discard contents).
     /// This function is called with Inode's lock is held.
@@ -478,8 +631,223 @@ pub trait FileSystem: 'static + Sized {
         ctx: &'a KernelCtx<'id, 'a>,
     );
 
+    /// Returns the type of the locked inode.
+    fn inode_type(guard: &InodeGuard<'_, Self>) -> InodeType;
+
+    /// Returns the size of the locked inode in bytes.
+    fn inode_size(guard: &InodeGuard<'_, Self>) -> u64;
+
     /// Copy stat information from inode.
     fn inode_stat(inode: &Inode<Self>, ctx: &KernelCtx<'_, '_>) -> Stat;
+
+    /// Sets the last access and modification times of the locked inode to `atime` and
+    /// `mtime`, leaving a `None` one unchanged, and its last status change time to now.
+    /// Returns Ok(()) on success, Err(errno) on error.
+    fn inode_set_times(
+        guard: &mut InodeGuard<'_, Self>,
+        atime: Option<Timespec>,
+        mtime: Option<Timespec>,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno>;
+
+    /// Sets the permission bits of the locked inode to `mode`, and its last status change
+    /// time to now.
+    /// Returns Ok(()) on success, Err(errno) on error.
+    fn inode_set_mode(
+        guard: &mut InodeGuard<'_, Self>,
+        mode: u16,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno>;
+
+    /// Sets the owner and its group of the locked inode to `uid` and `gid`, leaving a
+    /// `None` one unchanged, and its last status change time to now.
+    /// Returns Ok(()) on success, Err(errno) on error.
+    fn inode_set_owner(
+        guard: &mut InodeGuard<'_, Self>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno>;
+}
+
+/// A file system that keeps each directory as a file of entries that refer to inodes by
+/// number, and counts the links to each inode.
+pub trait DirFileSystem: FileSystem {
+    /// Looks for the directory entry `name` in the locked directory `dp`.
+    /// Returns Ok((the inode of the entry, the offset of the entry)) on success, Err(ENOENT)
+    /// if there is none.
+    fn dirlookup(
+        dp: &mut InodeGuard<'_, Self>,
+        name: &FileName<NAME_MAX>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(RcInode<Self>, u64), Errno>;
+
+    /// Writes a new directory entry `name` referring to the inode `inum` into the locked
+    /// directory `dp`.
+    /// Returns Ok(()) on success, Err(EEXIST) if `name` exists, Err(errno) on other errors.
+    fn dirlink(
+        dp: &mut InodeGuard<'_, Self>,
+        name: &FileName<NAME_MAX>,
+        inum: u32,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno>;
+
+    /// Makes the directory entry `name` of the locked directory `dp` refer to the inode
+    /// `inum` instead.
+    /// Returns Ok(()) on success, Err(ENOENT) if there is no such entry.
+    fn dirrelink(
+        dp: &mut InodeGuard<'_, Self>,
+        name: &FileName<NAME_MAX>,
+        inum: u32,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno>;
+
+    /// Removes the directory entry of the locked directory `dp` at the offset `off`, which
+    /// `dirlookup` returned.
+    fn dirunlink(
+        dp: &mut InodeGuard<'_, Self>,
+        off: u64,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    );
+
+    /// Returns whether the locked directory `dp` has no entries other than "." and "..".
+    fn is_dir_empty(dp: &mut InodeGuard<'_, Self>, ctx: &KernelCtx<'_, '_>) -> bool;
+
+    /// Returns the number of links to the locked inode.
+    fn inode_nlink<'a>(guard: &'a mut InodeGuard<'_, Self>) -> &'a mut i16;
+
+    /// Writes the changes to the locked inode back to the file system.
+    fn inode_update(guard: &InodeGuard<'_, Self>, tx: &Tx<'_, Self>, ctx: &KernelCtx<'_, '_>);
+}
+
+/// Moves the entry `name` of `dp` to the entry `newname` of `newdp`, as
+/// `FileSystem::rename` of a `DirFileSystem`.
+fn rename<FS: DirFileSystem>(
+    dp: &RcInode<FS>,
+    name: &FileName<NAME_MAX>,
+    newdp: &RcInode<FS>,
+    newname: &FileName<NAME_MAX>,
+    tx: &Tx<'_, FS>,
+    ctx: &KernelCtx<'_, '_>,
+) -> Result<(), Errno> {
+    // Cannot rename "." or "..", or to them.
+    if [name, newname]
+        .iter()
+        .any(|name| name.as_bytes() == b"." || name.as_bytes() == b"..")
+    {
+        return Err(Errno::EINVAL);
+    }
+
+    // Lock an ancestor before its descendant, as the other operations do.
+    let same = dp.inum == newdp.inum;
+    let below = if same {
+        None
+    } else {
+        newdp.below_ancestor(dp.inum, tx, ctx)
+    };
+    let newdp_first = !same && below.is_none() && dp.below_ancestor(newdp.inum, tx, ctx).is_some();
+    let (first, second) = if newdp_first {
+        (newdp, dp)
+    } else {
+        (dp, newdp)
+    };
+    let first = first.lock(ctx);
+    let mut first = scopeguard::guard(first, |ip| ip.free(ctx));
+    let mut second = (!same).then(|| scopeguard::guard(second.lock(ctx), |ip| ip.free(ctx)));
+    let (dp, newdp) = match second.as_deref_mut() {
+        None => (&mut *first, None),
+        Some(second) if newdp_first => (second, Some(&mut *first)),
+        Some(second) => (&mut *first, Some(second)),
+    };
+    rename_locked(dp, name, newdp, newname, below, tx, ctx)
+}
+
+/// Moves the entry `name` of `dp` to the entry `newname` of `newdp`, or of `dp` if
+/// `newdp` is `None`. If `dp` is a proper ancestor of `newdp`, `below` is the directory
+/// right below `dp` on the way to `newdp`.
+fn rename_locked<'a, FS: DirFileSystem>(
+    dp: &mut InodeGuard<'a, FS>,
+    name: &FileName<NAME_MAX>,
+    mut newdp: Option<&mut InodeGuard<'a, FS>>,
+    newname: &FileName<NAME_MAX>,
+    below: Option<u32>,
+    tx: &Tx<'_, FS>,
+    ctx: &KernelCtx<'_, '_>,
+) -> Result<(), Errno> {
+    let (ptr, off) = dp.dirlookup(name, ctx)?;
+    let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
+
+    // A directory cannot move into itself or its subdirectory, whose lock we may hold.
+    if below == Some(ptr.inum) {
+        return Err(Errno::EINVAL);
+    }
+    let ip = ptr.lock(ctx);
+    let is_dir = FS::inode_type(&ip) == InodeType::Dir;
+    ip.free(ctx);
+
+    let dpinum = dp.inum;
+    let nd = newdp.as_deref_mut().unwrap_or(&mut *dp);
+    match nd.dirlookup(newname, ctx) {
+        Ok((ptr2, _)) => {
+            let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
+            // Both are links to the same inode.
+            if ptr2.inum == ptr.inum {
+                return Ok(());
+            }
+            // `dp`, which we hold, is not empty.
+            if ptr2.inum == dpinum {
+                return Err(if is_dir {
+                    Errno::ENOTEMPTY
+                } else {
+                    Errno::EISDIR
+                });
+            }
+            let ip2 = ptr2.lock(ctx);
+            let mut ip2 = scopeguard::guard(ip2, |ip| ip.free(ctx));
+            let is_dir2 = FS::inode_type(&ip2) == InodeType::Dir;
+            if is_dir && !is_dir2 {
+                return Err(Errno::ENOTDIR);
+            }
+            if !is_dir && is_dir2 {
+                return Err(Errno::EISDIR);
+            }
+            if is_dir2 && !ip2.is_dir_empty(ctx) {
+                return Err(Errno::ENOTEMPTY);
+            }
+
+            nd.dirrelink(newname, ptr.inum, tx, ctx)?;
+            if is_dir2 {
+                *FS::inode_nlink(nd) -= 1;
+                FS::inode_update(nd, tx, ctx);
+            }
+            *FS::inode_nlink(&mut ip2) -= 1;
+            FS::inode_update(&ip2, tx, ctx);
+        }
+        Err(_) => nd.dirlink(newname, ptr.inum, tx, ctx)?,
+    }
+
+    dp.dirunlink(off, tx, ctx);
+
+    // Move ".." of the directory.
+    if let (true, Some(newdp)) = (is_dir, newdp) {
+        *FS::inode_nlink(dp) -= 1;
+        FS::inode_update(dp, tx, ctx);
+        *FS::inode_nlink(newdp) += 1;
+        FS::inode_update(newdp, tx, ctx);
+
+        let ip = ptr.lock(ctx);
+        let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+        // SAFETY: b".." does not contain any NUL characters, and is short enough.
+        let dotdot = unsafe { FileName::from_bytes_unchecked(b"..") };
+        ip.dirrelink(dotdot, newdp.inum, tx, ctx)?;
+    }
+    Ok(())
 }
 
 pub trait FileSystemExt: FileSystem {
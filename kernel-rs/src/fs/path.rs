@@ -2,33 +2,52 @@ use core::cmp;
 
 use cstr_core::CStr;
 
+use crate::errno::Errno;
+
 #[derive(PartialEq)]
 #[repr(transparent)]
 pub struct FileName<const MAXSIZE: usize> {
     // Invariant:
     // - The slice contains no NUL characters.
-    // - The slice is not longer than DIRSIZ.
+    // - The slice is not longer than MAXSIZE.
     inner: [u8],
 }
 
 impl<const MAXSIZE: usize> FileName<{ MAXSIZE }> {
-    /// Truncate bytes followed by the first DIRSIZ bytes.
+    /// Returns Err(ENAMETOOLONG) if `bytes` is longer than MAXSIZE bytes.
     ///
     /// # Safety
     ///
     /// `bytes` must not contain any NUL characters.
-    pub unsafe fn from_bytes(bytes: &[u8]) -> &Self {
-        debug_assert!(!bytes.contains(&0));
+    pub unsafe fn from_bytes(bytes: &[u8]) -> Result<&Self, Errno> {
+        if bytes.len() > MAXSIZE {
+            return Err(Errno::ENAMETOOLONG);
+        }
+        // SAFETY: `bytes` is not longer than MAXSIZE, and contains no NUL characters
+        // because of the safety condition of this method.
+        Ok(unsafe { Self::from_bytes_unchecked(bytes) })
+    }
+
+    /// # Safety
+    ///
+    /// `bytes` must not contain any NUL characters, and must not be longer than MAXSIZE.
+    pub unsafe fn from_bytes_unchecked(bytes: &[u8]) -> &Self {
+        debug_assert!(!bytes.contains(&0) && bytes.len() <= MAXSIZE);
         // SAFETY: `&FileName` is layout-compatible with `[u8]` because of its
         // attribute `#[repr(transparent)]`. Also, the slice satisfies the
-        // invariant of FileName because of the safety condition of this method
-        // and the fact that its length is at most DIRSIZ.
-        unsafe { &*(&bytes[..cmp::min(MAXSIZE, bytes.len())] as *const [u8] as *const Self) }
+        // invariant of FileName because of the safety condition of this method.
+        unsafe { &*(bytes as *const [u8] as *const Self) }
     }
 
     pub fn as_bytes(&self) -> &[u8] {
         &self.inner
     }
+
+    /// Returns the name truncated to its first `M` bytes.
+    pub fn truncate<const M: usize>(&self) -> &FileName<{ M }> {
+        // SAFETY: `self.inner` contains no NUL characters, and the slice is not longer than M.
+        unsafe { FileName::from_bytes_unchecked(&self.inner[..cmp::min(M, self.inner.len())]) }
+    }
 }
 
 #[repr(transparent)]
@@ -66,29 +85,32 @@ impl Path {
     /// The returned path has no leading slashes, so the caller can check path.inner.is_empty() to
     /// see if the name is the last one.
     ///
-    /// If no name to remove, returns `None`.
+    /// If no name to remove, returns `None`. `name` is Err(ENAMETOOLONG) if the next path
+    /// element is longer than MAXSIZE bytes.
     ///
     /// # Examples
     /// ```
     /// # unsafe {
     /// assert_eq!(
     ///     Path::from_bytes(b"a/bb/c").skipelem(),
-    ///     Some((Path::from_bytes(b"bb/c"), b"a")),
+    ///     Some((Path::from_bytes(b"bb/c"), Ok(b"a"))),
     /// );
     /// assert_eq!(
     ///     Path::from_bytes(b"///a//bb").skipelem(),
-    ///     Some((Path::from_bytes(b"bb"), b"a")),
+    ///     Some((Path::from_bytes(b"bb"), Ok(b"a"))),
     /// );
     /// assert_eq!(
     ///     Path::from_bytes(b"a").skipelem(),
-    ///     Some((Path::from_bytes(b""), b"a")),
+    ///     Some((Path::from_bytes(b""), Ok(b"a"))),
     /// );
     /// assert_eq!(Path::from_bytes(b"").skipelem(), None);
     /// assert_eq!(Path::from_bytes(b"////").skipelem(), None);
     /// # }
     /// ```
     // TODO(https://github.com/kaist-cp/rv6/issues/359): Fix doctests work.
-    pub fn skipelem<const MAXSIZE: usize>(&self) -> Option<(&Self, &FileName<{ MAXSIZE }>)> {
+    pub fn skipelem<const MAXSIZE: usize>(
+        &self,
+    ) -> Option<(&Self, Result<&FileName<{ MAXSIZE }>, Errno>)> {
         let mut bytes = &self.inner;
 
         let name_start = bytes.iter().position(|ch| *ch != b'/')?;
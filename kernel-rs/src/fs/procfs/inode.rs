@@ -0,0 +1,152 @@
+//! Inodes of the procfs.
+//!
+//! An inode of the procfs holds nothing but its inode number, which names the entry it
+//! refers to. `Entry` converts between the two.
+
+use core::{cmp, mem};
+
+use zerocopy::{AsBytes, FromBytes};
+
+use super::Procfs;
+use crate::{
+    arena::{Arena, ArrayArena},
+    fs::{Inode, InodeType, Itable, RcInode, DIRSIZ},
+    lock::SleepLock,
+    param::NINODE,
+    util::strong_pin::StrongPin,
+};
+
+/// root i-number
+pub const ROOTINO: u32 = 1;
+
+/// The inode number of the directory of the process 0. The entries of the process `pid`
+/// take the `NPROCENTRY` inode numbers from `PROCBASE + pid * NPROCENTRY`.
+const PROCBASE: u32 = 8;
+const NPROCENTRY: u32 = 4;
+
+/// An entry of the procfs.
+#[derive(Clone, Copy)]
+pub enum Entry {
+    /// `/proc`
+    Root,
+    /// `/proc/meminfo`
+    Meminfo,
+    /// `/proc/bcache`
+    Bcache,
+    /// `/proc/lfs`
+    LfsDir,
+    /// `/proc/lfs/segments`
+    LfsSegments,
+    /// `/proc/<pid>`
+    ProcDir(i32),
+    /// `/proc/<pid>/status`
+    Status(i32),
+    /// `/proc/<pid>/fd`
+    Fd(i32),
+    /// `/proc/<pid>/maps`
+    Maps(i32),
+}
+
+impl Entry {
+    /// Returns the entry that the inode number `inum` refers to.
+    pub fn from_inum(inum: u32) -> Self {
+        match inum {
+            ROOTINO => Entry::Root,
+            2 => Entry::Meminfo,
+            3 => Entry::Bcache,
+            4 => Entry::LfsDir,
+            5 => Entry::LfsSegments,
+            _ => {
+                assert!(inum >= PROCBASE, "Entry::from_inum");
+                let pid = ((inum - PROCBASE) / NPROCENTRY) as i32;
+                match (inum - PROCBASE) % NPROCENTRY {
+                    0 => Entry::ProcDir(pid),
+                    1 => Entry::Status(pid),
+                    2 => Entry::Fd(pid),
+                    _ => Entry::Maps(pid),
+                }
+            }
+        }
+    }
+
+    /// Returns the inode number of the entry.
+    pub fn inum(self) -> u32 {
+        let proc_inum = |pid: i32, i: u32| PROCBASE + pid as u32 * NPROCENTRY + i;
+        match self {
+            Entry::Root => ROOTINO,
+            Entry::Meminfo => 2,
+            Entry::Bcache => 3,
+            Entry::LfsDir => 4,
+            Entry::LfsSegments => 5,
+            Entry::ProcDir(pid) => proc_inum(pid, 0),
+            Entry::Status(pid) => proc_inum(pid, 1),
+            Entry::Fd(pid) => proc_inum(pid, 2),
+            Entry::Maps(pid) => proc_inum(pid, 3),
+        }
+    }
+
+    pub fn typ(self) -> InodeType {
+        match self {
+            Entry::Root | Entry::LfsDir | Entry::ProcDir(_) => InodeType::Dir,
+            _ => InodeType::File,
+        }
+    }
+}
+
+/// dirent size
+pub const DIRENT_SIZE: usize = mem::size_of::<Dirent>();
+
+#[repr(C)]
+#[derive(Default, AsBytes, FromBytes)]
+pub struct Dirent {
+    pub inum: u16,
+    name: [u8; DIRSIZ],
+}
+
+impl Dirent {
+    /// Returns a `Dirent` of `entry` named `name`, which must not be longer than DIRSIZ.
+    pub fn new(name: &[u8], entry: Entry) -> Self {
+        let mut de = Dirent {
+            // The inode number may not fit, but readers only care whether it is 0.
+            inum: cmp::max(entry.inum() as u16, 1),
+            ..Default::default()
+        };
+        de.name[..name.len()].copy_from_slice(name);
+        de
+    }
+}
+
+impl const Default for Inode<Procfs> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Inode<Procfs> {
+    pub const fn new() -> Self {
+        Self {
+            dev: 0,
+            inum: 0,
+            inner: SleepLock::new("inode", ()),
+        }
+    }
+}
+
+impl Itable<Procfs> {
+    pub const fn new_itable() -> Self {
+        ArrayArena::<Inode<Procfs>, NINODE>::new("ITABLE")
+    }
+
+    /// Find the inode with number inum on device dev
+    /// and return the in-memory copy.
+    pub fn get_inode(self: StrongPin<'_, Self>, dev: u32, inum: u32) -> RcInode<Procfs> {
+        self.find_or_alloc(
+            |inode| inode.dev == dev && inode.inum == inum,
+            |inode| {
+                inode.dev = dev;
+                inode.inum = inum;
+            },
+        )
+        .expect("[Itable::get_inode] no inodes")
+    }
+}
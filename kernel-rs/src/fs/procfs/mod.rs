@@ -0,0 +1,436 @@
+//! A synthetic file system that exposes the state of processes and the kernel.
+//!
+//! Nothing is stored in the procfs. The inode number of an inode names an `Entry`, and
+//! each read of the inode generates its content from `Procs`, `Kmem`, the `Bcache`, or
+//! the `SegManager` of the `Lfs` at that moment, into a page from `Kmem`. Hence a file
+//! holds at most a page, and is shown as empty by stat. A directory reads as a sequence
+//! of `Dirent`s, as in the ufs. Nothing can be written, created, or removed.
+//!
+//! * `/proc/meminfo`: the free memory.
+//! * `/proc/bcache`: the number of buffers, how many of them are referenced, and the
+//!   blocks that the others hold, most recently used first.
+//! * `/proc/lfs/segments`: the state of the segments of the lfs, if it has been mounted.
+//! * `/proc/<pid>/status`: the name, state, parent, scheduling and size of a process.
+//! * `/proc/<pid>/fd`: the open files of a process.
+//! * `/proc/<pid>/maps`: the memory areas of a process.
+
+use core::fmt::{self, Write};
+use core::ops::Deref;
+use core::{cmp, mem};
+
+use pin_project::pin_project;
+use zerocopy::AsBytes;
+
+use super::{
+    AnyTx, DirEntry, FileName, FileSystem, Inode, InodeGuard, InodeType, Itable, RcInode, Stat, Tx,
+    Vfs, Vnode, DIRSIZ, NAME_MAX, ROOT_UID,
+};
+use crate::{
+    addr::PGSIZE, errno::Errno, hal::hal, param::PROCFSDEV, proc::KernelCtx, time::Timespec,
+    util::strong_pin::StrongPin,
+};
+
+mod inode;
+
+use inode::{Dirent, Entry, DIRENT_SIZE, ROOTINO};
+
+#[pin_project]
+pub struct Procfs {
+    #[pin]
+    itable: Itable<Self>,
+}
+
+/// A `fmt::Write` into a byte buffer, which drops what does not fit.
+struct BufWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> BufWriter<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        let n = cmp::min(bytes.len(), self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+        self.len += n;
+    }
+}
+
+impl Write for BufWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+impl Procfs {
+    pub const fn new() -> Self {
+        Self {
+            itable: Itable::<Self>::new_itable(),
+        }
+    }
+}
+
+impl Entry {
+    /// Calls `f` with the name and the entry of each entry of the directory `self`.
+    fn for_each_child<F: FnMut(&[u8], Entry)>(self, ctx: &KernelCtx<'_, '_>, mut f: F) {
+        match self {
+            Entry::Root => {
+                f(b".", Entry::Root);
+                f(b"..", Entry::Root);
+                f(b"meminfo", Entry::Meminfo);
+                f(b"bcache", Entry::Bcache);
+                if ctx.kernel().fs().lfs().is_initialized() {
+                    f(b"lfs", Entry::LfsDir);
+                }
+                ctx.kernel().procs().for_each_pid(|pid| {
+                    let mut name = [0; DIRSIZ];
+                    let mut w = BufWriter::new(&mut name);
+                    let _ = write!(w, "{}", pid);
+                    let len = w.len;
+                    f(&name[..len], Entry::ProcDir(pid));
+                });
+            }
+            Entry::LfsDir => {
+                f(b".", self);
+                f(b"..", Entry::Root);
+                f(b"segments", Entry::LfsSegments);
+            }
+            Entry::ProcDir(pid) => {
+                f(b".", self);
+                f(b"..", Entry::Root);
+                f(b"status", Entry::Status(pid));
+                f(b"fd", Entry::Fd(pid));
+                f(b"maps", Entry::Maps(pid));
+            }
+            _ => (),
+        }
+    }
+
+    /// Writes the content of `self` to `w`.
+    /// Returns Err(ESRCH) if the process of `self` has exited.
+    fn generate(self, w: &mut BufWriter<'_>, ctx: &KernelCtx<'_, '_>) -> Result<(), Errno> {
+        match self {
+            Entry::Root | Entry::LfsDir | Entry::ProcDir(_) => {
+                self.for_each_child(ctx, |name, entry| {
+                    w.write_bytes(Dirent::new(name, entry).as_bytes())
+                });
+            }
+            Entry::Meminfo => {
+                let nfree = hal().kmem().nfree();
+                let _ = write!(
+                    w,
+                    "PageSize:\t{}\nFreePages:\t{}\nFree:\t{} kB\n",
+                    PGSIZE,
+                    nfree,
+                    nfree * PGSIZE / 1024
+                );
+            }
+            Entry::Bcache => {
+                let (mut nbuf, mut referenced) = (0, 0);
+                ctx.kernel().bcache().for_each(|refcnt, _| {
+                    nbuf += 1;
+                    if refcnt > 0 {
+                        referenced += 1;
+                    }
+                });
+                let _ = write!(w, "Buffers:\t{}\nReferenced:\t{}\n", nbuf, referenced);
+                ctx.kernel().bcache().for_each(|_, buf| {
+                    match buf {
+                        Some(buf) if buf.dev != 0 => {
+                            let _ = writeln!(w, "{} {}", buf.dev, buf.blockno);
+                        }
+                        _ => (),
+                    }
+                });
+            }
+            Entry::LfsSegments => {
+                let lfs = ctx.kernel().fs().lfs();
+                if !lfs.is_initialized() {
+                    return Err(Errno::ENOENT);
+                }
+                let seg = lfs.segmanager(ctx);
+                let _ = write!(
+                    w,
+                    "Segments:\t{}\nFree:\t{}\nCurrent:\t{}\nRemaining:\t{}\nWritten:\t{}\n",
+                    seg.nsegments(),
+                    seg.nfree(),
+                    seg.segment_no(),
+                    seg.remaining(),
+                    seg.blocks_written()
+                );
+                // One character per segment, '#' if used and '.' if free, 64 in a line.
+                for seg_no in 0..seg.nsegments() {
+                    let _ = w.write_char(if seg.segtable_is_free(seg_no) {
+                        '.'
+                    } else {
+                        '#'
+                    });
+                    if seg_no % 64 == 63 || seg_no + 1 == seg.nsegments() {
+                        let _ = w.write_char('\n');
+                    }
+                }
+                seg.free(ctx);
+            }
+            Entry::Status(pid) => ctx.kernel().procs().write_status(pid, w)?,
+            Entry::Fd(pid) => ctx.kernel().procs().write_fds(pid, w)?,
+            Entry::Maps(pid) => ctx.kernel().procs().write_maps(pid, w)?,
+        }
+        Ok(())
+    }
+}
+
+impl FileSystem for Procfs {
+    type Dirent = Dirent;
+    type InodeInner = ();
+
+    const NAME: &'static str = "procfs";
+
+    fn get(vfs: StrongPin<'_, Vfs>) -> StrongPin<'_, Self> {
+        vfs.procfs()
+    }
+
+    fn vnode(inode: RcInode<Self>) -> Vnode {
+        Vnode::Procfs(inode)
+    }
+
+    fn any_tx(tx: Tx<'_, Self>) -> AnyTx<'_> {
+        AnyTx::Procfs(tx)
+    }
+
+    fn tx<'a, 's>(tx: &'a AnyTx<'s>) -> Option<&'a Tx<'s, Self>> {
+        match tx {
+            AnyTx::Procfs(tx) => Some(tx),
+            _ => None,
+        }
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn itable<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Itable<Self>> {
+        unsafe { StrongPin::new_unchecked(&self.as_pin().get_ref().itable) }
+    }
+
+    fn mount_dev(_dev: u32) -> Result<u32, Errno> {
+        Ok(PROCFSDEV)
+    }
+
+    fn init(&self, _dev: u32, _ctx: &KernelCtx<'_, '_>) -> Result<(), Errno> {
+        Ok(())
+    }
+
+    fn root(self: StrongPin<'_, Self>, dev: u32) -> RcInode<Self> {
+        self.itable().get_inode(dev, ROOTINO)
+    }
+
+    fn lookup(
+        dp: &mut InodeGuard<'_, Self>,
+        name: &FileName<NAME_MAX>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcInode<Self>, Errno> {
+        let mut found = None;
+        Entry::from_inum(dp.inum).for_each_child(ctx, |child, entry| {
+            if found.is_none() && child == name.as_bytes() {
+                found = Some(entry);
+            }
+        });
+        let entry = found.ok_or(Errno::ENOENT)?;
+        Ok(ctx
+            .kernel()
+            .fs()
+            .procfs()
+            .itable()
+            .get_inode(dp.dev, entry.inum()))
+    }
+
+    fn readdir(
+        dp: &mut InodeGuard<'_, Self>,
+        off: u64,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Option<(DirEntry, u64)>, Errno> {
+        // The `i`th entry is at the offset `i * DIRENT_SIZE`, as in a read.
+        let index = (off as usize + DIRENT_SIZE - 1) / DIRENT_SIZE;
+        let mut found = None;
+        let mut i = 0;
+        Entry::from_inum(dp.inum).for_each_child(ctx, |name, entry| {
+            if i == index {
+                found = Some(DirEntry::new(entry.inum(), entry.typ(), name));
+            }
+            i += 1;
+        });
+        Ok(found.map(|entry| (entry, ((index + 1) * DIRENT_SIZE) as u64)))
+    }
+
+    fn link(
+        self: StrongPin<'_, Self>,
+        _dp: &RcInode<Self>,
+        _name: &FileName<NAME_MAX>,
+        _inode: &RcInode<Self>,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        Err(Errno::EACCES)
+    }
+
+    fn unlink(
+        self: StrongPin<'_, Self>,
+        _dp: &RcInode<Self>,
+        _name: &FileName<NAME_MAX>,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        Err(Errno::EACCES)
+    }
+
+    fn rename(
+        self: StrongPin<'_, Self>,
+        _dp: &RcInode<Self>,
+        _name: &FileName<NAME_MAX>,
+        _newdp: &RcInode<Self>,
+        _newname: &FileName<NAME_MAX>,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        Err(Errno::EACCES)
+    }
+
+    fn create(
+        self: StrongPin<'_, Self>,
+        _dp: &RcInode<Self>,
+        _name: &FileName<NAME_MAX>,
+        _typ: InodeType,
+        _excl: bool,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcInode<Self>, Errno> {
+        Err(Errno::EACCES)
+    }
+
+    fn tx_begin(&self, _ctx: &KernelCtx<'_, '_>) {}
+
+    unsafe fn tx_end(&self, _tx: &mut Tx<'_, Self>, _ctx: &KernelCtx<'_, '_>) {}
+
+    fn inode_read<
+        'id,
+        's,
+        K: Deref<Target = KernelCtx<'id, 's>>,
+        F: FnMut(u32, &[u8], &mut K) -> Result<(), Errno>,
+    >(
+        guard: &mut InodeGuard<'_, Self>,
+        off: u64,
+        n: u32,
+        mut f: F,
+        mut k: K,
+    ) -> Result<usize, Errno> {
+        let mut page = hal().kmem().alloc(None).ok_or(Errno::ENOMEM)?;
+        let mut w = BufWriter::new(&mut page[..]);
+        let res = Entry::from_inum(guard.inum).generate(&mut w, &k);
+        let len = w.len;
+        let res = res.and_then(|_| {
+            let begin = cmp::min(off as usize, len);
+            let end = cmp::min(begin + n as usize, len);
+            if begin < end {
+                f(0, &page[begin..end], &mut k)?;
+            }
+            Ok(end - begin)
+        });
+        hal().kmem().free(page);
+        res
+    }
+
+    fn inode_write<
+        'id,
+        's,
+        K: Deref<Target = KernelCtx<'id, 's>>,
+        F: FnMut(u32, &mut [u8], &mut K) -> Result<(), Errno>,
+    >(
+        _guard: &mut InodeGuard<'_, Self>,
+        _off: u64,
+        _n: u32,
+        _f: F,
+        _tx: &Tx<'_, Self>,
+        _k: K,
+    ) -> Result<usize, Errno> {
+        Err(Errno::EACCES)
+    }
+
+    fn inode_trunc(
+        _guard: &mut InodeGuard<'_, Self>,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) {
+    }
+
+    fn inode_lock<'a>(inode: &'a Inode<Self>, ctx: &KernelCtx<'_, '_>) -> InodeGuard<'a, Self> {
+        mem::forget(inode.inner.lock(ctx));
+        InodeGuard { inode }
+    }
+
+    fn inode_finalize<'a, 'id: 'a>(
+        _inode: &mut Inode<Self>,
+        _tx: &'a Tx<'a, Self>,
+        _ctx: &'a KernelCtx<'id, 'a>,
+    ) {
+    }
+
+    fn inode_type(guard: &InodeGuard<'_, Self>) -> InodeType {
+        Entry::from_inum(guard.inum).typ()
+    }
+
+    fn inode_size(_guard: &InodeGuard<'_, Self>) -> u64 {
+        0
+    }
+
+    fn inode_stat(inode: &Inode<Self>, _ctx: &KernelCtx<'_, '_>) -> Stat {
+        Stat {
+            dev: inode.dev as i32,
+            ino: inode.inum,
+            typ: match Entry::from_inum(inode.inum).typ() {
+                InodeType::Dir => 1,
+                _ => 2,
+            },
+            nlink: 1,
+            mode: match Entry::from_inum(inode.inum).typ() {
+                InodeType::Dir => 0o555,
+                _ => 0o444,
+            },
+            _padding: 0,
+            uid: ROOT_UID,
+            gid: 0,
+            size: 0,
+            atime: Timespec::ZERO,
+            mtime: Timespec::ZERO,
+            ctime: Timespec::ZERO,
+        }
+    }
+
+    fn inode_set_times(
+        _guard: &mut InodeGuard<'_, Self>,
+        _atime: Option<Timespec>,
+        _mtime: Option<Timespec>,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        Err(Errno::EACCES)
+    }
+
+    fn inode_set_mode(
+        _guard: &mut InodeGuard<'_, Self>,
+        _mode: u16,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        Err(Errno::EACCES)
+    }
+
+    fn inode_set_owner(
+        _guard: &mut InodeGuard<'_, Self>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        Err(Errno::EACCES)
+    }
+}
@@ -1,5 +1,7 @@
 use zerocopy::AsBytes;
 
+use crate::time::Timespec;
+
 #[derive(Copy, Clone, AsBytes)]
 #[repr(C)]
 pub struct Stat {
@@ -15,9 +17,27 @@ pub struct Stat {
     /// Number of links to file
     pub nlink: i16,
 
+    /// Permission bits
+    pub mode: u16,
+
     /// Padding for safetly serializing the struct
-    pub _padding: u32,
+    pub _padding: u16,
+
+    /// Owner's user id
+    pub uid: u32,
+
+    /// Owner's group id
+    pub gid: u32,
 
     /// Size of file in bytes
-    pub size: usize,
+    pub size: u64,
+
+    /// Time of last access
+    pub atime: Timespec,
+
+    /// Time of last modification
+    pub mtime: Timespec,
+
+    /// Time of last status change
+    pub ctime: Timespec,
 }
@@ -0,0 +1,415 @@
+//! Inodes of the tmpfs.
+//!
+//! An inode of the tmpfs lives in `Tmpfs::nodes` as a `Node`, just as an inode of the
+//! ufs lives on the disk as a `Dinode`. Inode::lock() copies the `Node` into the
+//! in-memory inode, and InodeGuard::update() copies it back. The content of a file is
+//! kept in pages from `Kmem`, whose addresses the `Node` holds.
+
+use core::{iter::StepBy, mem, ops::Range};
+
+use zerocopy::{AsBytes, FromBytes};
+
+use super::{FileName, Tmpfs, NPAGE};
+use crate::{
+    addr::PGSIZE,
+    arena::{Arena, ArrayArena},
+    errno::Errno,
+    fs::{DirFileSystem, FileSystem, Inode, InodeGuard, InodeType, Itable, RcInode, Tx, NAME_MAX},
+    hal::hal,
+    lock::SleepLock,
+    page::Page,
+    param::{NINODE, NTMPINODE},
+    proc::KernelCtx,
+    time::Timespec,
+    util::strong_pin::StrongPin,
+};
+
+/// dirent size
+pub const DIRENT_SIZE: usize = mem::size_of::<Dirent>();
+
+pub struct InodeInner {
+    /// inode has been copied from `Tmpfs::nodes`?
+    pub valid: bool,
+    /// copy of `Node`
+    pub typ: InodeType,
+    pub nlink: i16,
+    pub size: u64,
+    pub atime: Timespec,
+    pub mtime: Timespec,
+    pub ctime: Timespec,
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u16,
+    pub pages: [usize; NPAGE],
+}
+
+/// An inode kept in memory while no one refers to it.
+#[derive(Clone, Copy)]
+pub struct Node {
+    /// File type, or `InodeType::None` if the node is free.
+    pub typ: InodeType,
+
+    /// Number of links to inode in file system
+    pub nlink: i16,
+
+    /// Size of file (bytes)
+    pub size: u64,
+
+    /// Times of last access, modification, and status change
+    pub atime: Timespec,
+    pub mtime: Timespec,
+    pub ctime: Timespec,
+
+    /// Owner's user and group ids, and permission bits
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u16,
+
+    /// Physical addresses of the pages that hold the content, or 0 if not allocated.
+    pub pages: [usize; NPAGE],
+}
+
+impl Node {
+    pub const fn new() -> Self {
+        Self {
+            typ: InodeType::None,
+            nlink: 0,
+            size: 0,
+            atime: Timespec::ZERO,
+            mtime: Timespec::ZERO,
+            ctime: Timespec::ZERO,
+            uid: 0,
+            gid: 0,
+            mode: 0,
+            pages: [0; NPAGE],
+        }
+    }
+}
+
+/// A directory entry of the tmpfs. A directory is an array of them, kept in its
+/// pages like the content of a file. Unlike the disk file systems, the tmpfs keeps
+/// no compact layout, so an entry holds a whole name of up to `NAME_MAX` bytes.
+#[repr(C)]
+#[derive(AsBytes, FromBytes)]
+pub struct Dirent {
+    /// The node of the entry, or 0 if the entry is free.
+    pub inum: u32,
+    name: [u8; NAME_MAX + 1],
+}
+
+impl Default for Dirent {
+    fn default() -> Self {
+        Self {
+            inum: 0,
+            name: [0; NAME_MAX + 1],
+        }
+    }
+}
+
+impl Dirent {
+    fn new(
+        ip: &mut InodeGuard<'_, Tmpfs>,
+        off: u64,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Dirent, Errno> {
+        let mut dirent = Dirent::default();
+        ip.read_kernel(&mut dirent, off, ctx)?;
+        Ok(dirent)
+    }
+
+    /// Fill in name, followed by NUL character as terminator.
+    ///
+    /// `name` must not contain NUL characters, but this is not a safety invariant.
+    pub fn set_name(&mut self, name: &FileName<{ NAME_MAX }>) {
+        let name = name.as_bytes();
+        self.name[..name.len()].copy_from_slice(name);
+        self.name[name.len()] = 0;
+    }
+
+    /// Returns slice which exactly contains `name`.
+    ///
+    /// It contains no NUL characters.
+    pub fn get_name(&self) -> &FileName<{ NAME_MAX }> {
+        let len = self.name.iter().position(|ch| *ch == 0).unwrap_or(NAME_MAX);
+        // SAFETY: self.name[..len] doesn't contain '\0', and len must be <= NAME_MAX.
+        unsafe { FileName::from_bytes_unchecked(&self.name[..len]) }
+    }
+}
+
+struct DirentIter<'id, 's, 't> {
+    guard: &'s mut InodeGuard<'t, Tmpfs>,
+    iter: StepBy<Range<u64>>,
+    ctx: &'s KernelCtx<'id, 's>,
+}
+
+impl Iterator for DirentIter<'_, '_, '_> {
+    type Item = (Dirent, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let off = self.iter.next()?;
+        let dirent = Dirent::new(self.guard, off, self.ctx).expect("DirentIter");
+        Some((dirent, off))
+    }
+}
+
+impl<'t> InodeGuard<'t, Tmpfs> {
+    fn iter_dirents<'id, 's>(&'s mut self, ctx: &'s KernelCtx<'id, 's>) -> DirentIter<'id, 's, 't> {
+        self.iter_dirents_from(0, ctx)
+    }
+
+    /// Iterates the `Dirent`s from the offset `off`, which is rounded up to a `Dirent`.
+    fn iter_dirents_from<'id, 's>(
+        &'s mut self,
+        off: u64,
+        ctx: &'s KernelCtx<'id, 's>,
+    ) -> DirentIter<'id, 's, 't> {
+        let off = (off + DIRENT_SIZE as u64 - 1) / DIRENT_SIZE as u64 * DIRENT_SIZE as u64;
+        let iter = (off..self.deref_inner().size).step_by(DIRENT_SIZE);
+        DirentIter {
+            guard: self,
+            iter,
+            ctx,
+        }
+    }
+}
+
+// Directories
+impl InodeGuard<'_, Tmpfs> {
+    /// Returns the first used directory entry at the offset `off` or after it, and the
+    /// offset right after it.
+    pub fn next_dirent(&mut self, off: u64, ctx: &KernelCtx<'_, '_>) -> Option<(Dirent, u64)> {
+        self.iter_dirents_from(off, ctx)
+            .find(|(de, _)| de.inum != 0)
+            .map(|(de, off)| (de, off + DIRENT_SIZE as u64))
+    }
+}
+
+impl DirFileSystem for Tmpfs {
+    fn dirlookup(
+        dp: &mut InodeGuard<'_, Self>,
+        name: &FileName<NAME_MAX>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(RcInode<Self>, u64), Errno> {
+        assert_eq!(dp.deref_inner().typ, InodeType::Dir, "dirlookup not DIR");
+
+        dp.iter_dirents(ctx)
+            .find(|(de, _)| de.inum != 0 && de.get_name() == name)
+            .map(|(de, off)| {
+                (
+                    ctx.kernel()
+                        .fs()
+                        .tmpfs()
+                        .itable()
+                        .get_inode(dp.dev, de.inum),
+                    off,
+                )
+            })
+            .ok_or(Errno::ENOENT)
+    }
+
+    fn dirlink(
+        dp: &mut InodeGuard<'_, Self>,
+        name: &FileName<NAME_MAX>,
+        inum: u32,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        // Check that name is not present.
+        if let Ok((ip, _)) = dp.dirlookup(name, ctx) {
+            ip.free((tx, ctx));
+            return Err(Errno::EEXIST);
+        };
+
+        // Look for an empty Dirent.
+        let (mut de, off) = dp
+            .iter_dirents(ctx)
+            .find(|(de, _)| de.inum == 0)
+            .unwrap_or((Default::default(), dp.deref_inner().size));
+        de.inum = inum;
+        de.set_name(name);
+        // Unlike a disk, the memory may run out.
+        dp.write_kernel(&de, off, tx, ctx)
+    }
+
+    fn dirrelink(
+        dp: &mut InodeGuard<'_, Self>,
+        name: &FileName<NAME_MAX>,
+        inum: u32,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        let (ip, off) = dp.dirlookup(name, ctx)?;
+        ip.free((tx, ctx));
+        let mut de = Dirent {
+            inum,
+            ..Default::default()
+        };
+        de.set_name(name);
+        dp.write_kernel(&de, off, tx, ctx)
+    }
+
+    fn dirunlink(
+        dp: &mut InodeGuard<'_, Self>,
+        off: u64,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) {
+        // Clearing an existing entry does not allocate any memory.
+        dp.write_kernel(&Dirent::default(), off, tx, ctx)
+            .expect("dirunlink");
+    }
+
+    fn is_dir_empty(dp: &mut InodeGuard<'_, Self>, ctx: &KernelCtx<'_, '_>) -> bool {
+        let mut de: Dirent = Default::default();
+        for off in (2 * DIRENT_SIZE as u64..dp.deref_inner().size).step_by(DIRENT_SIZE) {
+            dp.read_kernel(&mut de, off, ctx)
+                .expect("is_dir_empty: read_kernel");
+            if de.inum != 0 {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn inode_nlink<'a>(guard: &'a mut InodeGuard<'_, Self>) -> &'a mut i16 {
+        &mut guard.deref_inner_mut().nlink
+    }
+
+    fn inode_update(guard: &InodeGuard<'_, Self>, tx: &Tx<'_, Self>, _ctx: &KernelCtx<'_, '_>) {
+        guard.update(tx);
+    }
+}
+
+impl InodeGuard<'_, Tmpfs> {
+    /// Copy a modified in-memory inode to `Tmpfs::nodes`.
+    /// Must be called after every change to an ip->xxx field
+    /// that lives in the `Node`.
+    pub fn update(&self, tx: &Tx<'_, Tmpfs>) {
+        let inner = self.deref_inner();
+        tx.fs.nodes.lock()[self.inum as usize] = Node {
+            typ: inner.typ,
+            nlink: inner.nlink,
+            size: inner.size,
+            atime: inner.atime,
+            mtime: inner.mtime,
+            ctime: inner.ctime,
+            uid: inner.uid,
+            gid: inner.gid,
+            mode: inner.mode,
+            pages: inner.pages,
+        };
+    }
+
+    /// Returns the content of the `pn`th page of the inode, or None if it is not allocated.
+    pub fn page(&self, pn: usize) -> Option<&[u8; PGSIZE]> {
+        let pa = self.deref_inner().pages[pn];
+        // SAFETY: pa is a page of this inode, and it is not written while the inode is
+        // locked, except through `page_or_alloc`, which takes `&mut self`.
+        (pa != 0).then(|| unsafe { &*(pa as *const [u8; PGSIZE]) })
+    }
+
+    /// Returns the content of the `pn`th page of the inode.
+    /// If there is no such page, allocates one. Returns None if the memory runs out.
+    pub fn page_or_alloc(&mut self, pn: usize) -> Option<&mut [u8; PGSIZE]> {
+        let mut pa = self.deref_inner().pages[pn];
+        if pa == 0 {
+            pa = hal().kmem().alloc(Some(0))?.into_usize();
+            self.deref_inner_mut().pages[pn] = pa;
+        }
+        // SAFETY: pa is a page of this inode, and &mut self is exclusive.
+        Some(unsafe { &mut *(pa as *mut [u8; PGSIZE]) })
+    }
+
+    /// Free the pages of the inode.
+    pub fn free_pages(&mut self) {
+        for pa in &mut self.deref_inner_mut().pages {
+            if *pa != 0 {
+                // SAFETY: pa is a page allocated by `page_or_alloc`, and no one refers to it.
+                hal().kmem().free(unsafe { Page::from_usize(*pa) });
+                *pa = 0;
+            }
+        }
+    }
+}
+
+impl const Default for Inode<Tmpfs> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Inode<Tmpfs> {
+    pub const fn new() -> Self {
+        Self {
+            dev: 0,
+            inum: 0,
+            inner: SleepLock::new(
+                "inode",
+                InodeInner {
+                    valid: false,
+                    typ: InodeType::None,
+                    nlink: 0,
+                    size: 0,
+                    atime: Timespec::ZERO,
+                    mtime: Timespec::ZERO,
+                    ctime: Timespec::ZERO,
+                    uid: 0,
+                    gid: 0,
+                    mode: 0,
+                    pages: [0; NPAGE],
+                },
+            ),
+        }
+    }
+}
+
+impl Itable<Tmpfs> {
+    pub const fn new_itable() -> Self {
+        ArrayArena::<Inode<Tmpfs>, NINODE>::new("ITABLE")
+    }
+
+    /// Find the inode with number inum on device dev
+    /// and return the in-memory copy. Does not lock
+    /// the inode and does not copy it from `Tmpfs::nodes`.
+    pub fn get_inode(self: StrongPin<'_, Self>, dev: u32, inum: u32) -> RcInode<Tmpfs> {
+        self.find_or_alloc(
+            |inode| inode.dev == dev && inode.inum == inum,
+            |inode| {
+                inode.dev = dev;
+                inode.inum = inum;
+                inode.inner.get_mut().valid = false;
+            },
+        )
+        .expect("[Itable::get_inode] no inodes")
+    }
+
+    /// Allocate an inode on device dev.
+    /// Mark it as allocated by giving it type.
+    /// Returns Ok(an unlocked but allocated and referenced inode) on success,
+    /// Err(ENOSPC) if there is no free node.
+    pub fn alloc_inode(
+        self: StrongPin<'_, Self>,
+        dev: u32,
+        typ: InodeType,
+        tx: &Tx<'_, Tmpfs>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcInode<Tmpfs>, Errno> {
+        let mut nodes = tx.fs.nodes.lock();
+        let inum = (1..NTMPINODE)
+            .find(|&inum| nodes[inum].typ == InodeType::None)
+            .ok_or(Errno::ENOSPC)?;
+        let now = Timespec::now();
+        nodes[inum] = Node {
+            typ,
+            atime: now,
+            mtime: now,
+            ctime: now,
+            uid: ctx.proc().creds().uid,
+            gid: ctx.proc().creds().gid,
+            mode: typ.default_mode(),
+            ..Node::new()
+        };
+        drop(nodes);
+        Ok(self.get_inode(dev, inum as u32))
+    }
+}
@@ -0,0 +1,534 @@
+//! An in-memory file system.
+//!
+//! The tmpfs keeps its inodes in `Tmpfs::nodes` and the content of its files in pages
+//! from `Kmem`, instead of on a disk through the `Bcache`. As there is nothing to
+//! commit, a transaction does nothing. Everything is lost when the machine shuts down.
+
+use core::ops::Deref;
+use core::{cmp, mem};
+
+use pin_project::pin_project;
+use zerocopy::AsBytes;
+
+use super::{
+    AnyTx, DirEntry, FileName, FileSystem, Inode, InodeGuard, InodeType, Itable, RcInode, Stat, Tx,
+    Vfs, Vnode, NAME_MAX,
+};
+use crate::{
+    addr::PGSIZE,
+    errno::Errno,
+    hal::hal,
+    lock::SpinLock,
+    param::{NTMPINODE, TMPFSDEV},
+    proc::KernelCtx,
+    time::Timespec,
+    util::strong_pin::StrongPin,
+};
+
+mod inode;
+
+use inode::{Dirent, InodeInner, Node, DIRENT_SIZE};
+
+/// root i-number
+const ROOTINO: u32 = 1;
+
+/// Maximum number of pages of a file.
+const NPAGE: usize = 64;
+const MAXFILE: usize = NPAGE * PGSIZE;
+
+#[pin_project]
+pub struct Tmpfs {
+    /// The inodes, indexed by the inode number.
+    nodes: SpinLock<[Node; NTMPINODE]>,
+    #[pin]
+    itable: Itable<Self>,
+}
+
+impl Tmpfs {
+    pub const fn new() -> Self {
+        Self {
+            nodes: SpinLock::new("tmpfs", [Node::new(); NTMPINODE]),
+            itable: Itable::<Self>::new_itable(),
+        }
+    }
+}
+
+impl FileSystem for Tmpfs {
+    type Dirent = Dirent;
+    type InodeInner = InodeInner;
+
+    const NAME: &'static str = "tmpfs";
+
+    fn get(vfs: StrongPin<'_, Vfs>) -> StrongPin<'_, Self> {
+        vfs.tmpfs()
+    }
+
+    fn vnode(inode: RcInode<Self>) -> Vnode {
+        Vnode::Tmpfs(inode)
+    }
+
+    fn any_tx(tx: Tx<'_, Self>) -> AnyTx<'_> {
+        AnyTx::Tmpfs(tx)
+    }
+
+    fn tx<'a, 's>(tx: &'a AnyTx<'s>) -> Option<&'a Tx<'s, Self>> {
+        match tx {
+            AnyTx::Tmpfs(tx) => Some(tx),
+            _ => None,
+        }
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn itable<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Itable<Self>> {
+        unsafe { StrongPin::new_unchecked(&self.as_pin().get_ref().itable) }
+    }
+
+    fn mount_dev(_dev: u32) -> Result<u32, Errno> {
+        Ok(TMPFSDEV)
+    }
+
+    fn init(&self, _dev: u32, _ctx: &KernelCtx<'_, '_>) -> Result<(), Errno> {
+        let mut nodes = self.nodes.lock();
+        if nodes[ROOTINO as usize].typ == InodeType::None {
+            // Create the root directory, with . and .. entries.
+            let mut page = hal().kmem().alloc(Some(0)).ok_or(Errno::ENOMEM)?;
+            // SAFETY: b"." and b".." do not contain any NUL characters, and are short enough.
+            let dots: [&FileName<NAME_MAX>; 2] = unsafe {
+                [
+                    FileName::from_bytes_unchecked(b"."),
+                    FileName::from_bytes_unchecked(b".."),
+                ]
+            };
+            for (i, name) in dots.iter().enumerate() {
+                let mut de = Dirent::default();
+                de.inum = ROOTINO;
+                de.set_name(name);
+                page[i * DIRENT_SIZE..(i + 1) * DIRENT_SIZE].copy_from_slice(de.as_bytes());
+            }
+            let now = Timespec::now();
+            let mut root = Node {
+                typ: InodeType::Dir,
+                nlink: 1,
+                size: (2 * DIRENT_SIZE) as u64,
+                atime: now,
+                mtime: now,
+                ctime: now,
+                mode: InodeType::Dir.default_mode(),
+                ..Node::new()
+            };
+            root.pages[0] = page.into_usize();
+            nodes[ROOTINO as usize] = root;
+        }
+        Ok(())
+    }
+
+    fn root(self: StrongPin<'_, Self>, dev: u32) -> RcInode<Self> {
+        self.itable().get_inode(dev, ROOTINO)
+    }
+
+    fn lookup(
+        dp: &mut InodeGuard<'_, Self>,
+        name: &FileName<NAME_MAX>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcInode<Self>, Errno> {
+        Ok(dp.dirlookup(name, ctx)?.0)
+    }
+
+    fn readdir(
+        dp: &mut InodeGuard<'_, Self>,
+        off: u64,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Option<(DirEntry, u64)>, Errno> {
+        Ok(dp.next_dirent(off, ctx).map(|(de, off)| {
+            let inum = de.inum;
+            let typ = ctx.kernel().fs().tmpfs().nodes.lock()[inum as usize].typ;
+            (DirEntry::new(inum, typ, de.get_name().as_bytes()), off)
+        }))
+    }
+
+    fn link(
+        self: StrongPin<'_, Self>,
+        dp: &RcInode<Self>,
+        name: &FileName<NAME_MAX>,
+        inode: &RcInode<Self>,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        let ip = inode.lock(ctx);
+        let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+        if ip.deref_inner().typ == InodeType::Dir {
+            return Err(Errno::EPERM);
+        }
+        ip.deref_inner_mut().nlink += 1;
+        ip.deref_inner_mut().ctime = Timespec::now();
+        ip.update(tx);
+        drop(ip);
+
+        let dp = dp.lock(ctx);
+        let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
+        let err = if dp.dev != inode.dev {
+            Errno::EXDEV
+        } else {
+            match dp.dirlink(name, inode.inum, tx, ctx) {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            }
+        };
+        drop(dp);
+
+        let ip = inode.lock(ctx);
+        let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+        ip.deref_inner_mut().nlink -= 1;
+        ip.update(tx);
+        Err(err)
+    }
+
+    fn unlink(
+        self: StrongPin<'_, Self>,
+        dp: &RcInode<Self>,
+        name: &FileName<NAME_MAX>,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        let dp = dp.lock(ctx);
+        let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
+
+        // Cannot unlink "." or "..".
+        if name.as_bytes() == b"." || name.as_bytes() == b".." {
+            return Err(Errno::EINVAL);
+        }
+
+        let (ptr2, off) = dp.dirlookup(name, ctx)?;
+        let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
+        let ip = ptr2.lock(ctx);
+        let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+        assert!(ip.deref_inner().nlink >= 1, "unlink: nlink < 1");
+
+        if ip.deref_inner().typ == InodeType::Dir && !ip.is_dir_empty(ctx) {
+            return Err(Errno::ENOTEMPTY);
+        }
+
+        dp.dirunlink(off, tx, ctx);
+        if ip.deref_inner().typ == InodeType::Dir {
+            dp.deref_inner_mut().nlink -= 1;
+            dp.update(tx);
+        }
+        drop(dp);
+        ip.deref_inner_mut().nlink -= 1;
+        ip.deref_inner_mut().ctime = Timespec::now();
+        ip.update(tx);
+        Ok(())
+    }
+
+    fn rename(
+        self: StrongPin<'_, Self>,
+        dp: &RcInode<Self>,
+        name: &FileName<NAME_MAX>,
+        newdp: &RcInode<Self>,
+        newname: &FileName<NAME_MAX>,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        super::rename(dp, name, newdp, newname, tx, ctx)
+    }
+
+    fn create(
+        self: StrongPin<'_, Self>,
+        dp: &RcInode<Self>,
+        name: &FileName<NAME_MAX>,
+        typ: InodeType,
+        excl: bool,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcInode<Self>, Errno> {
+        let dp = dp.lock(ctx);
+        let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
+        if let Ok((ptr2, _)) = dp.dirlookup(name, ctx) {
+            let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
+            drop(dp);
+            if typ != InodeType::File || excl {
+                return Err(Errno::EEXIST);
+            }
+            let ip = ptr2.lock(ctx);
+            let ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+            match ip.deref_inner().typ {
+                InodeType::None => return Err(Errno::ENOENT),
+                InodeType::Dir => return Err(Errno::EISDIR),
+                _ => (),
+            }
+            drop(ip);
+            return Ok(scopeguard::ScopeGuard::into_inner(ptr2));
+        }
+        let ptr2 = self.itable().alloc_inode(dp.dev, typ, tx, ctx)?;
+        let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
+        let ip = ptr2.lock(ctx);
+        let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+        ip.deref_inner_mut().nlink = 1;
+        ip.update(tx);
+
+        let inum = ip.inum;
+        let res = if typ == InodeType::Dir {
+            // Create . and .. entries.
+            // No ip->nlink++ for ".": avoid cyclic ref count.
+            // SAFETY: b"." and b".." do not contain any NUL characters, and are short enough.
+            let (dot, dotdot) = unsafe {
+                (
+                    FileName::from_bytes_unchecked(b"."),
+                    FileName::from_bytes_unchecked(b".."),
+                )
+            };
+            ip.dirlink(dot, inum, tx, ctx)
+                .and_then(|_| ip.dirlink(dotdot, dp.inum, tx, ctx))
+        } else {
+            Ok(())
+        }
+        .and_then(|_| dp.dirlink(name, inum, tx, ctx));
+        if let Err(err) = res {
+            // The inode is freed when its last reference is dropped.
+            ip.deref_inner_mut().nlink = 0;
+            ip.update(tx);
+            return Err(err);
+        }
+        if typ == InodeType::Dir {
+            // for ".."
+            dp.deref_inner_mut().nlink += 1;
+            dp.update(tx);
+        }
+        drop(ip);
+        Ok(scopeguard::ScopeGuard::into_inner(ptr2))
+    }
+
+    fn tx_begin(&self, _ctx: &KernelCtx<'_, '_>) {}
+
+    unsafe fn tx_end(&self, _tx: &mut Tx<'_, Self>, _ctx: &KernelCtx<'_, '_>) {}
+
+    #[inline]
+    fn inode_read<
+        'id,
+        's,
+        K: Deref<Target = KernelCtx<'id, 's>>,
+        F: FnMut(u32, &[u8], &mut K) -> Result<(), Errno>,
+    >(
+        guard: &mut InodeGuard<'_, Self>,
+        mut off: u64,
+        mut n: u32,
+        mut f: F,
+        mut k: K,
+    ) -> Result<usize, Errno> {
+        let inner = guard.deref_inner();
+        if off > inner.size || off.wrapping_add(u64::from(n)) < off {
+            return Ok(0);
+        }
+        if off + u64::from(n) > inner.size {
+            n = (inner.size - off) as u32;
+        }
+        let mut tot: u32 = 0;
+        while tot < n {
+            let page = guard
+                .page(off as usize / PGSIZE)
+                .expect("inode_read: no page");
+            let m = cmp::min(n - tot, PGSIZE as u32 - (off % PGSIZE as u64) as u32);
+            let begin = (off % PGSIZE as u64) as usize;
+            let end = begin + m as usize;
+            f(tot, &page[begin..end], &mut k)?;
+            tot += m;
+            off += u64::from(m);
+        }
+        Ok(tot as usize)
+    }
+
+    #[inline]
+    fn inode_write<
+        'id,
+        's,
+        K: Deref<Target = KernelCtx<'id, 's>>,
+        F: FnMut(u32, &mut [u8], &mut K) -> Result<(), Errno>,
+    >(
+        guard: &mut InodeGuard<'_, Self>,
+        mut off: u64,
+        n: u32,
+        mut f: F,
+        tx: &Tx<'_, Self>,
+        mut k: K,
+    ) -> Result<usize, Errno> {
+        if off > guard.deref_inner().size {
+            return Err(Errno::EINVAL);
+        }
+        if off.checked_add(u64::from(n)).ok_or(Errno::EFBIG)? > MAXFILE as u64 {
+            return Err(Errno::EFBIG);
+        }
+        let mut tot: u32 = 0;
+        let mut res = Ok(());
+        while tot < n {
+            let page = match guard.page_or_alloc(off as usize / PGSIZE) {
+                Some(page) => page,
+                None => {
+                    res = Err(Errno::ENOMEM);
+                    break;
+                }
+            };
+            let m = cmp::min(n - tot, PGSIZE as u32 - (off % PGSIZE as u64) as u32);
+            let begin = (off % PGSIZE as u64) as usize;
+            let end = begin + m as usize;
+            res = f(tot, &mut page[begin..end], &mut k);
+            if res.is_err() {
+                break;
+            }
+            tot += m;
+            off += u64::from(m);
+        }
+
+        if off > guard.deref_inner().size {
+            guard.deref_inner_mut().size = off;
+        }
+        if tot > 0 {
+            let now = Timespec::now();
+            guard.deref_inner_mut().mtime = now;
+            guard.deref_inner_mut().ctime = now;
+        }
+
+        // Write the i-node back even if the size didn't change
+        // because the loop above might have added a new page.
+        guard.update(tx);
+        if tot == 0 {
+            res?;
+        }
+        Ok(tot as usize)
+    }
+
+    fn inode_trunc(guard: &mut InodeGuard<'_, Self>, tx: &Tx<'_, Self>, _ctx: &KernelCtx<'_, '_>) {
+        guard.free_pages();
+        guard.deref_inner_mut().size = 0;
+        let now = Timespec::now();
+        guard.deref_inner_mut().mtime = now;
+        guard.deref_inner_mut().ctime = now;
+        guard.update(tx);
+    }
+
+    fn inode_lock<'a>(inode: &'a Inode<Self>, ctx: &KernelCtx<'_, '_>) -> InodeGuard<'a, Self> {
+        let mut guard = inode.inner.lock(ctx);
+        if !guard.valid {
+            let node = ctx.kernel().fs().tmpfs().nodes.lock()[inode.inum as usize];
+            guard.typ = node.typ;
+            guard.nlink = node.nlink;
+            guard.size = node.size;
+            guard.atime = node.atime;
+            guard.mtime = node.mtime;
+            guard.ctime = node.ctime;
+            guard.uid = node.uid;
+            guard.gid = node.gid;
+            guard.mode = node.mode;
+            guard.pages = node.pages;
+            guard.valid = true;
+            assert_ne!(guard.typ, InodeType::None, "Inode::lock: no type");
+        };
+        mem::forget(guard);
+        InodeGuard { inode }
+    }
+
+    fn inode_finalize<'a, 'id: 'a>(
+        inode: &mut Inode<Self>,
+        tx: &'a Tx<'a, Self>,
+        ctx: &'a KernelCtx<'id, 'a>,
+    ) {
+        if inode.inner.get_mut().valid && inode.inner.get_mut().nlink == 0 {
+            // inode has no links and no other references: truncate and free.
+
+            // self->ref == 1 means no other process can have self locked,
+            // so this acquiresleep() won't block (or deadlock).
+            let mut ip = inode.lock(ctx);
+
+            ip.trunc(tx, ctx);
+            ip.deref_inner_mut().typ = InodeType::None;
+            ip.update(tx);
+            ip.deref_inner_mut().valid = false;
+
+            ip.free(ctx);
+        }
+    }
+
+    fn inode_type(guard: &InodeGuard<'_, Self>) -> InodeType {
+        guard.deref_inner().typ
+    }
+
+    fn inode_size(guard: &InodeGuard<'_, Self>) -> u64 {
+        guard.deref_inner().size
+    }
+
+    fn inode_stat(inode: &Inode<Self>, ctx: &KernelCtx<'_, '_>) -> Stat {
+        let inner = inode.inner.lock(ctx);
+        let st = Stat {
+            dev: inode.dev as i32,
+            ino: inode.inum,
+            typ: match inner.typ {
+                InodeType::None => 0,
+                InodeType::Dir => 1,
+                InodeType::File => 2,
+                InodeType::Device { .. } => 3,
+                InodeType::Symlink => 4,
+            },
+            nlink: inner.nlink,
+            mode: inner.mode,
+            _padding: 0,
+            uid: inner.uid,
+            gid: inner.gid,
+            size: inner.size,
+            atime: inner.atime,
+            mtime: inner.mtime,
+            ctime: inner.ctime,
+        };
+        inner.free(ctx);
+        st
+    }
+
+    fn inode_set_times(
+        guard: &mut InodeGuard<'_, Self>,
+        atime: Option<Timespec>,
+        mtime: Option<Timespec>,
+        tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        let inner = guard.deref_inner_mut();
+        if let Some(atime) = atime {
+            inner.atime = atime;
+        }
+        if let Some(mtime) = mtime {
+            inner.mtime = mtime;
+        }
+        inner.ctime = Timespec::now();
+        guard.update(tx);
+        Ok(())
+    }
+
+    fn inode_set_mode(
+        guard: &mut InodeGuard<'_, Self>,
+        mode: u16,
+        tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        let inner = guard.deref_inner_mut();
+        inner.mode = mode;
+        inner.ctime = Timespec::now();
+        guard.update(tx);
+        Ok(())
+    }
+
+    fn inode_set_owner(
+        guard: &mut InodeGuard<'_, Self>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        let inner = guard.deref_inner_mut();
+        if let Some(uid) = uid {
+            inner.uid = uid;
+        }
+        if let Some(gid) = gid {
+            inner.gid = gid;
+        }
+        inner.ctime = Timespec::now();
+        guard.update(tx);
+        Ok(())
+    }
+}
@@ -72,22 +72,23 @@ use core::{iter::StepBy, mem, ops::Range};
 use static_assertions::const_assert;
 use zerocopy::{AsBytes, FromBytes};
 
-use super::{FileName, Path, Ufs, IPB, NDIRECT, NINDIRECT, ROOTINO};
+use super::{FileName, Ufs, IPB, NDIRECT, NINDIRECT, NLEVEL};
 use crate::{
     arena::{Arena, ArrayArena},
     bio::BufData,
-    fs::{DInodeType, Inode, InodeGuard, InodeType, Itable, RcInode, Tx},
+    errno::Errno,
+    fs::{
+        longdir, DInodeType, DirEntry, DirFileSystem, FileSystem, Inode, InodeGuard, InodeType,
+        Itable, RcInode, Tx, DIRSIZ, NAME_MAX,
+    },
     hal::hal,
     lock::SleepLock,
     param::NINODE,
-    param::ROOTDEV,
     proc::KernelCtx,
+    time::Timespec,
     util::{memset, strong_pin::StrongPin},
 };
 
-/// Directory is a file containing a sequence of Dirent structures.
-pub const DIRSIZ: usize = 14;
-
 /// dirent size
 pub const DIRENT_SIZE: usize = mem::size_of::<Dirent>();
 
@@ -97,9 +98,15 @@ pub struct InodeInner {
     /// copy of disk inode
     pub typ: InodeType,
     pub nlink: i16,
-    pub size: u32,
+    pub size: u64,
+    pub atime: Timespec,
+    pub mtime: Timespec,
+    pub ctime: Timespec,
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u16,
     pub addr_direct: [u32; NDIRECT],
-    pub addr_indirect: u32,
+    pub addr_indirect: [u32; NLEVEL],
 }
 
 /// On-disk inode structure
@@ -122,13 +129,64 @@ pub struct Dinode {
     pub nlink: i16,
 
     /// Size of file (bytes)
-    pub size: u32,
+    pub size: u64,
+
+    /// Time of last access
+    pub atime: Timespec,
+
+    /// Time of last modification
+    pub mtime: Timespec,
+
+    /// Time of last status change
+    pub ctime: Timespec,
+
+    /// Owner's user id
+    pub uid: u32,
+
+    /// Owner's group id
+    pub gid: u32,
+
+    /// Permission bits
+    pub mode: u16,
+
+    _pad: u16,
 
     /// Direct data block addresses
     pub addr_direct: [u32; NDIRECT],
 
-    /// Indirect data block address
-    pub addr_indirect: u32,
+    /// Single, double, and triple indirect block addresses
+    pub addr_indirect: [u32; NLEVEL],
+}
+
+/// Returns the levels of the indirect blocks that map the `bn`th data block, which must
+/// not be direct, and the index of the data block among the ones that they map.
+fn indirect_level(bn: usize) -> (usize, usize) {
+    let mut bn = bn - NDIRECT;
+    let mut level = 1;
+    while bn >= NINDIRECT.pow(level as u32) {
+        bn -= NINDIRECT.pow(level as u32);
+        level += 1;
+        assert!(level <= NLEVEL, "bmap: out of range");
+    }
+    (level, bn)
+}
+
+impl Dinode {
+    /// Returns the type of the inode, with its device numbers.
+    pub fn inode_type(&self) -> InodeType {
+        match self.typ {
+            DInodeType::None => InodeType::None,
+            DInodeType::Dir => InodeType::Dir,
+            DInodeType::File => InodeType::File,
+            DInodeType::Symlink => InodeType::Symlink,
+            DInodeType::Device => {
+                InodeType::Device {
+                    major: self.major,
+                    minor: self.minor,
+                }
+            }
+        }
+    }
 }
 
 #[repr(C)]
@@ -139,7 +197,11 @@ pub struct Dirent {
 }
 
 impl Dirent {
-    fn new(ip: &mut InodeGuard<'_, Ufs>, off: u32, ctx: &KernelCtx<'_, '_>) -> Result<Dirent, ()> {
+    fn new(
+        ip: &mut InodeGuard<'_, Ufs>,
+        off: u64,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Dirent, Errno> {
         let mut dirent = Dirent::default();
         ip.read_kernel(&mut dirent, off, ctx)?;
         Ok(dirent)
@@ -162,21 +224,21 @@ impl Dirent {
     /// Returns slice which exactly contains `name`.
     ///
     /// It contains no NUL characters.
-    fn get_name(&self) -> &FileName<{ DIRSIZ }> {
+    pub fn get_name(&self) -> &FileName<{ DIRSIZ }> {
         let len = self.name.iter().position(|ch| *ch == 0).unwrap_or(DIRSIZ);
         // SAFETY: self.name[..len] doesn't contain '\0', and len must be <= DIRSIZ.
-        unsafe { FileName::from_bytes(&self.name[..len]) }
+        unsafe { FileName::from_bytes_unchecked(&self.name[..len]) }
     }
 }
 
 struct DirentIter<'id, 's, 't> {
     guard: &'s mut InodeGuard<'t, Ufs>,
-    iter: StepBy<Range<u32>>,
+    iter: StepBy<Range<u64>>,
     ctx: &'s KernelCtx<'id, 's>,
 }
 
 impl Iterator for DirentIter<'_, '_, '_> {
-    type Item = (Dirent, u32);
+    type Item = (Dirent, u64);
 
     fn next(&mut self) -> Option<Self::Item> {
         let off = self.iter.next()?;
@@ -187,7 +249,17 @@ impl Iterator for DirentIter<'_, '_, '_> {
 
 impl<'t> InodeGuard<'t, Ufs> {
     fn iter_dirents<'id, 's>(&'s mut self, ctx: &'s KernelCtx<'id, 's>) -> DirentIter<'id, 's, 't> {
-        let iter = (0..self.deref_inner().size).step_by(DIRENT_SIZE);
+        self.iter_dirents_from(0, ctx)
+    }
+
+    /// Iterates the `Dirent`s from the offset `off`, which is rounded up to a `Dirent`.
+    fn iter_dirents_from<'id, 's>(
+        &'s mut self,
+        off: u64,
+        ctx: &'s KernelCtx<'id, 's>,
+    ) -> DirentIter<'id, 's, 't> {
+        let off = (off + DIRENT_SIZE as u64 - 1) / DIRENT_SIZE as u64 * DIRENT_SIZE as u64;
+        let iter = (off..self.deref_inner().size).step_by(DIRENT_SIZE);
         DirentIter {
             guard: self,
             iter,
@@ -198,52 +270,137 @@ impl<'t> InodeGuard<'t, Ufs> {
 
 // Directories
 impl InodeGuard<'_, Ufs> {
-    /// Write a new directory entry (name, inum) into the directory dp.
-    pub fn dirlink(
-        &mut self,
-        name: &FileName<DIRSIZ>,
+    /// Returns whether the directory has entries of variable length, which `longdir`
+    /// manages, instead of `Dirent`s.
+    fn has_long_names(&self, ctx: &KernelCtx<'_, '_>) -> bool {
+        ctx.kernel().fs().ufs().superblock().long_names()
+    }
+
+    /// Returns the first used directory entry at the offset `off` or after it, and the
+    /// offset right after it. The type of the returned entry is `InodeType::None`.
+    pub fn next_dirent(&mut self, off: u64, ctx: &KernelCtx<'_, '_>) -> Option<(DirEntry, u64)> {
+        if self.has_long_names(ctx) {
+            return longdir::next(self, off, ctx);
+        }
+        self.iter_dirents_from(off, ctx)
+            .find(|(de, _)| de.inum != 0)
+            .map(|(de, off)| {
+                let entry =
+                    DirEntry::new(de.inum as u32, InodeType::None, de.get_name().as_bytes());
+                (entry, off + DIRENT_SIZE as u64)
+            })
+    }
+}
+
+impl DirFileSystem for Ufs {
+    fn dirlookup(
+        dp: &mut InodeGuard<'_, Self>,
+        name: &FileName<NAME_MAX>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(RcInode<Self>, u64), Errno> {
+        assert_eq!(dp.deref_inner().typ, InodeType::Dir, "dirlookup not DIR");
+
+        let found = if dp.has_long_names(ctx) {
+            longdir::lookup(dp, name, ctx)
+        } else {
+            dp.iter_dirents(ctx)
+                .find(|(de, _)| de.inum != 0 && de.get_name() == name.truncate::<DIRSIZ>())
+                .map(|(de, off)| (de.inum as u32, off))
+        };
+        found
+            .map(|(inum, off)| {
+                (
+                    ctx.kernel().fs().ufs().itable().get_inode(dp.dev, inum),
+                    off,
+                )
+            })
+            .ok_or(Errno::ENOENT)
+    }
+
+    fn dirlink(
+        dp: &mut InodeGuard<'_, Self>,
+        name: &FileName<NAME_MAX>,
         inum: u32,
-        tx: &Tx<'_, Ufs>,
+        tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), Errno> {
         // Check that name is not present.
-        if let Ok((ip, _)) = self.dirlookup(name, ctx) {
+        if let Ok((ip, _)) = dp.dirlookup(name, ctx) {
             ip.free((tx, ctx));
-            return Err(());
+            return Err(Errno::EEXIST);
         };
 
+        if dp.has_long_names(ctx) {
+            return longdir::link(dp, name, inum, tx, ctx);
+        }
+
         // Look for an empty Dirent.
-        let (mut de, off) = self
+        let (mut de, off) = dp
             .iter_dirents(ctx)
             .find(|(de, _)| de.inum == 0)
-            .unwrap_or((Default::default(), self.deref_inner().size));
+            .unwrap_or((Default::default(), dp.deref_inner().size));
         de.inum = inum as _;
-        de.set_name(name);
-        self.write_kernel(&de, off, tx, ctx).expect("dirlink");
+        de.set_name(name.truncate());
+        dp.write_kernel(&de, off, tx, ctx)
+    }
+
+    fn dirrelink(
+        dp: &mut InodeGuard<'_, Self>,
+        name: &FileName<NAME_MAX>,
+        inum: u32,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        let (ip, off) = dp.dirlookup(name, ctx)?;
+        ip.free((tx, ctx));
+        if dp.has_long_names(ctx) {
+            longdir::relink(dp, off, inum, tx, ctx).expect("dirrelink");
+            return Ok(());
+        }
+        let mut de = Dirent {
+            inum: inum as _,
+            ..Default::default()
+        };
+        de.set_name(name.truncate());
+        dp.write_kernel(&de, off, tx, ctx).expect("dirrelink");
         Ok(())
     }
 
-    /// Look for a directory entry in a directory.
-    /// If found, return the entry and byte offset of entry.
-    pub fn dirlookup(
-        &mut self,
-        name: &FileName<DIRSIZ>,
+    fn dirunlink(
+        dp: &mut InodeGuard<'_, Self>,
+        off: u64,
+        tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(RcInode<Ufs>, u32), ()> {
-        assert_eq!(self.deref_inner().typ, InodeType::Dir, "dirlookup not DIR");
+    ) {
+        if dp.has_long_names(ctx) {
+            longdir::unlink(dp, off, tx, ctx)
+        } else {
+            dp.write_kernel(&Dirent::default(), off, tx, ctx)
+        }
+        .expect("dirunlink");
+    }
 
-        self.iter_dirents(ctx)
-            .find(|(de, _)| de.inum != 0 && de.get_name() == name)
-            .map(|(de, off)| {
-                (
-                    ctx.kernel()
-                        .fs()
-                        .itable()
-                        .get_inode(self.dev, de.inum as u32),
-                    off,
-                )
-            })
-            .ok_or(())
+    fn is_dir_empty(dp: &mut InodeGuard<'_, Self>, ctx: &KernelCtx<'_, '_>) -> bool {
+        if dp.has_long_names(ctx) {
+            return longdir::is_empty(dp, ctx);
+        }
+        let mut de: Dirent = Default::default();
+        for off in (2 * DIRENT_SIZE as u64..dp.deref_inner().size).step_by(DIRENT_SIZE) {
+            dp.read_kernel(&mut de, off, ctx)
+                .expect("is_dir_empty: read_kernel");
+            if de.inum != 0 {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn inode_nlink<'a>(guard: &'a mut InodeGuard<'_, Self>) -> &'a mut i16 {
+        &mut guard.deref_inner_mut().nlink
+    }
+
+    fn inode_update(guard: &InodeGuard<'_, Self>, tx: &Tx<'_, Self>, ctx: &KernelCtx<'_, '_>) {
+        guard.update(tx, ctx);
     }
 }
 
@@ -288,10 +445,21 @@ impl InodeGuard<'_, Ufs> {
                 dip.major = 0;
                 dip.minor = 0;
             }
+            InodeType::Symlink => {
+                dip.typ = DInodeType::Symlink;
+                dip.major = 0;
+                dip.minor = 0;
+            }
         }
 
         (*dip).nlink = inner.nlink;
         (*dip).size = inner.size;
+        (*dip).atime = inner.atime;
+        (*dip).mtime = inner.mtime;
+        (*dip).ctime = inner.ctime;
+        (*dip).uid = inner.uid;
+        (*dip).gid = inner.gid;
+        (*dip).mode = inner.mode;
         for (d, s) in (*dip).addr_direct.iter_mut().zip(&inner.addr_direct) {
             *d = *s;
         }
@@ -303,16 +471,25 @@ impl InodeGuard<'_, Ufs> {
     ///
     /// The content (data) associated with each inode is stored
     /// in blocks on the disk. The first NDIRECT block numbers
-    /// are listed in self->addrs[].  The next NINDIRECT blocks are
-    /// listed in block self->addr_indirect.
+    /// are listed in self->addr_direct[].  The next NINDIRECT blocks are
+    /// listed in block self->addr_indirect[0], the next NINDIRECT^2 blocks
+    /// in the blocks that self->addr_indirect[1] lists, and the next
+    /// NINDIRECT^3 blocks below self->addr_indirect[2].
     /// Return the disk block address of the nth block in inode self.
     /// If there is no such block, bmap allocates one.
-    pub fn bmap_or_alloc(&mut self, bn: usize, tx: &Tx<'_, Ufs>, ctx: &KernelCtx<'_, '_>) -> u32 {
+    /// Returns Err(ENOSPC) if the disk is full.
+    pub fn bmap_or_alloc(
+        &mut self,
+        bn: usize,
+        tx: &Tx<'_, Ufs>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<u32, Errno> {
         self.bmap_internal(bn, Some(tx), ctx)
     }
 
     pub fn bmap(&mut self, bn: usize, ctx: &KernelCtx<'_, '_>) -> u32 {
-        self.bmap_internal(bn, None, ctx)
+        // bmap_internal fails only when it allocates a block.
+        self.bmap_internal(bn, None, ctx).unwrap()
     }
 
     fn bmap_internal(
@@ -320,54 +497,50 @@ impl InodeGuard<'_, Ufs> {
         bn: usize,
         tx_opt: Option<&Tx<'_, Ufs>>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> u32 {
+    ) -> Result<u32, Errno> {
         let inner = self.deref_inner();
 
         if bn < NDIRECT {
             let mut addr = inner.addr_direct[bn];
             if addr == 0 {
-                addr = tx_opt.expect("bmap: out of range").balloc(self.dev, ctx);
+                addr = tx_opt.expect("bmap: out of range").balloc(self.dev, ctx)?;
                 self.deref_inner_mut().addr_direct[bn] = addr;
             }
-            addr
+            Ok(addr)
         } else {
-            let bn = bn - NDIRECT;
-            assert!(bn < NINDIRECT, "bmap: out of range");
-
-            let mut indirect = inner.addr_indirect;
-            if indirect == 0 {
-                indirect = tx_opt.expect("bmap: out of range").balloc(self.dev, ctx);
-                self.deref_inner_mut().addr_indirect = indirect;
-            }
-
-            let mut bp = hal().disk().read(self.dev, indirect, ctx);
-            let (prefix, data, _) = unsafe { bp.data_mut().align_to_mut::<u32>() };
-            debug_assert_eq!(prefix.len(), 0, "bmap: Buf data unaligned");
-            let mut addr = data[bn];
+            let (level, bn) = indirect_level(bn);
+            let dev = self.dev;
+            let mut addr = inner.addr_indirect[level - 1];
             if addr == 0 {
-                let tx = tx_opt.expect("bmap: out of range");
-                addr = tx.balloc(self.dev, ctx);
-                data[bn] = addr;
-                tx.write(bp, ctx);
-            } else {
-                bp.free(ctx);
+                addr = tx_opt.expect("bmap: out of range").balloc(dev, ctx)?;
+                self.deref_inner_mut().addr_indirect[level - 1] = addr;
             }
-            addr
-        }
-    }
 
-    /// Is the directory dp empty except for "." and ".." ?
-    #[allow(clippy::wrong_self_convention)] // for invariant reads
-    pub fn is_dir_empty(&mut self, ctx: &KernelCtx<'_, '_>) -> bool {
-        let mut de: Dirent = Default::default();
-        for off in (2 * DIRENT_SIZE as u32..self.deref_inner().size).step_by(DIRENT_SIZE) {
-            self.read_kernel(&mut de, off, ctx)
-                .expect("is_dir_empty: read_kernel");
-            if de.inum != 0 {
-                return false;
+            // Walk down the indirect blocks, whose entries at `level` map
+            // NINDIRECT^(level - 1) data blocks each.
+            for level in (0..level).rev() {
+                let mut bp = hal().disk().read(dev, addr, ctx);
+                let (prefix, data, _) = unsafe { bp.data_mut().align_to_mut::<u32>() };
+                debug_assert_eq!(prefix.len(), 0, "bmap: Buf data unaligned");
+                let i = bn / NINDIRECT.pow(level as u32) % NINDIRECT;
+                addr = data[i];
+                if addr == 0 {
+                    let tx = tx_opt.expect("bmap: out of range");
+                    addr = match tx.balloc(dev, ctx) {
+                        Ok(addr) => addr,
+                        Err(err) => {
+                            bp.free(ctx);
+                            return Err(err);
+                        }
+                    };
+                    data[i] = addr;
+                    tx.write(bp, ctx);
+                } else {
+                    bp.free(ctx);
+                }
             }
+            Ok(addr)
         }
-        true
     }
 }
 
@@ -389,8 +562,14 @@ impl Inode<Ufs> {
                     typ: InodeType::None,
                     nlink: 0,
                     size: 0,
+                    atime: Timespec::ZERO,
+                    mtime: Timespec::ZERO,
+                    ctime: Timespec::ZERO,
+                    uid: 0,
+                    gid: 0,
+                    mode: 0,
                     addr_direct: [0; NDIRECT],
-                    addr_indirect: 0,
+                    addr_indirect: [0; NLEVEL],
                 },
             ),
         }
@@ -419,14 +598,15 @@ impl Itable<Ufs> {
 
     /// Allocate an inode on device dev.
     /// Mark it as allocated by giving it type.
-    /// Returns an unlocked but allocated and referenced inode.
+    /// Returns Ok(an unlocked but allocated and referenced inode), or
+    /// Err(ENOSPC) if there is no free inode.
     pub fn alloc_inode(
         self: StrongPin<'_, Self>,
         dev: u32,
         typ: InodeType,
         tx: &Tx<'_, Ufs>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> RcInode<Ufs> {
+    ) -> Result<RcInode<Ufs>, Errno> {
         for inum in 1..tx.fs.superblock().ninodes {
             let mut bp = hal().disk().read(dev, tx.fs.superblock().iblock(inum), ctx);
 
@@ -450,83 +630,28 @@ impl Itable<Ufs> {
                     InodeType::None => dip.typ = DInodeType::None,
                     InodeType::Dir => dip.typ = DInodeType::Dir,
                     InodeType::File => dip.typ = DInodeType::File,
+                    InodeType::Symlink => dip.typ = DInodeType::Symlink,
                     InodeType::Device { major, minor } => {
                         dip.typ = DInodeType::Device;
                         dip.major = major;
                         dip.minor = minor;
                     }
                 }
+                let now = Timespec::now();
+                dip.atime = now;
+                dip.mtime = now;
+                dip.ctime = now;
+                dip.uid = ctx.proc().creds().uid;
+                dip.gid = ctx.proc().creds().gid;
+                dip.mode = typ.default_mode();
 
                 // mark it allocated on the disk
                 tx.write(bp, ctx);
-                return self.get_inode(dev, inum);
+                return Ok(self.get_inode(dev, inum));
             } else {
                 bp.free(ctx);
             }
         }
-        panic!("[Itable::alloc_inode] no inodes");
-    }
-
-    pub fn root(self: StrongPin<'_, Self>) -> RcInode<Ufs> {
-        self.get_inode(ROOTDEV, ROOTINO)
-    }
-
-    pub fn namei(
-        self: StrongPin<'_, Self>,
-        path: &Path,
-        tx: &Tx<'_, Ufs>,
-        proc: &KernelCtx<'_, '_>,
-    ) -> Result<RcInode<Ufs>, ()> {
-        Ok(self.namex(path, false, tx, proc)?.0)
-    }
-
-    pub fn nameiparent<'s>(
-        self: StrongPin<'_, Self>,
-        path: &'s Path,
-        tx: &Tx<'_, Ufs>,
-        ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(RcInode<Ufs>, &'s FileName<{ DIRSIZ }>), ()> {
-        let (ip, name_in_path) = self.namex(path, true, tx, ctx)?;
-        let name_in_path = name_in_path.ok_or(())?;
-        Ok((ip, name_in_path))
-    }
-
-    fn namex<'s>(
-        self: StrongPin<'_, Self>,
-        mut path: &'s Path,
-        parent: bool,
-        tx: &Tx<'_, Ufs>,
-        ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(RcInode<Ufs>, Option<&'s FileName<{ DIRSIZ }>>), ()> {
-        let mut ptr = if path.is_absolute() {
-            self.root()
-        } else {
-            ctx.proc().cwd().clone()
-        };
-
-        while let Some((new_path, name)) = path.skipelem() {
-            path = new_path;
-
-            let mut ip = ptr.lock(ctx);
-            if ip.deref_inner().typ != InodeType::Dir {
-                ip.free(ctx);
-                ptr.free((tx, ctx));
-                return Err(());
-            }
-            if parent && path.is_empty_string() {
-                // Stop one level early.
-                ip.free(ctx);
-                return Ok((ptr, Some(name)));
-            }
-            let next = ip.dirlookup(name, ctx);
-            ip.free(ctx);
-            ptr.free((tx, ctx));
-            ptr = next?.0
-        }
-        if parent {
-            ptr.free((tx, ctx));
-            return Err(());
-        }
-        Ok((ptr, None))
+        Err(Errno::ENOSPC)
     }
 }
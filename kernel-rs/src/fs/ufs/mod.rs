@@ -11,7 +11,6 @@
 //!
 //! On-disk file system format used for both kernel and user programs are also included here.
 
-use core::cell::UnsafeCell;
 use core::ops::Deref;
 use core::{cmp, mem};
 
@@ -20,37 +19,38 @@ use spin::Once;
 
 use self::log::Log;
 use super::{
-    FcntlFlags, FileName, FileSystem, Inode, InodeGuard, InodeType, Itable, Path, RcInode, Stat, Tx,
+    AnyTx, DirEntry, FileName, FileSystem, Inode, InodeGuard, InodeType, Itable, RcInode, Stat, Tx,
+    Vfs, Vnode, NAME_MAX,
 };
 use crate::fs::DInodeType;
 use crate::util::strong_pin::StrongPin;
 use crate::{
-    bio::Buf,
-    file::{FileType, InodeFileType},
-    hal::hal,
-    lock::SleepableLock,
-    param::BSIZE,
-    proc::KernelCtx,
+    bio::Buf, errno::Errno, hal::hal, lock::SleepableLock, param::BSIZE, proc::KernelCtx,
+    time::Timespec,
 };
 
 mod inode;
 mod log;
 mod superblock;
 
-pub use inode::{Dinode, Dirent, InodeInner, DIRENT_SIZE, DIRSIZ};
+pub use inode::{Dinode, Dirent, InodeInner, DIRENT_SIZE};
 pub use superblock::{Superblock, BPB, IPB};
 
 /// root i-number
 const ROOTINO: u32 = 1;
 
-const NDIRECT: usize = 12;
+const NDIRECT: usize = 10;
 const NINDIRECT: usize = BSIZE.wrapping_div(mem::size_of::<u32>());
-const MAXFILE: usize = NDIRECT.wrapping_add(NINDIRECT);
+/// Levels of indirect blocks: single, double, and triple.
+const NLEVEL: usize = 3;
+const MAXFILE: usize =
+    NDIRECT + NINDIRECT + NINDIRECT * NINDIRECT + NINDIRECT * NINDIRECT * NINDIRECT;
 
 #[pin_project]
 pub struct Ufs {
+    /// The disk device that the file system is on.
+    dev: Once<u32>,
     /// Initializing superblock should run only once because forkret() calls FileSystem::init().
-    /// There should be one superblock per disk device, but we run with only one device.
     superblock: Once<Superblock>,
     log: Once<SleepableLock<Log>>,
     #[pin]
@@ -60,6 +60,7 @@ pub struct Ufs {
 impl Ufs {
     pub const fn new() -> Self {
         Self {
+            dev: Once::new(),
             superblock: Once::new(),
             log: Once::new(),
             itable: Itable::<Self>::new_itable(),
@@ -74,9 +75,51 @@ impl Ufs {
         self.superblock.get().expect("superblock")
     }
 
-    #[allow(clippy::needless_lifetimes)]
-    fn itable<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Itable<Self>> {
-        unsafe { StrongPin::new_unchecked(&self.as_pin().get_ref().itable) }
+    /// Reads the inode `inum` of the disk `dev` from the disk, and returns `f` applied to it.
+    fn read_dinode<R>(
+        &self,
+        dev: u32,
+        inum: u32,
+        ctx: &KernelCtx<'_, '_>,
+        f: impl FnOnce(&Dinode) -> R,
+    ) -> R {
+        let bp = hal().disk().read(dev, self.superblock().iblock(inum), ctx);
+
+        // SAFETY: dip is inside bp.data.
+        let dip = unsafe { (bp.data().as_ptr() as *const Dinode).add(inum as usize % IPB) };
+        // SAFETY: i16 does not have internal structure.
+        let t = unsafe { *(dip as *const i16) };
+        // If t >= #(variants of DInodeType), UB will happen when we read dip.typ.
+        assert!(t < core::mem::variant_count::<DInodeType>() as i16);
+        // SAFETY: dip is aligned properly and t < #(variants of DInodeType).
+        let ret = f(unsafe { &*dip });
+        bp.free(ctx);
+        ret
+    }
+
+    /// Frees the indirect block `addr` of `level` levels, and the blocks that it maps.
+    fn free_indirect(
+        dev: u32,
+        addr: u32,
+        level: usize,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) {
+        let mut bp = hal().disk().read(dev, addr, ctx);
+        // SAFETY: u32 does not have internal structure.
+        let (prefix, data, _) = unsafe { bp.data_mut().align_to_mut::<u32>() };
+        debug_assert_eq!(prefix.len(), 0, "itrunc: Buf data unaligned");
+        for a in data {
+            if *a != 0 {
+                if level == 1 {
+                    tx.bfree(dev, *a, ctx);
+                } else {
+                    Self::free_indirect(dev, *a, level - 1, tx, ctx);
+                }
+            }
+        }
+        bp.free(ctx);
+        tx.bfree(dev, addr, ctx);
     }
 }
 
@@ -101,7 +144,8 @@ impl Tx<'_, Ufs> {
 
     /// Blocks.
     /// Allocate a zeroed disk block.
-    fn balloc(&self, dev: u32, ctx: &KernelCtx<'_, '_>) -> u32 {
+    /// Returns Ok(block number) on success, Err(ENOSPC) if the disk is full.
+    fn balloc(&self, dev: u32, ctx: &KernelCtx<'_, '_>) -> Result<u32, Errno> {
         for b in num_iter::range_step(0, self.fs.superblock().size, BPB as u32) {
             let mut bp = hal().disk().read(dev, self.fs.superblock().bblock(b), ctx);
             for bi in 0..cmp::min(BPB as u32, self.fs.superblock().size - b) {
@@ -111,13 +155,12 @@ impl Tx<'_, Ufs> {
                     bp.data_mut()[(bi / 8) as usize] |= m; // Mark block in use.
                     self.write(bp, ctx);
                     self.bzero(dev, b + bi, ctx);
-                    return b + bi;
+                    return Ok(b + bi);
                 }
             }
             bp.free(ctx);
         }
-
-        panic!("balloc: out of blocks");
+        Err(Errno::ENOSPC)
     }
 
     /// Free a disk block.
@@ -135,7 +178,44 @@ impl FileSystem for Ufs {
     type Dirent = Dirent;
     type InodeInner = InodeInner;
 
-    fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>) {
+    const NAME: &'static str = "ufs";
+
+    fn get(vfs: StrongPin<'_, Vfs>) -> StrongPin<'_, Self> {
+        vfs.ufs()
+    }
+
+    fn vnode(inode: RcInode<Self>) -> Vnode {
+        Vnode::Ufs(inode)
+    }
+
+    fn any_tx(tx: Tx<'_, Self>) -> AnyTx<'_> {
+        AnyTx::Ufs(tx)
+    }
+
+    fn tx<'a, 's>(tx: &'a AnyTx<'s>) -> Option<&'a Tx<'s, Self>> {
+        match tx {
+            AnyTx::Ufs(tx) => Some(tx),
+            _ => None,
+        }
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn itable<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Itable<Self>> {
+        unsafe { StrongPin::new_unchecked(&self.as_pin().get_ref().itable) }
+    }
+
+    fn mount_dev(dev: u32) -> Result<u32, Errno> {
+        if hal().disk().is_present(dev) {
+            Ok(dev)
+        } else {
+            Err(Errno::ENXIO)
+        }
+    }
+
+    fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>) -> Result<(), Errno> {
+        if *self.dev.call_once(|| dev) != dev {
+            return Err(Errno::EBUSY);
+        }
         if !self.superblock.is_completed() {
             let buf = hal().disk().read(dev, 1, ctx);
             let superblock = self.superblock.call_once(|| Superblock::new(&buf));
@@ -147,68 +227,84 @@ impl FileSystem for Ufs {
                 )
             });
         }
+        Ok(())
     }
 
-    fn root(self: StrongPin<'_, Self>) -> RcInode<Self> {
-        self.itable().root()
+    fn root(self: StrongPin<'_, Self>, dev: u32) -> RcInode<Self> {
+        self.itable().get_inode(dev, ROOTINO)
     }
 
-    fn namei(
-        self: StrongPin<'_, Self>,
-        path: &Path,
-        tx: &Tx<'_, Self>,
+    fn lookup(
+        dp: &mut InodeGuard<'_, Self>,
+        name: &FileName<NAME_MAX>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<RcInode<Self>, ()> {
-        self.itable().namei(path, tx, ctx)
+    ) -> Result<RcInode<Self>, Errno> {
+        Ok(dp.dirlookup(name, ctx)?.0)
+    }
+
+    fn readdir(
+        dp: &mut InodeGuard<'_, Self>,
+        off: u64,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Option<(DirEntry, u64)>, Errno> {
+        Ok(dp.next_dirent(off, ctx).map(|(mut entry, off)| {
+            // Read the type from the disk, as locking the inode of "." or ".." may deadlock.
+            let ufs = ctx.kernel().fs().ufs();
+            entry.typ = ufs.read_dinode(dp.dev, entry.inum, ctx, Dinode::inode_type);
+            (entry, off)
+        }))
     }
 
     fn link(
         self: StrongPin<'_, Self>,
-        inode: RcInode<Self>,
-        path: &Path,
+        dp: &RcInode<Self>,
+        name: &FileName<NAME_MAX>,
+        inode: &RcInode<Self>,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
-        let inode = scopeguard::guard(inode, |ptr| ptr.free((tx, ctx)));
+    ) -> Result<(), Errno> {
         let ip = inode.lock(ctx);
         let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
         if ip.deref_inner().typ == InodeType::Dir {
-            return Err(());
+            return Err(Errno::EPERM);
         }
         ip.deref_inner_mut().nlink += 1;
+        ip.deref_inner_mut().ctime = Timespec::now();
         ip.update(tx, ctx);
         drop(ip);
 
-        if let Ok((ptr2, name)) = self.itable().nameiparent(path, tx, ctx) {
-            let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
-            let dp = ptr2.lock(ctx);
-            let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
-            if dp.dev == inode.dev && dp.dirlink(name, inode.inum, tx, ctx).is_ok() {
-                return Ok(());
+        let dp = dp.lock(ctx);
+        let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
+        let err = if dp.dev != inode.dev {
+            Errno::EXDEV
+        } else {
+            match dp.dirlink(name, inode.inum, tx, ctx) {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
             }
-        }
+        };
+        drop(dp);
 
         let ip = inode.lock(ctx);
         let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
         ip.deref_inner_mut().nlink -= 1;
         ip.update(tx, ctx);
-        Err(())
+        Err(err)
     }
 
     fn unlink(
         self: StrongPin<'_, Self>,
-        path: &Path,
+        dp: &RcInode<Self>,
+        name: &FileName<NAME_MAX>,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
-        let (ptr, name) = self.itable().nameiparent(path, tx, ctx)?;
-        let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
-        let dp = ptr.lock(ctx);
+    ) -> Result<(), Errno> {
+        let dp = dp.lock(ctx);
         let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
 
         // Cannot unlink "." or "..".
         if name.as_bytes() == b"." || name.as_bytes() == b".." {
-            return Err(());
+            return Err(Errno::EINVAL);
         }
 
         let (ptr2, off) = dp.dirlookup(name, ctx)?;
@@ -218,152 +314,97 @@ impl FileSystem for Ufs {
         assert!(ip.deref_inner().nlink >= 1, "unlink: nlink < 1");
 
         if ip.deref_inner().typ == InodeType::Dir && !ip.is_dir_empty(ctx) {
-            return Err(());
+            return Err(Errno::ENOTEMPTY);
         }
 
-        dp.write_kernel(&Dirent::default(), off, tx, ctx)
-            .expect("unlink: writei");
+        dp.dirunlink(off, tx, ctx);
         if ip.deref_inner().typ == InodeType::Dir {
             dp.deref_inner_mut().nlink -= 1;
             dp.update(tx, ctx);
         }
         drop(dp);
-        drop(ptr);
         ip.deref_inner_mut().nlink -= 1;
+        ip.deref_inner_mut().ctime = Timespec::now();
         ip.update(tx, ctx);
         Ok(())
     }
 
-    fn create<F, T>(
+    fn rename(
+        self: StrongPin<'_, Self>,
+        dp: &RcInode<Self>,
+        name: &FileName<NAME_MAX>,
+        newdp: &RcInode<Self>,
+        newname: &FileName<NAME_MAX>,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        super::rename(dp, name, newdp, newname, tx, ctx)
+    }
+
+    fn create(
         self: StrongPin<'_, Self>,
-        path: &Path,
+        dp: &RcInode<Self>,
+        name: &FileName<NAME_MAX>,
         typ: InodeType,
+        excl: bool,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
-        f: F,
-    ) -> Result<(RcInode<Self>, T), ()>
-    where
-        F: FnOnce(&mut InodeGuard<'_, Self>) -> T,
-    {
-        let (ptr, name) = self.itable().nameiparent(path, tx, ctx)?;
-        let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
-        let dp = ptr.lock(ctx);
+    ) -> Result<RcInode<Self>, Errno> {
+        let dp = dp.lock(ctx);
         let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
         if let Ok((ptr2, _)) = dp.dirlookup(name, ctx) {
             let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
             drop(dp);
-            if typ != InodeType::File {
-                return Err(());
+            if typ != InodeType::File || excl {
+                return Err(Errno::EEXIST);
             }
             let ip = ptr2.lock(ctx);
-            let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
-            if let InodeType::None | InodeType::Dir = ip.deref_inner().typ {
-                return Err(());
+            let ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+            match ip.deref_inner().typ {
+                InodeType::None => return Err(Errno::ENOENT),
+                InodeType::Dir => return Err(Errno::EISDIR),
+                _ => (),
             }
-            let ret = f(&mut ip);
             drop(ip);
-            return Ok((scopeguard::ScopeGuard::into_inner(ptr2), ret));
+            return Ok(scopeguard::ScopeGuard::into_inner(ptr2));
         }
-        let ptr2 = self.itable().alloc_inode(dp.dev, typ, tx, ctx);
+        let ptr2 = self.itable().alloc_inode(dp.dev, typ, tx, ctx)?;
+        let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
         let ip = ptr2.lock(ctx);
         let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
         ip.deref_inner_mut().nlink = 1;
         ip.update(tx, ctx);
 
-        // Create . and .. entries.
+        let inum = ip.inum;
+        let res = if typ == InodeType::Dir {
+            // Create . and .. entries.
+            // No ip->nlink++ for ".": avoid cyclic ref count.
+            // SAFETY: b"." and b".." do not contain any NUL characters, and are short enough.
+            let (dot, dotdot) = unsafe {
+                (
+                    FileName::from_bytes_unchecked(b"."),
+                    FileName::from_bytes_unchecked(b".."),
+                )
+            };
+            ip.dirlink(dot, inum, tx, ctx)
+                .and_then(|_| ip.dirlink(dotdot, dp.inum, tx, ctx))
+        } else {
+            Ok(())
+        }
+        .and_then(|_| dp.dirlink(name, inum, tx, ctx));
+        if let Err(err) = res {
+            // The inode is freed when its last reference is dropped.
+            ip.deref_inner_mut().nlink = 0;
+            ip.update(tx, ctx);
+            return Err(err);
+        }
         if typ == InodeType::Dir {
             // for ".."
             dp.deref_inner_mut().nlink += 1;
             dp.update(tx, ctx);
-
-            let inum = ip.inum;
-            // No ip->nlink++ for ".": avoid cyclic ref count.
-            // SAFETY: b"." does not contain any NUL characters.
-            ip.dirlink(unsafe { FileName::from_bytes(b".") }, inum, tx, ctx)
-                // SAFETY: b".." does not contain any NUL characters.
-                .and_then(|_| ip.dirlink(unsafe { FileName::from_bytes(b"..") }, dp.inum, tx, ctx))
-                .expect("create dots");
         }
-        dp.dirlink(name, ip.inum, tx, ctx).expect("create: dirlink");
-        let ret = f(&mut ip);
         drop(ip);
-        Ok((ptr2, ret))
-    }
-
-    fn open(
-        self: StrongPin<'_, Self>,
-        path: &Path,
-        omode: FcntlFlags,
-        tx: &Tx<'_, Self>,
-        ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<usize, ()> {
-        let (ip, typ) = if omode.contains(FcntlFlags::O_CREATE) {
-            self.create(path, InodeType::File, tx, ctx, |ip| ip.deref_inner().typ)?
-        } else {
-            let ptr = self.itable().namei(path, tx, ctx)?;
-            let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
-            let ip = ptr.lock(ctx);
-            let ip = scopeguard::guard(ip, |ip| ip.free(ctx));
-            let typ = ip.deref_inner().typ;
-
-            if typ == InodeType::Dir && omode != FcntlFlags::O_RDONLY {
-                return Err(());
-            }
-            drop(ip);
-            (scopeguard::ScopeGuard::into_inner(ptr), typ)
-        };
-
-        let filetype = match typ {
-            InodeType::Device { major, .. } => FileType::Device { ip, major },
-            _ => {
-                FileType::Inode {
-                    inner: InodeFileType {
-                        ip,
-                        off: UnsafeCell::new(0),
-                    },
-                }
-            }
-        };
-
-        let f = ctx.kernel().ftable().alloc_file(
-            filetype,
-            !omode.intersects(FcntlFlags::O_WRONLY),
-            omode.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR),
-        )?;
-
-        if omode.contains(FcntlFlags::O_TRUNC) && typ == InodeType::File {
-            match &f.typ {
-                // It is safe to call itrunc because ip.lock() is held
-                FileType::Device { ip, .. }
-                | FileType::Inode {
-                    inner: InodeFileType { ip, .. },
-                } => {
-                    let mut ip = ip.lock(ctx);
-                    ip.trunc(tx, ctx);
-                    ip.free(ctx);
-                }
-                _ => panic!("sys_open : Not reach"),
-            };
-        }
-        let fd = f.fdalloc(ctx)?;
-        Ok(fd as usize)
-    }
-
-    fn chdir(
-        self: StrongPin<'_, Self>,
-        inode: RcInode<Self>,
-        tx: &Tx<'_, Self>,
-        ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
-        let ip = inode.lock(ctx);
-        let typ = ip.deref_inner().typ;
-        ip.free(ctx);
-        if typ != InodeType::Dir {
-            inode.free((tx, ctx));
-            return Err(());
-        }
-        mem::replace(ctx.proc_mut().cwd_mut(), inode).free((tx, ctx));
-        Ok(())
+        Ok(scopeguard::ScopeGuard::into_inner(ptr2))
     }
 
     fn tx_begin(&self, ctx: &KernelCtx<'_, '_>) {
@@ -380,34 +421,34 @@ impl FileSystem for Ufs {
         'id,
         's,
         K: Deref<Target = KernelCtx<'id, 's>>,
-        F: FnMut(u32, &[u8], &mut K) -> Result<(), ()>,
+        F: FnMut(u32, &[u8], &mut K) -> Result<(), Errno>,
     >(
         guard: &mut InodeGuard<'_, Self>,
-        mut off: u32,
+        mut off: u64,
         mut n: u32,
         mut f: F,
         mut k: K,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, Errno> {
         let inner = guard.deref_inner();
-        if off > inner.size || off.wrapping_add(n) < off {
+        if off > inner.size || off.wrapping_add(u64::from(n)) < off {
             return Ok(0);
         }
-        if off + n > inner.size {
-            n = inner.size - off;
+        if off + u64::from(n) > inner.size {
+            n = (inner.size - off) as u32;
         }
         let mut tot: u32 = 0;
         while tot < n {
             let bp = hal()
                 .disk()
                 .read(guard.dev, guard.bmap(off as usize / BSIZE, &k), &k);
-            let m = core::cmp::min(n - tot, BSIZE as u32 - off % BSIZE as u32);
-            let begin = (off % BSIZE as u32) as usize;
+            let m = core::cmp::min(n - tot, BSIZE as u32 - (off % BSIZE as u64) as u32);
+            let begin = (off % BSIZE as u64) as usize;
             let end = begin + m as usize;
             let res = f(tot, &bp.data()[begin..end], &mut k);
             bp.free(&k);
             res?;
             tot += m;
-            off += m;
+            off += u64::from(m);
         }
         Ok(tot as usize)
     }
@@ -417,49 +458,64 @@ impl FileSystem for Ufs {
         'id,
         's,
         K: Deref<Target = KernelCtx<'id, 's>>,
-        F: FnMut(u32, &mut [u8], &mut K) -> Result<(), ()>,
+        F: FnMut(u32, &mut [u8], &mut K) -> Result<(), Errno>,
     >(
         guard: &mut InodeGuard<'_, Self>,
-        mut off: u32,
+        mut off: u64,
         n: u32,
         mut f: F,
         tx: &Tx<'_, Self>,
         mut k: K,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, Errno> {
         if off > guard.deref_inner().size {
-            return Err(());
+            return Err(Errno::EINVAL);
         }
-        if off.checked_add(n).ok_or(())? as usize > MAXFILE * BSIZE {
-            return Err(());
+        if off.checked_add(u64::from(n)).ok_or(Errno::EFBIG)? > (MAXFILE * BSIZE) as u64 {
+            return Err(Errno::EFBIG);
         }
         let mut tot: u32 = 0;
+        let mut res = Ok(());
         while tot < n {
-            let mut bp = hal().disk().read(
-                guard.dev,
-                guard.bmap_or_alloc(off as usize / BSIZE, tx, &k),
-                &k,
-            );
-            let m = core::cmp::min(n - tot, BSIZE as u32 - off % BSIZE as u32);
-            let begin = (off % BSIZE as u32) as usize;
+            let addr = match guard.bmap_or_alloc(off as usize / BSIZE, tx, &k) {
+                Ok(addr) => addr,
+                Err(err) => {
+                    res = Err(err);
+                    break;
+                }
+            };
+            let mut bp = hal().disk().read(guard.dev, addr, &k);
+            let m = core::cmp::min(n - tot, BSIZE as u32 - (off % BSIZE as u64) as u32);
+            let begin = (off % BSIZE as u64) as usize;
             let end = begin + m as usize;
-            if f(tot, &mut bp.data_mut()[begin..end], &mut k).is_ok() {
+            res = f(tot, &mut bp.data_mut()[begin..end], &mut k);
+            if res.is_ok() {
                 tx.write(bp, &k);
             } else {
                 bp.free(&k);
+            }
+            if res.is_err() {
                 break;
             }
             tot += m;
-            off += m;
+            off += u64::from(m);
         }
 
         if off > guard.deref_inner().size {
             guard.deref_inner_mut().size = off;
         }
+        if tot > 0 {
+            let now = Timespec::now();
+            guard.deref_inner_mut().mtime = now;
+            guard.deref_inner_mut().ctime = now;
+        }
 
         // Write the i-node back to disk even if the size didn't change
         // because the loop above might have called bmap() and added a new
         // block to self->addrs[].
         guard.update(tx, &k);
+        if tot == 0 {
+            res?;
+        }
         Ok(tot as usize)
     }
 
@@ -472,64 +528,40 @@ impl FileSystem for Ufs {
             }
         }
 
-        if guard.deref_inner().addr_indirect != 0 {
-            let mut bp = hal()
-                .disk()
-                .read(dev, guard.deref_inner().addr_indirect, ctx);
-            // SAFETY: u32 does not have internal structure.
-            let (prefix, data, _) = unsafe { bp.data_mut().align_to_mut::<u32>() };
-            debug_assert_eq!(prefix.len(), 0, "itrunc: Buf data unaligned");
-            for a in data {
-                if *a != 0 {
-                    tx.bfree(dev, *a, ctx);
-                }
+        for level in 1..=NLEVEL {
+            let addr = guard.deref_inner().addr_indirect[level - 1];
+            if addr != 0 {
+                Self::free_indirect(dev, addr, level, tx, ctx);
+                guard.deref_inner_mut().addr_indirect[level - 1] = 0;
             }
-            bp.free(ctx);
-            tx.bfree(dev, guard.deref_inner().addr_indirect, ctx);
-            guard.deref_inner_mut().addr_indirect = 0
         }
 
         guard.deref_inner_mut().size = 0;
+        let now = Timespec::now();
+        guard.deref_inner_mut().mtime = now;
+        guard.deref_inner_mut().ctime = now;
         guard.update(tx, ctx);
     }
 
     fn inode_lock<'a>(inode: &'a Inode<Self>, ctx: &KernelCtx<'_, '_>) -> InodeGuard<'a, Self> {
         let mut guard = inode.inner.lock(ctx);
         if !guard.valid {
-            let bp = hal().disk().read(
-                inode.dev,
-                ctx.kernel().fs().superblock().iblock(inode.inum),
-                ctx,
-            );
-
-            // SAFETY: dip is inside bp.data.
-            let dip =
-                unsafe { (bp.data().as_ptr() as *const Dinode).add(inode.inum as usize % IPB) };
-            // SAFETY: i16 does not have internal structure.
-            let t = unsafe { *(dip as *const i16) };
-            // If t >= #(variants of DInodeType), UB will happen when we read dip.typ.
-            assert!(t < core::mem::variant_count::<DInodeType>() as i16);
-            // SAFETY: dip is aligned properly and t < #(variants of DInodeType).
-            let dip = unsafe { &*dip };
-
-            match dip.typ {
-                DInodeType::None => guard.typ = InodeType::None,
-                DInodeType::Dir => guard.typ = InodeType::Dir,
-                DInodeType::File => guard.typ = InodeType::File,
-                DInodeType::Device => {
-                    guard.typ = InodeType::Device {
-                        major: dip.major,
-                        minor: dip.minor,
-                    }
+            let ufs = ctx.kernel().fs().ufs();
+            ufs.read_dinode(inode.dev, inode.inum, ctx, |dip| {
+                guard.typ = dip.inode_type();
+                guard.nlink = dip.nlink;
+                guard.size = dip.size;
+                guard.atime = dip.atime;
+                guard.mtime = dip.mtime;
+                guard.ctime = dip.ctime;
+                guard.uid = dip.uid;
+                guard.gid = dip.gid;
+                guard.mode = dip.mode;
+                for (d, s) in guard.addr_direct.iter_mut().zip(&dip.addr_direct) {
+                    *d = *s;
                 }
-            }
-            guard.nlink = dip.nlink;
-            guard.size = dip.size;
-            for (d, s) in guard.addr_direct.iter_mut().zip(&dip.addr_direct) {
-                *d = *s;
-            }
-            guard.addr_indirect = dip.addr_indirect;
-            bp.free(ctx);
+                guard.addr_indirect = dip.addr_indirect;
+            });
             guard.valid = true;
             assert_ne!(guard.typ, InodeType::None, "Inode::lock: no type");
         };
@@ -558,6 +590,14 @@ impl FileSystem for Ufs {
         }
     }
 
+    fn inode_type(guard: &InodeGuard<'_, Self>) -> InodeType {
+        guard.deref_inner().typ
+    }
+
+    fn inode_size(guard: &InodeGuard<'_, Self>) -> u64 {
+        guard.deref_inner().size
+    }
+
     fn inode_stat(inode: &Inode<Self>, ctx: &KernelCtx<'_, '_>) -> Stat {
         let inner = inode.inner.lock(ctx);
         let st = Stat {
@@ -568,12 +608,70 @@ impl FileSystem for Ufs {
                 InodeType::Dir => 1,
                 InodeType::File => 2,
                 InodeType::Device { .. } => 3,
+                InodeType::Symlink => 4,
             },
             nlink: inner.nlink,
+            mode: inner.mode,
             _padding: 0,
-            size: inner.size as usize,
+            uid: inner.uid,
+            gid: inner.gid,
+            size: inner.size,
+            atime: inner.atime,
+            mtime: inner.mtime,
+            ctime: inner.ctime,
         };
         inner.free(ctx);
         st
     }
+
+    fn inode_set_times(
+        guard: &mut InodeGuard<'_, Self>,
+        atime: Option<Timespec>,
+        mtime: Option<Timespec>,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        let inner = guard.deref_inner_mut();
+        if let Some(atime) = atime {
+            inner.atime = atime;
+        }
+        if let Some(mtime) = mtime {
+            inner.mtime = mtime;
+        }
+        inner.ctime = Timespec::now();
+        guard.update(tx, ctx);
+        Ok(())
+    }
+
+    fn inode_set_mode(
+        guard: &mut InodeGuard<'_, Self>,
+        mode: u16,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        let inner = guard.deref_inner_mut();
+        inner.mode = mode;
+        inner.ctime = Timespec::now();
+        guard.update(tx, ctx);
+        Ok(())
+    }
+
+    fn inode_set_owner(
+        guard: &mut InodeGuard<'_, Self>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        let inner = guard.deref_inner_mut();
+        if let Some(uid) = uid {
+            inner.uid = uid;
+        }
+        if let Some(gid) = gid {
+            inner.gid = gid;
+        }
+        inner.ctime = Timespec::now();
+        guard.update(tx, ctx);
+        Ok(())
+    }
 }
@@ -5,6 +5,7 @@ use static_assertions::const_assert;
 use super::Dinode;
 use crate::{
     bio::{Buf, BufData},
+    fs::longdir::LONGNAMES,
     param::BSIZE,
 };
 
@@ -42,6 +43,9 @@ pub struct Superblock {
 
     /// Block number of first free map block
     pub bmapstart: u32,
+
+    /// Optional features of the file system, such as `LONGNAMES`
+    features: u32,
 }
 
 /// Inodes per block.
@@ -65,6 +69,11 @@ impl Superblock {
         result
     }
 
+    /// Returns whether the directories have entries of variable length.
+    pub const fn long_names(self) -> bool {
+        self.features & LONGNAMES != 0
+    }
+
     /// Block containing inode i
     pub const fn iblock(self, i: u32) -> u32 {
         i / IPB as u32 + self.inodestart
@@ -0,0 +1,1239 @@
+//! The virtual file system, which puts file systems together into a single tree.
+//!
+//! The root of the tree is the root of `DefaultFs` on the disk `ROOTDEV`. Every other
+//! file system is mounted on a directory, which it covers. Path name lookup moves
+//! from a covered directory to the root of the file system mounted on it, and from
+//! ".." of the root of a mounted file system to ".." of the directory it covers.
+//!
+//! A `Vnode` refers to an inode of any file system. Each operation on a `Vnode`
+//! locks the inode or begins a transaction of its file system by itself, so it must
+//! not be called while holding an inode lock or inside a transaction. An operation
+//! of `Vfs` runs inside a `VfsTx` instead, in which its path lookups release the
+//! inodes that they pass.
+
+use core::cell::UnsafeCell;
+use core::mem;
+use core::sync::atomic::AtomicU64;
+
+use array_macro::array;
+use pin_project::pin_project;
+
+use super::{
+    Access, DefaultFs, Devfs, FcntlFlags, FileName, FileSystem, FileSystemExt, InodeType, Lfs,
+    Path, Procfs, RcInode, Stat, Tmpfs, Tx, Ufs, NAME_MAX, ROOT_UID,
+};
+use crate::{
+    errno::Errno,
+    file::{DeviceFileType, FileType, InodeFileType},
+    lock::{SleepLock, SpinLock},
+    param::{MAXPATH, MAXSYMLINKS, NMOUNT, ROOTDEV},
+    proc::KernelCtx,
+    time::Timespec,
+    util::strong_pin::StrongPin,
+};
+
+/// Evaluates `$body` with `$ip` bound to the inode of the `Vnode` `$vnode`.
+/// If `$fs` is given, it names the type of the file system of the inode in `$body`.
+macro_rules! dispatch {
+    ($vnode:expr, $ip:ident => $body:expr) => {
+        match $vnode {
+            $crate::fs::Vnode::Ufs($ip) => $body,
+            $crate::fs::Vnode::Lfs($ip) => $body,
+            $crate::fs::Vnode::Tmpfs($ip) => $body,
+            $crate::fs::Vnode::Procfs($ip) => $body,
+            $crate::fs::Vnode::Devfs($ip) => $body,
+        }
+    };
+    ($vnode:expr, $fs:ident, $ip:ident => $body:expr) => {
+        match $vnode {
+            $crate::fs::Vnode::Ufs($ip) => {
+                type $fs = $crate::fs::Ufs;
+                $body
+            }
+            $crate::fs::Vnode::Lfs($ip) => {
+                type $fs = $crate::fs::Lfs;
+                $body
+            }
+            $crate::fs::Vnode::Tmpfs($ip) => {
+                type $fs = $crate::fs::Tmpfs;
+                $body
+            }
+            $crate::fs::Vnode::Procfs($ip) => {
+                type $fs = $crate::fs::Procfs;
+                $body
+            }
+            $crate::fs::Vnode::Devfs($ip) => {
+                type $fs = $crate::fs::Devfs;
+                $body
+            }
+        }
+    };
+}
+
+pub(crate) use dispatch;
+
+/// A reference to an inode of any file system.
+#[derive(Clone)]
+pub enum Vnode {
+    Ufs(RcInode<Ufs>),
+    Lfs(RcInode<Lfs>),
+    Tmpfs(RcInode<Tmpfs>),
+    Procfs(RcInode<Procfs>),
+    Devfs(RcInode<Devfs>),
+}
+
+/// A file system mounted on a directory.
+struct Mount {
+    /// The name of the file system.
+    fstype: &'static str,
+
+    /// The directory that the file system covers.
+    covered: Vnode,
+
+    /// The root directory of the file system.
+    root: Vnode,
+}
+
+/// A slot of the mount table.
+enum MountSlot {
+    Free,
+
+    /// Reserved for the file system `fstype` on the disk `dev` while it is being
+    /// initialized to cover `covered`.
+    Mounting {
+        fstype: &'static str,
+        dev: u32,
+        covered: Vnode,
+    },
+
+    Mounted(Mount),
+}
+
+impl MountSlot {
+    fn mounted(&self) -> Option<&Mount> {
+        match self {
+            Self::Mounted(m) => Some(m),
+            _ => None,
+        }
+    }
+
+    /// Returns the directory that the slot covers or is about to cover.
+    fn covered(&self) -> Option<&Vnode> {
+        match self {
+            Self::Free => None,
+            Self::Mounting { covered, .. } => Some(covered),
+            Self::Mounted(m) => Some(&m.covered),
+        }
+    }
+}
+
+#[pin_project]
+pub struct Vfs {
+    #[pin]
+    ufs: Ufs,
+
+    #[pin]
+    lfs: Lfs,
+
+    #[pin]
+    tmpfs: Tmpfs,
+
+    #[pin]
+    procfs: Procfs,
+
+    #[pin]
+    devfs: Devfs,
+
+    mounts: SpinLock<[MountSlot; NMOUNT]>,
+
+    /// Serializes renames, so that two renames cannot together move a directory into its
+    /// own subdirectory.
+    renames: SleepLock<()>,
+}
+
+/// Runs `f` inside a transaction of the file system `FS`.
+fn in_tx<FS: FileSystem, T, F: FnOnce(StrongPin<'_, FS>, &Tx<'_, FS>) -> T>(
+    vfs: StrongPin<'_, Vfs>,
+    ctx: &KernelCtx<'_, '_>,
+    f: F,
+) -> T {
+    let fs = FS::get(vfs);
+    let tx = fs.as_pin().get_ref().begin_tx(ctx);
+    let ret = f(fs, &tx);
+    tx.end(ctx);
+    ret
+}
+
+/// A transaction of any file system.
+pub enum AnyTx<'s> {
+    Ufs(Tx<'s, Ufs>),
+    Lfs(Tx<'s, Lfs>),
+    Tmpfs(Tx<'s, Tmpfs>),
+    Procfs(Tx<'s, Procfs>),
+    Devfs(Tx<'s, Devfs>),
+}
+
+/// The transaction of an operation of `Vfs`. It begins a transaction of a file system
+/// when the operation first needs one, and ends it when the operation moves on to
+/// another file system, so that it holds at most one at a time.
+pub struct VfsTx<'s> {
+    vfs: StrongPin<'s, Vfs>,
+    tx: Option<AnyTx<'s>>,
+}
+
+impl AnyTx<'_> {
+    fn end(self, ctx: &KernelCtx<'_, '_>) {
+        match self {
+            AnyTx::Ufs(tx) => tx.end(ctx),
+            AnyTx::Lfs(tx) => tx.end(ctx),
+            AnyTx::Tmpfs(tx) => tx.end(ctx),
+            AnyTx::Procfs(tx) => tx.end(ctx),
+            AnyTx::Devfs(tx) => tx.end(ctx),
+        }
+    }
+}
+
+impl<'s> VfsTx<'s> {
+    fn new(vfs: StrongPin<'s, Vfs>) -> Self {
+        Self { vfs, tx: None }
+    }
+
+    /// Returns the transaction of the file system `FS`, beginning it if needed.
+    fn get<FS: FileSystem>(&mut self, ctx: &KernelCtx<'_, '_>) -> &Tx<'s, FS> {
+        if !self.tx.as_ref().map_or(false, |tx| FS::tx(tx).is_some()) {
+            if let Some(tx) = self.tx.take() {
+                tx.end(ctx);
+            }
+            let fs = FS::get(self.vfs).as_pin().get_ref();
+            self.tx = Some(FS::any_tx(fs.begin_tx(ctx)));
+        }
+        self.tx.as_ref().and_then(FS::tx).expect("VfsTx::get")
+    }
+
+    /// Runs `f` inside the transaction of the file system `FS`.
+    fn with<FS: FileSystem, T, F: FnOnce(StrongPin<'s, FS>, &Tx<'s, FS>) -> T>(
+        &mut self,
+        ctx: &KernelCtx<'_, '_>,
+        f: F,
+    ) -> T {
+        let fs = FS::get(self.vfs);
+        f(fs, self.get::<FS>(ctx))
+    }
+
+    fn end(self, ctx: &KernelCtx<'_, '_>) {
+        if let Some(tx) = self.tx {
+            tx.end(ctx);
+        }
+    }
+}
+
+impl Vnode {
+    /// Device number
+    pub fn dev(&self) -> u32 {
+        dispatch!(self, ip => ip.dev)
+    }
+
+    /// Inode number
+    pub fn inum(&self) -> u32 {
+        dispatch!(self, ip => ip.inum)
+    }
+
+    /// Drop the reference, inside a transaction of its file system.
+    pub fn put(self, ctx: &KernelCtx<'_, '_>) {
+        ctx.kernel().fs().with_tx(ctx, |tx| self.put_in(tx, ctx))
+    }
+
+    /// Drop the reference, inside `tx`.
+    fn put_in(self, tx: &mut VfsTx<'_>, ctx: &KernelCtx<'_, '_>) {
+        dispatch!(self, FS, ip => ip.free((tx.get::<FS>(ctx), ctx)))
+    }
+
+    /// Returns the type of the inode.
+    pub fn typ(&self, ctx: &KernelCtx<'_, '_>) -> InodeType {
+        dispatch!(self, FS, ip => {
+            let guard = ip.lock(ctx);
+            let typ = FS::inode_type(&guard);
+            guard.free(ctx);
+            typ
+        })
+    }
+
+    /// Copy stat information from the inode.
+    pub fn stat(&self, ctx: &KernelCtx<'_, '_>) -> Stat {
+        dispatch!(self, ip => ip.stat(ctx))
+    }
+
+    /// Checks that the current process may access the inode as `access` asks, by the
+    /// permission bits of the class that the process belongs to. The superuser may access
+    /// any inode, but may execute a file only if any class may execute it.
+    /// Returns Ok(()) if it may, Err(EACCES) otherwise.
+    pub fn permit(&self, access: Access, ctx: &KernelCtx<'_, '_>) -> Result<(), Errno> {
+        let creds = *ctx.proc().creds();
+        if creds.uid == ROOT_UID {
+            if access.contains(Access::EXEC)
+                && self.typ(ctx) != InodeType::Dir
+                && self.stat(ctx).mode & 0o111 == 0
+            {
+                return Err(Errno::EACCES);
+            }
+            return Ok(());
+        }
+        let st = self.stat(ctx);
+        let mode = if st.uid == creds.uid {
+            st.mode >> 6
+        } else if st.gid == creds.gid {
+            st.mode >> 3
+        } else {
+            st.mode
+        };
+        if Access::from_bits_truncate(mode).contains(access) {
+            Ok(())
+        } else {
+            Err(Errno::EACCES)
+        }
+    }
+
+    /// Checks that the current process owns the inode or is the superuser.
+    /// Returns Ok(()) if it does, Err(EPERM) otherwise.
+    fn check_owner(&self, ctx: &KernelCtx<'_, '_>) -> Result<(), Errno> {
+        let uid = ctx.proc().creds().uid;
+        if uid == ROOT_UID || self.stat(ctx).uid == uid {
+            Ok(())
+        } else {
+            Err(Errno::EPERM)
+        }
+    }
+
+    /// Copy data into `dst` from the content of the inode at offset `off`.
+    /// Return the number of bytes copied.
+    pub fn read_bytes_kernel(&self, dst: &mut [u8], off: u64, ctx: &KernelCtx<'_, '_>) -> usize {
+        dispatch!(self, ip => {
+            let mut guard = ip.lock(ctx);
+            let ret = guard.read_bytes_kernel(dst, off, ctx);
+            guard.free(ctx);
+            ret
+        })
+    }
+
+    /// Truncate the inode (discard contents).
+    pub fn trunc(&self, ctx: &KernelCtx<'_, '_>) {
+        dispatch!(self, FS, ip => in_tx::<FS, _, _>(ctx.kernel().fs(), ctx, |_, tx| {
+            let mut guard = ip.lock(ctx);
+            guard.trunc(tx, ctx);
+            guard.free(ctx);
+        }))
+    }
+
+    /// Sets the last access and modification times of the inode, leaving a `None` one
+    /// unchanged.
+    /// Returns Ok(()) on success, Err(errno) on error.
+    fn set_times(
+        &self,
+        atime: Option<Timespec>,
+        mtime: Option<Timespec>,
+        tx: &mut VfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        dispatch!(self, FS, ip => tx.with::<FS, _, _>(ctx, |_, tx| {
+            let mut guard = ip.lock(ctx);
+            let ret = FS::inode_set_times(&mut guard, atime, mtime, tx, ctx);
+            guard.free(ctx);
+            ret
+        }))
+    }
+
+    /// Sets the permission bits of the inode to `mode`.
+    /// Returns Ok(()) on success, Err(errno) on error.
+    fn set_mode(
+        &self,
+        mode: u16,
+        tx: &mut VfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        dispatch!(self, FS, ip => tx.with::<FS, _, _>(ctx, |_, tx| {
+            let mut guard = ip.lock(ctx);
+            let ret = FS::inode_set_mode(&mut guard, mode, tx, ctx);
+            guard.free(ctx);
+            ret
+        }))
+    }
+
+    /// Sets the owner and its group of the inode, leaving a `None` one unchanged.
+    /// Returns Ok(()) on success, Err(errno) on error.
+    fn set_owner(
+        &self,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        tx: &mut VfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        dispatch!(self, FS, ip => tx.with::<FS, _, _>(ctx, |_, tx| {
+            let mut guard = ip.lock(ctx);
+            let ret = FS::inode_set_owner(&mut guard, uid, gid, tx, ctx);
+            guard.free(ctx);
+            ret
+        }))
+    }
+
+    /// Looks for `name` in the directory `self`, without crossing mount points.
+    /// Returns Err(ENOTDIR) if `self` is not a directory.
+    fn lookup(&self, name: &FileName<NAME_MAX>, ctx: &KernelCtx<'_, '_>) -> Result<Vnode, Errno> {
+        dispatch!(self, FS, ip => {
+            let mut guard = ip.lock(ctx);
+            let ret = if FS::inode_type(&guard) == InodeType::Dir {
+                FS::lookup(&mut guard, name, ctx).map(FS::vnode)
+            } else {
+                Err(Errno::ENOTDIR)
+            };
+            guard.free(ctx);
+            ret
+        })
+    }
+}
+
+/// Two `Vnode`s are equal if they refer to the same inode.
+impl PartialEq for Vnode {
+    fn eq(&self, other: &Self) -> bool {
+        self.dev() == other.dev() && self.inum() == other.inum()
+    }
+}
+
+impl Vfs {
+    pub const fn new() -> Self {
+        Self {
+            ufs: Ufs::new(),
+            lfs: Lfs::new(),
+            tmpfs: Tmpfs::new(),
+            procfs: Procfs::new(),
+            devfs: Devfs::new(),
+            mounts: SpinLock::new("mounts", array![_ => MountSlot::Free; NMOUNT]),
+            renames: SleepLock::new("renames", ()),
+        }
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    pub fn ufs<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Ufs> {
+        unsafe { StrongPin::new_unchecked(&self.as_pin().get_ref().ufs) }
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    pub fn lfs<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Lfs> {
+        unsafe { StrongPin::new_unchecked(&self.as_pin().get_ref().lfs) }
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    pub fn tmpfs<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Tmpfs> {
+        unsafe { StrongPin::new_unchecked(&self.as_pin().get_ref().tmpfs) }
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    pub fn procfs<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Procfs> {
+        unsafe { StrongPin::new_unchecked(&self.as_pin().get_ref().procfs) }
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    pub fn devfs<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Devfs> {
+        unsafe { StrongPin::new_unchecked(&self.as_pin().get_ref().devfs) }
+    }
+
+    /// Initializes the root file system.
+    pub fn init(self: StrongPin<'_, Self>, ctx: &KernelCtx<'_, '_>) {
+        DefaultFs::get(self)
+            .init(ROOTDEV, ctx)
+            .expect("root file system");
+    }
+
+    /// Returns the root directory of the root file system.
+    pub fn root(self: StrongPin<'_, Self>) -> Vnode {
+        DefaultFs::vnode(DefaultFs::get(self).root(ROOTDEV))
+    }
+
+    /// If a file system is mounted on `vp`, puts `vp` and returns the root of the file system.
+    /// Otherwise, returns `vp`.
+    fn cover(
+        self: StrongPin<'_, Self>,
+        mut vp: Vnode,
+        tx: &mut VfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Vnode {
+        loop {
+            let root = self
+                .mounts
+                .lock()
+                .iter()
+                .filter_map(MountSlot::mounted)
+                .find(|m| m.covered == vp)
+                .map(|m| m.root.clone());
+            match root {
+                Some(root) => mem::replace(&mut vp, root).put_in(tx, ctx),
+                None => return vp,
+            }
+        }
+    }
+
+    /// If `vp` is the root of a mounted file system, puts `vp` and returns the directory
+    /// that the file system covers. Otherwise, returns `vp`.
+    fn uncover(
+        self: StrongPin<'_, Self>,
+        mut vp: Vnode,
+        tx: &mut VfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Vnode {
+        loop {
+            let covered = self
+                .mounts
+                .lock()
+                .iter()
+                .filter_map(MountSlot::mounted)
+                .find(|m| m.root == vp)
+                .map(|m| m.covered.clone());
+            match covered {
+                Some(covered) => mem::replace(&mut vp, covered).put_in(tx, ctx),
+                None => return vp,
+            }
+        }
+    }
+
+    /// Runs `f` inside a new `VfsTx`.
+    pub fn with_tx<T, F: FnOnce(&mut VfsTx<'_>) -> T>(
+        self: StrongPin<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+        f: F,
+    ) -> T {
+        let mut tx = VfsTx::new(self);
+        let ret = f(&mut tx);
+        tx.end(ctx);
+        ret
+    }
+
+    /// Finds the vnode of the given path, following a symbolic link at the end of it.
+    /// The inodes passed on the way are released inside `tx`.
+    pub fn namei(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        tx: &mut VfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Vnode, Errno> {
+        Ok(self.namex(path, false, true, false, tx, ctx)?.0)
+    }
+
+    /// Finds the vnode of the given path, without following a symbolic link at the end of it.
+    /// The inodes passed on the way are released inside `tx`.
+    pub fn lnamei(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        tx: &mut VfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Vnode, Errno> {
+        Ok(self.namex(path, false, false, false, tx, ctx)?.0)
+    }
+
+    /// Finds the vnode of the parent directory of the given path, and the last element of the path.
+    /// The inodes passed on the way are released inside `tx`.
+    pub fn nameiparent<'s>(
+        self: StrongPin<'_, Self>,
+        path: &'s Path,
+        tx: &mut VfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(Vnode, &'s FileName<NAME_MAX>), Errno> {
+        let (vp, name_in_path) = self.namex(path, true, false, false, tx, ctx)?;
+        let name_in_path = name_in_path.ok_or(Errno::ENOENT)?;
+        Ok((vp, name_in_path))
+    }
+
+    /// Walks `path`, following the symbolic links in the middle of it. A symbolic link at
+    /// the end of it is followed only if `follow` is true. If `create` is true, a file is
+    /// created for the last element if it does not exist, even as the target of a link.
+    fn namex<'s>(
+        self: StrongPin<'_, Self>,
+        mut path: &'s Path,
+        parent: bool,
+        follow: bool,
+        create: bool,
+        tx: &mut VfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(Vnode, Option<&'s FileName<NAME_MAX>>), Errno> {
+        let mut vp = if path.is_absolute() {
+            self.cover(self.root(), tx, ctx)
+        } else {
+            ctx.proc().cwd()
+        };
+
+        // `link[start..end]` is the rest of the targets of the symbolic links followed so
+        // far, which is walked before the rest of `path`.
+        let mut link = [0; MAXPATH];
+        let (mut start, mut end) = (0, 0);
+        let mut nlinks = 0;
+
+        loop {
+            // SAFETY: `link[start..end]` is made of `path` and the targets of symbolic
+            // links, without NUL characters.
+            let rest = unsafe { Path::from_bytes(&link[start..end]) };
+            let (name, last): (Result<&FileName<NAME_MAX>, Errno>, bool) =
+                if let Some((new_rest, name)) = rest.skipelem() {
+                    start = end - new_rest.as_bytes().len();
+                    (name, start == end && path.is_empty_string())
+                } else if let Some((new_path, name)) = path.skipelem() {
+                    path = new_path;
+
+                    if parent && path.is_empty_string() {
+                        // Stop one level early.
+                        let ret = if vp.typ(ctx) != InodeType::Dir {
+                            Err(Errno::ENOTDIR)
+                        } else {
+                            vp.permit(Access::EXEC, ctx).and(name)
+                        };
+                        return match ret {
+                            Ok(name) => Ok((vp, Some(name))),
+                            Err(err) => {
+                                vp.put_in(tx, ctx);
+                                Err(err)
+                            }
+                        };
+                    }
+                    (name, path.is_empty_string())
+                } else {
+                    break;
+                };
+            let name = match name {
+                Ok(name) => name,
+                Err(err) => {
+                    vp.put_in(tx, ctx);
+                    return Err(err);
+                }
+            };
+
+            if name.as_bytes() == b".." {
+                vp = self.uncover(vp, tx, ctx);
+            }
+            let next = match vp
+                .permit(Access::EXEC, ctx)
+                .and_then(|_| vp.lookup(name, ctx))
+            {
+                Ok(next) => self.cover(next, tx, ctx),
+                Err(Errno::ENOENT) if last && create => {
+                    let ret = vp.permit(Access::WRITE, ctx).and_then(|_| {
+                        dispatch!(&vp, FS, ip => tx.with::<FS, _, _>(ctx, |fs, tx| {
+                            fs.create(ip, name, InodeType::File, false, tx, ctx).map(FS::vnode)
+                        }))
+                    });
+                    vp.put_in(tx, ctx);
+                    return ret.map(|ip| (ip, None));
+                }
+                Err(err) => {
+                    vp.put_in(tx, ctx);
+                    return Err(err);
+                }
+            };
+            if (last && !follow) || next.typ(ctx) != InodeType::Symlink {
+                vp.put_in(tx, ctx);
+                vp = next;
+                continue;
+            }
+
+            // Put the target of the link before the rest. A relative target starts from
+            // `vp`, the directory that holds the link.
+            nlinks += 1;
+            let size = next.stat(ctx).size;
+            let len = end - start;
+            if nlinks > MAXSYMLINKS || size + 1 + len as u64 > MAXPATH as u64 {
+                next.put_in(tx, ctx);
+                vp.put_in(tx, ctx);
+                return Err(if nlinks > MAXSYMLINKS {
+                    Errno::ELOOP
+                } else {
+                    Errno::ENAMETOOLONG
+                });
+            }
+            link.copy_within(start..end, MAXPATH - len);
+            let n = next.read_bytes_kernel(&mut link[..size as usize], 0, ctx);
+            next.put_in(tx, ctx);
+            let n = link[..n].iter().position(|c| *c == 0).unwrap_or(n);
+            link[n] = b'/';
+            link.copy_within(MAXPATH - len.., n + 1);
+            start = 0;
+            end = n + 1 + len;
+            if link[0] == b'/' {
+                vp.put_in(tx, ctx);
+                vp = self.cover(self.root(), tx, ctx);
+            }
+        }
+        if parent {
+            vp.put_in(tx, ctx);
+            return Err(Errno::ENOENT);
+        }
+        Ok((vp, None))
+    }
+
+    /// Create an inode with given type at the given path.
+    /// If `typ` is `File`, `excl` is false, and a file exists at the path, returns it instead.
+    /// Returns Ok(the vnode) on success, Err(errno) on error.
+    pub fn create(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        typ: InodeType,
+        excl: bool,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Vnode, Errno> {
+        self.with_tx(ctx, |tx| self.create_in(path, typ, excl, tx, ctx))
+    }
+
+    /// `create` inside `tx`.
+    fn create_in(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        typ: InodeType,
+        excl: bool,
+        tx: &mut VfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Vnode, Errno> {
+        let (dp, name) = self.nameiparent(path, tx, ctx)?;
+        // Returning an existing file does not write to the directory.
+        let ret = match dp.lookup(name, ctx) {
+            Ok(ip) => {
+                ip.put_in(tx, ctx);
+                Ok(())
+            }
+            Err(_) => dp.permit(Access::WRITE, ctx),
+        };
+        let ret = ret.and_then(|_| {
+            dispatch!(&dp, FS, ip => tx.with::<FS, _, _>(ctx, |fs, tx| {
+                fs.create(ip, name, typ, excl, tx, ctx).map(FS::vnode)
+            }))
+        });
+        dp.put_in(tx, ctx);
+        ret
+    }
+
+    /// Create another name(newname) for the file oldname.
+    /// Returns Ok(()) on success, Err(EXDEV) if they are on different file systems,
+    /// or Err(errno) on other errors.
+    pub fn link(
+        self: StrongPin<'_, Self>,
+        oldname: &Path,
+        newname: &Path,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        self.with_tx(ctx, |tx| {
+            let ip = self.namei(oldname, tx, ctx)?;
+            let ret = self.nameiparent(newname, tx, ctx).and_then(|(dp, name)| {
+                let ret = dp.permit(Access::WRITE, ctx).and_then(|_| {
+                    match (&dp, &ip) {
+                        (Vnode::Ufs(dp), Vnode::Ufs(ip)) => {
+                            tx.with::<Ufs, _, _>(ctx, |fs, tx| fs.link(dp, name, ip, tx, ctx))
+                        }
+                        (Vnode::Lfs(dp), Vnode::Lfs(ip)) => {
+                            tx.with::<Lfs, _, _>(ctx, |fs, tx| fs.link(dp, name, ip, tx, ctx))
+                        }
+                        (Vnode::Tmpfs(dp), Vnode::Tmpfs(ip)) => {
+                            tx.with::<Tmpfs, _, _>(ctx, |fs, tx| fs.link(dp, name, ip, tx, ctx))
+                        }
+                        (Vnode::Procfs(dp), Vnode::Procfs(ip)) => {
+                            tx.with::<Procfs, _, _>(ctx, |fs, tx| fs.link(dp, name, ip, tx, ctx))
+                        }
+                        (Vnode::Devfs(dp), Vnode::Devfs(ip)) => {
+                            tx.with::<Devfs, _, _>(ctx, |fs, tx| fs.link(dp, name, ip, tx, ctx))
+                        }
+                        _ => Err(Errno::EXDEV),
+                    }
+                });
+                dp.put_in(tx, ctx);
+                ret
+            });
+            ip.put_in(tx, ctx);
+            ret
+        })
+    }
+
+    /// Remove a file(filename).
+    /// Returns Ok(()) on success, Err(EBUSY) if a file system is mounted on it,
+    /// or Err(errno) on other errors.
+    pub fn unlink(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        self.with_tx(ctx, |tx| {
+            let (dp, name) = self.nameiparent(path, tx, ctx)?;
+            let ret = dp
+                .permit(Access::WRITE, ctx)
+                .and_then(|_| dp.lookup(name, ctx));
+            let ret = ret.and_then(|ip| {
+                let covered = self
+                    .mounts
+                    .lock()
+                    .iter()
+                    .filter_map(MountSlot::covered)
+                    .any(|vp| *vp == ip);
+                ip.put_in(tx, ctx);
+                if covered {
+                    return Err(Errno::EBUSY);
+                }
+                dispatch!(&dp, FS, dp => tx.with::<FS, _, _>(ctx, |fs, tx| fs.unlink(dp, name, tx, ctx)))
+            });
+            dp.put_in(tx, ctx);
+            ret
+        })
+    }
+
+    /// Create a symbolic link at the given path, which refers to `target`.
+    /// Returns Ok(()) on success, Err(errno) on error.
+    pub fn symlink(
+        self: StrongPin<'_, Self>,
+        target: &Path,
+        path: &Path,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        if target.is_empty_string() {
+            return Err(Errno::ENOENT);
+        }
+        self.with_tx(ctx, |tx| {
+            let (dp, name) = self.nameiparent(path, tx, ctx)?;
+            if let Err(err) = dp.permit(Access::WRITE, ctx) {
+                dp.put_in(tx, ctx);
+                return Err(err);
+            }
+            let ret = dispatch!(&dp, FS, dp => tx.with::<FS, _, _>(ctx, |fs, tx| {
+                let ip = fs.create(dp, name, InodeType::Symlink, true, tx, ctx)?;
+                let mut guard = ip.lock(ctx);
+                let ret = guard.write_bytes_kernel(target.as_bytes(), 0, tx, ctx);
+                guard.free(ctx);
+                ip.free((tx, ctx));
+                if ret.is_err() {
+                    let _ = fs.unlink(dp, name, tx, ctx);
+                }
+                ret.map(|_| ())
+            }));
+            dp.put_in(tx, ctx);
+            ret
+        })
+    }
+
+    /// Copy the target of the symbolic link at the given path into `buf`.
+    /// Returns Ok(the length of the target) on success, Err(EINVAL) if it is not a symbolic
+    /// link, or Err(errno) on other errors.
+    pub fn readlink(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        buf: &mut [u8; MAXPATH],
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, Errno> {
+        self.with_tx(ctx, |tx| {
+            let ip = self.lnamei(path, tx, ctx)?;
+            let ret = if ip.typ(ctx) == InodeType::Symlink {
+                Ok(ip.read_bytes_kernel(buf, 0, ctx))
+            } else {
+                Err(Errno::EINVAL)
+            };
+            ip.put_in(tx, ctx);
+            ret
+        })
+    }
+
+    /// Sets the last access and modification times of the file at the given path,
+    /// leaving a `None` one unchanged.
+    /// Returns Ok(()) on success, Err(errno) on error.
+    pub fn utimens(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        atime: Option<Timespec>,
+        mtime: Option<Timespec>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        self.with_tx(ctx, |tx| {
+            let ip = self.namei(path, tx, ctx)?;
+            let ret = ip
+                .check_owner(ctx)
+                .and_then(|_| ip.set_times(atime, mtime, tx, ctx));
+            ip.put_in(tx, ctx);
+            ret
+        })
+    }
+
+    /// Sets the permission bits of the file at the given path to `mode`.
+    /// Returns Ok(()) on success, Err(EPERM) if the current process neither owns the file
+    /// nor is the superuser, or Err(errno) on other errors.
+    pub fn chmod(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        mode: u16,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        self.with_tx(ctx, |tx| {
+            let ip = self.namei(path, tx, ctx)?;
+            let ret = ip
+                .check_owner(ctx)
+                .and_then(|_| ip.set_mode(mode & 0o777, tx, ctx));
+            ip.put_in(tx, ctx);
+            ret
+        })
+    }
+
+    /// Sets the owner and its group of the file at the given path to `uid` and `gid`,
+    /// leaving a `None` one unchanged.
+    /// Returns Ok(()) on success, Err(EPERM) if the current process is not the superuser,
+    /// or Err(errno) on other errors.
+    pub fn chown(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        self.with_tx(ctx, |tx| {
+            let ip = self.namei(path, tx, ctx)?;
+            let ret = if ctx.proc().creds().uid == ROOT_UID {
+                ip.set_owner(uid, gid, tx, ctx)
+            } else {
+                Err(Errno::EPERM)
+            };
+            ip.put_in(tx, ctx);
+            ret
+        })
+    }
+
+    /// Atomically rename a file(oldname) to newname, replacing the file at newname if any.
+    /// Returns Ok(()) on success, Err(EXDEV) if they are on different file systems,
+    /// Err(EBUSY) if a file system is mounted on either of them, or Err(errno) on other errors.
+    pub fn rename(
+        self: StrongPin<'_, Self>,
+        oldname: &Path,
+        newname: &Path,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        self.with_tx(ctx, |tx| {
+            let (dp, name) = self.nameiparent(oldname, tx, ctx)?;
+            let ret = self
+                .nameiparent(newname, tx, ctx)
+                .and_then(|(newdp, newname)| {
+                    let ret = dp.permit(Access::WRITE, ctx);
+                    let ret = ret.and_then(|_| newdp.permit(Access::WRITE, ctx));
+                    let ret = ret.and_then(|_| {
+                        if self.is_covered(&dp, name, tx, ctx)
+                            || self.is_covered(&newdp, newname, tx, ctx)
+                        {
+                            return Err(Errno::EBUSY);
+                        }
+                        // `renames` must be locked inside the transaction.
+                        dispatch!(&dp, FS, _ip => {
+                            let _ = tx.get::<FS>(ctx);
+                        });
+                        let guard = self.renames.lock(ctx);
+                        let ret = match (&dp, &newdp) {
+                            (Vnode::Ufs(dp), Vnode::Ufs(newdp)) => {
+                                tx.with::<Ufs, _, _>(ctx, |fs, tx| {
+                                    fs.rename(dp, name, newdp, newname, tx, ctx)
+                                })
+                            }
+                            (Vnode::Lfs(dp), Vnode::Lfs(newdp)) => {
+                                tx.with::<Lfs, _, _>(ctx, |fs, tx| {
+                                    fs.rename(dp, name, newdp, newname, tx, ctx)
+                                })
+                            }
+                            (Vnode::Tmpfs(dp), Vnode::Tmpfs(newdp)) => {
+                                tx.with::<Tmpfs, _, _>(ctx, |fs, tx| {
+                                    fs.rename(dp, name, newdp, newname, tx, ctx)
+                                })
+                            }
+                            (Vnode::Procfs(dp), Vnode::Procfs(newdp)) => {
+                                tx.with::<Procfs, _, _>(ctx, |fs, tx| {
+                                    fs.rename(dp, name, newdp, newname, tx, ctx)
+                                })
+                            }
+                            (Vnode::Devfs(dp), Vnode::Devfs(newdp)) => {
+                                tx.with::<Devfs, _, _>(ctx, |fs, tx| {
+                                    fs.rename(dp, name, newdp, newname, tx, ctx)
+                                })
+                            }
+                            _ => Err(Errno::EXDEV),
+                        };
+                        guard.free(ctx);
+                        ret
+                    });
+                    newdp.put_in(tx, ctx);
+                    ret
+                });
+            dp.put_in(tx, ctx);
+            ret
+        })
+    }
+
+    /// Returns whether a file system is mounted on the entry `name` of the directory `dp`.
+    fn is_covered(
+        &self,
+        dp: &Vnode,
+        name: &FileName<NAME_MAX>,
+        tx: &mut VfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> bool {
+        dp.lookup(name, ctx).map_or(false, |ip| {
+            let covered = self
+                .mounts
+                .lock()
+                .iter()
+                .filter_map(MountSlot::covered)
+                .any(|vp| *vp == ip);
+            ip.put_in(tx, ctx);
+            covered
+        })
+    }
+
+    /// Open a file; omode indicate read/write.
+    /// Returns Ok(file descriptor) on success, Err(errno) on error.
+    pub fn open(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        omode: FcntlFlags,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, Errno> {
+        let follow = !omode.contains(FcntlFlags::O_NOFOLLOW);
+        let ip = self.with_tx(ctx, |tx| {
+            if !omode.contains(FcntlFlags::O_CREATE) {
+                if follow {
+                    self.namei(path, tx, ctx)
+                } else {
+                    self.lnamei(path, tx, ctx)
+                }
+            } else if follow && !omode.contains(FcntlFlags::O_EXCL) {
+                // A dangling symbolic link gets its target created.
+                Ok(self.namex(path, false, true, true, tx, ctx)?.0)
+            } else {
+                self.create_in(
+                    path,
+                    InodeType::File,
+                    omode.contains(FcntlFlags::O_EXCL),
+                    tx,
+                    ctx,
+                )
+            }
+        })?;
+        let typ = ip.typ(ctx);
+        if typ == InodeType::Symlink {
+            ip.put(ctx);
+            return Err(Errno::ELOOP);
+        }
+        let dir_flags = FcntlFlags::O_NOFOLLOW | FcntlFlags::O_NONBLOCK | FcntlFlags::O_CLOEXEC;
+        if typ == InodeType::Dir && omode.difference(dir_flags) != FcntlFlags::O_RDONLY {
+            ip.put(ctx);
+            return Err(Errno::EISDIR);
+        }
+        let mut access = Access::empty();
+        if !omode.intersects(FcntlFlags::O_WRONLY) {
+            access |= Access::READ;
+        }
+        if omode.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR | FcntlFlags::O_TRUNC) {
+            access |= Access::WRITE;
+        }
+        if let Err(err) = ip.permit(access, ctx) {
+            ip.put(ctx);
+            return Err(err);
+        }
+
+        let filetype = match typ {
+            InodeType::Device { major, minor } => {
+                FileType::Device {
+                    inner: DeviceFileType {
+                        ip,
+                        major,
+                        minor,
+                        off: AtomicU64::new(0),
+                    },
+                }
+            }
+            _ => {
+                FileType::Inode {
+                    inner: InodeFileType {
+                        ip,
+                        off: UnsafeCell::new(0),
+                    },
+                }
+            }
+        };
+
+        let f = ctx.kernel().ftable().alloc_file(
+            filetype,
+            !omode.intersects(FcntlFlags::O_WRONLY),
+            omode.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR),
+        )?;
+
+        if omode.contains(FcntlFlags::O_TRUNC) && typ == InodeType::File {
+            match &f.typ {
+                FileType::Inode { inner } => inner.ip.trunc(ctx),
+                _ => panic!("sys_open : Not reach"),
+            }
+        }
+        f.set_flags(omode);
+        let fd = f.fdalloc_from(0, omode.contains(FcntlFlags::O_CLOEXEC), ctx)?;
+        Ok(fd as usize)
+    }
+
+    /// Change the current directory.
+    /// Returns Ok(()) on success, Err(errno) on error.
+    pub fn chdir(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        self.with_tx(ctx, |tx| {
+            let vp = self.namei(path, tx, ctx)?;
+            if vp.typ(ctx) != InodeType::Dir {
+                vp.put_in(tx, ctx);
+                return Err(Errno::ENOTDIR);
+            }
+            if let Err(err) = vp.permit(Access::EXEC, ctx) {
+                vp.put_in(tx, ctx);
+                return Err(err);
+            }
+            ctx.proc().set_cwd(vp).put_in(tx, ctx);
+            Ok(())
+        })
+    }
+
+    /// Mount the file system named `fstype` on the disk `dev` at the directory `target`.
+    /// The tmpfs, the procfs, and the devfs, which are not on a disk, ignore `dev`.
+    /// Returns Ok(()) on success, Err(ENODEV) if there is no such file system,
+    /// Err(ENXIO) if there is no such disk, Err(EBUSY) if the file system or the
+    /// disk is already in use, Err(EPERM) if the current process is not the superuser,
+    /// or Err(errno) on other errors.
+    pub fn mount(
+        self: StrongPin<'_, Self>,
+        dev: u32,
+        target: &Path,
+        fstype: &[u8],
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        if ctx.proc().creds().uid != ROOT_UID {
+            Err(Errno::EPERM)
+        } else if fstype == Ufs::NAME.as_bytes() {
+            self.mount_fs::<Ufs>(dev, target, ctx)
+        } else if fstype == Lfs::NAME.as_bytes() {
+            self.mount_fs::<Lfs>(dev, target, ctx)
+        } else if fstype == Tmpfs::NAME.as_bytes() {
+            self.mount_fs::<Tmpfs>(dev, target, ctx)
+        } else if fstype == Procfs::NAME.as_bytes() {
+            self.mount_fs::<Procfs>(dev, target, ctx)
+        } else if fstype == Devfs::NAME.as_bytes() {
+            self.mount_fs::<Devfs>(dev, target, ctx)
+        } else {
+            Err(Errno::ENODEV)
+        }
+    }
+
+    fn mount_fs<FS: FileSystem>(
+        self: StrongPin<'_, Self>,
+        dev: u32,
+        target: &Path,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        let dev = FS::mount_dev(dev)?;
+        let covered = self.with_tx(ctx, |tx| self.namei(target, tx, ctx))?;
+        if covered.typ(ctx) != InodeType::Dir {
+            covered.put(ctx);
+            return Err(Errno::ENOTDIR);
+        }
+
+        // Reserve a slot before initializing the file system, so that no one else
+        // mounts the same file system or disk meanwhile.
+        let mut mounts = self.mounts.lock();
+        let err = if Self::is_busy(&mounts, FS::NAME, dev) {
+            Errno::EBUSY
+        } else if let Some(i) = mounts.iter().position(|m| matches!(m, MountSlot::Free)) {
+            mounts[i] = MountSlot::Mounting {
+                fstype: FS::NAME,
+                dev,
+                covered,
+            };
+            drop(mounts);
+            return self.finish_mount::<FS>(i, dev, ctx);
+        } else {
+            Errno::ENOMEM
+        };
+        drop(mounts);
+        covered.put(ctx);
+        Err(err)
+    }
+
+    /// Initializes the file system `FS` on the disk `dev`, and mounts it in the `i`th
+    /// slot, which `mount_fs` has reserved. Frees the slot if it fails.
+    fn finish_mount<FS: FileSystem>(
+        self: StrongPin<'_, Self>,
+        i: usize,
+        dev: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        let root = FS::get(self)
+            .init(dev, ctx)
+            .map(|_| FS::vnode(FS::get(self).root(dev)));
+        let mut mounts = self.mounts.lock();
+        let covered = match mem::replace(&mut mounts[i], MountSlot::Free) {
+            MountSlot::Mounting { covered, .. } => covered,
+            _ => unreachable!("finish_mount"),
+        };
+        match root {
+            Ok(root) => {
+                mounts[i] = MountSlot::Mounted(Mount {
+                    fstype: FS::NAME,
+                    covered,
+                    root,
+                });
+                Ok(())
+            }
+            Err(err) => {
+                drop(mounts);
+                covered.put(ctx);
+                Err(err)
+            }
+        }
+    }
+
+    /// Returns true if the file system named `fstype` or the disk `dev` is in use.
+    fn is_busy(mounts: &[MountSlot; NMOUNT], fstype: &str, dev: u32) -> bool {
+        fstype == DefaultFs::NAME
+            || dev == ROOTDEV
+            || mounts.iter().any(|m| {
+                match m {
+                    MountSlot::Free => false,
+                    MountSlot::Mounting {
+                        fstype: t, dev: d, ..
+                    } => *t == fstype || *d == dev,
+                    MountSlot::Mounted(m) => m.fstype == fstype || m.root.dev() == dev,
+                }
+            })
+    }
+
+    /// Unmount the file system mounted at the directory `target`.
+    /// Returns Ok(()) on success, Err(EINVAL) if no file system is mounted there,
+    /// Err(EBUSY) if any file of the file system is in use, or Err(EPERM) if the current
+    /// process is not the superuser.
+    pub fn umount(
+        self: StrongPin<'_, Self>,
+        target: &Path,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        if ctx.proc().creds().uid != ROOT_UID {
+            return Err(Errno::EPERM);
+        }
+        let vp = self.with_tx(ctx, |tx| self.namei(target, tx, ctx))?;
+        let mut mounts = self.mounts.lock();
+        let ret = match mounts
+            .iter_mut()
+            .find(|m| m.mounted().map_or(false, |m| m.root == vp))
+        {
+            // Only the mount and `vp` refer to the inodes of an unused file system.
+            Some(m) if dispatch!(&vp, FS, _ip => FS::get(self).itable().refs()) == 2 => {
+                match mem::replace(m, MountSlot::Free) {
+                    MountSlot::Mounted(m) => Ok(m),
+                    _ => unreachable!("umount"),
+                }
+            }
+            Some(_) => Err(Errno::EBUSY),
+            None => Err(Errno::EINVAL),
+        };
+        drop(mounts);
+        vp.put(ctx);
+        let m = ret?;
+        m.root.put(ctx);
+        m.covered.put(ctx);
+        Ok(())
+    }
+}
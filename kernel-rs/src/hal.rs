@@ -8,8 +8,8 @@ use crate::{
     console::{Console, Printer},
     cpu::Cpus,
     kalloc::Kmem,
-    lock::{SleepableLock, SpinLock},
-    virtio::VirtioDisk,
+    lock::SpinLock,
+    virtio::VirtioDisks,
 };
 
 static mut HAL: Hal = unsafe { Hal::new::<TargetArch>() };
@@ -49,7 +49,7 @@ pub struct Hal {
     cpus: Cpus,
 
     #[pin]
-    disk: SleepableLock<VirtioDisk>,
+    disk: VirtioDisks,
 }
 
 impl Hal {
@@ -62,7 +62,7 @@ impl Hal {
             printer: Printer::new(),
             kmem: SpinLock::new("KMEM", unsafe { Kmem::new() }),
             cpus: Cpus::new(),
-            disk: SleepableLock::new("DISK", unsafe { VirtioDisk::new() }),
+            disk: unsafe { VirtioDisks::new::<A>() },
         }
     }
 
@@ -80,7 +80,7 @@ impl Hal {
         // Physical page allocator.
         unsafe { this.kmem.get_pin_mut().init() };
 
-        this.disk.get_pin_mut().as_ref().init();
+        this.disk.init();
     }
 
     pub fn console(&self) -> &Console {
@@ -100,7 +100,7 @@ impl Hal {
         &self.cpus
     }
 
-    pub fn disk(self: Pin<&Self>) -> Pin<&SleepableLock<VirtioDisk>> {
+    pub fn disk(self: Pin<&Self>) -> Pin<&VirtioDisks> {
         // SAFETY: `HAL` is never moved inside this module, and only shared references are exposed.
         unsafe { Pin::new_unchecked(&self.get_ref().disk) }
     }
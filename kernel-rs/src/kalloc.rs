@@ -6,7 +6,8 @@ use core::{mem, pin::Pin};
 use pin_project::pin_project;
 
 use crate::{
-    addr::{pgrounddown, pgroundup, PGSIZE},
+    addr::{pgrounddown, pgroundup, Addr, PAddr, PGSIZE},
+    arch::{interface::MemLayout, TargetArch},
     lock::SpinLock,
     memlayout::PHYSTOP,
     page::Page,
@@ -52,9 +53,14 @@ unsafe impl ListNode for Run {
     }
 }
 
+/// Number of physical pages between `KERNBASE` and `PHYSTOP`.
+const NPAGE: usize = (PHYSTOP - TargetArch::KERNBASE) / PGSIZE;
+
 /// # Safety
 ///
 /// The address of each `Run` in `runs` can become a `Page` by `Page::from_usize`.
+/// The reference count of each page in `runs` is 0, and the reference count of each
+/// allocated page is the number of `Page`s referring to it.
 // This implementation defers from xv6. Kmem of xv6 uses intrusive singly linked list, while this
 // Kmem uses List, which is a intrusive doubly linked list type of rv6. In a intrusive singly
 // linked list, it is impossible to automatically remove an entry from a list when it is dropped.
@@ -65,6 +71,10 @@ unsafe impl ListNode for Run {
 pub struct Kmem {
     #[pin]
     runs: List<Run>,
+    /// Reference counts of physical pages, indexed by `Kmem::index`.
+    refcounts: [u16; NPAGE],
+    /// Number of pages in `runs`.
+    nfree: usize,
 }
 
 impl Kmem {
@@ -74,9 +84,15 @@ impl Kmem {
     pub const unsafe fn new() -> Self {
         Self {
             runs: unsafe { List::new() },
+            refcounts: [0; NPAGE],
+            nfree: 0,
         }
     }
 
+    fn index(pa: PAddr) -> usize {
+        (pa.into_usize() - TargetArch::KERNBASE) / PGSIZE
+    }
+
     /// Create pages between `end` and `PHYSTOP`.
     ///
     /// # Safety
@@ -95,35 +111,83 @@ impl Kmem {
             // * end <= pa < PHYSTOP
             // * the safety condition of this method guarantees that the
             //   created page does not overlap with existing pages
-            self.as_mut().free(unsafe { Page::from_usize(pa) });
+            self.as_mut().push(unsafe { Page::from_usize(pa) });
+        }
+    }
+
+    /// Drops a reference to the given page. The page returns to the free list
+    /// only if it was the last reference.
+    pub fn free(mut self: Pin<&mut Self>, page: Page) {
+        let refcount = &mut self.as_mut().project().refcounts[Self::index(page.addr())];
+        assert!(*refcount > 0, "Kmem::free");
+        *refcount -= 1;
+        if *refcount > 0 {
+            // Other references remain, so forget the page.
+            mem::forget(page);
+            return;
         }
+        self.push(page);
     }
 
-    pub fn free(self: Pin<&mut Self>, mut page: Page) {
+    fn push(self: Pin<&mut Self>, mut page: Page) {
         let run = page.as_uninit_mut();
         // SAFETY: `run` will be initialized by the following `init`.
         let run = run.write(unsafe { Run::new() });
         let mut run = unsafe { Pin::new_unchecked(run) };
         run.as_mut().init();
-        self.project().runs.push_front(run);
+        let this = self.project();
+        this.runs.push_front(run);
+        *this.nfree += 1;
 
         // Since the page has returned to the list, forget the page.
         mem::forget(page);
     }
 
     pub fn alloc(self: Pin<&mut Self>) -> Option<Page> {
-        let run = self.project().runs.pop_front()?;
+        let this = self.project();
+        let run = this.runs.pop_front()?;
+        *this.nfree -= 1;
         // SAFETY: the invariant of `Kmem`.
         let page = unsafe { Page::from_usize(run as _) };
+        this.refcounts[Self::index(page.addr())] = 1;
         Some(page)
     }
+
+    /// Returns another reference to the allocated page at `pa` by
+    /// incrementing its reference count.
+    ///
+    /// # Safety
+    ///
+    /// `pa` must be the address of a page allocated by this `Kmem`. While the
+    /// reference count is larger than 1, the page must not be written through
+    /// any of its references.
+    pub unsafe fn share(self: Pin<&mut Self>, pa: PAddr) -> Page {
+        let refcount = &mut self.project().refcounts[Self::index(pa)];
+        assert!(*refcount > 0, "Kmem::share");
+        *refcount += 1;
+        // SAFETY: the safety condition of this method.
+        unsafe { Page::from_usize(pa.into_usize()) }
+    }
+
+    /// Returns the number of references to the page at `pa`.
+    pub fn refcount(&self, pa: PAddr) -> usize {
+        self.refcounts[Self::index(pa)] as usize
+    }
+
+    /// Returns the number of free pages.
+    pub fn nfree(&self) -> usize {
+        self.nfree
+    }
 }
 
 impl SpinLock<Kmem> {
     pub fn free(self: Pin<&Self>, mut page: Page) {
-        // Fill with junk to catch dangling refs.
-        page.write_bytes(1);
-        self.pinned_lock().get_pin_mut().free(page);
+        let mut guard = self.pinned_lock();
+        if guard.refcount(page.addr()) == 1 {
+            // Fill with junk to catch dangling refs.
+            page.write_bytes(1);
+        }
+        guard.get_pin_mut().free(page);
     }
 
     pub fn alloc(self: Pin<&Self>, init_value: Option<u8>) -> Option<Page> {
@@ -134,4 +198,20 @@ impl SpinLock<Kmem> {
         page.write_bytes(init_value);
         Some(page)
     }
+
+    /// # Safety
+    ///
+    /// See `Kmem::share`.
+    pub unsafe fn share(self: Pin<&Self>, pa: PAddr) -> Page {
+        // SAFETY: the safety condition of this method.
+        unsafe { self.pinned_lock().get_pin_mut().share(pa) }
+    }
+
+    pub fn refcount(self: Pin<&Self>, pa: PAddr) -> usize {
+        self.pinned_lock().refcount(pa)
+    }
+
+    pub fn nfree(self: Pin<&Self>) -> usize {
+        self.pinned_lock().nfree()
+    }
 }
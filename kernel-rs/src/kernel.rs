@@ -11,21 +11,19 @@ use crate::{
     arch::interface::Arch,
     arch::TargetArch,
     bio::Bcache,
-    console::{console_read, console_write},
     cpu::cpuid,
-    file::{Devsw, FileTable},
-    fs::{DefaultFs, FileSystem},
+    device::Devices,
+    file::FileTable,
+    fs::Vfs,
     hal::{hal, hal_init},
     kalloc::Kmem,
     lock::{SleepableLock, SpinLock},
-    param::NDEV,
-    proc::Procs,
+    poll::Pollers,
+    proc::{Futexes, Procs},
     util::{branded::Branded, spin_loop},
     vm::KernelMemory,
 };
 
-const CONSOLE_IN_DEVSW: usize = 1;
-
 /// The kernel.
 static mut KERNEL: Kernel<TargetArch> = unsafe { Kernel::new() };
 
@@ -75,6 +73,10 @@ pub struct Kernel<A: Arch> {
 
     ticks: SleepableLock<u32>,
 
+    futexes: Futexes,
+
+    pollers: Pollers,
+
     /// Current process system.
     #[pin]
     procs: Procs,
@@ -82,13 +84,13 @@ pub struct Kernel<A: Arch> {
     #[pin]
     bcache: Bcache,
 
-    devsw: [Devsw; NDEV],
+    devices: Devices,
 
     #[pin]
     ftable: FileTable,
 
     #[pin]
-    file_system: DefaultFs,
+    file_system: Vfs,
 }
 
 /// A branded reference to a `Kernel`.
@@ -124,6 +126,16 @@ impl<'id, 's> KernelRef<'id, 's> {
         &self.0.as_pin().get_ref().ticks
     }
 
+    /// Returns a reference to the kernel's futex table.
+    pub fn futexes(&self) -> &'s Futexes {
+        &self.0.as_pin().get_ref().futexes
+    }
+
+    /// Returns a reference to the processes waiting for files to become ready.
+    pub fn pollers(&self) -> &'s Pollers {
+        &self.0.as_pin().get_ref().pollers
+    }
+
     pub fn ps(&self) -> Pin<&'s Procs> {
         unsafe { Pin::new_unchecked(&self.0.as_pin().get_ref().procs) }
     }
@@ -132,13 +144,13 @@ impl<'id, 's> KernelRef<'id, 's> {
         unsafe { StrongPin::new_unchecked(&self.0.as_pin().get_ref().bcache) }
     }
 
-    /// Returns a reference to the kernel's `Devsw` array.
-    pub fn devsw(&self) -> &'s [Devsw; NDEV] {
-        &self.0.as_pin().get_ref().devsw
+    /// Returns a reference to the kernel's device registry.
+    pub fn devices(&self) -> &'s Devices {
+        &self.0.as_pin().get_ref().devices
     }
 
-    /// Returns a reference to the kernel's `FileSystem`.
-    pub fn fs(&self) -> StrongPin<'s, DefaultFs> {
+    /// Returns a reference to the kernel's `Vfs`.
+    pub fn fs(&self) -> StrongPin<'s, Vfs> {
         unsafe { StrongPin::new_unchecked(&self.0.as_pin().get_ref().file_system) }
     }
 
@@ -164,14 +176,13 @@ impl<A: Arch> Kernel<A> {
             panicked: AtomicBool::new(false),
             memory: MaybeUninit::uninit(),
             ticks: SleepableLock::new("time", 0),
+            futexes: Futexes::new(),
+            pollers: Pollers::new(),
             procs: Procs::new(),
             bcache: unsafe { Bcache::new_bcache() },
-            devsw: [Devsw {
-                read: None,
-                write: None,
-            }; NDEV],
+            devices: Devices::new(),
             ftable: FileTable::new_ftable(),
-            file_system: DefaultFs::new(),
+            file_system: Vfs::new(),
         }
     }
 
@@ -185,11 +196,9 @@ impl<A: Arch> Kernel<A> {
 
         let mut this = self.project();
 
-        // Connect read and write system calls to consoleread and consolewrite.
-        this.devsw[CONSOLE_IN_DEVSW] = Devsw {
-            read: Some(console_read),
-            write: Some(console_write),
-        };
+        // Register the device drivers, connecting read, write, and ioctl system calls
+        // on device files to them.
+        this.devices.init();
 
         // Create kernel memory manager.
         let memory = KernelMemory::new(allocator).expect("PageTable::new failed");
@@ -52,6 +52,8 @@ mod arena;
 mod bio;
 mod console;
 mod cpu;
+mod device;
+mod errno;
 mod exec;
 mod file;
 mod fs;
@@ -63,9 +65,11 @@ mod memlayout;
 mod page;
 mod param;
 mod pipe;
+mod poll;
 mod proc;
 mod start;
 mod syscall;
+mod time;
 mod trap;
 mod util;
 mod virtio;
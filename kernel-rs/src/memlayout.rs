@@ -21,6 +21,7 @@
 
 use crate::addr::{MAXVA, PGSIZE};
 use crate::arch::{interface::MemLayout, TargetArch};
+use crate::param::NTHREAD;
 
 /// User memory layout.
 /// Address zero first:
@@ -29,10 +30,22 @@ use crate::arch::{interface::MemLayout, TargetArch};
 ///   fixed-size stack
 ///   expandable heap
 ///   ...
+///   memory-mapped areas
+///   USERTOP
+///   trap frames of the other threads
 ///   TRAPFRAME (p->trapframe, used by the trampoline)
 ///   TRAMPOLINE (the same page as in the kernel)
 pub const TRAPFRAME: usize = TRAMPOLINE.wrapping_sub(PGSIZE);
 
+/// map the trap frame of each thread of a process beneath TRAMPOLINE.
+/// The main thread uses slot 0, which is TRAPFRAME.
+pub const fn trapframe(slot: usize) -> usize {
+    TRAPFRAME - slot * PGSIZE
+}
+
+/// user memory lies below the trap frames.
+pub const USERTOP: usize = trapframe(NTHREAD - 1);
+
 /// map the trampoline page to the highest address,
 /// in both user and kernel space.
 pub const TRAMPOLINE: usize = MAXVA.wrapping_sub(PGSIZE);
@@ -34,6 +34,8 @@ impl RawPage {
 /// - end <= inner < PHYSTOP
 /// - Two different pages never overwrap. If p1: Page and p2: Page, then
 ///   *(p1.inner).inner and *(p1.inner).inner are non-overwrapping arrays.
+///   The only exception is a page shared by `Kmem::share`, which must not be
+///   written while it is shared.
 pub struct Page {
     inner: NonNull<RawPage>,
 }
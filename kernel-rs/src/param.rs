@@ -1,14 +1,21 @@
-use cfg_if::cfg_if;
-
 /// Maximum number of processes.
 pub const NPROC: usize = 64;
 
+/// Maximum number of threads per process.
+pub const NTHREAD: usize = 16;
+
 /// Maximum number of CPUs.
 pub const NCPU: usize = 8;
 
 /// Open files per process.
 pub const NOFILE: usize = 16;
 
+/// Memory-mapped areas per process.
+pub const NVMA: usize = 16;
+
+/// Futexes that processes can sleep on at the same time.
+pub const NFUTEX: usize = 64;
+
 /// Open files per system.
 pub const NFILE: usize = 100;
 
@@ -18,9 +25,30 @@ pub const NINODE: usize = 50;
 /// Maximum major device number.
 pub const NDEV: usize = 10;
 
+/// Maximum number of device nodes that the devfs shows.
+pub const NDEVNODE: usize = 16;
+
+/// Number of virtio disks. The disk `i` is the device `i + 1`.
+pub const NDISK: usize = 2;
+
 /// Device number of file system root disk.
 pub const ROOTDEV: u32 = 1;
 
+/// Maximum number of mounted file systems.
+pub const NMOUNT: usize = 8;
+
+/// Device number of the tmpfs, which is not on a disk.
+pub const TMPFSDEV: u32 = NDISK as u32 + 1;
+
+/// Maximum number of inodes of the tmpfs.
+pub const NTMPINODE: usize = 64;
+
+/// Device number of the procfs, which is not on a disk.
+pub const PROCFSDEV: u32 = TMPFSDEV + 1;
+
+/// Device number of the devfs, which is not on a disk.
+pub const DEVFSDEV: u32 = PROCFSDEV + 1;
+
 /// Max exec arguments.
 pub const MAXARG: usize = 32;
 
@@ -31,38 +59,40 @@ pub const BSIZE: usize = 1024;
 /// Will be handled in #31.
 pub const MAXOPBLOCKS: usize = 10;
 
-cfg_if! {
-    if #[cfg(feature = "lfs")] {
-        // TODO: The following may be actually unknown at compile time.
-
-        /// Size of a segment in blocks
-        ///
-        /// An optimal size of segments for LFS is dependent to
-        /// the performance of a disk and a desired effective bandwith of developers.
-        /// Check the formula for getting the size of segments here:
-        /// https://pages.cs.wisc.edu/~remzi/OSTEP/file-lfs.pdf
-        ///
-        /// TODO: optimize the size of the segment.
-        /// Note that this is much smaller than in sprite-lfs. sprite-lfs uses segments
-        /// of size 512KB ~ 1MB.
-        pub const SEGSIZE: usize = 10;
-
-        /// Size of the imap in blocks
-        pub const IMAPSIZE: usize = 1;
-
-        /// Size of the segment usage table in bytes
-        pub const SEGTABLESIZE: usize = 64;
-    } else {
-        /// Max data blocks in on-disk log.
-        pub const LOGSIZE: usize = MAXOPBLOCKS * 3;
-    }
-}
+// TODO: The following may be actually unknown at compile time.
+
+/// Size of a segment in blocks
+///
+/// An optimal size of segments for LFS is dependent to
+/// the performance of a disk and a desired effective bandwith of developers.
+/// Check the formula for getting the size of segments here:
+/// https://pages.cs.wisc.edu/~remzi/OSTEP/file-lfs.pdf
+///
+/// TODO: optimize the size of the segment.
+/// Note that this is much smaller than in sprite-lfs. sprite-lfs uses segments
+/// of size 512KB ~ 1MB.
+pub const SEGSIZE: usize = 10;
+
+/// Size of the imap in blocks
+pub const IMAPSIZE: usize = 1;
+
+/// Size of the segment usage table in bytes
+pub const SEGTABLESIZE: usize = 252;
+
+/// Max data blocks in on-disk log.
+pub const LOGSIZE: usize = MAXOPBLOCKS * 3;
 
 /// Size of disk block cache.
 pub const NBUF: usize = MAXOPBLOCKS * 3;
 
 /// Maximum file path name.
-pub const MAXPATH: usize = 128;
+pub const MAXPATH: usize = 256;
+
+/// Maximum number of symbolic links followed in a path name lookup.
+pub const MAXSYMLINKS: usize = 8;
+
+/// Clock ticks per second. Timers interrupt every 100ms.
+pub const TICKS_PER_SEC: u32 = 10;
 
 /// Maximum length of process name.
 pub const MAXPROCNAME: usize = 16;
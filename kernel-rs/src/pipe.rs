@@ -2,10 +2,12 @@ use core::{mem, ops::Deref, ptr::NonNull};
 
 use crate::{
     addr::UVAddr,
+    errno::Errno,
     file::{FileType, RcFile, SelectEvent},
     hal::hal,
     lock::SpinLock,
     page::Page,
+    poll::PollQueue,
     proc::{KernelCtx, WaitChannel},
 };
 
@@ -35,38 +37,58 @@ pub struct Pipe {
 
     /// WaitChannel for saying all bytes in Pipe.data are already read.
     write_waitchannel: WaitChannel,
+
+    /// Notified whenever either end may have become ready.
+    pub queue: PollQueue,
 }
 
 impl Pipe {
     /// Tries to read up to `n` bytes using `Pipe::try_read()`.
-    /// If successfully read i > 0 bytes, wakeups the `write_waitchannel` and returns `Ok(i: usize)`.
-    /// If the pipe was empty, sleeps at `read_waitchannel` and tries again after wakeup.
-    /// If an error happened, returns `Err(())`.
-    pub fn read(&self, addr: UVAddr, n: usize, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, ()> {
+    /// If successfully read i > 0 bytes, wakeups the `write_waitchannel` and the pollers, and
+    /// returns `Ok(i: usize)`.
+    /// If the pipe was empty, sleeps at `read_waitchannel` and tries again after wakeup,
+    /// or returns `Err(EAGAIN)` if `nonblock` is true.
+    /// If the process was killed or a signal is pending, returns `Err(EINTR)`.
+    pub fn read(
+        &self,
+        addr: UVAddr,
+        n: usize,
+        nonblock: bool,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, Errno> {
         let mut inner = self.inner.lock();
         loop {
             match inner.try_read(addr, n, ctx) {
                 Ok(r) => {
                     //DOC: piperead-wakeup
                     self.write_waitchannel.wakeup(ctx.kernel());
+                    self.queue.notify(ctx.kernel());
                     return Ok(r);
                 }
-                Err(PipeError::WaitForIO) => {
+                Err(PipeError::WaitForIO) if !nonblock => {
                     //DOC: piperead-sleep
                     self.read_waitchannel.sleep(&mut inner, ctx);
                 }
-                _ => return Err(()),
+                Err(err) => return Err(err.into()),
             }
         }
     }
 
     /// Tries to write up to `n` bytes by repeatedly calling `Pipe::try_write()`.
-    /// Wakeups `read_waitchannel` for every successful `Pipe::try_write()`.
+    /// Wakeups `read_waitchannel` and the pollers for every successful `Pipe::try_write()`.
     /// After successfully writing i >= 0 bytes, returns `Ok(i)`.
     /// Note that we may have i < `n` if an copy-in error happened.
     /// If the pipe was full, sleeps at `write_waitchannel` and tries again after wakeup.
-    /// If an error happened, returns `Err(())`.
-    pub fn write(&self, addr: UVAddr, n: usize, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, ()> {
+    /// If `nonblock` is true, returns `Ok(i)` instead, or `Err(EAGAIN)` if i = 0.
+    /// If the read end was closed, returns `Err(EPIPE)`. If the process was killed or
+    /// a signal is pending, returns `Err(EINTR)`, or `Ok(i)` if i > 0.
+    pub fn write(
+        &self,
+        addr: UVAddr,
+        n: usize,
+        nonblock: bool,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, Errno> {
         let mut written = 0;
         let mut inner = self.inner.lock();
         loop {
@@ -74,17 +96,28 @@ impl Pipe {
                 Ok(r) => {
                     written += r;
                     self.read_waitchannel.wakeup(ctx.kernel());
-                    if written < n {
+                    self.queue.notify(ctx.kernel());
+                    if written == n {
+                        return Ok(written);
+                    } else if !nonblock {
                         self.write_waitchannel.sleep(&mut inner, ctx);
-                    } else {
+                    } else if written > 0 {
                         return Ok(written);
+                    } else {
+                        return Err(Errno::EAGAIN);
                     }
                 }
                 Err(PipeError::InvalidCopyin(i)) => {
                     self.read_waitchannel.wakeup(ctx.kernel());
+                    self.queue.notify(ctx.kernel());
+                    if written + i == 0 {
+                        return Err(Errno::EFAULT);
+                    }
                     return Ok(written + i);
                 }
-                _ => return Err(()),
+                // A signal interrupts the write after some bytes have been written.
+                Err(PipeError::InvalidStatus) if written > 0 => return Ok(written),
+                Err(err) => return Err(err.into()),
             }
         }
     }
@@ -99,6 +132,8 @@ impl Pipe {
             inner.readopen = false;
             self.write_waitchannel.wakeup(ctx.kernel());
         }
+        // The other end is now ready, as reading or writing it does not block.
+        self.queue.notify(ctx.kernel());
 
         // Return whether pipe should be freed or not.
         !inner.readopen && !inner.writeopen
@@ -130,9 +165,9 @@ impl Deref for AllocatedPipe {
 }
 
 impl KernelCtx<'_, '_> {
-    pub fn allocate_pipe(&self) -> Result<(RcFile, RcFile), ()> {
+    pub fn allocate_pipe(&self) -> Result<(RcFile, RcFile), Errno> {
         let allocator = hal().kmem();
-        let page = allocator.alloc(None).ok_or(())?;
+        let page = allocator.alloc(None).ok_or(Errno::ENOMEM)?;
         let mut page = scopeguard::guard(page, |page| allocator.free(page));
         let ptr = page.as_uninit_mut();
 
@@ -151,6 +186,7 @@ impl KernelCtx<'_, '_> {
             ),
             read_waitchannel: WaitChannel::new(),
             write_waitchannel: WaitChannel::new(),
+            queue: PollQueue::new(),
         }));
         let f0 = self.kernel().ftable().alloc_file(
             FileType::Pipe {
@@ -196,12 +232,25 @@ impl AllocatedPipe {
 pub enum PipeError {
     WaitForIO,
     InvalidStatus,
+    Broken,
     InvalidCopyin(usize),
 }
 
+impl From<PipeError> for Errno {
+    fn from(err: PipeError) -> Self {
+        match err {
+            PipeError::WaitForIO => Errno::EAGAIN,
+            PipeError::InvalidStatus => Errno::EINTR,
+            PipeError::Broken => Errno::EPIPE,
+            PipeError::InvalidCopyin(_) => Errno::EFAULT,
+        }
+    }
+}
+
 impl PipeInner {
     /// Tries to write up to `n` bytes.
-    /// If the process was killed, returns `Err(InvalidStatus)`.
+    /// If the read end was closed, returns `Err(Broken)`.
+    /// If the process was killed or a signal is pending, returns `Err(InvalidStatus)`.
     /// If an copy-in error happened after successfully writing i >= 0 bytes, returns `Err(InvalidCopyIn(i))`.
     /// Otherwise, returns `Ok(i)` after successfully writing i >= 0 bytes.
     fn try_write(
@@ -211,7 +260,10 @@ impl PipeInner {
         ctx: &mut KernelCtx<'_, '_>,
     ) -> Result<usize, PipeError> {
         let mut ch = [0u8];
-        if !self.readopen || ctx.proc().killed() {
+        if !self.readopen {
+            return Err(PipeError::Broken);
+        }
+        if ctx.proc().killed() || ctx.proc().signal_pending() {
             return Err(PipeError::InvalidStatus);
         }
         for i in 0..n {
@@ -220,8 +272,8 @@ impl PipeInner {
                 return Ok(i);
             }
             if ctx
-                .proc_mut()
-                .memory_mut()
+                .proc()
+                .memory()
                 .copy_in_bytes(&mut ch, addr + i)
                 .is_err()
             {
@@ -236,7 +288,7 @@ impl PipeInner {
     /// Tries to read up to `n` bytes.
     /// If successful read i > 0 bytes, returns `Ok(i: usize)`.
     /// If the pipe was empty, returns `Err(WaitForIO)`.
-    /// If the process was killed, returns `Err(InvalidStatus)`.
+    /// If the process was killed or a signal is pending, returns `Err(InvalidStatus)`.
     fn try_read(
         &mut self,
         addr: UVAddr,
@@ -245,7 +297,7 @@ impl PipeInner {
     ) -> Result<usize, PipeError> {
         //DOC: pipe-empty
         if self.nread == self.nwrite && self.writeopen {
-            if ctx.proc().killed() {
+            if ctx.proc().killed() || ctx.proc().signal_pending() {
                 return Err(PipeError::InvalidStatus);
             }
             return Err(PipeError::WaitForIO);
@@ -258,12 +310,7 @@ impl PipeInner {
             }
             let ch = [self.data[self.nread as usize % PIPESIZE]];
             self.nread = self.nread.wrapping_add(1);
-            if ctx
-                .proc_mut()
-                .memory_mut()
-                .copy_out_bytes(addr + i, &ch)
-                .is_err()
-            {
+            if ctx.proc().memory().copy_out_bytes(addr + i, &ch).is_err() {
                 return Ok(i);
             }
         }
@@ -272,35 +319,40 @@ impl PipeInner {
 
     fn is_ready(&self, event: SelectEvent) -> bool {
         match event {
-            SelectEvent::Read => self.nread != self.nwrite,
-            _ => unimplemented!(),
+            // Reading does not wait at the end of the file either.
+            SelectEvent::Read => self.nread != self.nwrite || !self.writeopen,
+            // Writing fails without waiting if the read end was closed.
+            SelectEvent::Write => {
+                self.nwrite != self.nread.wrapping_add(PIPESIZE as u32) || !self.readopen
+            }
+            SelectEvent::Error => false,
         }
     }
 }
 
 impl KernelCtx<'_, '_> {
     /// Create a pipe, put read/write file descriptors in fd0 and fd1.
-    /// Returns Ok(()) on success, Err(()) on error.
-    pub fn pipe(&mut self, fdarray: UVAddr) -> Result<(), ()> {
+    /// Returns Ok(()) on success, Err(errno) on error.
+    pub fn pipe(&mut self, fdarray: UVAddr) -> Result<(), Errno> {
         let (pipereader, pipewriter) = self.allocate_pipe()?;
 
-        let fd1 = if let Ok(fd) = pipereader.fdalloc(self) {
-            fd
-        } else {
-            pipewriter.free(self);
-            return Err(());
+        let fd1 = match pipereader.fdalloc(self) {
+            Ok(fd) => fd,
+            Err(err) => {
+                pipewriter.free(self);
+                return Err(err);
+            }
         };
 
-        let fd2 = if let Ok(fd) = pipewriter.fdalloc(self) {
-            fd
-        } else {
-            self.proc_mut().deref_mut_data().open_files[fd1 as usize]
-                .take()
-                .unwrap()
-                .free(self);
-            return Err(());
+        let fd2 = match pipewriter.fdalloc(self) {
+            Ok(fd) => fd,
+            Err(err) => {
+                let pipereader = self.proc().open_files()[fd1 as usize].take();
+                pipereader.unwrap().free(self);
+                return Err(err);
+            }
         };
 
-        self.proc_mut().memory_mut().copy_out(fdarray, &[fd1, fd2])
+        self.copy_out(fdarray, &[fd1, fd2])
     }
 }
@@ -0,0 +1,598 @@
+//! Waiting for files to become ready, for `select`, `poll`, and the epoll interface.
+//!
+//! A file that can block its readers or writers, such as a pipe or the console, has a
+//! `PollQueue` of the processes waiting for it, and notifies the queue whenever it may have
+//! become ready. A process waiting in `Pollers::poll` adds itself to the queues of the files
+//! that it checks, and sleeps on a `WaitChannel` of its own until one of them notifies it
+//! or its timeout expires. Neither a change of another file nor a tick before the timeout
+//! wakes it up.
+//!
+//! An epoll instance is a file that keeps the file descriptors that it watches, so that
+//! `epoll_wait` does not need the whole set from the user every time. It watches the file
+//! descriptors of the process that calls `epoll_wait`. A watch belongs to the open file
+//! that the file descriptor referred to when it was added, and is dropped once the file
+//! descriptor no longer refers to that file, e.g., after it is closed, so that a file
+//! opened later with the same file descriptor is not watched. It is level-triggered only.
+
+use core::{
+    cmp, mem,
+    ops::Deref,
+    ptr::NonNull,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use array_macro::array;
+use arrayvec::ArrayVec;
+use bitflags::bitflags;
+use static_assertions::const_assert;
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::{
+    addr::UVAddr,
+    errno::Errno,
+    file::{File, FileType, RcFile, SelectEvent},
+    hal::hal,
+    kernel::KernelRef,
+    lock::SpinLock,
+    page::Page,
+    param::{NOFILE, NPROC, TICKS_PER_SEC},
+    proc::{KernelCtx, WaitChannel},
+    some_or,
+};
+
+// A `PollQueue` has a bit for each slot of `Pollers`.
+const_assert!(NPROC <= 64);
+
+/// `op` of `epoll_ctl`.
+pub const EPOLL_CTL_ADD: i32 = 1;
+pub const EPOLL_CTL_DEL: i32 = 2;
+pub const EPOLL_CTL_MOD: i32 = 3;
+
+bitflags! {
+    /// Events of `struct pollfd` and `struct epoll_event`.
+    pub struct PollEvents: u32 {
+        /// Reading would not block.
+        const IN = 0x1;
+        /// There is an exceptional condition.
+        const PRI = 0x2;
+        /// Writing would not block.
+        const OUT = 0x4;
+        /// The file descriptor is not open. Only `poll` reports it.
+        const NVAL = 0x20;
+    }
+}
+
+/// `struct pollfd` of the user.
+#[derive(Clone, Copy, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct PollFd {
+    fd: i32,
+    events: i16,
+    revents: i16,
+}
+
+/// `struct epoll_event` of the user.
+#[derive(Clone, Copy, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct EpollEvent {
+    events: u32,
+    _pad: u32,
+    data: u64,
+}
+
+/// The processes waiting for a file to become ready, which the file notifies.
+pub struct PollQueue {
+    /// Has the bit `i` set if the waiter in the slot `i` of `Pollers` waits for the file.
+    waiters: AtomicU64,
+}
+
+#[derive(Clone, Copy)]
+struct Waiter {
+    used: bool,
+
+    /// Whether a file of the waiter may have become ready since it last checked them.
+    woken: bool,
+
+    /// The tick when the waiter started waiting.
+    start: u32,
+
+    /// Number of ticks after `start` until the timeout, or None if there is no timeout.
+    timeout: Option<u32>,
+}
+
+/// The processes waiting for files to become ready.
+///
+/// # Safety
+///
+/// The waiter in `waiters[i]` sleeps on `channels[i]`.
+pub struct Pollers {
+    waiters: SpinLock<[Waiter; NPROC]>,
+    channels: [WaitChannel; NPROC],
+}
+
+/// The files that a waiter of `Pollers::poll` has checked since it last slept, whose
+/// queues have the waiter.
+pub struct PollTable {
+    /// The slot of the waiter in `Pollers`.
+    slot: usize,
+    files: ArrayVec<RcFile, NOFILE>,
+
+    /// Whether some files did not fit in `files`, in which case the waiter checks its files
+    /// again without sleeping.
+    overflowed: bool,
+}
+
+/// What an epoll instance watches a file descriptor for.
+#[derive(Clone, Copy)]
+struct Watch {
+    /// `File::id` of the watched file.
+    file: u64,
+    events: PollEvents,
+    data: u64,
+}
+
+pub struct Epoll {
+    /// The watches of each file descriptor.
+    watches: SpinLock<[Option<Watch>; NOFILE]>,
+
+    /// Notified when the watches change.
+    pub queue: PollQueue,
+}
+
+/// # Safety
+///
+/// `ptr` always refers to an `Epoll` stored in a page allocated from `Kmem::alloc`, and only
+/// a single `File` has it.
+pub struct AllocatedEpoll {
+    ptr: NonNull<Epoll>,
+}
+
+// `AllocatedEpoll` is `Send` because we access the watches only after acquiring a lock
+// and because `AllocatedEpoll` does not point to thread-local data.
+unsafe impl Send for AllocatedEpoll {}
+
+/// Returns the number of ticks in `timeout` milliseconds, rounded up, or None if `timeout`
+/// is negative, which means no timeout.
+fn timeout_ticks(timeout: i32) -> Option<u32> {
+    let ms = u64::try_from(timeout).ok()?;
+    Some(((ms * u64::from(TICKS_PER_SEC) + 999) / 1000) as u32)
+}
+
+impl PollEvents {
+    /// Returns the events of `self` that the file `f` is ready for.
+    fn ready(self, f: &File, ctx: &KernelCtx<'_, '_>) -> Result<Self, Errno> {
+        let events = [
+            (Self::IN, SelectEvent::Read),
+            (Self::OUT, SelectEvent::Write),
+            (Self::PRI, SelectEvent::Error),
+        ];
+        let mut revents = Self::empty();
+        for (flag, event) in events {
+            if self.contains(flag) && f.is_ready(event, ctx)? {
+                revents |= flag;
+            }
+        }
+        Ok(revents)
+    }
+}
+
+impl PollQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: AtomicU64::new(0),
+        }
+    }
+
+    fn add(&self, slot: usize) {
+        let _ = self.waiters.fetch_or(1 << slot, Ordering::SeqCst);
+    }
+
+    fn remove(&self, slot: usize) {
+        let _ = self.waiters.fetch_and(!(1 << slot), Ordering::SeqCst);
+    }
+
+    /// Wakes up the processes waiting for the file, which may have become ready.
+    pub fn notify(&self, kernel: KernelRef<'_, '_>) {
+        let mask = self.waiters.load(Ordering::SeqCst);
+        if mask != 0 {
+            kernel.pollers().wake(mask, kernel);
+        }
+    }
+}
+
+impl Waiter {
+    const fn new() -> Self {
+        Self {
+            used: false,
+            woken: false,
+            start: 0,
+            timeout: None,
+        }
+    }
+
+    /// Has the timeout expired at the tick `now`?
+    fn expired(&self, now: u32) -> bool {
+        matches!(self.timeout, Some(timeout) if now.wrapping_sub(self.start) >= timeout)
+    }
+}
+
+impl Pollers {
+    pub const fn new() -> Self {
+        Self {
+            waiters: SpinLock::new("pollers", [Waiter::new(); NPROC]),
+            channels: array![_ => WaitChannel::new(); NPROC],
+        }
+    }
+
+    /// Calls `check` until it returns a nonzero number of ready files or an error, and
+    /// returns what it returns. `check` adds the files that it checks to the `PollTable`,
+    /// and the current process sleeps between the calls until one of them may have become
+    /// ready. Returns Ok(0) once `timeout` ticks pass if `timeout` is not None, and
+    /// Err(EINTR) if a signal arrives.
+    pub fn poll<F>(
+        &self,
+        timeout: Option<u32>,
+        ctx: &KernelCtx<'_, '_>,
+        mut check: F,
+    ) -> Result<usize, Errno>
+    where
+        F: FnMut(&mut PollTable) -> Result<usize, Errno>,
+    {
+        let start = *ctx.kernel().ticks().lock();
+        let mut waiters = self.waiters.lock();
+        let slot = waiters.iter().position(|w| !w.used).ok_or(Errno::ENOMEM)?;
+        waiters[slot] = Waiter {
+            used: true,
+            woken: false,
+            start,
+            timeout,
+        };
+        drop(waiters);
+
+        let mut table = PollTable {
+            slot,
+            files: ArrayVec::new(),
+            overflowed: false,
+        };
+        let result = 'poll: loop {
+            // A notification from now on makes the waiter check its files again.
+            self.waiters.lock()[slot].woken = false;
+            let result = check(&mut table);
+            if !matches!(result, Ok(0)) {
+                break result;
+            }
+
+            let mut waiters = self.waiters.lock();
+            loop {
+                if waiters[slot].expired(*ctx.kernel().ticks().lock()) {
+                    break 'poll Ok(0);
+                }
+                if ctx.proc().killed() || ctx.proc().signal_pending() {
+                    break 'poll Err(Errno::EINTR);
+                }
+                if waiters[slot].woken || table.overflowed {
+                    break;
+                }
+                self.channels[slot].sleep(&mut waiters, ctx);
+            }
+            drop(waiters);
+            table.clear(ctx);
+        };
+
+        table.clear(ctx);
+        self.waiters.lock()[slot] = Waiter::new();
+        result
+    }
+
+    /// Wakes up the waiters in the slots of `mask`.
+    fn wake(&self, mask: u64, kernel: KernelRef<'_, '_>) {
+        let mut waiters = self.waiters.lock();
+        for (i, waiter) in waiters.iter_mut().enumerate() {
+            if mask & (1 << i) != 0 && waiter.used {
+                waiter.woken = true;
+                self.channels[i].wakeup(kernel);
+            }
+        }
+    }
+
+    /// Wakes up the waiters whose timeout has expired at the tick `now`. Called on every tick.
+    pub fn expire(&self, now: u32, kernel: KernelRef<'_, '_>) {
+        let waiters = self.waiters.lock();
+        for (waiter, channel) in waiters.iter().zip(self.channels.iter()) {
+            if waiter.used && waiter.expired(now) {
+                channel.wakeup(kernel);
+            }
+        }
+    }
+}
+
+impl PollTable {
+    /// Makes the file `f` notify the waiter when it may have become ready, together with
+    /// the files that `f` watches if it is an epoll instance.
+    pub fn add(&mut self, f: &RcFile, ctx: &KernelCtx<'_, '_>) {
+        if self.files.iter().any(|g| g.id() == f.id()) {
+            return;
+        }
+        let queue = some_or!(f.poll_queue(ctx), return);
+        if self.files.is_full() {
+            self.overflowed = true;
+            return;
+        }
+        queue.add(self.slot);
+        self.files.push(f.clone());
+        if let FileType::Epoll { epoll } = &f.typ {
+            epoll.register(self, ctx);
+        }
+    }
+
+    /// Removes the waiter from the queues of the files.
+    fn clear(&mut self, ctx: &KernelCtx<'_, '_>) {
+        for f in self.files.drain(..) {
+            if let Some(queue) = f.poll_queue(ctx) {
+                queue.remove(self.slot);
+            }
+            f.free(ctx);
+        }
+        self.overflowed = false;
+    }
+}
+
+impl Epoll {
+    /// Returns the file of the watch `watch` of the file descriptor `fd`, or None after
+    /// dropping the watch if `fd` no longer refers to the watched file.
+    fn file(&self, fd: usize, watch: &Watch, ctx: &KernelCtx<'_, '_>) -> Option<RcFile> {
+        if let Ok(f) = ctx.proc().getfile(fd as i32) {
+            if f.id() == watch.file {
+                return Some(f);
+            }
+            f.free(ctx);
+        }
+        let mut watches = self.watches.lock();
+        if matches!(watches[fd], Some(w) if w.file == watch.file) {
+            watches[fd] = None;
+        }
+        None
+    }
+
+    /// Writes the events that the watched files are ready for into `out`, and returns how
+    /// many it wrote.
+    fn collect(&self, out: &mut [EpollEvent], ctx: &KernelCtx<'_, '_>) -> Result<usize, Errno> {
+        let watches = *self.watches.lock();
+        let mut n = 0;
+        for (fd, watch) in watches.iter().enumerate() {
+            if n == out.len() {
+                break;
+            }
+            let watch = some_or!(*watch, continue);
+            let f = some_or!(self.file(fd, &watch, ctx), continue);
+            let ready = watch.events.ready(&f, ctx);
+            f.free(ctx);
+            let ready = ready?;
+            if !ready.is_empty() {
+                out[n] = EpollEvent {
+                    events: ready.bits(),
+                    _pad: 0,
+                    data: watch.data,
+                };
+                n += 1;
+            }
+        }
+        Ok(n)
+    }
+
+    /// Adds the watched files to `table`.
+    fn register(&self, table: &mut PollTable, ctx: &KernelCtx<'_, '_>) {
+        let watches = *self.watches.lock();
+        for (fd, watch) in watches.iter().enumerate() {
+            let watch = some_or!(*watch, continue);
+            let f = some_or!(self.file(fd, &watch, ctx), continue);
+            table.add(&f, ctx);
+            f.free(ctx);
+        }
+    }
+
+    /// Is any of the watched files ready?
+    pub fn is_ready(&self, ctx: &KernelCtx<'_, '_>) -> Result<bool, Errno> {
+        let mut out = [EpollEvent::default()];
+        Ok(self.collect(&mut out, ctx)? > 0)
+    }
+}
+
+impl Deref for AllocatedEpoll {
+    type Target = Epoll;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: `ptr` always refers to an `Epoll`.
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl AllocatedEpoll {
+    /// Returns the page of the `Epoll`, which the caller should free.
+    pub fn close(self) -> Page {
+        // SAFETY: `ptr` holds an `Epoll` stored in a valid page allocated from `Kmem::alloc`,
+        // and only the closed file had it.
+        unsafe { Page::from_usize(self.ptr.as_ptr() as _) }
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Wait for the `nfds` file descriptors of the `struct pollfd`s at `fds` to become
+    /// ready for their events, up to `timeout` milliseconds. A `timeout` of 0 only checks
+    /// them, and a negative `timeout` waits without a limit.
+    /// Returns Ok(number of the ready file descriptors) on success, Err(errno) on error.
+    pub fn poll(&mut self, addr: UVAddr, nfds: usize, timeout: i32) -> Result<usize, Errno> {
+        if nfds > NOFILE {
+            return Err(Errno::EINVAL);
+        }
+        let mut fds = [PollFd::default(); NOFILE];
+        let fds = &mut fds[..nfds];
+        self.copy_in_bytes(fds.as_bytes_mut(), addr)?;
+
+        let ctx: &KernelCtx<'_, '_> = self;
+        let ready = ctx
+            .kernel()
+            .pollers()
+            .poll(timeout_ticks(timeout), ctx, |table| {
+                let mut ready = 0;
+                for pfd in fds.iter_mut() {
+                    pfd.revents = 0;
+                    // A negative file descriptor is ignored.
+                    if pfd.fd < 0 {
+                        continue;
+                    }
+                    let revents = match ctx.proc().getfile(pfd.fd) {
+                        Ok(f) => {
+                            table.add(&f, ctx);
+                            let events =
+                                PollEvents::from_bits_truncate(u32::from(pfd.events as u16));
+                            let revents = events.ready(&f, ctx);
+                            f.free(ctx);
+                            revents?
+                        }
+                        Err(_) => PollEvents::NVAL,
+                    };
+                    if !revents.is_empty() {
+                        pfd.revents = revents.bits() as i16;
+                        ready += 1;
+                    }
+                }
+                Ok(ready)
+            })?;
+
+        self.copy_out_bytes(addr, fds.as_bytes())?;
+        Ok(ready)
+    }
+
+    /// Create an epoll instance, and put its file descriptor in the returned value.
+    /// Returns Ok(file descriptor) on success, Err(errno) on error.
+    pub fn epoll_create(&mut self) -> Result<usize, Errno> {
+        let allocator = hal().kmem();
+        let page = allocator.alloc(None).ok_or(Errno::ENOMEM)?;
+        let mut page = scopeguard::guard(page, |page| allocator.free(page));
+        let ptr = page.as_uninit_mut();
+        let ptr = NonNull::from(ptr.write(Epoll {
+            watches: SpinLock::new("epoll", [None; NOFILE]),
+            queue: PollQueue::new(),
+        }));
+        let f = self.kernel().ftable().alloc_file(
+            FileType::Epoll {
+                epoll: AllocatedEpoll { ptr },
+            },
+            true,
+            false,
+        )?;
+
+        // Since the file has been created successfully, the file frees the page when it is closed.
+        mem::forget(scopeguard::ScopeGuard::into_inner(page));
+        let fd = f.fdalloc(self)?;
+        Ok(fd as usize)
+    }
+
+    /// Add (EPOLL_CTL_ADD), change (EPOLL_CTL_MOD), or remove (EPOLL_CTL_DEL) the watch of
+    /// the epoll instance `epfd` on the file descriptor `fd`, using the events and the data
+    /// of the `struct epoll_event` at `addr` unless removing it.
+    /// Returns Ok(()) on success, Err(errno) on error.
+    pub fn epoll_ctl(&mut self, epfd: i32, op: i32, fd: i32, addr: UVAddr) -> Result<(), Errno> {
+        let ep = self.proc().getfile(epfd)?;
+        let result = self.epoll_ctl_file(&ep, op, fd, addr);
+        ep.free(self);
+        result
+    }
+
+    fn epoll_ctl_file(&mut self, ep: &RcFile, op: i32, fd: i32, addr: UVAddr) -> Result<(), Errno> {
+        let epoll = match &ep.typ {
+            FileType::Epoll { epoll } => epoll,
+            _ => return Err(Errno::EINVAL),
+        };
+        if !matches!(op, EPOLL_CTL_ADD | EPOLL_CTL_MOD | EPOLL_CTL_DEL) {
+            return Err(Errno::EINVAL);
+        }
+        let f = self.proc().getfile(fd)?;
+        let id = f.id();
+        let is_epoll = matches!(f.typ, FileType::Epoll { .. });
+        f.free(self);
+        // Epoll instances cannot be nested.
+        if is_epoll {
+            return Err(Errno::EINVAL);
+        }
+
+        let watch = if op == EPOLL_CTL_DEL {
+            None
+        } else {
+            let mut event = EpollEvent::default();
+            // SAFETY: EpollEvent consists of integer fields only.
+            unsafe { self.copy_in(&mut event, addr) }?;
+            Some(Watch {
+                file: id,
+                events: PollEvents::from_bits_truncate(event.events) - PollEvents::NVAL,
+                data: event.data,
+            })
+        };
+
+        let mut watches = epoll.watches.lock();
+        let slot = &mut watches[fd as usize];
+        // The watch of a closed file is gone, even if `fd` refers to another file now.
+        if matches!(slot, Some(w) if w.file != id) {
+            *slot = None;
+        }
+        match op {
+            EPOLL_CTL_ADD if slot.is_some() => return Err(Errno::EEXIST),
+            EPOLL_CTL_MOD | EPOLL_CTL_DEL if slot.is_none() => return Err(Errno::ENOENT),
+            _ => *slot = watch,
+        }
+        drop(watches);
+        // The file may already be ready.
+        epoll.queue.notify(self.kernel());
+        Ok(())
+    }
+
+    /// Wait for the files that the epoll instance `epfd` watches to become ready, up to
+    /// `timeout` milliseconds, and write the `struct epoll_event`s of at most `maxevents`
+    /// ready files to `addr`. A `timeout` of 0 only checks them, and a negative `timeout`
+    /// waits without a limit.
+    /// Returns Ok(number of the ready files) on success, Err(errno) on error.
+    pub fn epoll_wait(
+        &mut self,
+        epfd: i32,
+        addr: UVAddr,
+        maxevents: i32,
+        timeout: i32,
+    ) -> Result<usize, Errno> {
+        let ep = self.proc().getfile(epfd)?;
+        let result = self.epoll_wait_file(&ep, addr, maxevents, timeout);
+        ep.free(self);
+        result
+    }
+
+    fn epoll_wait_file(
+        &mut self,
+        ep: &RcFile,
+        addr: UVAddr,
+        maxevents: i32,
+        timeout: i32,
+    ) -> Result<usize, Errno> {
+        let epoll = match &ep.typ {
+            FileType::Epoll { epoll } => epoll,
+            _ => return Err(Errno::EINVAL),
+        };
+        if maxevents <= 0 {
+            return Err(Errno::EINVAL);
+        }
+        let mut events = [EpollEvent::default(); NOFILE];
+        let events = &mut events[..cmp::min(maxevents as usize, NOFILE)];
+
+        let ctx: &KernelCtx<'_, '_> = self;
+        let n = ctx
+            .kernel()
+            .pollers()
+            .poll(timeout_ticks(timeout), ctx, |table| {
+                // Adds the watched files as well.
+                table.add(ep, ctx);
+                epoll.collect(events, ctx)
+            })?;
+
+        self.copy_out_bytes(addr, events[..n].as_bytes())?;
+        Ok(n)
+    }
+}
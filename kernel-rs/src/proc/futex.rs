@@ -0,0 +1,179 @@
+//! Futexes, which let user processes sleep until a word of their memory changes.
+//!
+//! A futex is identified by the physical address of its word, so that the
+//! threads of a process share it. The waiters of a futex sleep on the
+//! `WaitChannel` of its entry in a table hashed by the address.
+
+use core::{cmp, mem};
+
+use super::*;
+use crate::{
+    addr::{Addr, UVAddr},
+    errno::Errno,
+    kernel::KernelRef,
+    param::NFUTEX,
+    some_or,
+    vm::AccessFlags,
+};
+
+/// `op` of `futex`.
+pub const FUTEX_WAIT: i32 = 0;
+pub const FUTEX_WAKE: i32 = 1;
+
+#[derive(Clone, Copy)]
+struct FutexEntry {
+    /// Physical address of the futex word, or 0 if the entry is unused.
+    addr: usize,
+
+    /// Number of processes sleeping on the futex.
+    waiters: usize,
+
+    /// Number of the waiters that have been woken up but have not returned yet.
+    wakeups: usize,
+
+    /// Number of the waiters that have a timeout.
+    timed: usize,
+}
+
+/// # Safety
+///
+/// * The waiters of `entries[i]` sleep on `channels[i]`.
+/// * For each entry, `wakeups` ≤ `waiters`, and the entry is unused if `waiters` is 0.
+pub struct Futexes {
+    entries: SpinLock<[FutexEntry; NFUTEX]>,
+    channels: [WaitChannel; NFUTEX],
+}
+
+impl FutexEntry {
+    const fn new() -> Self {
+        Self {
+            addr: 0,
+            waiters: 0,
+            wakeups: 0,
+            timed: 0,
+        }
+    }
+}
+
+impl Futexes {
+    pub const fn new() -> Self {
+        Self {
+            entries: SpinLock::new("futex", [FutexEntry::new(); NFUTEX]),
+            channels: array![_ => WaitChannel::new(); NFUTEX],
+        }
+    }
+
+    /// Returns the indices of the entries in the order that the entry of `addr` is looked for.
+    fn probe(addr: usize) -> impl Iterator<Item = usize> {
+        let hash = (addr / mem::size_of::<u32>()) % NFUTEX;
+        (0..NFUTEX).map(move |i| (hash + i) % NFUTEX)
+    }
+
+    /// Returns the index of the entry of `addr`, if any.
+    fn find(entries: &[FutexEntry; NFUTEX], addr: usize) -> Option<usize> {
+        Self::probe(addr).find(|&i| entries[i].addr == addr)
+    }
+
+    /// Returns the index of the entry of `addr`, using an unused entry if there is
+    /// none. Returns None if the table is full.
+    fn find_or_insert(entries: &mut [FutexEntry; NFUTEX], addr: usize) -> Option<usize> {
+        let i = Self::find(entries, addr)
+            .or_else(|| Self::probe(addr).find(|&i| entries[i].addr == 0))?;
+        entries[i].addr = addr;
+        Some(i)
+    }
+
+    /// Sleep on the futex at `addr` of the current process if it contains `val`,
+    /// until `wake` wakes the process up, or `timeout` ticks pass if `timeout` is not 0.
+    /// Returns Ok(()) if the process is woken up, and Err(EAGAIN) if the futex word
+    /// does not contain `val`, Err(ETIMEDOUT) if the timeout expires, or Err(EINTR)
+    /// if a signal arrives.
+    pub fn wait(
+        &self,
+        addr: UVAddr,
+        val: u32,
+        timeout: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        if addr.into_usize() % mem::size_of::<u32>() != 0 {
+            return Err(Errno::EINVAL);
+        }
+        // Read the page now if it is file-backed, as it cannot be read while holding the lock.
+        ctx.fault_in(addr, mem::size_of::<u32>(), AccessFlags::W);
+        let start = *ctx.kernel().ticks().lock();
+
+        let mut entries = self.entries.lock();
+        // Check the futex word while holding the lock, so that a wakeup after
+        // the word changes is not missed.
+        let mut memory = ctx.proc().memory();
+        let pa = memory.futex_addr(addr).ok_or(Errno::EFAULT)?;
+        let mut cur = 0u32;
+        // SAFETY: u32 is an integer.
+        unsafe { memory.copy_in(&mut cur, addr) }?;
+        drop(memory);
+        if cur != val {
+            return Err(Errno::EAGAIN);
+        }
+
+        let i = Self::find_or_insert(&mut entries, pa.into_usize()).ok_or(Errno::ENOMEM)?;
+        entries[i].waiters += 1;
+        if timeout != 0 {
+            entries[i].timed += 1;
+        }
+
+        let result = loop {
+            if entries[i].wakeups > 0 {
+                entries[i].wakeups -= 1;
+                break Ok(());
+            }
+            if ctx.proc().killed() || ctx.proc().signal_pending() {
+                break Err(Errno::EINTR);
+            }
+            if timeout != 0 && ctx.kernel().ticks().lock().wrapping_sub(start) >= timeout {
+                break Err(Errno::ETIMEDOUT);
+            }
+            self.channels[i].sleep(&mut entries, ctx);
+        };
+
+        let entry = &mut entries[i];
+        entry.waiters -= 1;
+        if timeout != 0 {
+            entry.timed -= 1;
+        }
+        if entry.waiters == 0 {
+            *entry = FutexEntry::new();
+        }
+        result
+    }
+
+    /// Wake up at most `n` processes sleeping on the futex at `addr` of the current process.
+    /// Returns Ok(the number of processes woken up) on success, Err(errno) on error.
+    pub fn wake(&self, addr: UVAddr, n: u32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, Errno> {
+        if addr.into_usize() % mem::size_of::<u32>() != 0 {
+            return Err(Errno::EINVAL);
+        }
+        ctx.fault_in(addr, mem::size_of::<u32>(), AccessFlags::W);
+
+        let mut entries = self.entries.lock();
+        let pa = ctx.proc().memory().futex_addr(addr).ok_or(Errno::EFAULT)?;
+        let i = some_or!(Self::find(&entries, pa.into_usize()), return Ok(0));
+        let entry = &mut entries[i];
+        let n = cmp::min(n as usize, entry.waiters - entry.wakeups);
+        entry.wakeups += n;
+        if n > 0 {
+            self.channels[i].wakeup(ctx.kernel());
+        }
+        Ok(n)
+    }
+
+    /// Wake up the processes sleeping on a futex with a timeout, so that they
+    /// check whether the timeout has expired. Called on every tick.
+    pub fn wakeup_timed(&self, kernel: KernelRef<'_, '_>) {
+        let entries = self.entries.lock();
+        for (entry, channel) in entries.iter().zip(self.channels.iter()) {
+            if entry.timed > 0 {
+                channel.wakeup(kernel);
+            }
+        }
+    }
+}
@@ -1,11 +1,15 @@
+use core::ops::DerefMut;
+
 use derive_more::Deref;
 
 use super::*;
 use crate::{
     arch::interface::ProcManager,
     arch::TargetArch,
-    fs::DefaultFs,
+    cpu::PreemptDisabled,
+    fs::Vnode,
     kernel::{kernel_ref, KernelRef},
+    lock::SpinLockGuard,
     vm::UserMemory,
 };
 
@@ -42,6 +46,10 @@ pub struct CurrentProc<'id, 'p> {
     inner: ProcRef<'id, 'p>,
 }
 
+/// The locked `UserMemory` of the current process.
+/// Do not sleep while holding it.
+pub struct UserMemoryGuard<'a>(SpinLockGuard<'a, MaybeUninit<UserMemory>>);
+
 impl<'id, 'p> KernelCtx<'id, 'p> {
     pub fn kernel(&self) -> KernelRef<'id, 'p> {
         self.kernel
@@ -55,13 +63,28 @@ impl<'id, 'p> KernelCtx<'id, 'p> {
         &mut self.proc
     }
 
-    /// Give up the CPU for one scheduling round.
+    /// Give up the CPU for one scheduling round, at the end of a clock tick.
+    /// The tick is charged to the current process, which may lower its priority.
     // Its name cannot be `yield` because `yield` is a reserved keyword.
     pub fn yield_cpu(&self) {
         let mut guard = self.proc.lock();
-        guard.deref_mut_info().state = Procstate::RUNNABLE;
+        guard.charge_tick();
+        self.kernel.procs().make_runnable(&mut guard);
         unsafe { guard.sched() };
     }
+
+    /// Disables preemption of the current thread. See `Cpus::preempt_disable`.
+    pub fn preempt_disable(&self) -> PreemptDisabled {
+        hal().cpus().preempt_disable()
+    }
+
+    /// Enables preemption of the current thread again, and yields the CPU at once if a timer
+    /// interrupt arrived while preemption was disabled.
+    pub fn preempt_enable(&self, preempt: PreemptDisabled) {
+        if hal().cpus().preempt_enable(preempt) {
+            self.yield_cpu();
+        }
+    }
 }
 
 /// Creates the `KernelCtx` of the current Cpu.
@@ -109,28 +132,84 @@ impl<'id, 'p> CurrentProc<'id, 'p> {
         unsafe { &mut *self.deref_mut_data().trap_frame }
     }
 
-    pub fn memory(&self) -> &UserMemory {
-        // SAFETY: memory has been initialized according to the invariants
+    /// Returns the main thread of the current process, whose memory and open
+    /// files are shared by every thread of the process.
+    pub fn main_thread(&self) -> ProcRef<'id, 'p> {
+        // SAFETY:
+        // * leader is not modified while CurrentProc exists.
+        // * leader is a valid pointer according to the invariants of Proc and CurrentProc.
+        let leader = unsafe { &*(*self.info.get_mut_raw()).leader };
+        ProcRef(self.inner.0.brand(leader))
+    }
+
+    /// Returns true if the current process is the main thread of its process.
+    pub fn is_main_thread(&self) -> bool {
+        ptr::eq(*self.main_thread().0, *self.inner.0)
+    }
+
+    pub fn trap_frame_va(&self) -> usize {
+        self.deref_data().trap_frame_va
+    }
+
+    /// Locks and returns the memory of the current process.
+    pub fn memory(&self) -> UserMemoryGuard<'p> {
+        let leader: &'p Proc = *self.main_thread().0;
+        UserMemoryGuard(leader.memory.lock())
+    }
+
+    /// Locks and returns the open files of the current process.
+    /// Do not free a file while holding it.
+    pub fn open_files(&self) -> SpinLockGuard<'p, OpenFiles> {
+        let leader: &'p Proc = *self.main_thread().0;
+        leader.open_files.lock()
+    }
+
+    /// Returns a new reference to the current directory of the current process.
+    /// The caller should put it.
+    pub fn cwd(&self) -> Vnode {
+        let leader: &'p Proc = *self.main_thread().0;
+        // SAFETY: cwd of the main thread has been initialized according to the invariants
         // of Proc and CurrentProc.
-        unsafe { self.deref_data().memory.assume_init_ref() }
+        unsafe { leader.cwd.lock().assume_init_ref() }.clone()
     }
 
-    pub fn memory_mut(&mut self) -> &mut UserMemory {
-        // SAFETY: memory has been initialized according to the invariants
+    /// Changes the current directory of the current process to `cwd`, and returns the
+    /// previous one. The caller should put it.
+    pub fn set_cwd(&self, cwd: Vnode) -> Vnode {
+        let leader: &'p Proc = *self.main_thread().0;
+        // SAFETY: cwd of the main thread has been initialized according to the invariants
         // of Proc and CurrentProc.
-        unsafe { self.deref_mut_data().memory.assume_init_mut() }
+        mem::replace(unsafe { leader.cwd.lock().assume_init_mut() }, cwd)
     }
 
-    pub fn cwd(&self) -> &RcInode<DefaultFs> {
-        // SAFETY: cwd has been initialized according to the invariants
+    /// Locks and returns the signal actions of the current process.
+    pub fn sig_actions(&self) -> SpinLockGuard<'p, SigActions> {
+        let leader: &'p Proc = *self.main_thread().0;
+        leader.sig_actions.lock()
+    }
+
+    /// Locks and returns the user and group ids of the current process.
+    pub fn creds(&self) -> SpinLockGuard<'p, Creds> {
+        let leader: &'p Proc = *self.main_thread().0;
+        leader.creds.lock()
+    }
+}
+
+impl Deref for UserMemoryGuard<'_> {
+    type Target = UserMemory;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: memory has been initialized according to the invariants
         // of Proc and CurrentProc.
-        unsafe { self.deref_data().cwd.assume_init_ref() }
+        unsafe { self.0.assume_init_ref() }
     }
+}
 
-    pub fn cwd_mut(&mut self) -> &mut RcInode<DefaultFs> {
-        // SAFETY: cwd has been initialized according to the invariants
+impl DerefMut for UserMemoryGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: memory has been initialized according to the invariants
         // of Proc and CurrentProc.
-        unsafe { self.deref_mut_data().cwd.assume_init_mut() }
+        unsafe { self.0.assume_init_mut() }
     }
 }
 
@@ -1,19 +1,21 @@
 use core::{
     cell::UnsafeCell,
     mem::{self, MaybeUninit},
-    ops::Deref,
+    ops::{Deref, DerefMut},
     ptr, str,
-    sync::atomic::{AtomicBool, Ordering},
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
 };
 
 use array_macro::array;
 use derive_more::Deref;
+use static_assertions::const_assert;
 
 use crate::{
     arch::interface::{ContextManager, ProcManager, TrapManager},
     arch::TargetArch,
+    errno::Errno,
     file::RcFile,
-    fs::{DefaultFs, RcInode},
+    fs::{Vnode, ROOT_UID},
     hal::hal,
     lock::SpinLock,
     page::Page,
@@ -22,12 +24,18 @@ use crate::{
     vm::UserMemory,
 };
 
+mod futex;
 mod kernel_ctx;
 mod procs;
+mod sched;
+mod signal;
 mod wait_channel;
 
+pub use futex::*;
 pub use kernel_ctx::*;
 pub use procs::*;
+pub use sched::*;
+pub use signal::*;
 pub use wait_channel::*;
 
 type Context = <TargetArch as ProcManager>::Context;
@@ -62,6 +70,36 @@ pub struct ProcInfo {
 
     /// Process ID.
     pid: Pid,
+
+    /// Exit status given by `exit_group` of another thread, which the main
+    /// thread exits with.
+    group_xstate: Option<i32>,
+
+    /// The main thread of the process. It points to the `Proc` itself if it
+    /// is the main thread.
+    leader: *const Proc,
+
+    /// Nice value, in [`NICE_MIN`, `NICE_MAX`].
+    nice: i32,
+
+    /// Priority level of the scheduler, in [0, `NLEVEL`). Lower is higher priority.
+    level: usize,
+
+    /// Ticks used at the current level.
+    run_ticks: u32,
+
+    /// Bitmask of the CPUs that the process can run on.
+    affinity: usize,
+
+    /// The CPU whose run queue the process is in, or that it last ran on.
+    cpu: usize,
+}
+
+/// User and group ids, which decide the files that a process may access.
+#[derive(Clone, Copy)]
+pub struct Creds {
+    pub uid: u32,
+    pub gid: u32,
 }
 
 /// Proc::data are private to the process, so lock need not be held.
@@ -72,33 +110,44 @@ pub struct ProcData {
     /// Data page for trampoline.S.
     trap_frame: *mut <TargetArch as ProcManager>::TrapFrame,
 
-    /// User memory manager
-    memory: MaybeUninit<UserMemory>,
+    /// User virtual address of the trap frame.
+    trap_frame_va: usize,
 
     /// swtch() here to run process.
     context: Context,
 
-    /// Open files.
-    pub open_files: [Option<RcFile>; NOFILE],
-
-    /// Current directory.
-    cwd: MaybeUninit<RcInode<DefaultFs>>,
+    /// Blocked signals of the thread.
+    signals: Signals,
 
     /// Process name (debugging).
     pub name: [u8; MAXPROCNAME],
 }
 
+/// The open files of a process, indexed by their file descriptors.
+pub struct OpenFiles {
+    files: [Option<RcFile>; NOFILE],
+
+    /// Bitmask of the file descriptors that `exec` closes.
+    cloexec: u32,
+}
+
+const_assert!(NOFILE <= u32::BITS as usize);
+
 /// Per-process state.
 ///
 /// # Safety
 ///
 /// * If `info.state` ≠ `UNUSED`, then
 ///   - `data.trap_frame` is a valid pointer, and `Page::from_usize(data.trap_frame)` is safe.
-///   - `data.memory` has been initialized.
+///   - `info.leader` is a valid pointer, and its state is not `UNUSED`.
+///   - `memory` has been initialized if `info.leader` points to `self`.
 /// * If `info.state` ∉ { `UNUSED`, `USED` }, then
-///   - `data.cwd` has been initialized.
+///   - `cwd` has been initialized if `info.leader` points to `self`.
 ///   - `parent` contains null or a valid pointer. `parent` can be null only when `self` is the same
-///     as `initial_proc` of `Procs` that contains `self`.
+///     as `initial_proc` of `Procs` that contains `self`, or `self` is not a main thread.
+///
+/// The threads of a process share `memory`, `open_files`, `cwd`, `sig_actions`, and `creds`
+/// of its main thread, which exits only after the other threads.
 pub struct Proc {
     /// Parent process.
     parent: UnsafeCell<*const Proc>,
@@ -107,11 +156,37 @@ pub struct Proc {
 
     data: UnsafeCell<ProcData>,
 
+    /// User memory manager.
+    memory: SpinLock<MaybeUninit<UserMemory>>,
+
+    /// Open files.
+    open_files: SpinLock<OpenFiles>,
+
+    /// Current directory.
+    cwd: SpinLock<MaybeUninit<Vnode>>,
+
+    /// Actions for each signal.
+    sig_actions: SpinLock<SigActions>,
+
+    /// User and group ids.
+    creds: SpinLock<Creds>,
+
     /// Waitchannel saying child proc is dead.
     child_waitchannel: WaitChannel,
 
     /// If true, the process have been killed.
     killed: AtomicBool,
+
+    /// Signals sent to the process but not handled yet.
+    pending: AtomicU32,
+
+    /// The tick at which `SIGALRM` is sent to the process, or 0 if none.
+    alarm: AtomicU32,
+
+    /// Entry of a run queue, accessed only while holding the lock of the run queue.
+    /// It is in the run queue of `info.cpu` iff `info.state` is `RUNNABLE`, except
+    /// while a scheduler is about to run the process.
+    run_entry: UnsafeCell<RunEntry>,
 }
 
 /// A branded reference to a `Proc`.
@@ -141,20 +216,74 @@ impl Procstate {
     }
 }
 
+impl Creds {
+    const fn new() -> Self {
+        Self { uid: 0, gid: 0 }
+    }
+}
+
 impl ProcData {
     const fn new() -> Self {
         Self {
             kstack: 0,
             trap_frame: ptr::null_mut(),
-            memory: MaybeUninit::uninit(),
+            trap_frame_va: 0,
             context: Context::new(),
-            open_files: array![_ => None; NOFILE],
-            cwd: MaybeUninit::uninit(),
+            signals: Signals::new(),
             name: [0; MAXPROCNAME],
         }
     }
 }
 
+impl OpenFiles {
+    const fn new() -> Self {
+        Self {
+            files: array![_ => None; NOFILE],
+            cloexec: 0,
+        }
+    }
+
+    /// Does `exec` close the file descriptor `fd`?
+    pub fn is_cloexec(&self, fd: usize) -> bool {
+        self.cloexec & (1 << fd) != 0
+    }
+
+    /// Makes `exec` close the file descriptor `fd` if `cloexec` is true, and keep it otherwise.
+    pub fn set_cloexec(&mut self, fd: usize, cloexec: bool) {
+        if cloexec {
+            self.cloexec |= 1 << fd;
+        } else {
+            self.cloexec &= !(1 << fd);
+        }
+    }
+
+    /// Takes the files that `exec` closes, which the caller should free.
+    pub fn take_cloexec(&mut self) -> [Option<RcFile>; NOFILE] {
+        let mut files = array![_ => None; NOFILE];
+        for (fd, f) in files.iter_mut().enumerate() {
+            if self.is_cloexec(fd) {
+                *f = self.files[fd].take();
+            }
+        }
+        self.cloexec = 0;
+        files
+    }
+}
+
+impl Deref for OpenFiles {
+    type Target = [Option<RcFile>; NOFILE];
+
+    fn deref(&self) -> &Self::Target {
+        &self.files
+    }
+}
+
+impl DerefMut for OpenFiles {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.files
+    }
+}
+
 impl Proc {
     const fn new() -> Self {
         Self {
@@ -166,11 +295,27 @@ impl Proc {
                     waitchannel: ptr::null(),
                     xstate: 0,
                     pid: 0,
+                    group_xstate: None,
+                    leader: ptr::null(),
+                    nice: 0,
+                    level: 0,
+                    run_ticks: 0,
+                    affinity: ALL_CPUS,
+                    cpu: 0,
                 },
             ),
             data: UnsafeCell::new(ProcData::new()),
+            memory: SpinLock::new("memory", MaybeUninit::uninit()),
+            open_files: SpinLock::new("open_files", OpenFiles::new()),
+            cwd: SpinLock::new("cwd", MaybeUninit::uninit()),
+            sig_actions: SpinLock::new("sig_actions", SigActions::new()),
+            creds: SpinLock::new("creds", Creds::new()),
             child_waitchannel: WaitChannel::new(),
             killed: AtomicBool::new(false),
+            pending: AtomicU32::new(0),
+            alarm: AtomicU32::new(0),
+            // SAFETY: run_entry is initialized by `Procs::init` before it is used.
+            run_entry: UnsafeCell::new(unsafe { RunEntry::new() }),
         }
     }
 }
@@ -237,7 +382,7 @@ impl<'id> ProcGuard<'id, '_> {
 
     /// Switch to scheduler.  Must hold only p->lock
     /// and have changed proc->state. Saves and restores
-    /// interrupt_enabled because interrupt_enabled is a property of this
+    /// interrupt_enabled and preempt_count because they are properties of this
     /// kernel thread, not this CPU. It should
     /// be proc->interrupt_enabled and proc->noff, but that would
     /// break in the few places where a lock is held but
@@ -251,58 +396,89 @@ impl<'id> ProcGuard<'id, '_> {
         assert_eq!(cpu.get_noff(), 1, "sched locks");
 
         let interrupt_enabled = cpu.get_interrupt();
+        let preempt_count = cpu.get_preempt_count();
+        // The thread gives up the CPU anyway, so a missed timer interrupt is handled.
+        cpu.set_preempt_count(0);
+        let _ = cpu.take_missed_tick();
         unsafe { swtch(&mut self.deref_mut_data().context, cpu.context_raw_mut()) };
 
         // We cannot use `cpu` again because `swtch` may move this thread to another cpu.
         // SAFETY: interrupts are disabled.
         let cpu = unsafe { hal().get_ref().cpus().current_unchecked() };
         cpu.set_interrupt(interrupt_enabled);
+        cpu.set_preempt_count(preempt_count);
     }
 
     /// Frees a `Proc` structure and the data hanging from it, including user pages.
-    /// Also, clears `p`'s parent field into `ptr::null_mut()`.
+    /// For a thread other than the main thread, only unmaps its trap frame from the
+    /// shared user memory. Also, clears `p`'s parent field into `ptr::null_mut()`.
     /// The caller must provide a `ProcGuard`.
     ///
     /// # Safety
     ///
-    /// `self.info.state` ≠ `UNUSED`
-    unsafe fn clear(&mut self, mut parent_guard: WaitGuard<'id, '_>) {
+    /// * `self.info.state` ≠ `UNUSED`
+    /// * If `self` is a main thread, the other threads of its process have been cleared.
+    unsafe fn clear(&mut self, parent_guard: &mut WaitGuard<'id, '_>) {
+        let is_main_thread = self.is_main_thread();
+        let leader = self.deref_info().leader;
+        let allocator = hal().kmem();
+        if is_main_thread {
+            // SAFETY:
+            // * ok to assume_init() because memory has been initialized according to the invariant.
+            // * ok to replace memory with uninit() because state will become UNUSED.
+            unsafe {
+                mem::replace(&mut *self.memory.lock(), MaybeUninit::uninit())
+                    .assume_init()
+                    .free(allocator)
+            };
+        } else {
+            // SAFETY: the main thread exits only after this thread, so `leader` is valid,
+            // and its memory has been initialized.
+            let mut memory = unsafe { (*leader).memory.lock() };
+            // SAFETY: this process cannot be the current process any longer.
+            let va = unsafe { self.deref_mut_data() }.trap_frame_va;
+            unsafe { memory.assume_init_mut() }.unmap_trap_frame(va);
+        }
+
         // SAFETY: this process cannot be the current process any longer.
         let data = unsafe { self.deref_mut_data() };
         let trap_frame = mem::replace(&mut data.trap_frame, ptr::null_mut());
-        let allocator = hal().kmem();
-        // SAFETY: trap_frame uniquely refers to a valid page.
+        data.trap_frame_va = 0;
+        // SAFETY: trap_frame uniquely refers to a valid page, which is not mapped anymore.
         allocator.free(unsafe { Page::from_usize(trap_frame as _) });
-        // SAFETY:
-        // * ok to assume_init() because memory has been initialized according to the invariant.
-        // * ok to replace memory with uninit() because state will become UNUSED.
-        unsafe {
-            mem::replace(&mut data.memory, MaybeUninit::uninit())
-                .assume_init()
-                .free(allocator)
-        };
 
         // Clear the name.
         data.name[0] = 0;
 
+        data.signals = Signals::new();
+        *self.sig_actions.lock() = SigActions::new();
+        *self.creds.lock() = Creds::new();
+
         // Clear the process's parent field.
-        *self.get_mut_parent(&mut parent_guard) = ptr::null_mut();
-        drop(parent_guard);
+        *self.get_mut_parent(parent_guard) = ptr::null_mut();
 
         // Clear the `ProcInfo`.
         let info = self.deref_mut_info();
         info.waitchannel = ptr::null();
         info.pid = 0;
         info.xstate = 0;
+        info.group_xstate = None;
+        info.leader = ptr::null();
+        info.nice = 0;
+        info.level = 0;
+        info.run_ticks = 0;
+        info.affinity = ALL_CPUS;
+        info.cpu = 0;
         info.state = Procstate::UNUSED;
 
         self.killed.store(false, Ordering::Release);
+        self.clear_signals();
     }
 
     /// Wake process from sleep().
-    fn wakeup(&mut self) {
+    fn wakeup(&mut self, procs: &ProcsRef<'id, '_>) {
         if self.state() == Procstate::SLEEPING {
-            self.deref_mut_info().state = Procstate::RUNNABLE;
+            procs.make_runnable(self);
         }
     }
 
@@ -310,6 +486,24 @@ impl<'id> ProcGuard<'id, '_> {
         self.deref_info().state
     }
 
+    /// Returns true if this is the main thread of its process.
+    fn is_main_thread(&self) -> bool {
+        ptr::eq(self.deref_info().leader, self.deref().deref())
+    }
+
+    /// Returns Ok(()) if the user `uid` may signal or reschedule the process, which it may
+    /// if it is the superuser or owns the process, Err(EPERM) otherwise.
+    /// The state of the process must not be `UNUSED`.
+    fn check_owner(&self, uid: u32) -> Result<(), Errno> {
+        // SAFETY: `leader` is valid, as the state is not `UNUSED`.
+        let owner = unsafe { (*self.deref_info().leader).creds.lock().uid };
+        if uid == ROOT_UID || uid == owner {
+            Ok(())
+        } else {
+            Err(Errno::EPERM)
+        }
+    }
+
     fn reacquire_after<F, U>(&mut self, f: F) -> U
     where
         F: FnOnce(ProcRef<'id, '_>) -> U,
@@ -1,9 +1,11 @@
 use core::{
+    fmt,
     marker::PhantomPinned,
+    mem,
     ops::Deref,
     pin::Pin,
     ptr, str,
-    sync::atomic::{AtomicI32, Ordering},
+    sync::atomic::{AtomicI32, AtomicU32, AtomicUsize, Ordering},
 };
 
 use array_macro::array;
@@ -14,16 +16,18 @@ use super::*;
 use crate::{
     addr::{Addr, UVAddr, PGSIZE},
     arch::interface::TrapFrameManager,
-    fs::{DefaultFs, FileSystem, FileSystemExt},
+    errno::Errno,
+    fs::{Vnode, ROOT_UID},
     hal::hal,
     kalloc::Kmem,
     kernel::KernelRef,
     lock::{SpinLock, SpinLockGuard},
-    memlayout::kstack,
+    memlayout::{kstack, TRAPFRAME, USERTOP},
     page::Page,
-    param::{NPROC, ROOTDEV},
+    param::{NCPU, NPROC},
+    some_or,
     util::branded::Branded,
-    vm::UserMemory,
+    vm::{AccessFlags, UserMemory},
 };
 
 /// Process system type containing & managing whole processes.
@@ -43,6 +47,15 @@ pub struct Procs {
     // memory model when using p->parent.
     // Must be acquired before any p->lock.
     wait_lock: SpinLock<()>,
+    /// Run queues of the RUNNABLE processes, one for each CPU.
+    /// Must be acquired after p->lock. At most one of them is held at a time.
+    #[pin]
+    run_queues: [SpinLock<RunQueue>; NCPU],
+    /// Bitmask of the CPUs that have started their scheduler.
+    online_cpus: AtomicUsize,
+    /// The earliest alarm deadline, or 0 if no alarm is set. It may be
+    /// earlier than every alarm, when alarms were cancelled.
+    next_alarm: AtomicU32,
     #[pin]
     _marker: PhantomPinned,
 }
@@ -74,6 +87,10 @@ impl Procs {
             process_pool: array![_ => Proc::new(); NPROC],
             initial_proc: ptr::null(),
             wait_lock: SpinLock::new("wait_lock", ()),
+            // SAFETY: run_queues are initialized by `Procs::init`.
+            run_queues: array![_ => SpinLock::new("run_queue", unsafe { RunQueue::new() }); NCPU],
+            online_cpus: AtomicUsize::new(0),
+            next_alarm: AtomicU32::new(0),
             _marker: PhantomPinned,
         }
     }
@@ -84,15 +101,20 @@ impl Procs {
         let this = unsafe { self.get_unchecked_mut() };
         for (i, p) in this.process_pool.iter_mut().enumerate() {
             p.data.get_mut().kstack = kstack(i);
+            let proc = p as *const _;
+            // SAFETY: we don't move the `Proc`.
+            unsafe { Pin::new_unchecked(p.run_entry.get_mut()) }.init(proc);
+        }
+        for run_queue in &mut this.run_queues {
+            // SAFETY: we don't move the `Procs`.
+            unsafe { Pin::new_unchecked(run_queue) }
+                .get_pin_mut()
+                .init();
         }
     }
 
     /// Set up first user process.
-    pub fn user_proc_init(
-        self: Pin<&mut Self>,
-        cwd: RcInode<DefaultFs>,
-        allocator: Pin<&SpinLock<Kmem>>,
-    ) {
+    pub fn user_proc_init(self: Pin<&mut Self>, cwd: Vnode, allocator: Pin<&SpinLock<Kmem>>) {
         let initial_proc = Branded::new(self.as_ref(), |procs| {
             let procs = ProcsRef(procs);
 
@@ -111,9 +133,11 @@ impl Procs {
             )
             .expect("user_proc_init: UserMemory::new");
 
-            let mut guard = procs
-                .alloc(scopeguard::ScopeGuard::into_inner(trap_frame), memory)
-                .expect("user_proc_init: Procs::alloc");
+            let mut guard =
+                match procs.alloc(scopeguard::ScopeGuard::into_inner(trap_frame), memory) {
+                    Ok(guard) => guard,
+                    Err(_) => panic!("user_proc_init: Procs::alloc"),
+                };
 
             // SAFETY: this process cannot be the current process yet.
             let data = unsafe { guard.deref_mut_data() };
@@ -134,9 +158,9 @@ impl Procs {
 
             let name = b"initcode\x00";
             (&mut data.name[..name.len()]).copy_from_slice(name);
-            let _ = data.cwd.write(cwd);
+            let _ = guard.cwd.lock().write(cwd);
             // It's safe because cwd now has been initialized.
-            guard.deref_mut_info().state = Procstate::RUNNABLE;
+            procs.make_runnable(&mut guard);
 
             guard.deref().deref() as *const _
         });
@@ -171,38 +195,59 @@ impl<'id, 's> ProcsRef<'id, 's> {
 
     /// Look into process system for an UNUSED proc.
     /// If found, initialize state required to run in the kernel,
-    /// and return with p->lock held.
-    /// If there are no free procs, or a memory allocation fails, return Err.
-    fn alloc(&self, trap_frame: Page, memory: UserMemory) -> Result<ProcGuard<'id, '_>, ()> {
-        for p in self.process_pool() {
-            let mut guard = p.lock();
-            if guard.deref_info().state == Procstate::UNUSED {
-                // SAFETY: this process cannot be the current process yet.
-                let data = unsafe { guard.deref_mut_data() };
-
-                // Initialize trap frame and page table.
-                data.trap_frame = trap_frame.into_usize() as _;
-                let _ = data.memory.write(memory);
-
-                // Set up new context to start executing at forkret,
-                // which returns to user space.
-                data.context = Default::default();
-                data.context.set_ret_addr(forkret as usize);
-                data.context.sp = data.kstack + PGSIZE;
-
-                let info = guard.deref_mut_info();
-                info.pid = self.0.allocpid();
-                // It's safe because trap_frame and memory now have been initialized.
-                info.state = Procstate::USED;
-
-                return Ok(guard);
-            }
-        }
+    /// and return with p->lock held. The proc is the main thread of
+    /// a new process that owns `memory`.
+    /// If there are no free procs, free `trap_frame` and return Err(memory).
+    fn alloc(
+        &self,
+        trap_frame: Page,
+        memory: UserMemory,
+    ) -> Result<ProcGuard<'id, '_>, UserMemory> {
+        let mut guard = some_or!(self.find_unused(), {
+            hal().kmem().free(trap_frame);
+            return Err(memory);
+        });
+        let _ = guard.memory.lock().write(memory);
+        let leader = guard.deref().deref() as *const _;
+        self.init_proc(&mut guard, trap_frame, TRAPFRAME, leader);
+        Ok(guard)
+    }
 
-        let allocator = hal().kmem();
-        allocator.free(trap_frame);
-        memory.free(allocator);
-        Err(())
+    /// Look into process system for an UNUSED proc, and return it with p->lock held.
+    fn find_unused(&self) -> Option<ProcGuard<'id, 's>> {
+        self.process_pool()
+            .map(|p| p.lock())
+            .find(|guard| guard.state() == Procstate::UNUSED)
+    }
+
+    /// Initialize state required to run in the kernel, as a thread of the
+    /// process whose main thread is `leader`. Its trap frame is `trap_frame`,
+    /// mapped at `trap_frame_va`.
+    fn init_proc(
+        &self,
+        guard: &mut ProcGuard<'id, '_>,
+        trap_frame: Page,
+        trap_frame_va: usize,
+        leader: *const Proc,
+    ) {
+        // SAFETY: this process cannot be the current process yet.
+        let data = unsafe { guard.deref_mut_data() };
+
+        // Initialize trap frame.
+        data.trap_frame = trap_frame.into_usize() as _;
+        data.trap_frame_va = trap_frame_va;
+
+        // Set up new context to start executing at forkret,
+        // which returns to user space.
+        data.context = Default::default();
+        data.context.set_ret_addr(forkret as usize);
+        data.context.sp = data.kstack + PGSIZE;
+
+        let info = guard.deref_mut_info();
+        info.pid = self.0.allocpid();
+        info.leader = leader;
+        // It's safe because trap_frame and the memory of leader now have been initialized.
+        info.state = Procstate::USED;
     }
 
     /// Wake up all processes in the pool sleeping on waitchannel.
@@ -213,7 +258,7 @@ impl<'id, 's> ProcsRef<'id, 's> {
             if p.deref() as *const _ != current_proc {
                 let mut guard = p.lock();
                 if guard.deref_info().waitchannel == target as _ {
-                    guard.wakeup()
+                    guard.wakeup(self)
                 }
             }
         }
@@ -238,29 +283,53 @@ impl<'id, 's> ProcsRef<'id, 's> {
 
     /// Create a new process, copying the parent.
     /// Sets up child kernel stack to return as if from fork() system call.
-    /// Returns Ok(new process id) on success, Err(()) on error.
+    /// Returns Ok(new process id) on success, Err(errno) on error.
     ///
     /// # Note
     ///
     /// `self` and `ctx` must have the same `'id` tag attached.
     /// Otherwise, UB may happen if the new `Proc` tries to read its `parent` field
     /// that points to a `Proc` that already dropped.
-    pub fn fork(&self, ctx: &mut KernelCtx<'id, '_>) -> Result<Pid, ()> {
+    pub fn fork(&self, ctx: &mut KernelCtx<'id, '_>) -> Result<Pid, Errno> {
+        let (nice, affinity) = ctx.proc().sched_attr();
         let allocator = hal().kmem();
         // Allocate trap frame.
-        let trap_frame = scopeguard::guard(allocator.alloc(None).ok_or(())?, |page| {
+        let trap_frame = scopeguard::guard(allocator.alloc(None).ok_or(Errno::ENOMEM)?, |page| {
             allocator.free(page)
         });
 
-        // Copy user memory from parent to child.
+        // Copy user memory from parent to child. The pages of the shared areas
+        // are allocated first, so that both share the same pages.
+        ctx.populate_shared();
         let memory = ctx
-            .proc_mut()
-            .memory_mut()
+            .proc()
+            .memory()
             .clone(trap_frame.addr(), allocator)
-            .ok_or(())?;
+            .ok_or(Errno::ENOMEM)?;
 
         // Allocate process.
-        let mut np = self.alloc(scopeguard::ScopeGuard::into_inner(trap_frame), memory)?;
+        let mut np = match self.alloc(scopeguard::ScopeGuard::into_inner(trap_frame), memory) {
+            Ok(np) => np,
+            Err(mut memory) => {
+                for f in memory.munmap_all(allocator) {
+                    f.free(ctx);
+                }
+                memory.free(allocator);
+                return Err(Errno::EAGAIN);
+            }
+        };
+        // Increment reference counts on open file descriptors.
+        let mut open_files = np.open_files.lock();
+        let parent_files = ctx.proc().open_files();
+        for (nf, f) in izip!(open_files.iter_mut(), parent_files.iter()) {
+            if let Some(file) = f {
+                *nf = Some(file.clone());
+            }
+        }
+        open_files.cloexec = parent_files.cloexec;
+        drop(parent_files);
+        drop(open_files);
+
         // SAFETY: this process cannot be the current process yet.
         let npdata = unsafe { np.deref_mut_data() };
 
@@ -272,19 +341,16 @@ impl<'id, 's> ProcsRef<'id, 's> {
         // SAFETY: trap_frame has been initialized by alloc.
         unsafe { (*npdata.trap_frame).set_ret_val(0) };
 
-        // Increment reference counts on open file descriptors.
-        for (nf, f) in izip!(
-            npdata.open_files.iter_mut(),
-            ctx.proc().deref_data().open_files.iter()
-        ) {
-            if let Some(file) = f {
-                *nf = Some(file.clone());
-            }
-        }
-        let _ = npdata.cwd.write(ctx.proc().cwd().clone());
+        npdata.signals = ctx.proc().deref_data().signals.clone();
 
         npdata.name.copy_from_slice(&ctx.proc().deref_data().name);
 
+        let _ = np.cwd.lock().write(ctx.proc().cwd());
+        let actions = *ctx.proc().sig_actions();
+        *np.sig_actions.lock() = actions;
+        let creds = *ctx.proc().creds();
+        *np.creds.lock() = creds;
+
         let pid = np.deref_mut_info().pid;
 
         // Now drop the guard before we acquire the `wait_lock`.
@@ -297,14 +363,19 @@ impl<'id, 's> ProcsRef<'id, 's> {
 
         // Set the process's state to RUNNABLE.
         // It does not break the invariant because cwd now has been initialized.
-        np.deref_mut_info().state = Procstate::RUNNABLE;
+        np.set_nice(nice);
+        np.deref_mut_info().affinity = affinity;
+        self.make_runnable(&mut np);
 
         Ok(pid)
     }
 
     /// Wait for a child process to exit and return its pid.
-    /// Return Err(()) if this process has no children.
-    pub fn wait(&self, addr: UVAddr, ctx: &mut KernelCtx<'id, '_>) -> Result<Pid, ()> {
+    /// Return Err(ECHILD) if this process has no children.
+    pub fn wait(&self, addr: UVAddr, ctx: &mut KernelCtx<'id, '_>) -> Result<Pid, Errno> {
+        // Read the page of addr now if it is file-backed, as it cannot be read
+        // while holding the locks below.
+        ctx.fault_in(addr, mem::size_of::<i32>(), AccessFlags::W);
         let mut parent_guard = self.wait_guard();
 
         loop {
@@ -321,24 +392,27 @@ impl<'id, 's> ProcsRef<'id, 's> {
                         let pid = np.deref_mut_info().pid;
                         if !addr.is_null()
                             && ctx
-                                .proc_mut()
-                                .memory_mut()
+                                .proc()
+                                .memory()
                                 .copy_out(addr, &np.deref_info().xstate)
                                 .is_err()
                         {
-                            return Err(());
+                            return Err(Errno::EFAULT);
                         }
                         // Reap the zombie child process.
-                        // SAFETY: np.state() equals ZOMBIE.
-                        unsafe { np.clear(parent_guard) };
+                        // SAFETY: np.state() equals ZOMBIE, and it is a main thread.
+                        unsafe { np.clear(&mut parent_guard) };
                         return Ok(pid);
                     }
                 }
             }
 
             // No point waiting if we don't have any children.
-            if !havekids || ctx.proc().killed() {
-                return Err(());
+            if !havekids {
+                return Err(Errno::ECHILD);
+            }
+            if ctx.proc().killed() || ctx.proc().signal_pending() {
+                return Err(Errno::EINTR);
             }
 
             // Wait for a child to exit.
@@ -348,7 +422,15 @@ impl<'id, 's> ProcsRef<'id, 's> {
     }
 
     // Wait for a child process with `pid` to exit.
-    pub fn waitpid(&self, pid: Pid, addr: UVAddr, ctx: &mut KernelCtx<'id, '_>) -> Result<Pid, ()> {
+    pub fn waitpid(
+        &self,
+        pid: Pid,
+        addr: UVAddr,
+        ctx: &mut KernelCtx<'id, '_>,
+    ) -> Result<Pid, Errno> {
+        // Read the page of addr now if it is file-backed, as it cannot be read
+        // while holding the locks below.
+        ctx.fault_in(addr, mem::size_of::<i32>(), AccessFlags::W);
         let mut parent_guard = self.wait_guard();
 
         let mut found = false;
@@ -360,7 +442,7 @@ impl<'id, 's> ProcsRef<'id, 's> {
                     found = true;
                     if *np.get_mut_parent(&mut parent_guard) != ctx.proc().deref().deref() {
                         // Found a process, but not a child
-                        return Err(());
+                        return Err(Errno::ECHILD);
                     }
 
                     // Make sure the child isn't still in exit() or swtch().
@@ -370,24 +452,27 @@ impl<'id, 's> ProcsRef<'id, 's> {
                         let pid = np.deref_mut_info().pid;
                         if !addr.is_null()
                             && ctx
-                                .proc_mut()
-                                .memory_mut()
+                                .proc()
+                                .memory()
                                 .copy_out(addr, &np.deref_info().xstate)
                                 .is_err()
                         {
-                            return Err(());
+                            return Err(Errno::EFAULT);
                         }
                         // Reap the zombie child process.
-                        // SAFETY: np.state() equals ZOMBIE.
-                        unsafe { np.clear(parent_guard) };
+                        // SAFETY: np.state() equals ZOMBIE, and it is a main thread.
+                        unsafe { np.clear(&mut parent_guard) };
                         return Ok(pid);
                     }
                 }
             }
 
             // No point waiting if we don't have any children.
-            if !found || ctx.proc().killed() {
-                return Err(());
+            if !found {
+                return Err(Errno::ECHILD);
+            }
+            if ctx.proc().killed() || ctx.proc().signal_pending() {
+                return Err(Errno::EINTR);
             }
 
             // Wait for a child to exit.
@@ -397,25 +482,68 @@ impl<'id, 's> ProcsRef<'id, 's> {
         }
     }
 
-    /// Kill the process with the given pid.
-    /// The victim won't exit until it tries to return
+    /// Send signal `sig` to the process with the given pid.
+    /// If `sig` is 0, only checks that the process exists.
+    /// The victim won't handle the signal until it tries to return
     /// to user space (see usertrap() in trap.c).
-    /// Returns Ok(()) on success, Err(()) on error.
-    pub fn kill(&self, pid: Pid) -> Result<(), ()> {
+    /// Returns Ok(()) on success, Err(EINVAL) if `sig` is invalid, Err(ESRCH)
+    /// if there is no such process, and Err(EPERM) if the current process is neither
+    /// the superuser nor the owner of the process.
+    pub fn kill(&self, pid: Pid, sig: i32, ctx: &KernelCtx<'id, '_>) -> Result<(), Errno> {
+        if sig < 0 || sig as usize >= NSIG {
+            return Err(Errno::EINVAL);
+        }
+        let uid = ctx.proc().creds().uid;
         for p in self.process_pool() {
             let mut guard = p.lock();
-            if guard.deref_info().pid == pid {
-                p.kill();
-                guard.wakeup();
+            if guard.deref_info().pid == pid && guard.state() != Procstate::UNUSED {
+                guard.check_owner(uid)?;
+                if sig != 0 {
+                    p.signal(sig);
+                    guard.wakeup(self);
+                }
                 return Ok(());
             }
         }
-        Err(())
+        Err(Errno::ESRCH)
+    }
+
+    /// Lower `next_alarm` to `deadline` if it is earlier.
+    pub fn schedule_alarm(&self, deadline: u32) {
+        let _ = self
+            .next_alarm
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |next| {
+                (next == 0 || (deadline.wrapping_sub(next) as i32) < 0).then(|| deadline)
+            });
+    }
+
+    /// Send `SIGALRM` to the processes whose alarm expires at `now`.
+    /// Scans the processes only when the earliest alarm is due.
+    pub fn expire_alarms(&self, now: u32) {
+        let next = self.next_alarm.load(Ordering::Acquire);
+        if next == 0 || (now.wrapping_sub(next) as i32) < 0 {
+            return;
+        }
+        // Alarms set during the scan lower `next_alarm` themselves.
+        self.next_alarm.store(0, Ordering::Release);
+        for p in self.process_pool() {
+            if p.expire_alarm(now) {
+                p.signal(SIGALRM);
+                p.lock().wakeup(self);
+            } else if let Some(deadline) = p.alarm_deadline() {
+                self.schedule_alarm(deadline);
+            }
+        }
     }
 
     /// Exit the current process.  Does not return.
     /// An exited process remains in the zombie state
     /// until its parent calls wait().
+    ///
+    /// If the current process is a thread other than the main thread, only
+    /// the thread exits, and it remains in the zombie state until joined.
+    /// The main thread kills the other threads and waits for them to exit
+    /// first, as they share its memory and open files.
     pub fn exit_current(&self, status: i32, ctx: &mut KernelCtx<'id, '_>) -> ! {
         assert_ne!(
             ctx.proc().deref().deref() as *const _,
@@ -423,35 +551,60 @@ impl<'id, 's> ProcsRef<'id, 's> {
             "init exiting"
         );
 
-        for i in 0..NOFILE {
-            let files = &mut ctx.proc_mut().deref_mut_data().open_files;
-            if let Some(f) = unsafe { files.get_unchecked_mut(i) }.take() {
+        let is_main_thread = ctx.proc().is_main_thread();
+        if is_main_thread {
+            self.exit_threads(ctx);
+
+            for i in 0..NOFILE {
+                let file = ctx.proc().open_files()[i].take();
+                if let Some(f) = file {
+                    f.free(ctx);
+                }
+            }
+
+            // Unmap the memory-mapped areas, since their files should be closed as well.
+            ctx.write_back(0, USERTOP);
+            let files = ctx.proc().memory().munmap_all(hal().kmem());
+            for f in files {
                 f.free(ctx);
             }
-        }
 
-        let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
-        // SAFETY:
-        // * CurrentProc's cwd has been initialized.
-        // * It's ok to take cwd because proc will not be used any longer.
-        let cwd = unsafe { ctx.proc_mut().deref_mut_data().cwd.assume_init_read() };
-        cwd.free((&tx, ctx));
-        tx.end(ctx);
+            // SAFETY:
+            // * The cwd of the main thread has been initialized.
+            // * It's ok to take cwd because the other threads have exited, and proc will not be
+            //   used any longer.
+            let cwd = unsafe {
+                mem::replace(&mut *ctx.proc().cwd.lock(), MaybeUninit::uninit()).assume_init()
+            };
+            cwd.put(ctx);
+        }
 
         // Give all children to init.
         let mut parent_guard = self.wait_guard();
         self.reparent(ctx.proc().deref().deref(), &mut parent_guard, ctx.kernel());
 
-        // Parent might be sleeping in wait().
-        let parent = *ctx.proc().get_mut_parent(&mut parent_guard);
-        // SAFETY:
-        // * `parent` cannot be null because it is not the initial process.
-        // * `parent` is a valid pointer according to the invariants of
-        //   `Proc` and `CurrentProc`.
-        unsafe { (*parent).child_waitchannel.wakeup(ctx.kernel()) };
+        if is_main_thread {
+            // Parent might be sleeping in wait().
+            let parent = *ctx.proc().get_mut_parent(&mut parent_guard);
+            // SAFETY:
+            // * `parent` cannot be null because it is not the initial process.
+            // * `parent` is a valid pointer according to the invariants of
+            //   `Proc` and `CurrentProc`.
+            unsafe {
+                (*parent).signal(SIGCHLD);
+                (*parent).child_waitchannel.wakeup(ctx.kernel());
+            }
+        } else {
+            // Threads might be sleeping in join(), and the main thread in exit().
+            ctx.proc()
+                .main_thread()
+                .child_waitchannel
+                .wakeup(ctx.kernel());
+        }
 
         let mut guard = ctx.proc().lock();
 
+        let status = guard.deref_info().group_xstate.unwrap_or(status);
         guard.deref_mut_info().xstate = status;
         guard.deref_mut_info().state = Procstate::ZOMBIE;
 
@@ -464,14 +617,444 @@ impl<'id, 's> ProcsRef<'id, 's> {
         unreachable!("zombie exit")
     }
 
+    /// Exit every thread of the current process with `status`.  Does not return.
+    pub fn exit_group(&self, status: i32, ctx: &mut KernelCtx<'id, '_>) -> ! {
+        if !ctx.proc().is_main_thread() {
+            // Make the main thread exit with `status`, which kills the other threads.
+            let main_thread = ctx.proc().main_thread();
+            let mut guard = main_thread.lock();
+            let _ = guard.deref_mut_info().group_xstate.get_or_insert(status);
+            main_thread.kill();
+            guard.wakeup(self);
+        }
+        self.exit_current(status, ctx)
+    }
+
+    /// Kill the other threads of the current process, and wait for them to exit.
+    /// The current process must be a main thread.
+    pub fn exit_threads(&self, ctx: &mut KernelCtx<'id, '_>) {
+        assert!(ctx.proc().is_main_thread(), "exit_threads");
+        let leader = ctx.proc().deref().deref() as *const Proc;
+        let mut parent_guard = self.wait_guard();
+        loop {
+            let mut alive = false;
+            for np in self.process_pool() {
+                let mut np = np.lock();
+                if np.state() == Procstate::UNUSED
+                    || np.deref_info().leader != leader
+                    || np.is_main_thread()
+                {
+                    continue;
+                }
+                if np.state() == Procstate::ZOMBIE {
+                    // SAFETY: np.state() equals ZOMBIE, and it is not a main thread.
+                    unsafe { np.clear(&mut parent_guard) };
+                } else {
+                    // Kill it again, since it may have been created in the meantime.
+                    np.kill();
+                    np.wakeup(self);
+                    alive = true;
+                }
+            }
+
+            if !alive {
+                return;
+            }
+
+            // Wait for the threads to exit.
+            ctx.proc().child_waitchannel.sleep(&mut parent_guard.0, ctx);
+        }
+    }
+
+    /// Create a new thread of the current process, which shares the memory
+    /// and the open files with the current thread. It starts by calling
+    /// `func(arg)` on the user stack `stack`.
+    /// Returns Ok(new thread id) on success, Err(errno) on error.
+    pub fn clone_thread(
+        &self,
+        func: usize,
+        arg: usize,
+        stack: usize,
+        ctx: &mut KernelCtx<'id, '_>,
+    ) -> Result<Pid, Errno> {
+        // The process may be exiting.
+        if ctx.proc().killed() {
+            return Err(Errno::EINTR);
+        }
+        let (nice, affinity) = ctx.proc().sched_attr();
+
+        let allocator = hal().kmem();
+        // Allocate trap frame, and map it to the shared memory.
+        let trap_frame = allocator.alloc(None).ok_or(Errno::ENOMEM)?;
+        let trap_frame_va = ctx
+            .proc()
+            .memory()
+            .map_trap_frame(trap_frame.addr(), allocator);
+        let trap_frame_va = match trap_frame_va {
+            Ok(va) => va,
+            Err(err) => {
+                allocator.free(trap_frame);
+                return Err(err);
+            }
+        };
+
+        // Allocate thread.
+        let mut np = some_or!(self.find_unused(), {
+            ctx.proc().memory().unmap_trap_frame(trap_frame_va);
+            allocator.free(trap_frame);
+            return Err(Errno::EAGAIN);
+        });
+        let leader = ctx.proc().main_thread().deref() as *const _;
+        self.init_proc(&mut np, trap_frame, trap_frame_va, leader);
+
+        // SAFETY: this process cannot be the current process yet.
+        let npdata = unsafe { np.deref_mut_data() };
+
+        // Copy saved user registers, and call func(arg) on stack.
+        // SAFETY: trap_frame has been initialized by init_proc.
+        let trap_frame = unsafe { &mut *npdata.trap_frame };
+        *trap_frame = *ctx.proc().trap_frame();
+        trap_frame.set_pc(func);
+        trap_frame.set_sp(stack);
+        *trap_frame.param_reg_mut(RegNum::R0) = arg;
+
+        npdata.signals = ctx.proc().deref_data().signals.clone();
+
+        npdata.name.copy_from_slice(&ctx.proc().deref_data().name);
+
+        let tid = np.deref_mut_info().pid;
+
+        // A thread has no parent, as only the threads of its process join it.
+        // It does not break the invariant because it is not a main thread.
+        np.set_nice(nice);
+        np.deref_mut_info().affinity = affinity;
+        self.make_runnable(&mut np);
+
+        Ok(tid)
+    }
+
+    /// Wait for the thread `tid` of the current process to exit, or for any
+    /// thread if `tid` is 0. Its exit status is stored at `addr` if it is not null.
+    /// Returns Ok(thread id of the exited thread) on success, Err(errno) on error.
+    pub fn join(&self, tid: Pid, addr: UVAddr, ctx: &mut KernelCtx<'id, '_>) -> Result<Pid, Errno> {
+        let leader = ctx.proc().main_thread().deref() as *const Proc;
+        // Read the page of addr now if it is file-backed, as it cannot be read
+        // while holding the locks below.
+        ctx.fault_in(addr, mem::size_of::<i32>(), AccessFlags::W);
+        let mut parent_guard = self.wait_guard();
+
+        loop {
+            // Scan through pool looking for exited threads.
+            let mut found = false;
+            for np in self.process_pool() {
+                if ptr::eq(np.deref(), ctx.proc().deref().deref()) {
+                    continue;
+                }
+                let mut np = np.lock();
+                if np.state() == Procstate::UNUSED
+                    || np.deref_info().leader != leader
+                    || np.is_main_thread()
+                    || (tid != 0 && np.deref_info().pid != tid)
+                {
+                    continue;
+                }
+
+                found = true;
+                if np.state() == Procstate::ZOMBIE {
+                    let tid = np.deref_info().pid;
+                    if !addr.is_null()
+                        && ctx
+                            .proc()
+                            .memory()
+                            .copy_out(addr, &np.deref_info().xstate)
+                            .is_err()
+                    {
+                        return Err(Errno::EFAULT);
+                    }
+                    // Reap the zombie thread.
+                    // SAFETY: np.state() equals ZOMBIE, and it is not a main thread.
+                    unsafe { np.clear(&mut parent_guard) };
+                    return Ok(tid);
+                }
+            }
+
+            // No point waiting if there is no such thread.
+            if !found {
+                return Err(Errno::ESRCH);
+            }
+            if ctx.proc().killed() || ctx.proc().signal_pending() {
+                return Err(Errno::EINTR);
+            }
+
+            // Wait for a thread to exit.
+            ctx.proc()
+                .main_thread()
+                .child_waitchannel
+                .sleep(&mut parent_guard.0, ctx);
+        }
+    }
+
+    fn run_queue(&self, cpu: usize) -> Pin<&'s SpinLock<RunQueue>> {
+        // SAFETY: the run queues are never moved.
+        unsafe {
+            self.0
+                .project_ref()
+                .run_queues
+                .map_unchecked(|run_queues| &run_queues[cpu])
+        }
+    }
+
+    /// Returns the CPU whose run queue the process of `guard` should be pushed to:
+    /// the CPU it last ran on if its affinity allows it, or else the first allowed CPU
+    /// that has started its scheduler.
+    fn target_cpu(&self, guard: &ProcGuard<'id, '_>) -> usize {
+        let info = guard.deref_info();
+        if info.affinity & (1 << info.cpu) != 0 {
+            return info.cpu;
+        }
+        let online = info.affinity & self.online_cpus.load(Ordering::Acquire);
+        let allowed = if online != 0 { online } else { info.affinity };
+        allowed.trailing_zeros() as usize
+    }
+
+    /// Make the process of `guard` RUNNABLE, and push it to the run queue of its level
+    /// of a CPU that its affinity allows.
+    pub fn make_runnable(&self, guard: &mut ProcGuard<'id, '_>) {
+        let cpu = self.target_cpu(guard);
+        let info = guard.deref_mut_info();
+        info.state = Procstate::RUNNABLE;
+        info.cpu = cpu;
+        let level = info.level;
+        let affinity = info.affinity;
+        self.run_queue(cpu)
+            .pinned_lock()
+            .get_pin_mut()
+            .push(guard, level, affinity);
+    }
+
+    /// Remove the process to run next on `cpu` from its run queue, and return it.
+    /// If the run queue is empty, steal a process from the run queue of another CPU.
+    fn pop_runnable(&self, cpu: usize) -> Option<ProcRef<'id, 's>> {
+        // Release the lock of this CPU's run queue before stealing, so that two CPUs
+        // stealing from each other do not deadlock.
+        let proc = self.run_queue(cpu).pinned_lock().get_pin_mut().pop();
+        let proc = proc.or_else(|| {
+            (1..NCPU).find_map(|i| {
+                self.run_queue((cpu + i) % NCPU)
+                    .pinned_lock()
+                    .get_pin_mut()
+                    .steal(cpu)
+            })
+        })?;
+        // SAFETY: only the `Proc`s of this `Procs` are in the run queues.
+        Some(ProcRef(self.0.brand(unsafe { &*proc })))
+    }
+
+    /// Move the process of `guard` to the run queue of its current level and
+    /// affinity if it is in a run queue.
+    fn requeue(&self, guard: &mut ProcGuard<'id, '_>) {
+        if guard.state() != Procstate::RUNNABLE {
+            return;
+        }
+        let cpu = guard.deref_info().cpu;
+        // A RUNNABLE process is not in a run queue if a scheduler is about to run it.
+        let queued = self
+            .run_queue(cpu)
+            .pinned_lock()
+            .get_pin_mut()
+            .remove(guard);
+        if queued {
+            self.make_runnable(guard);
+        }
+    }
+
+    /// Set the nice value of the process of `guard`, and move it to the run queue
+    /// of its new level if it is in a run queue.
+    fn renice(&self, guard: &mut ProcGuard<'id, '_>, nice: i32) {
+        guard.set_nice(nice);
+        self.requeue(guard);
+    }
+
+    /// Move every process to the highest level allowed by its nice value,
+    /// so that CPU-bound processes do not starve.
+    pub fn boost(&self) {
+        for p in self.process_pool() {
+            let mut guard = p.lock();
+            if guard.state() != Procstate::UNUSED {
+                let nice = guard.deref_info().nice;
+                self.renice(&mut guard, nice);
+            }
+        }
+    }
+
+    /// Set the nice value of the process `pid`, or the current process if `pid` is 0.
+    /// Returns Ok(()) on success, Err(ESRCH) if there is no such process, and Err(EPERM)
+    /// if the current process is neither the superuser nor the owner of the process,
+    /// or lowers the nice value without being the superuser.
+    pub fn setpriority(&self, pid: Pid, nice: i32, ctx: &KernelCtx<'id, '_>) -> Result<(), Errno> {
+        let pid = if pid == 0 { ctx.proc().pid() } else { pid };
+        let uid = ctx.proc().creds().uid;
+        for p in self.process_pool() {
+            let mut guard = p.lock();
+            if guard.deref_info().pid == pid && guard.state() != Procstate::UNUSED {
+                guard.check_owner(uid)?;
+                if uid != ROOT_UID && nice.clamp(NICE_MIN, NICE_MAX) < guard.deref_info().nice {
+                    return Err(Errno::EPERM);
+                }
+                self.renice(&mut guard, nice);
+                return Ok(());
+            }
+        }
+        Err(Errno::ESRCH)
+    }
+
+    /// Set the affinity of the process `pid`, or the current process if `pid` is 0, to
+    /// the CPUs in `mask`. A RUNNING process moves to an allowed CPU when it yields.
+    /// Returns Ok(()) on success, Err(EINVAL) if `mask` has no CPU that has
+    /// started its scheduler, Err(ESRCH) if there is no such process, and Err(EPERM) if
+    /// the current process is neither the superuser nor the owner of the process.
+    pub fn set_affinity(
+        &self,
+        pid: Pid,
+        mask: usize,
+        ctx: &KernelCtx<'id, '_>,
+    ) -> Result<(), Errno> {
+        let mask = mask & ALL_CPUS;
+        if mask & self.online_cpus.load(Ordering::Acquire) == 0 {
+            return Err(Errno::EINVAL);
+        }
+        let pid = if pid == 0 { ctx.proc().pid() } else { pid };
+        let uid = ctx.proc().creds().uid;
+        for p in self.process_pool() {
+            let mut guard = p.lock();
+            if guard.deref_info().pid == pid && guard.state() != Procstate::UNUSED {
+                guard.check_owner(uid)?;
+                guard.deref_mut_info().affinity = mask;
+                self.requeue(&mut guard);
+                return Ok(());
+            }
+        }
+        Err(Errno::ESRCH)
+    }
+
+    /// Returns the nice value of the process `pid`, or the current process if `pid` is 0.
+    /// Returns Err(ESRCH) if there is no such process.
+    pub fn getpriority(&self, pid: Pid, ctx: &KernelCtx<'id, '_>) -> Result<i32, Errno> {
+        let pid = if pid == 0 { ctx.proc().pid() } else { pid };
+        for p in self.process_pool() {
+            let guard = p.lock();
+            if guard.deref_info().pid == pid && guard.state() != Procstate::UNUSED {
+                return Ok(guard.deref_info().nice);
+            }
+        }
+        Err(Errno::ESRCH)
+    }
+
     // get the pid of current process's parent
     pub fn get_parent_pid(&mut self, ctx: &mut KernelCtx<'id, '_>) -> Pid {
         let mut parent_guard = self.wait_guard();
-        let parent = *ctx.proc().get_mut_parent(&mut parent_guard);
+        let parent = *ctx.proc().main_thread().get_mut_parent(&mut parent_guard);
 
         let lock = unsafe { (*parent).info.lock() };
         lock.pid
     }
+
+    /// Returns the main thread of the process `pid` with its lock held,
+    /// or None if there is no such process.
+    fn find_process(&self, pid: Pid) -> Option<ProcGuard<'id, 's>> {
+        self.process_pool().map(|p| p.lock()).find(|guard| {
+            guard.deref_info().pid == pid
+                && guard.state() != Procstate::UNUSED
+                && guard.is_main_thread()
+        })
+    }
+
+    /// Calls `f` with the pid of each process, leaving out the threads other than
+    /// the main thread.
+    pub fn for_each_pid<F: FnMut(Pid)>(&self, mut f: F) {
+        for p in self.process_pool() {
+            let guard = p.lock();
+            if guard.state() != Procstate::UNUSED && guard.is_main_thread() {
+                let pid = guard.deref_info().pid;
+                drop(guard);
+                f(pid);
+            }
+        }
+    }
+
+    /// Writes the status of the process `pid` to `w`, one "Key:\tvalue" line per field,
+    /// as `/proc/<pid>/status` shows it.
+    /// Returns Err(ESRCH) if there is no such process.
+    pub fn write_status(&self, pid: Pid, w: &mut impl fmt::Write) -> Result<(), Errno> {
+        let mut parent_guard = self.wait_guard();
+        let guard = self.find_process(pid).ok_or(Errno::ESRCH)?;
+        let leader: *const Proc = guard.deref().deref();
+        let parent = *guard.get_mut_parent(&mut parent_guard);
+        let info = guard.deref_info();
+        let (state, nice, level, cpu, affinity) =
+            (info.state, info.nice, info.level, info.cpu, info.affinity);
+        // SAFETY: the process is not UNUSED, so its memory has been initialized.
+        let size = unsafe { guard.memory.lock().assume_init_ref() }.size();
+        // Like `dump`, reads the name that only the process itself writes.
+        let name = unsafe { (*guard.data.get()).name };
+        drop(guard);
+
+        let ppid = if parent.is_null() {
+            0
+        } else {
+            // SAFETY: `parent_guard` keeps the parent valid.
+            unsafe { (*parent).info.lock().pid }
+        };
+        let threads = self
+            .process_pool()
+            .filter(|p| {
+                let guard = p.lock();
+                guard.state() != Procstate::UNUSED && ptr::eq(guard.deref_info().leader, leader)
+            })
+            .count();
+        drop(parent_guard);
+
+        let length = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+        // `w` may drop what does not fit.
+        let _ = write!(
+            w,
+            "Name:\t{}\nState:\t{}\nPid:\t{}\nPPid:\t{}\nNice:\t{}\nLevel:\t{}\nCpu:\t{}\n\
+             Affinity:\t{:#x}\nThreads:\t{}\nSize:\t{}\n",
+            str::from_utf8(&name[0..length]).unwrap_or("???"),
+            Procstate::as_str(&state).trim_end(),
+            pid,
+            ppid,
+            nice,
+            level,
+            cpu,
+            affinity,
+            threads,
+            size
+        );
+        Ok(())
+    }
+
+    /// Writes the open files of the process `pid` to `w`, one "fd file" line per file,
+    /// as `/proc/<pid>/fd` shows them.
+    /// Returns Err(ESRCH) if there is no such process.
+    pub fn write_fds(&self, pid: Pid, w: &mut impl fmt::Write) -> Result<(), Errno> {
+        let guard = self.find_process(pid).ok_or(Errno::ESRCH)?;
+        for (fd, f) in guard.open_files.lock().iter().enumerate() {
+            if let Some(f) = f {
+                let _ = writeln!(w, "{} {}", fd, **f);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the memory areas of the process `pid` to `w`, as `/proc/<pid>/maps` shows them.
+    /// Returns Err(ESRCH) if there is no such process.
+    pub fn write_maps(&self, pid: Pid, w: &mut impl fmt::Write) -> Result<(), Errno> {
+        let guard = self.find_process(pid).ok_or(Errno::ESRCH)?;
+        // SAFETY: the process is not UNUSED, so its memory has been initialized.
+        let _ = unsafe { guard.memory.lock().assume_init_ref() }.write_maps(w);
+        Ok(())
+    }
 }
 
 impl Deref for ProcsRef<'_, '_> {
@@ -490,7 +1073,7 @@ unsafe fn forkret() -> ! {
         // File system initialization must be run in the context of a
         // regular process (e.g., because it calls sleep), and thus cannot
         // be run from main().
-        ctx.kernel().fs().init(ROOTDEV, &ctx);
+        ctx.kernel().fs().init(&ctx);
         unsafe { ctx.user_trap_ret() }
     };
 
@@ -528,25 +1111,29 @@ impl<'id, 's> KernelRef<'id, 's> {
         // SAFETY: this function never moves to another CPU.
         let cpu = unsafe { hal().get_ref().cpus().current_unchecked() };
         cpu.set_proc(ptr::null_mut());
+        let id = TargetArch::cpu_id();
+        let _ = self.procs().online_cpus.fetch_or(1 << id, Ordering::AcqRel);
         loop {
             // Avoid deadlock by ensuring that devices can interrupt.
             unsafe { TargetArch::intr_on() };
 
-            for p in self.procs().process_pool() {
-                let mut guard = p.lock();
-                if guard.state() == Procstate::RUNNABLE {
-                    // Switch to chosen process.  It is the process's job
-                    // to release its lock and then reacquire it
-                    // before jumping back to us.
-                    guard.deref_mut_info().state = Procstate::RUNNING;
-                    cpu.set_proc(p.deref());
-                    unsafe { swtch(cpu.context_raw_mut(), &mut guard.deref_mut_data().context) };
-
-                    // Process is done running for now.
-                    // It should have changed its p->state before coming back.
-                    cpu.set_proc(ptr::null_mut());
-                }
-            }
+            // Choose the process at the front of the highest-priority run queue,
+            // stealing one from another CPU if this CPU has nothing to run.
+            let p = some_or!(self.procs().pop_runnable(id), continue);
+            let mut guard = p.lock();
+            assert_eq!(guard.state(), Procstate::RUNNABLE, "scheduler");
+            // Switch to chosen process.  It is the process's job
+            // to release its lock and then reacquire it
+            // before jumping back to us.
+            let info = guard.deref_mut_info();
+            info.state = Procstate::RUNNING;
+            info.cpu = id;
+            cpu.set_proc(p.deref());
+            unsafe { swtch(cpu.context_raw_mut(), &mut guard.deref_mut_data().context) };
+
+            // Process is done running for now.
+            // It should have changed its p->state before coming back.
+            cpu.set_proc(ptr::null_mut());
         }
     }
 
@@ -0,0 +1,200 @@
+//! A multi-level feedback queue scheduler with per-CPU run queues.
+//!
+//! Each RUNNABLE process is in the run queue of a CPU that its affinity allows,
+//! at its priority level, where 0 is the highest priority. The scheduler of a CPU
+//! runs the front process of the highest nonempty level of its run queue. If the
+//! run queue is empty, it steals a process from the run queues of other CPUs.
+//!
+//! A process that uses up the time slice of its level moves to the next lower
+//! level, so that CPU-bound processes yield to interactive ones. Periodically,
+//! every process is boosted back to the highest level allowed by its nice value,
+//! so that CPU-bound processes do not starve.
+
+use core::{cmp, pin::Pin};
+
+use pin_project::pin_project;
+
+use super::*;
+use crate::{
+    param::NCPU,
+    util::intrusive_list::{List, ListEntry, ListNode},
+};
+
+/// Number of priority levels.
+pub const NLEVEL: usize = 8;
+
+/// Range of nice values. A lower nice value gives a higher priority.
+pub const NICE_MIN: i32 = -20;
+pub const NICE_MAX: i32 = 19;
+
+/// Every process is boosted once per this many ticks.
+pub const BOOST_TICKS: u32 = 50;
+
+/// `which` of `setpriority` and `getpriority`.
+pub const PRIO_PROCESS: i32 = 0;
+
+/// The affinity that allows every CPU.
+pub const ALL_CPUS: usize = (1 << NCPU) - 1;
+
+/// An entry of a `RunQueue`, owned by a `Proc`.
+#[repr(C)]
+#[pin_project]
+pub struct RunEntry {
+    #[pin]
+    entry: ListEntry,
+
+    /// The `Proc` that owns this entry.
+    proc: *const Proc,
+
+    /// The affinity of the `Proc` when it was pushed to the run queue.
+    affinity: usize,
+}
+
+/// The run queue of a CPU, which consists of a queue for each priority level.
+pub struct RunQueue {
+    queues: [List<RunEntry>; NLEVEL],
+}
+
+/// Returns the highest level that a process with `nice` can have.
+fn base_level(nice: i32) -> usize {
+    ((nice - NICE_MIN) / 10) as usize
+}
+
+/// Returns the number of ticks that a process can use at `level`
+/// before it moves to the next level.
+fn time_slice(level: usize) -> u32 {
+    1 << (level / 2)
+}
+
+impl RunEntry {
+    /// # Safety
+    ///
+    /// It must be used only after initializing it with `RunEntry::init`.
+    pub const unsafe fn new() -> Self {
+        Self {
+            entry: unsafe { ListEntry::new() },
+            proc: ptr::null(),
+            affinity: 0,
+        }
+    }
+
+    pub fn init(self: Pin<&mut Self>, proc: *const Proc) {
+        let this = self.project();
+        this.entry.init();
+        *this.proc = proc;
+    }
+}
+
+// SAFETY: `RunEntry` owns a `ListEntry`.
+unsafe impl ListNode for RunEntry {
+    fn get_list_entry(self: Pin<&mut Self>) -> Pin<&mut ListEntry> {
+        self.project().entry
+    }
+
+    fn from_list_entry(list_entry: *mut ListEntry) -> *mut Self {
+        list_entry as _
+    }
+}
+
+impl RunQueue {
+    /// # Safety
+    ///
+    /// It must be used only after initializing it with `RunQueue::init`.
+    pub const unsafe fn new() -> Self {
+        Self {
+            queues: array![_ => unsafe { List::new() }; NLEVEL],
+        }
+    }
+
+    pub fn init(mut self: Pin<&mut Self>) {
+        for level in 0..NLEVEL {
+            self.as_mut().queue(level).init();
+        }
+    }
+
+    fn queue(self: Pin<&mut Self>, level: usize) -> Pin<&mut List<RunEntry>> {
+        // SAFETY: the queues are never moved.
+        unsafe { self.map_unchecked_mut(|this| &mut this.queues[level]) }
+    }
+
+    /// Push `proc`, whose affinity is `affinity`, at the back of the queue of `level`.
+    /// `proc` must not be in any run queue.
+    pub fn push(self: Pin<&mut Self>, proc: &Proc, level: usize, affinity: usize) {
+        // SAFETY: `run_entry` is accessed only while holding the lock of the run
+        // queue that contains it, and it is never moved.
+        let mut entry = unsafe { Pin::new_unchecked(&mut *proc.run_entry.get()) };
+        *entry.as_mut().project().affinity = affinity;
+        self.queue(level).push_back(entry);
+    }
+
+    /// Remove `proc` from this run queue, and return true if it was in this run queue.
+    pub fn remove(self: Pin<&mut Self>, proc: &Proc) -> bool {
+        // SAFETY: `run_entry` is accessed only while holding the lock of the run
+        // queue that contains it, and it is never moved.
+        let entry = unsafe { Pin::new_unchecked(&mut *proc.run_entry.get()) };
+        let entry = entry.project().entry;
+        if entry.as_ref().is_unlinked() {
+            return false;
+        }
+        entry.remove();
+        true
+    }
+
+    /// Remove the front process of the highest nonempty level, and return it.
+    pub fn pop(mut self: Pin<&mut Self>) -> Option<*const Proc> {
+        for level in 0..NLEVEL {
+            if let Some(entry) = self.as_mut().queue(level).pop_front() {
+                // SAFETY: entry is a valid `RunEntry` owned by a `Proc`.
+                return Some(unsafe { (*entry).proc });
+            }
+        }
+        None
+    }
+
+    /// Remove the front process of the highest nonempty level among the ones
+    /// that can run on `cpu`, and return it.
+    pub fn steal(mut self: Pin<&mut Self>, cpu: usize) -> Option<*const Proc> {
+        for level in 0..NLEVEL {
+            // SAFETY: the entries are accessed only while holding the run queue lock.
+            let entry = unsafe { self.as_mut().queue(level).iter_pin_mut_unchecked() }
+                .find(|entry| entry.affinity & (1 << cpu) != 0);
+            if let Some(entry) = entry {
+                let entry = entry.project();
+                entry.entry.remove();
+                return Some(*entry.proc);
+            }
+        }
+        None
+    }
+}
+
+impl ProcGuard<'_, '_> {
+    /// Set the nice value of the process, and move it to the highest level it can have.
+    pub fn set_nice(&mut self, nice: i32) {
+        let info = self.deref_mut_info();
+        info.nice = cmp::min(cmp::max(nice, NICE_MIN), NICE_MAX);
+        info.level = base_level(info.nice);
+        info.run_ticks = 0;
+    }
+
+    /// Charge a tick to the process. It moves to the next level once it uses up
+    /// the time slice of its level.
+    pub fn charge_tick(&mut self) {
+        let info = self.deref_mut_info();
+        info.run_ticks += 1;
+        if info.run_ticks >= time_slice(info.level) {
+            info.level = cmp::min(info.level + 1, NLEVEL - 1);
+            info.run_ticks = 0;
+        }
+    }
+}
+
+impl CurrentProc<'_, '_> {
+    /// Returns the nice value and the affinity of the current process,
+    /// which its children and threads inherit.
+    pub fn sched_attr(&self) -> (i32, usize) {
+        let guard = self.lock();
+        let info = guard.deref_info();
+        (info.nice, info.affinity)
+    }
+}
@@ -0,0 +1,427 @@
+//! POSIX-style signals.
+//!
+//! A signal is sent to a process by setting its bit in `Proc::pending`. The
+//! process handles its pending signals that are not blocked right before it
+//! returns to user space. If the signal has a user handler, the kernel saves the
+//! user registers in a `SignalFrame` on the user stack, and makes the process
+//! run the handler. When the handler returns, it jumps to the restorer given by
+//! `sigaction`, which calls `sigreturn` to restore the saved registers.
+
+use core::{cmp, mem, sync::atomic::Ordering};
+
+use zerocopy::{AsBytes, FromBytes};
+
+use super::*;
+use crate::{
+    addr::UVAddr,
+    arch::interface::{ProcManager, TrapFrameManager},
+    arch::TargetArch,
+    errno::Errno,
+    param::TICKS_PER_SEC,
+    some_or,
+};
+
+/// Number of signals. Valid signal numbers are in [1, NSIG).
+pub const NSIG: usize = 32;
+
+pub const SIGKILL: i32 = 9;
+pub const SIGALRM: i32 = 14;
+pub const SIGCHLD: i32 = 20;
+
+/// The handler of the default action.
+const SIG_DFL: usize = 0;
+/// The handler that ignores the signal.
+const SIG_IGN: usize = 1;
+
+/// `how` of `sigprocmask`.
+const SIG_BLOCK: i32 = 0;
+const SIG_UNBLOCK: i32 = 1;
+const SIG_SETMASK: i32 = 2;
+
+/// A set of signals.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct SigSet(u32);
+
+/// `sigset_t` of the user library.
+#[repr(C)]
+#[derive(Clone, Copy, AsBytes, FromBytes)]
+pub struct UserSigSet {
+    val: [usize; 16],
+}
+
+/// `struct sigaction` of the user library.
+#[repr(C)]
+#[derive(Clone, Copy, AsBytes, FromBytes)]
+pub struct UserSigAction {
+    handler: usize,
+    mask: UserSigSet,
+    flags: i32,
+    _padding: u32,
+    restorer: usize,
+}
+
+/// The action taken when a signal is delivered.
+#[derive(Clone, Copy)]
+pub struct SigAction {
+    /// `SIG_DFL`, `SIG_IGN`, or the user address of the handler.
+    handler: usize,
+    /// Signals blocked while the handler runs.
+    mask: SigSet,
+    /// Flags given by the user. Kept only to be reported back.
+    flags: i32,
+    /// User address that the handler returns to. It should call `sigreturn`.
+    restorer: usize,
+}
+
+/// Per-thread signal state, which is private to the thread.
+/// A new thread or child process inherits a clone of its creator's.
+#[derive(Clone)]
+pub struct Signals {
+    /// Blocked signals.
+    mask: SigSet,
+
+    /// User address of the innermost `SignalFrame`, or 0 if no handler is running.
+    frame: usize,
+}
+
+/// The actions for each signal, which are shared by the threads of a process.
+/// A child process inherits a copy of its parent's.
+#[derive(Clone, Copy)]
+pub struct SigActions([SigAction; NSIG]);
+
+/// Saved user state, pushed onto the user stack while a handler runs.
+#[repr(C)]
+#[derive(Clone, Copy, AsBytes, FromBytes)]
+struct SignalFrame {
+    /// The user registers of the trap frame.
+    regs: <<TargetArch as ProcManager>::TrapFrame as TrapFrameManager>::UserRegs,
+    /// `Signals::mask` before the handler runs.
+    mask: usize,
+    /// `Signals::frame` before the handler runs.
+    prev: usize,
+}
+
+impl SigSet {
+    /// Signals that cannot be blocked, caught, or ignored.
+    const UNBLOCKABLE: Self = Self(1 << SIGKILL);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    const fn of(sig: i32) -> Self {
+        Self(1 << sig)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(self, sig: i32) -> bool {
+        self.0 & (1 << sig) != 0
+    }
+
+    fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    /// Returns the smallest signal in the set.
+    fn first(self) -> Option<i32> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.0.trailing_zeros() as i32)
+        }
+    }
+}
+
+impl From<UserSigSet> for SigSet {
+    fn from(set: UserSigSet) -> Self {
+        // Signal 0 does not exist.
+        Self(set.val[0] as u32 & !1)
+    }
+}
+
+impl From<SigSet> for UserSigSet {
+    fn from(set: SigSet) -> Self {
+        let mut val = [0; 16];
+        val[0] = set.0 as usize;
+        Self { val }
+    }
+}
+
+impl SigAction {
+    const fn new() -> Self {
+        Self {
+            handler: SIG_DFL,
+            mask: SigSet::empty(),
+            flags: 0,
+            restorer: 0,
+        }
+    }
+
+    /// Returns true if delivering `sig` with this action does nothing.
+    fn ignores(&self, sig: i32) -> bool {
+        match self.handler {
+            SIG_IGN => true,
+            SIG_DFL => sig == SIGCHLD,
+            _ => false,
+        }
+    }
+}
+
+impl From<UserSigAction> for SigAction {
+    fn from(act: UserSigAction) -> Self {
+        Self {
+            handler: act.handler,
+            mask: act.mask.into(),
+            flags: act.flags,
+            restorer: act.restorer,
+        }
+    }
+}
+
+impl From<SigAction> for UserSigAction {
+    fn from(act: SigAction) -> Self {
+        Self {
+            handler: act.handler,
+            mask: act.mask.into(),
+            flags: act.flags,
+            _padding: 0,
+            restorer: act.restorer,
+        }
+    }
+}
+
+impl Signals {
+    pub const fn new() -> Self {
+        Self {
+            mask: SigSet::empty(),
+            frame: 0,
+        }
+    }
+}
+
+impl SigActions {
+    pub const fn new() -> Self {
+        Self([SigAction::new(); NSIG])
+    }
+}
+
+impl Proc {
+    /// Send signal `sig` to the process. Sending `SIGKILL` kills it.
+    /// The caller should wake the process up.
+    pub fn signal(&self, sig: i32) {
+        if sig == SIGKILL {
+            self.kill();
+        } else {
+            let _ = self.pending.fetch_or(SigSet::of(sig).0, Ordering::AcqRel);
+        }
+    }
+
+    /// Removes a pending signal that is not in `blocked` from the pending
+    /// signals, and returns it.
+    fn take_pending(&self, blocked: SigSet) -> Option<i32> {
+        loop {
+            let pending = SigSet(self.pending.load(Ordering::Acquire));
+            let sig = pending.difference(blocked).first()?;
+            let prev = self.pending.fetch_and(!SigSet::of(sig).0, Ordering::AcqRel);
+            if SigSet(prev).contains(sig) {
+                return Some(sig);
+            }
+        }
+    }
+
+    /// Schedule `SIGALRM` at `deadline`, or cancel it if `deadline` is 0.
+    /// Returns the ticks that remained from `now` until the previous alarm,
+    /// or 0 if there was none.
+    fn set_alarm(&self, now: u32, deadline: u32) -> u32 {
+        let prev = self.alarm.swap(deadline, Ordering::AcqRel);
+        if prev == 0 {
+            0
+        } else {
+            cmp::max(prev.wrapping_sub(now) as i32, 0) as u32
+        }
+    }
+
+    /// Returns the deadline of the alarm, if any.
+    pub fn alarm_deadline(&self) -> Option<u32> {
+        let deadline = self.alarm.load(Ordering::Acquire);
+        (deadline != 0).then(|| deadline)
+    }
+
+    /// Returns true and cancels the alarm if it expires at `now`.
+    pub fn expire_alarm(&self, now: u32) -> bool {
+        let deadline = self.alarm.load(Ordering::Acquire);
+        deadline != 0
+            && now.wrapping_sub(deadline) as i32 >= 0
+            && self
+                .alarm
+                .compare_exchange(deadline, 0, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+    }
+
+    /// Clears the signals sent to the process and its alarm.
+    pub fn clear_signals(&self) {
+        self.pending.store(0, Ordering::Release);
+        self.alarm.store(0, Ordering::Release);
+    }
+}
+
+impl CurrentProc<'_, '_> {
+    /// Returns true if a pending signal will interrupt the process when it
+    /// returns to user space.
+    pub fn signal_pending(&self) -> bool {
+        let pending =
+            SigSet(self.pending.load(Ordering::Acquire)).difference(self.deref_data().signals.mask);
+        let actions = self.sig_actions();
+        (1..NSIG as i32).any(|sig| pending.contains(sig) && !actions.0[sig as usize].ignores(sig))
+    }
+
+    /// Reset the caught signals to the default action, as the handlers are
+    /// gone with the old memory after exec. Ignored signals stay ignored.
+    pub fn reset_signal_actions(&mut self) {
+        for action in &mut self.sig_actions().0 {
+            if action.handler != SIG_IGN {
+                *action = SigAction::new();
+            }
+        }
+        self.deref_mut_data().signals.frame = 0;
+    }
+
+    /// Set the action for `sig` to `act` if it is `Some`, and return the previous action.
+    pub fn sigaction(
+        &mut self,
+        sig: i32,
+        act: Option<UserSigAction>,
+    ) -> Result<UserSigAction, Errno> {
+        if sig <= 0 || sig as usize >= NSIG {
+            return Err(Errno::EINVAL);
+        }
+        let mut actions = self.sig_actions();
+        let action = &mut actions.0[sig as usize];
+        let old = *action;
+        if let Some(act) = act {
+            if SigSet::UNBLOCKABLE.contains(sig) {
+                return Err(Errno::EINVAL);
+            }
+            *action = act.into();
+        }
+        Ok(old.into())
+    }
+
+    /// Change the blocked signals according to `how` if `set` is `Some`, and
+    /// return the previously blocked signals.
+    pub fn sigprocmask(&mut self, how: i32, set: Option<UserSigSet>) -> Result<UserSigSet, Errno> {
+        let signals = &mut self.deref_mut_data().signals;
+        let old = signals.mask;
+        if let Some(set) = set {
+            let set = SigSet::from(set);
+            let mask = match how {
+                SIG_BLOCK => old.union(set),
+                SIG_UNBLOCK => old.difference(set),
+                SIG_SETMASK => set,
+                _ => return Err(Errno::EINVAL),
+            };
+            signals.mask = mask.difference(SigSet::UNBLOCKABLE);
+        }
+        Ok(old.into())
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Schedule `SIGALRM` for the current process after `seconds` seconds, or
+    /// cancel it if `seconds` is 0. Returns the seconds that remained until
+    /// the previous alarm.
+    pub fn alarm(&self, seconds: u32, now: u32) -> u32 {
+        let deadline = if seconds == 0 {
+            0
+        } else {
+            // 0 means no alarm.
+            cmp::max(now.wrapping_add(seconds.saturating_mul(TICKS_PER_SEC)), 1)
+        };
+        let prev = self.proc().set_alarm(now, deadline);
+        if deadline != 0 {
+            self.kernel().procs().schedule_alarm(deadline);
+        }
+        prev / TICKS_PER_SEC + (prev % TICKS_PER_SEC != 0) as u32
+    }
+
+    /// Handle the pending signals of the current process that are not blocked.
+    /// The process is terminated, or runs the handler of a signal when it
+    /// returns to user space.
+    pub fn handle_signals(&mut self) {
+        loop {
+            let mask = self.proc().deref_data().signals.mask;
+            let sig = some_or!(self.proc().take_pending(mask), return);
+            let action = self.proc().sig_actions().0[sig as usize];
+            if action.ignores(sig) {
+                continue;
+            }
+            if action.handler == SIG_DFL || self.push_signal_frame(sig, &action).is_err() {
+                self.kernel().procs().exit_group(-1, self);
+            }
+            return;
+        }
+    }
+
+    /// Save the user registers on the user stack, and make the process run
+    /// the handler of `action` for `sig`.
+    /// Returns Ok(()) on success, Err(EFAULT) if the user stack is invalid.
+    fn push_signal_frame(&mut self, sig: i32, action: &SigAction) -> Result<(), Errno> {
+        let data = self.proc().deref_data();
+        let frame = SignalFrame {
+            regs: self.proc().trap_frame().user_regs(),
+            mask: data.signals.mask.0 as usize,
+            prev: data.signals.frame,
+        };
+        let sp = self
+            .proc()
+            .trap_frame()
+            .get_sp()
+            .checked_sub(mem::size_of::<SignalFrame>())
+            .ok_or(Errno::EFAULT)?;
+        // The stack pointer must be 16-byte aligned.
+        let sp = sp & !0xf;
+        self.copy_out(sp.into(), &frame)?;
+
+        let signals = &mut self.proc_mut().deref_mut_data().signals;
+        signals.frame = sp;
+        signals.mask = signals
+            .mask
+            .union(action.mask)
+            .union(SigSet::of(sig))
+            .difference(SigSet::UNBLOCKABLE);
+
+        let trap_frame = self.proc_mut().trap_frame_mut();
+        trap_frame.set_pc(action.handler);
+        trap_frame.set_sp(sp);
+        trap_frame.set_ra(action.restorer);
+        *trap_frame.param_reg_mut(RegNum::R0) = sig as usize;
+        Ok(())
+    }
+
+    /// Return from a signal handler, restoring the registers and the blocked
+    /// signals saved by `handle_signals`.
+    /// Returns Ok(restored return value register) on success, Err(errno) on error.
+    pub fn sigreturn(&mut self) -> Result<usize, Errno> {
+        let sp = self.proc().deref_data().signals.frame;
+        if sp == 0 {
+            return Err(Errno::EINVAL);
+        }
+        let mut frame = SignalFrame::new_zeroed();
+        // SAFETY: SignalFrame consists of usize fields only.
+        unsafe { self.copy_in(&mut frame, UVAddr::from(sp)) }?;
+
+        let signals = &mut self.proc_mut().deref_mut_data().signals;
+        signals.mask = SigSet(frame.mask as u32).difference(SigSet::UNBLOCKABLE);
+        signals.frame = frame.prev;
+        let trap_frame = self.proc_mut().trap_frame_mut();
+        trap_frame.set_user_regs(&frame.regs);
+        Ok(trap_frame.get_param_reg(RegNum::R0))
+    }
+}
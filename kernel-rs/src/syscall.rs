@@ -4,91 +4,115 @@
 
 #![allow(clippy::unit_arg)]
 
-use core::{mem, str};
+use core::{cmp, mem, str};
 
 use arrayvec::ArrayVec;
 use cstr_core::CStr;
+use zerocopy::FromBytes;
 
 use crate::{
     addr::{Addr, UVAddr},
     arch::interface::{PowerOff, TimeManager, TrapFrameManager},
     arch::TargetArch,
-    file::{RcFile, SeekWhence, SelectEvent},
-    fs::{FcntlFlags, FileSystem, FileSystemExt, InodeType, Path},
+    errno::Errno,
+    file::{
+        RcFile, SeekWhence, SelectEvent, FD_CLOEXEC, FD_SETSIZE, F_DUPFD, F_GETFD, F_GETFL,
+        F_SETFD, F_SETFL,
+    },
+    fs::{FcntlFlags, InodeType, Path, ROOT_UID},
     hal::hal,
     ok_or,
     page::{Page, PGSIZE},
-    param::{MAXARG, MAXPATH},
-    proc::{CurrentProc, KernelCtx},
+    param::{MAXARG, MAXPATH, NOFILE},
+    proc::{
+        CurrentProc, KernelCtx, UserSigAction, UserSigSet, FUTEX_WAIT, FUTEX_WAKE, PRIO_PROCESS,
+    },
     some_or,
+    time::{Timespec, CLOCK_REALTIME, UTIME_NOW, UTIME_OMIT},
+    vm::{AccessFlags, MapFlags, ProtFlags},
 };
 
 impl CurrentProc<'_, '_> {
     /// Fetch the usize at addr from the current process.
-    /// Returns Ok(fetched integer) on success, Err(()) on error.
-    pub fn fetchaddr(&mut self, addr: UVAddr) -> Result<usize, ()> {
+    /// Returns Ok(fetched integer) on success, Err(EFAULT) on error.
+    pub fn fetchaddr(&mut self, addr: UVAddr) -> Result<usize, Errno> {
         let mut ip = 0;
         let sz = mem::size_of::<usize>();
-        if addr.into_usize() >= self.memory().size()
-            || addr.into_usize() + sz > self.memory().size()
-        {
-            return Err(());
+        let size = self.memory().size();
+        if addr.into_usize() >= size || addr.into_usize() + sz > size {
+            return Err(Errno::EFAULT);
         }
         // SAFETY: usize does not have any internal structure.
-        unsafe { self.memory_mut().copy_in(&mut ip, addr) }?;
+        unsafe { self.memory().copy_in(&mut ip, addr) }?;
         Ok(ip)
     }
 
-    /// Fetch the nul-terminated string at addr from the current process.
-    /// Returns reference to the string in the buffer.
-    pub fn fetchstr<'a>(&mut self, addr: UVAddr, buf: &'a mut [u8]) -> Result<&'a CStr, ()> {
-        self.memory_mut().copy_in_str(buf, addr)?;
-
-        // SAFETY: buf contains '\0' as copy_in_str has succeeded.
-        Ok(unsafe { CStr::from_ptr(buf.as_ptr()) })
-    }
-
     fn argraw(&self, n: usize) -> usize {
         self.trap_frame().get_param_reg(n.into())
     }
 
     /// Fetch the nth 32-bit system call argument.
-    pub fn argint(&self, n: usize) -> Result<i32, ()> {
+    pub fn argint(&self, n: usize) -> Result<i32, Errno> {
         Ok(self.argraw(n) as i32)
     }
 
+    /// Fetch the nth 64-bit system call argument.
+    pub fn arglong(&self, n: usize) -> Result<i64, Errno> {
+        Ok(self.argraw(n) as i64)
+    }
+
     /// Retrieve an argument as a pointer.
     /// Doesn't check for legality, since
     /// copyin/copyout will do that.
-    pub fn argaddr(&self, n: usize) -> Result<usize, ()> {
+    pub fn argaddr(&self, n: usize) -> Result<usize, Errno> {
         Ok(self.argraw(n))
     }
 
-    /// Fetch the nth word-sized system call argument as a null-terminated string.
-    /// Copies into buf, at most max.
-    /// Returns reference to the string in the buffer.
-    pub fn argstr<'a>(&mut self, n: usize, buf: &'a mut [u8]) -> Result<&'a CStr, ()> {
-        let addr = self.argaddr(n)?;
-        self.fetchstr(addr.into(), buf)
-    }
-
     /// Fetch the nth word-sized system call argument as a file descriptor
     /// and return both the descriptor and the corresponding struct file.
-    fn argfd(&self, n: usize) -> Result<(i32, &RcFile), ()> {
+    /// The caller should free the returned file.
+    fn argfd(&self, n: usize) -> Result<(i32, RcFile), Errno> {
         let fd = self.argint(n)?;
+        let f = self.getfile(fd)?;
+        Ok((fd, f))
+    }
+
+    /// Return the struct file of the file descriptor fd, which the caller
+    /// should free. Another thread may close fd while the file is being used.
+    /// Returns Err(EBADF) if fd is not an open file descriptor.
+    pub fn getfile(&self, fd: i32) -> Result<RcFile, Errno> {
         let f = self
-            .deref_data()
-            .open_files
+            .open_files()
             .get(fd as usize)
-            .ok_or(())?
+            .ok_or(Errno::EBADF)?
             .as_ref()
-            .ok_or(())?;
-        Ok((fd, f))
+            .ok_or(Errno::EBADF)?
+            .clone();
+        Ok(f)
     }
 }
 
 impl KernelCtx<'_, '_> {
-    pub fn syscall(&mut self, num: i32) -> Result<usize, ()> {
+    /// Fetch the nul-terminated string at addr from the current process.
+    /// Returns reference to the string in the buffer.
+    pub fn fetchstr<'a>(&self, addr: UVAddr, buf: &'a mut [u8]) -> Result<&'a CStr, Errno> {
+        self.copy_in_str(buf, addr)?;
+
+        // SAFETY: buf contains '\0' as copy_in_str has succeeded.
+        Ok(unsafe { CStr::from_ptr(buf.as_ptr()) })
+    }
+
+    /// Fetch the nth word-sized system call argument as a null-terminated string.
+    /// Copies into buf, at most max.
+    /// Returns reference to the string in the buffer.
+    pub fn argstr<'a>(&self, n: usize, buf: &'a mut [u8]) -> Result<&'a CStr, Errno> {
+        let addr = self.proc().argaddr(n)?;
+        self.fetchstr(addr.into(), buf)
+    }
+
+    /// Run the system call `num`.
+    /// Returns Ok(return value) on success, Err(errno) on error.
+    pub fn syscall(&mut self, num: i32) -> Result<usize, Errno> {
         match num {
             1 => self.sys_fork(),
             2 => self.sys_exit(),
@@ -119,6 +143,40 @@ impl KernelCtx<'_, '_> {
             27 => self.sys_lseek(),
             28 => self.sys_uptime_as_micro(),
             29 => self.sys_clock(),
+            30 => self.sys_mmap(),
+            31 => self.sys_munmap(),
+            32 => self.sys_mprotect(),
+            33 => self.sys_sigaction(),
+            34 => self.sys_sigprocmask(),
+            35 => self.sys_sigreturn(),
+            36 => self.sys_alarm(),
+            37 => self.sys_clone(),
+            38 => self.sys_join(),
+            39 => self.sys_exit_group(),
+            40 => self.sys_futex(),
+            41 => self.sys_setpriority(),
+            42 => self.sys_getpriority(),
+            43 => self.sys_sched_setaffinity(),
+            44 => self.sys_mount(),
+            45 => self.sys_umount(),
+            46 => self.sys_ioctl(),
+            47 => self.sys_getdents(),
+            48 => self.sys_rename(),
+            49 => self.sys_symlink(),
+            50 => self.sys_readlink(),
+            51 => self.sys_clock_gettime(),
+            52 => self.sys_utimens(),
+            53 => self.sys_setuid(),
+            54 => self.sys_getuid(),
+            55 => self.sys_setgid(),
+            56 => self.sys_getgid(),
+            57 => self.sys_chmod(),
+            58 => self.sys_chown(),
+            59 => self.sys_poll(),
+            60 => self.sys_epoll_create(),
+            61 => self.sys_epoll_ctl(),
+            62 => self.sys_epoll_wait(),
+            63 => self.sys_fcntl(),
             _ => {
                 self.kernel().as_ref().write_fmt(format_args!(
                     "{} {}: unknown sys call {}",
@@ -126,267 +184,511 @@ impl KernelCtx<'_, '_> {
                     str::from_utf8(&self.proc().deref_data().name).unwrap_or("???"),
                     num
                 ));
-                Err(())
+                Err(Errno::ENOSYS)
             }
         }
     }
 
     /// Terminate the current process; status reported to wait(). No return.
-    pub fn sys_exit(&mut self) -> Result<usize, ()> {
+    pub fn sys_exit(&mut self) -> Result<usize, Errno> {
         let n = self.proc().argint(0)?;
         self.kernel().procs().exit_current(n, self);
     }
 
     /// Create a process.
-    /// Returns Ok(child’s PID) on success, Err(()) on error.
-    pub fn sys_fork(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(child’s PID) on success, Err(errno) on error.
+    pub fn sys_fork(&mut self) -> Result<usize, Errno> {
         Ok(self.kernel().procs().fork(self)? as _)
     }
 
     /// Wait for a child to exit.
-    /// Returns Ok(child’s PID) on success, Err(()) on error.
-    pub fn sys_wait(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(child’s PID) on success, Err(errno) on error.
+    pub fn sys_wait(&mut self) -> Result<usize, Errno> {
         let p = self.proc().argaddr(0)?;
         Ok(self.kernel().procs().wait(p.into(), self)? as _)
     }
 
     /// Return the current process’s PID.
-    pub fn sys_getpid(&self) -> Result<usize, ()> {
+    pub fn sys_getpid(&self) -> Result<usize, Errno> {
         Ok(self.proc().pid() as _)
     }
 
     /// Grow process’s memory by n bytes.
-    /// Returns Ok(start of new memory) on success, Err(()) on error.
-    pub fn sys_sbrk(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(start of new memory) on success, Err(errno) on error.
+    pub fn sys_sbrk(&mut self) -> Result<usize, Errno> {
         let n = self.proc().argint(0)?;
-        self.proc_mut().memory_mut().resize(n, hal().kmem())
+        self.proc().memory().resize(n, hal().kmem())
+    }
+
+    /// Map len bytes of the file fd from offset, or of anonymous memory if
+    /// MAP_ANONYMOUS is given, with protection prot. The address hint addr is
+    /// ignored. Exactly one of MAP_SHARED and MAP_PRIVATE must be given.
+    /// Returns Ok(start of the mapping) on success, Err(errno) on error.
+    pub fn sys_mmap(&mut self) -> Result<usize, Errno> {
+        let len = self.proc().argaddr(1)?;
+        let prot = ProtFlags::from_bits(self.proc().argint(2)?).ok_or(Errno::EINVAL)?;
+        let flags = MapFlags::from_bits(self.proc().argint(3)?).ok_or(Errno::EINVAL)?;
+        let offset = self.proc().argint(5)?;
+        let shared = flags.contains(MapFlags::SHARED);
+        if prot.is_empty()
+            || shared == flags.contains(MapFlags::PRIVATE)
+            || offset < 0
+            || offset as usize % PGSIZE != 0
+        {
+            return Err(Errno::EINVAL);
+        }
+
+        if flags.contains(MapFlags::ANONYMOUS) {
+            return self.proc().memory().mmap(len, prot.into(), None, shared);
+        }
+        let (_, f) = self.proc().argfd(4)?;
+        if !f.is_mappable(shared && prot.contains(ProtFlags::WRITE)) {
+            f.free(self);
+            return Err(Errno::EACCES);
+        }
+        let ret = self
+            .proc()
+            .memory()
+            .mmap(len, prot.into(), Some((&f, offset as u64)), shared);
+        f.free(self);
+        ret
+    }
+
+    /// Unmap the mappings in [addr, addr + len).
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_munmap(&mut self) -> Result<usize, Errno> {
+        let addr = self.proc().argaddr(0)?;
+        let len = self.proc().argaddr(1)?;
+        self.write_back(addr, addr.saturating_add(len));
+        let files = self
+            .proc()
+            .memory()
+            .munmap(addr.into(), len, hal().kmem())?;
+        for f in files {
+            f.free(self);
+        }
+        Ok(0)
+    }
+
+    /// Change the protection of the mappings in [addr, addr + len) to prot.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_mprotect(&mut self) -> Result<usize, Errno> {
+        let addr = self.proc().argaddr(0)?;
+        let len = self.proc().argaddr(1)?;
+        let prot = ProtFlags::from_bits(self.proc().argint(2)?).ok_or(Errno::EINVAL)?;
+        if prot.is_empty() {
+            return Err(Errno::EINVAL);
+        }
+        self.proc()
+            .memory()
+            .mprotect(addr.into(), len, prot.into(), hal().kmem())?;
+        Ok(0)
     }
 
     /// Pause for n clock ticks.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_sleep(&self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(EINTR) if interrupted.
+    pub fn sys_sleep(&self) -> Result<usize, Errno> {
         let n = self.proc().argint(0)?;
         assert!(n >= 0);
 
         let mut ticks = self.kernel().ticks().lock();
         let ticks0 = *ticks;
         while ticks.wrapping_sub(ticks0) < n as u32 {
-            if self.proc().killed() {
-                return Err(());
+            if self.proc().killed() || self.proc().signal_pending() {
+                return Err(Errno::EINTR);
             }
             ticks.sleep(self);
         }
         Ok(0)
     }
 
-    /// Terminate process PID.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_kill(&self) -> Result<usize, ()> {
+    /// Send signal sig to process PID.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_kill(&self) -> Result<usize, Errno> {
         let pid = self.proc().argint(0)?;
-        self.kernel().procs().kill(pid)?;
+        let sig = self.proc().argint(1)?;
+        self.kernel().procs().kill(pid, sig, self)?;
         Ok(0)
     }
 
+    /// Examine and change the action for signal sig. If act is not null, it
+    /// points to the new action. If oldact is not null, the previous action
+    /// is stored there.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_sigaction(&mut self) -> Result<usize, Errno> {
+        let sig = self.proc().argint(0)?;
+        let act = UVAddr::from(self.proc().argaddr(1)?);
+        let oldact = UVAddr::from(self.proc().argaddr(2)?);
+        let act = if act.is_null() {
+            None
+        } else {
+            let mut buf = UserSigAction::new_zeroed();
+            // SAFETY: UserSigAction consists of integer fields only.
+            unsafe { self.copy_in(&mut buf, act) }?;
+            Some(buf)
+        };
+        let old = self.proc_mut().sigaction(sig, act)?;
+        if !oldact.is_null() {
+            self.copy_out(oldact, &old)?;
+        }
+        Ok(0)
+    }
+
+    /// Examine and change the blocked signals. If set is not null, it points to
+    /// the signals to be blocked, unblocked, or set, depending on how. If
+    /// oldset is not null, the previously blocked signals are stored there.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_sigprocmask(&mut self) -> Result<usize, Errno> {
+        let how = self.proc().argint(0)?;
+        let set = UVAddr::from(self.proc().argaddr(1)?);
+        let oldset = UVAddr::from(self.proc().argaddr(2)?);
+        let set = if set.is_null() {
+            None
+        } else {
+            let mut buf = UserSigSet::new_zeroed();
+            // SAFETY: UserSigSet consists of integer fields only.
+            unsafe { self.copy_in(&mut buf, set) }?;
+            Some(buf)
+        };
+        let old = self.proc_mut().sigprocmask(how, set)?;
+        if !oldset.is_null() {
+            self.copy_out(oldset, &old)?;
+        }
+        Ok(0)
+    }
+
+    /// Return from a signal handler. Called by the restorer of sigaction.
+    /// Returns Ok(return value of the interrupted code) on success, Err(errno) on error.
+    pub fn sys_sigreturn(&mut self) -> Result<usize, Errno> {
+        self.sigreturn()
+    }
+
+    /// Arrange for SIGALRM to be sent after seconds seconds, cancelling the
+    /// previous alarm. If seconds is 0, only cancels it.
+    /// Returns Ok(seconds remaining until the previous alarm, or 0).
+    pub fn sys_alarm(&self) -> Result<usize, Errno> {
+        let seconds = self.proc().argint(0)?;
+        let now = *self.kernel().ticks().lock();
+        Ok(self.alarm(seconds as u32, now) as usize)
+    }
+
     /// Return how many clock tick interrupts have occurred
     /// since start.
-    pub fn sys_uptime(&self) -> Result<usize, ()> {
+    pub fn sys_uptime(&self) -> Result<usize, Errno> {
         Ok(*self.kernel().ticks().lock() as usize)
     }
 
     /// Return how much time has passed since start,
     /// in microseconds.
-    pub fn sys_uptime_as_micro(&self) -> Result<usize, ()> {
-        TargetArch::uptime_as_micro()
+    pub fn sys_uptime_as_micro(&self) -> Result<usize, Errno> {
+        TargetArch::uptime_as_micro().map_err(|_| Errno::ENOSYS)
     }
 
     /// Shutdowns this machine, discarding all unsaved data. No return.
-    pub fn sys_poweroff(&self) -> Result<usize, ()> {
+    pub fn sys_poweroff(&self) -> Result<usize, Errno> {
         let exitcode = self.proc().argint(0)?;
         TargetArch::machine_poweroff(exitcode as _);
     }
 
     /// Return a new file descriptor referring to the same file as given fd.
-    /// Returns Ok(new file descriptor) on success, Err(()) on error.
-    pub fn sys_dup(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(new file descriptor) on success, Err(errno) on error.
+    pub fn sys_dup(&mut self) -> Result<usize, Errno> {
         let (_, f) = self.proc().argfd(0)?;
-        let newfile = f.clone();
-        let fd = newfile.fdalloc(self)?;
+        let fd = f.fdalloc(self)?;
         Ok(fd as usize)
     }
 
+    /// Manipulate the file descriptor fd according to cmd: duplicate it to the lowest
+    /// file descriptor not less than arg (F_DUPFD), get or set its FD_CLOEXEC flag
+    /// (F_GETFD, F_SETFD), or get or set the status flags of its file (F_GETFL, F_SETFL).
+    /// Returns Ok(cmd-specific value) on success, Err(errno) on error.
+    pub fn sys_fcntl(&mut self) -> Result<usize, Errno> {
+        let cmd = self.proc().argint(1)?;
+        let arg = self.proc().argint(2)?;
+        let (fd, f) = self.proc().argfd(0)?;
+        let ret = match cmd {
+            F_DUPFD => {
+                match usize::try_from(arg) {
+                    Ok(min) if min < NOFILE => {
+                        return f.fdalloc_from(min, false, self).map(|fd| fd as usize);
+                    }
+                    _ => Err(Errno::EINVAL),
+                }
+            }
+            F_GETFD => {
+                let cloexec = self.proc().open_files().is_cloexec(fd as usize);
+                Ok(if cloexec { FD_CLOEXEC as usize } else { 0 })
+            }
+            F_SETFD => {
+                let cloexec = arg & FD_CLOEXEC != 0;
+                self.proc().open_files().set_cloexec(fd as usize, cloexec);
+                Ok(0)
+            }
+            F_GETFL => Ok(f.flags().bits() as usize),
+            F_SETFL => {
+                f.set_flags(FcntlFlags::from_bits_truncate(arg));
+                Ok(0)
+            }
+            _ => Err(Errno::EINVAL),
+        };
+        f.free(self);
+        ret
+    }
+
     /// Read n bytes into buf.
-    /// Returns Ok(number read) on success, Err(()) on error.
-    pub fn sys_read(&mut self) -> Result<usize, ()> {
-        let (_, f) = self.proc().argfd(0)?;
+    /// Returns Ok(number read) on success, Err(errno) on error.
+    pub fn sys_read(&mut self) -> Result<usize, Errno> {
         let n = self.proc().argint(2)?;
         let p = self.proc().argaddr(1)?;
-        // SAFETY: read will not access proc's open_files.
-        unsafe { (*(f as *const RcFile)).read(p.into(), n, self) }
+        let (_, f) = self.proc().argfd(0)?;
+        if n > 0 {
+            // Read file-backed pages of buf now, as they cannot be read while f is locked.
+            self.fault_in(p.into(), n as usize, AccessFlags::W);
+        }
+        let ret = f.read(p.into(), n, self);
+        f.free(self);
+        ret
     }
 
     /// Write n bytes from buf to given file descriptor fd.
-    /// Returns Ok(n) on success, Err(()) on error.
-    pub fn sys_write(&mut self) -> Result<usize, ()> {
-        let (_, f) = self.proc().argfd(0)?;
+    /// Returns Ok(n) on success, Err(errno) on error.
+    pub fn sys_write(&mut self) -> Result<usize, Errno> {
         let n = self.proc().argint(2)?;
         let p = self.proc().argaddr(1)?;
-        // SAFETY: write will not access proc's open_files.
-        unsafe { (*(f as *const RcFile)).write(p.into(), n, self) }
+        let (_, f) = self.proc().argfd(0)?;
+        if n > 0 {
+            // Read file-backed pages of buf now, as they cannot be read while f is locked.
+            self.fault_in(p.into(), n as usize, AccessFlags::R);
+        }
+        let ret = f.write(p.into(), n, self);
+        f.free(self);
+        ret
     }
 
     /// Release open file fd.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_close(&mut self) -> Result<usize, ()> {
-        let (fd, _) = self.proc().argfd(0)?;
-        if let Some(f) = self.proc_mut().deref_mut_data().open_files[fd as usize].take() {
-            f.free(self);
-        }
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_close(&mut self) -> Result<usize, Errno> {
+        let fd = self.proc().argint(0)?;
+        let f = self
+            .proc()
+            .open_files()
+            .get_mut(fd as usize)
+            .ok_or(Errno::EBADF)?
+            .take()
+            .ok_or(Errno::EBADF)?;
+        f.free(self);
         Ok(0)
     }
 
     /// Place info about an open file into struct stat.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_fstat(&mut self) -> Result<usize, ()> {
-        let (_, f) = self.proc().argfd(0)?;
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_fstat(&mut self) -> Result<usize, Errno> {
         // user pointer to struct stat
         let st = self.proc().argaddr(1)?;
-        // SAFETY: stat will not access proc's open_files.
-        unsafe { (*(f as *const RcFile)).stat(st.into(), self) }?;
+        let (_, f) = self.proc().argfd(0)?;
+        let ret = f.stat(st.into(), self);
+        f.free(self);
+        ret?;
         Ok(0)
     }
 
     /// Create the path new as a link to the same inode as old.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_link(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_link(&mut self) -> Result<usize, Errno> {
         let mut new: [u8; MAXPATH] = [0; MAXPATH];
         let mut old: [u8; MAXPATH] = [0; MAXPATH];
-        let old = Path::new(self.proc_mut().argstr(0, &mut old)?);
-        let new = Path::new(self.proc_mut().argstr(1, &mut new)?);
-        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let res = try {
-            let inode = self.kernel().fs().namei(old, &tx, self)?;
-            let _ = self.kernel().fs().link(inode, new, &tx, self)?;
-            0
+        let old = Path::new(self.argstr(0, &mut old)?);
+        let new = Path::new(self.argstr(1, &mut new)?);
+        self.kernel().fs().link(old, new, self)?;
+        Ok(0)
+    }
+
+    /// Atomically rename the path old to new, replacing the file at new if any.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_rename(&mut self) -> Result<usize, Errno> {
+        let mut new: [u8; MAXPATH] = [0; MAXPATH];
+        let mut old: [u8; MAXPATH] = [0; MAXPATH];
+        let old = Path::new(self.argstr(0, &mut old)?);
+        let new = Path::new(self.argstr(1, &mut new)?);
+        self.kernel().fs().rename(old, new, self)?;
+        Ok(0)
+    }
+
+    /// Create a symbolic link at the path path, which refers to target.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_symlink(&mut self) -> Result<usize, Errno> {
+        let mut target: [u8; MAXPATH] = [0; MAXPATH];
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let target = Path::new(self.argstr(0, &mut target)?);
+        let path = Path::new(self.argstr(1, &mut path)?);
+        self.kernel().fs().symlink(target, path, self)?;
+        Ok(0)
+    }
+
+    /// Read the target of the symbolic link path into buf of n bytes, without a NUL.
+    /// Returns Ok(number of bytes read) on success, Err(errno) on error.
+    pub fn sys_readlink(&mut self) -> Result<usize, Errno> {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.argstr(0, &mut path)?);
+        let buf = self.proc().argaddr(1)?;
+        let n = self.proc().argint(2)?;
+        if n <= 0 {
+            return Err(Errno::EINVAL);
+        }
+        let mut target = [0; MAXPATH];
+        let len = self.kernel().fs().readlink(path, &mut target, self)?;
+        let len = cmp::min(len, n as usize);
+        self.copy_out_bytes(buf.into(), &target[..len])?;
+        Ok(len)
+    }
+
+    /// Set the last access and modification times of the file at path to times[0] and
+    /// times[1], or to the current time if times is null. A time whose tv_nsec is
+    /// UTIME_NOW is set to the current time, and one whose tv_nsec is UTIME_OMIT is left
+    /// unchanged.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_utimens(&mut self) -> Result<usize, Errno> {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.argstr(0, &mut path)?);
+        let times = self.proc().argaddr(1)?;
+        let fetch = |i: usize| -> Result<Option<Timespec>, Errno> {
+            if times == 0 {
+                return Ok(Some(Timespec::now()));
+            }
+            let mut time = Timespec::ZERO;
+            let addr = UVAddr::from(times + i * mem::size_of::<Timespec>());
+            // SAFETY: Timespec does not have any internal structure.
+            unsafe { self.copy_in(&mut time, addr) }?;
+            match time.nsec {
+                UTIME_NOW => Ok(Some(Timespec::now())),
+                UTIME_OMIT => Ok(None),
+                _ if time.is_valid() => Ok(Some(time)),
+                _ => Err(Errno::EINVAL),
+            }
         };
-        tx.end(self);
-        res
+        let atime = fetch(0)?;
+        let mtime = fetch(1)?;
+        self.kernel().fs().utimens(path, atime, mtime, self)?;
+        Ok(0)
+    }
+
+    /// Set the permission bits of the file at path to mode.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_chmod(&mut self) -> Result<usize, Errno> {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.argstr(0, &mut path)?);
+        let mode = self.proc().argint(1)?;
+        self.kernel().fs().chmod(path, mode as u16, self)?;
+        Ok(0)
+    }
+
+    /// Set the owner and its group of the file at path to uid and gid. An id of -1 is
+    /// left unchanged. Only the superuser may change them.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_chown(&mut self) -> Result<usize, Errno> {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.argstr(0, &mut path)?);
+        let uid = self.proc().argint(1)?;
+        let gid = self.proc().argint(2)?;
+        let id = |id: i32| (id != -1).then(|| id as u32);
+        self.kernel().fs().chown(path, id(uid), id(gid), self)?;
+        Ok(0)
     }
 
     /// Remove a file.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_unlink(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_unlink(&mut self) -> Result<usize, Errno> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
-        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
-        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let res = self.kernel().fs().unlink(path, &tx, self).map(|_| 0);
-        tx.end(self);
-        res
+        let path = Path::new(self.argstr(0, &mut path)?);
+        self.kernel().fs().unlink(path, self)?;
+        Ok(0)
     }
 
     /// Open a file.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_open(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_open(&mut self) -> Result<usize, Errno> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
-        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
+        let path = Path::new(self.argstr(0, &mut path)?);
         let omode = self.proc().argint(1)?;
         let omode = FcntlFlags::from_bits_truncate(omode);
-        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let res = self.kernel().fs().open(path, omode, &tx, self);
-        tx.end(self);
-        res
+        self.kernel().fs().open(path, omode, self)
     }
 
     /// Create a new directory.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_mkdir(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_mkdir(&mut self) -> Result<usize, Errno> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
-        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
-        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let res = self
-            .kernel()
+        let path = Path::new(self.argstr(0, &mut path)?);
+        self.kernel()
             .fs()
-            .create(path, InodeType::Dir, &tx, self, |_| ())
-            .map(|(ptr, _)| {
-                ptr.free((&tx, self));
-                0
-            });
-        tx.end(self);
-        res
+            .create(path, InodeType::Dir, true, self)?
+            .put(self);
+        Ok(0)
     }
 
     /// Create a new device file.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_mknod(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_mknod(&mut self) -> Result<usize, Errno> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
-        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
+        let path = Path::new(self.argstr(0, &mut path)?);
         let major = self.proc().argint(1)? as u16;
         let minor = self.proc().argint(2)? as u16;
-        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let res = self
-            .kernel()
+        self.kernel()
             .fs()
-            .create(path, InodeType::Device { major, minor }, &tx, self, |_| ())
-            .map(|(ptr, _)| {
-                ptr.free((&tx, self));
-                0
-            });
-        tx.end(self);
-        res
+            .create(path, InodeType::Device { major, minor }, true, self)?
+            .put(self);
+        Ok(0)
     }
 
     /// Change the current directory.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_chdir(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_chdir(&mut self) -> Result<usize, Errno> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
-        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
-        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let res = try {
-            let inode = self.kernel().fs().namei(path, &tx, self)?;
-            let _ = self.kernel().fs().chdir(inode, &tx, self)?;
-            0
-        };
-        tx.end(self);
-        res
+        let path = Path::new(self.argstr(0, &mut path)?);
+        self.kernel().fs().chdir(path, self)?;
+        Ok(0)
     }
 
     /// Load a file and execute it with arguments.
-    /// Returns Ok(argc argument to user main) on success, Err(()) on error.
-    pub fn sys_exec(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(argc argument to user main) on success, Err(errno) on error.
+    pub fn sys_exec(&mut self) -> Result<usize, Errno> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
         let mut args = ArrayVec::<Page, MAXARG>::new();
-        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
+        let path = Path::new(self.argstr(0, &mut path)?);
         let uargv = self.proc().argaddr(1)?;
         let allocator = hal().kmem();
 
-        let mut success = false;
+        let mut result = Err(Errno::E2BIG);
         for i in 0..MAXARG {
             let uarg = ok_or!(
                 self.proc_mut()
                     .fetchaddr((uargv + mem::size_of::<usize>() * i).into()),
-                break
+                {
+                    result = Err(Errno::EFAULT);
+                    break;
+                }
             );
 
             if uarg == 0 {
-                success = true;
+                result = Ok(());
                 break;
             }
 
-            let mut page = some_or!(allocator.alloc(None), break);
-            if self
-                .proc_mut()
-                .fetchstr(uarg.into(), &mut page[..])
-                .is_err()
-            {
+            let mut page = some_or!(allocator.alloc(None), {
+                result = Err(Errno::ENOMEM);
+                break;
+            });
+            if let Err(err) = self.fetchstr(uarg.into(), &mut page[..]) {
                 allocator.free(page);
+                result = Err(err);
                 break;
             }
             args.push(page);
         }
 
-        let ret = if success {
-            self.exec(path, &args)
-        } else {
-            Err(())
+        let ret = match result {
+            Ok(()) => self.exec(path, &args),
+            Err(err) => Err(err),
         };
 
         for page in args.drain(..) {
@@ -397,8 +699,8 @@ impl KernelCtx<'_, '_> {
     }
 
     /// Create a pipe.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_pipe(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_pipe(&mut self) -> Result<usize, Errno> {
         // user pointer to array of two integers
         let fdarray = self.proc().argaddr(0)?.into();
         self.pipe(fdarray)?;
@@ -407,150 +709,335 @@ impl KernelCtx<'_, '_> {
 
     /* Check the first NFDS descriptors each in READFDS (if not NULL) for read
     readiness, in WRITEFDS (if not NULL) for write readiness, and in EXCEPTFDS
-    (if not NULL) for exceptional conditions.  If TIMEOUT is not NULL, time out
-    after waiting the interval specified therein.  Returns the number of ready
-    descriptors, or -1 for errors.
-    Returns Ok(number of ready descriptors) on success, Err(()) on error.  */
-    pub fn sys_select(&mut self) -> Result<usize, ()> {
+    (if not NULL) for exceptional conditions.  Wait up to TIMEOUT ticks for a
+    descriptor to become ready; a TIMEOUT of 0 only polls the descriptors, and
+    a negative TIMEOUT waits without a limit.  On return, each set holds only
+    its ready descriptors.
+    Returns Ok(number of ready descriptors) on success, Err(errno) on error.  */
+    pub fn sys_select(&mut self) -> Result<usize, Errno> {
         let nfds = self.proc().argint(0)?;
-        let read_fds = self.proc().argaddr(1)?;
-        let write_fds = self.proc().argaddr(2)?;
-        let err_fds = self.proc().argaddr(3)?;
-
+        let addrs = [
+            self.proc().argaddr(1)?,
+            self.proc().argaddr(2)?,
+            self.proc().argaddr(3)?,
+        ];
         let n_ticks = self.proc().argint(4)?;
-
-        let ticks = self.kernel().ticks().lock();
-        let ticks0 = *ticks;
-        drop(ticks);
-
-        let mut rfds = [0u8; 1024 / 8];
-        let mut wfds = [0u8; 1024 / 8];
-        let mut efds = [0u8; 1024 / 8];
-
-        if read_fds != 0 {
-            // SAFETY: `read_fds` is a valid user space address given by a user.
-            unsafe {
-                self.proc_mut()
-                    .memory_mut()
-                    .copy_in(&mut rfds, read_fds.into())
-            }?;
+        if !(0..=FD_SETSIZE as i32).contains(&nfds) {
+            return Err(Errno::EINVAL);
         }
 
-        if write_fds != 0 {
-            unimplemented!("Handling for write fd set has not been implemented yet");
-        }
-
-        if err_fds != 0 {
-            unimplemented!("Handling for exceptional fd set has not been implemented yet");
+        let mut fds = [[0u8; FD_SETSIZE / 8]; 3];
+        for (set, &addr) in fds.iter_mut().zip(&addrs) {
+            if addr != 0 {
+                // SAFETY: `addr` is a valid user space address given by a user.
+                unsafe { self.copy_in(set, addr.into()) }?;
+            }
         }
 
-        // the number of fds that are ready
-        let mut ready_cnt = 0;
-
         let events = [SelectEvent::Read, SelectEvent::Write, SelectEvent::Error];
-        let fds = [&mut rfds, &mut wfds, &mut efds];
-
-        loop {
-            // check fds
+        let timeout = u32::try_from(n_ticks).ok();
+        let mut ready = [[0u8; FD_SETSIZE / 8]; 3];
+
+        // check fds, and wait for a file to become ready to check them again
+        let ctx: &KernelCtx<'_, '_> = self;
+        let ready_cnt = ctx.kernel().pollers().poll(timeout, ctx, |table| {
+            // the number of fds that are ready
+            let mut ready_cnt = 0;
             for i in 0..3 {
-                let event = events[i];
-
-                for fd in 0..nfds + 1 {
+                for fd in 0..nfds {
                     let idx = (fd / 8) as usize;
                     let mask = 1 << (fd % 8);
 
                     if fds[i][idx] & mask != 0 {
-                        let f = self
-                            .proc()
-                            .deref_data()
-                            .open_files
-                            .get(fd as usize)
-                            .ok_or(())?
-                            .as_ref()
-                            .ok_or(())?;
-                        // SAFETY: `is_ready` will not access proc's open_files.
-                        if unsafe { (*(f as *const RcFile)).is_ready(event)? } {
+                        let f = ctx.proc().getfile(fd)?;
+                        table.add(&f, ctx);
+                        let res = f.is_ready(events[i], ctx);
+                        f.free(ctx);
+                        if res? {
+                            ready[i][idx] |= mask;
                             ready_cnt += 1;
-                        } else {
-                            // If the fd is not ready, clear the bit.
-                            fds[i][idx] &= !mask;
                         }
                     }
                 }
             }
+            Ok(ready_cnt)
+        })?;
 
-            if ready_cnt > 0 {
-                break;
-            }
-
-            // check timeout
-            let ticks = self.kernel().ticks().lock();
-            if ticks.wrapping_sub(ticks0) >= n_ticks as u32 {
-                for idx in 0..(nfds + 1) / 8 + 1 {
-                    rfds[idx as usize] = 0;
-                }
-                break;
+        for (set, &addr) in ready.iter().zip(&addrs) {
+            if addr != 0 {
+                self.copy_out(addr.into(), set)?;
             }
-            drop(ticks);
         }
 
-        if read_fds != 0 {
-            self.proc_mut()
-                .memory_mut()
-                .copy_out(read_fds.into(), &rfds)?;
-        }
+        Ok(ready_cnt)
+    }
 
-        if write_fds != 0 {
-            self.proc_mut()
-                .memory_mut()
-                .copy_out(write_fds.into(), &wfds)?;
-        }
+    /// Wait for the nfds file descriptors of the struct pollfds at fds to become
+    /// ready, up to timeout milliseconds, or without a limit if timeout is negative.
+    /// Returns Ok(number of ready descriptors) on success, Err(errno) on error.
+    pub fn sys_poll(&mut self) -> Result<usize, Errno> {
+        let fds = self.proc().argaddr(0)?;
+        let nfds = self.proc().argint(1)?;
+        let timeout = self.proc().argint(2)?;
+        let nfds = usize::try_from(nfds).map_err(|_| Errno::EINVAL)?;
+        self.poll(fds.into(), nfds, timeout)
+    }
 
-        if err_fds != 0 {
-            self.proc_mut()
-                .memory_mut()
-                .copy_out(err_fds.into(), &efds)?;
+    /// Create an epoll instance. size is ignored, but must be positive.
+    /// Returns Ok(file descriptor) on success, Err(errno) on error.
+    pub fn sys_epoll_create(&mut self) -> Result<usize, Errno> {
+        let size = self.proc().argint(0)?;
+        if size <= 0 {
+            return Err(Errno::EINVAL);
         }
+        self.epoll_create()
+    }
 
-        Ok(ready_cnt)
+    /// Add, change, or remove the watch of the epoll instance epfd on fd, according
+    /// to op and the struct epoll_event at event.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_epoll_ctl(&mut self) -> Result<usize, Errno> {
+        let epfd = self.proc().argint(0)?;
+        let op = self.proc().argint(1)?;
+        let fd = self.proc().argint(2)?;
+        let event = self.proc().argaddr(3)?;
+        self.epoll_ctl(epfd, op, fd, event.into())?;
+        Ok(0)
     }
 
-    pub fn sys_getpagesize(&mut self) -> Result<usize, ()> {
+    /// Wait for the files that the epoll instance epfd watches to become ready, up to
+    /// timeout milliseconds, and store at most maxevents struct epoll_events at events.
+    /// Returns Ok(number of ready files) on success, Err(errno) on error.
+    pub fn sys_epoll_wait(&mut self) -> Result<usize, Errno> {
+        let epfd = self.proc().argint(0)?;
+        let events = self.proc().argaddr(1)?;
+        let maxevents = self.proc().argint(2)?;
+        let timeout = self.proc().argint(3)?;
+        self.epoll_wait(epfd, events.into(), maxevents, timeout)
+    }
+
+    pub fn sys_getpagesize(&mut self) -> Result<usize, Errno> {
         Ok(PGSIZE)
     }
 
-    pub fn sys_waitpid(&mut self) -> Result<usize, ()> {
+    pub fn sys_waitpid(&mut self) -> Result<usize, Errno> {
         let pid = self.proc().argint(0)?;
         let stat = self.proc().argaddr(1)?;
         Ok(self.kernel().procs().waitpid(pid, stat.into(), self)? as _)
     }
 
-    pub fn sys_getppid(&mut self) -> Result<usize, ()> {
+    pub fn sys_getppid(&mut self) -> Result<usize, Errno> {
         Ok(self.kernel().procs().get_parent_pid(self) as _)
     }
 
-    pub fn sys_lseek(&mut self) -> Result<usize, ()> {
-        let (_, f) = self.proc().argfd(0)?;
-        let offset = self.proc().argint(1)?;
+    /// Set the user id of the current process to uid. Only the superuser may change it
+    /// to another user.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_setuid(&mut self) -> Result<usize, Errno> {
+        let uid = self.proc().argint(0)? as u32;
+        let mut creds = self.proc().creds();
+        if creds.uid != ROOT_UID && creds.uid != uid {
+            return Err(Errno::EPERM);
+        }
+        creds.uid = uid;
+        Ok(0)
+    }
+
+    /// Return the user id of the current process.
+    pub fn sys_getuid(&mut self) -> Result<usize, Errno> {
+        Ok(self.proc().creds().uid as _)
+    }
+
+    /// Set the group id of the current process to gid. Only the superuser may change it
+    /// to another group.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_setgid(&mut self) -> Result<usize, Errno> {
+        let gid = self.proc().argint(0)? as u32;
+        let mut creds = self.proc().creds();
+        if creds.uid != ROOT_UID && creds.gid != gid {
+            return Err(Errno::EPERM);
+        }
+        creds.gid = gid;
+        Ok(0)
+    }
+
+    /// Return the group id of the current process.
+    pub fn sys_getgid(&mut self) -> Result<usize, Errno> {
+        Ok(self.proc().creds().gid as _)
+    }
+
+    pub fn sys_lseek(&mut self) -> Result<usize, Errno> {
+        let offset = self.proc().arglong(1)?;
         let whence = self.proc().argint(2)?;
 
         let whence = match whence {
             0 => SeekWhence::Set,
             1 => SeekWhence::Cur,
             2 => SeekWhence::End,
-            _ => return Err(()),
+            _ => return Err(Errno::EINVAL),
         };
-        // SAFETY: `lseek` will not access proc's open_files.
-        unsafe { (*(f as *const RcFile)).lseek(offset, whence, self) }
+        let (_, f) = self.proc().argfd(0)?;
+        let ret = f.lseek(offset, whence, self);
+        f.free(self);
+        ret
     }
 
-    pub fn sys_clock(&mut self) -> Result<usize, ()> {
+    pub fn sys_clock(&mut self) -> Result<usize, Errno> {
         let p = self.proc().argaddr(0)?;
         let addr = UVAddr::from(p);
 
         let clk = TargetArch::r_cycle();
-        self.proc_mut().memory_mut().copy_out(addr, &clk)?;
+        self.copy_out(addr, &clk)?;
+
+        Ok(0)
+    }
+
+    /// Store the time of the clock clk into the struct timespec at tp.
+    /// Only CLOCK_REALTIME is supported for clk.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_clock_gettime(&mut self) -> Result<usize, Errno> {
+        let clk = self.proc().argint(0)?;
+        let tp = self.proc().argaddr(1)?;
+        if clk != CLOCK_REALTIME {
+            return Err(Errno::EINVAL);
+        }
+        self.copy_out(tp.into(), &Timespec::now())?;
+        Ok(0)
+    }
+
+    /// Create a thread that shares the memory and the open files with the
+    /// current process, and calls fn(arg) on the user stack pointed to by stack.
+    /// Returns Ok(new thread id) on success, Err(errno) on error.
+    pub fn sys_clone(&mut self) -> Result<usize, Errno> {
+        let func = self.proc().argaddr(0)?;
+        let arg = self.proc().argaddr(1)?;
+        let stack = self.proc().argaddr(2)?;
+        Ok(self.kernel().procs().clone_thread(func, arg, stack, self)? as _)
+    }
+
+    /// Wait for the thread tid, or any thread if tid is 0, to exit.
+    /// Returns Ok(thread id) on success, Err(errno) on error.
+    pub fn sys_join(&mut self) -> Result<usize, Errno> {
+        let tid = self.proc().argint(0)?;
+        let p = self.proc().argaddr(1)?;
+        Ok(self.kernel().procs().join(tid, p.into(), self)? as _)
+    }
+
+    /// Terminate every thread of the current process; status reported to wait(). No return.
+    pub fn sys_exit_group(&mut self) -> Result<usize, Errno> {
+        let n = self.proc().argint(0)?;
+        self.kernel().procs().exit_group(n, self);
+    }
+
+    /// Operate on the futex at addr according to op.
+    /// FUTEX_WAIT sleeps if addr contains val, until the process is woken up, or
+    /// timeout ticks pass if timeout is not 0. FUTEX_WAKE wakes up at most val
+    /// processes sleeping on the futex.
+    /// Returns Ok(0) or Ok(number of processes woken up) on success, Err(errno) on error.
+    pub fn sys_futex(&mut self) -> Result<usize, Errno> {
+        let addr = self.proc().argaddr(0)?;
+        let op = self.proc().argint(1)?;
+        let val = self.proc().argint(2)?;
+        let timeout = self.proc().argint(3)?;
+        let futexes = self.kernel().futexes();
+        match op {
+            FUTEX_WAIT if timeout >= 0 => {
+                futexes.wait(addr.into(), val as u32, timeout as u32, self)?;
+                Ok(0)
+            }
+            FUTEX_WAKE if val >= 0 => futexes.wake(addr.into(), val as u32, self),
+            _ => Err(Errno::EINVAL),
+        }
+    }
 
+    /// Set the nice value of the process who, or the current process if who is 0.
+    /// Only PRIO_PROCESS is supported for which.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_setpriority(&mut self) -> Result<usize, Errno> {
+        let which = self.proc().argint(0)?;
+        let who = self.proc().argint(1)?;
+        let prio = self.proc().argint(2)?;
+        if which != PRIO_PROCESS {
+            return Err(Errno::EINVAL);
+        }
+        self.kernel().procs().setpriority(who, prio, self)?;
+        Ok(0)
+    }
+
+    /// Return the nice value of the process who, or the current process if who is 0.
+    /// Only PRIO_PROCESS is supported for which.
+    /// Returns Ok(20 - nice value) on success, Err(errno) on error. As in Linux, the
+    /// value is offset so that it is never negative, and the
+    /// getpriority wrapper of ulib converts it back.
+    pub fn sys_getpriority(&mut self) -> Result<usize, Errno> {
+        let which = self.proc().argint(0)?;
+        let who = self.proc().argint(1)?;
+        if which != PRIO_PROCESS {
+            return Err(Errno::EINVAL);
+        }
+        let nice = self.kernel().procs().getpriority(who, self)?;
+        Ok((20 - nice) as usize)
+    }
+
+    /// Restrict the process pid, or the current process if pid is 0, to the CPUs
+    /// in the bitmask mask.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_sched_setaffinity(&mut self) -> Result<usize, Errno> {
+        let pid = self.proc().argint(0)?;
+        let mask = self.proc().argaddr(1)?;
+        self.kernel().procs().set_affinity(pid, mask, self)?;
         Ok(0)
     }
+
+    /// Mount the file system named fstype on the disk dev at the directory target.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_mount(&mut self) -> Result<usize, Errno> {
+        let mut target: [u8; MAXPATH] = [0; MAXPATH];
+        let mut fstype: [u8; MAXPATH] = [0; MAXPATH];
+        let dev = self.proc().argint(0)?;
+        let target = Path::new(self.argstr(1, &mut target)?);
+        let fstype = self.argstr(2, &mut fstype)?;
+        if dev < 0 {
+            return Err(Errno::ENXIO);
+        }
+        self.kernel()
+            .fs()
+            .mount(dev as u32, target, fstype.to_bytes(), self)?;
+        Ok(0)
+    }
+
+    /// Unmount the file system mounted at the directory target.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_umount(&mut self) -> Result<usize, Errno> {
+        let mut target: [u8; MAXPATH] = [0; MAXPATH];
+        let target = Path::new(self.argstr(0, &mut target)?);
+        self.kernel().fs().umount(target, self)?;
+        Ok(0)
+    }
+
+    /// Read the entries of the directory fd into buf of n bytes.
+    /// Returns Ok(number of bytes read) on success, Err(errno) on error.
+    pub fn sys_getdents(&mut self) -> Result<usize, Errno> {
+        let n = self.proc().argint(2)?;
+        let p = self.proc().argaddr(1)?;
+        let (_, f) = self.proc().argfd(0)?;
+        if n > 0 {
+            // Read file-backed pages of buf now, as they cannot be read while f is locked.
+            self.fault_in(p.into(), n as usize, AccessFlags::W);
+        }
+        let ret = f.getdents(p.into(), n, self);
+        f.free(self);
+        ret
+    }
+
+    /// Run the device-specific request req with the argument arg on the device file fd.
+    /// Returns Ok(request-specific value) on success, Err(errno) on error.
+    pub fn sys_ioctl(&mut self) -> Result<usize, Errno> {
+        let req = self.proc().argint(1)?;
+        let arg = self.proc().argaddr(2)?;
+        let (_, f) = self.proc().argfd(0)?;
+        let ret = f.ioctl(req, arg.into(), self);
+        f.free(self);
+        ret
+    }
 }
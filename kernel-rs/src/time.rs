@@ -0,0 +1,44 @@
+//! Wall-clock time, which the real-time clock keeps.
+
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::arch::{interface::TimeManager, TargetArch};
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// The clock of `clock_gettime` that tells the wall-clock time.
+pub const CLOCK_REALTIME: i32 = 0;
+
+/// A `tv_nsec` of `utimens` that sets the time to the current time.
+pub const UTIME_NOW: i64 = (1 << 30) - 1;
+
+/// A `tv_nsec` of `utimens` that leaves the time unchanged.
+pub const UTIME_OMIT: i64 = (1 << 30) - 2;
+
+/// A point in time, in seconds and nanoseconds since the Unix epoch.
+/// It is `struct timespec` of the user and is also stored in on-disk inodes.
+#[derive(Clone, Copy, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct Timespec {
+    pub sec: i64,
+    pub nsec: i64,
+}
+
+impl Timespec {
+    /// The Unix epoch.
+    pub const ZERO: Self = Self { sec: 0, nsec: 0 };
+
+    /// Returns the current time.
+    pub fn now() -> Self {
+        let nanos = TargetArch::rtc_time_as_nanos();
+        Self {
+            sec: (nanos / NSEC_PER_SEC) as i64,
+            nsec: (nanos % NSEC_PER_SEC) as i64,
+        }
+    }
+
+    /// Is `nsec` in the range of valid nanoseconds?
+    pub fn is_valid(&self) -> bool {
+        (0..NSEC_PER_SEC as i64).contains(&self.nsec)
+    }
+}
@@ -5,8 +5,8 @@ use crate::{
     arch::TargetArch,
     hal::hal,
     kernel::{kernel_ref, KernelRef},
-    ok_or,
-    proc::{kernel_ctx, KernelCtx, Procstate},
+    proc::{kernel_ctx, KernelCtx, Procstate, BOOST_TICKS},
+    vm::AccessFlags,
 };
 
 /// In ARM.v8 architecture, interrupts are part
@@ -21,13 +21,23 @@ use crate::{
 pub enum TrapTypes {
     Irq(IrqTypes),
     Syscall,
+    /// A page fault at `addr`, caused by an access of kind `access`
+    /// (one of `AccessFlags::R`, `AccessFlags::W`, and `AccessFlags::X`).
+    PageFault {
+        addr: usize,
+        access: AccessFlags,
+    },
     BadTrap,
     TimerInterrupt,
+    /// An interrupt from another CPU that asks this CPU to flush its TLB.
+    /// The TLB is flushed when the CPU returns to user mode.
+    Ipi,
 }
 
 #[derive(Debug)]
 pub enum IrqTypes {
-    Virtio,
+    /// An interrupt from the virtio disk on the mmio bus of the index.
+    Virtio(usize),
     Uart,
     Others(IrqNum),
     Unknown(IrqNum),
@@ -61,6 +71,10 @@ impl KernelCtx<'_, '_> {
             "usertrap: not from user mode(EL0)"
         );
 
+        // Interrupts are still disabled, so this is the CPU that left user mode.
+        // SAFETY: the `CpuMut` is dropped before interrupts are enabled.
+        unsafe { hal().cpus().current_unchecked() }.leave_user();
+
         // Send interrupts and exceptions to kerneltrap(),
         // since we're now in the kernel.
         // SAFETY: We are in a kerel mode now.
@@ -90,12 +104,27 @@ impl KernelCtx<'_, '_> {
                 // SAFETY: Interrupt handlers has been configured properly
                 unsafe { TargetArch::intr_on() };
                 let syscall_no = self.proc_mut().trap_frame_mut().get_param_reg(7.into()) as i32;
+                // A failed system call returns the negated error number.
                 *self.proc_mut().trap_frame_mut().param_reg_mut(0.into()) =
-                    ok_or!(self.syscall(syscall_no), usize::MAX);
+                    match self.syscall(syscall_no) {
+                        Ok(ret) => ret,
+                        Err(err) => err.to_user(),
+                    };
             }
             TrapTypes::Irq(irq_type) => unsafe {
                 self.kernel().handle_irq(irq_type);
             },
+            TrapTypes::PageFault { addr, access } => {
+                if self.handle_page_fault((*addr).into(), *access).is_err() {
+                    self.kernel().as_ref().write_fmt(format_args!(
+                        "usertrap(): page fault va={:#x} pid={}\n",
+                        addr,
+                        self.proc().pid()
+                    ));
+                    self.proc().kill();
+                    self.kernel().procs().exit_current(-1, &mut self);
+                }
+            }
             TrapTypes::BadTrap => {
                 self.kernel().as_ref().write_str("usertrap(): ");
 
@@ -110,6 +139,9 @@ impl KernelCtx<'_, '_> {
                     self.kernel().clock_intr();
                 }
             }
+            TrapTypes::Ipi => {
+                // do nothing
+            }
         }
 
         // SAFETY: It is coupled with `before_handling_trap` with same trap,
@@ -136,15 +168,34 @@ impl KernelCtx<'_, '_> {
     ///
     /// It must be called only by `user_trap`.
     pub unsafe fn user_trap_ret(mut self) -> ! {
+        // Run a signal handler on the way back, or exit.
+        self.handle_signals();
+
         // Tell trampoline.S the user page table to switch to.
         let user_table = self.proc().memory().page_table_addr();
 
         let kstack = self.proc_mut().deref_mut_data().kstack;
 
+        let trapframe_va = self.proc().trap_frame_va();
+
         let trapframe = self.proc_mut().trap_frame_mut();
 
+        // Interrupts stay disabled until we are back in user space, so other CPUs can tell
+        // which page table this CPU runs on.
+        TargetArch::intr_off();
+        // SAFETY: interrupts are disabled.
+        unsafe { hal().cpus().current_unchecked() }.enter_user(user_table);
+
         // SAFETY: It is called by `user_trap_ret`, after handling the user trap.
-        unsafe { TargetArch::user_trap_ret(user_table, trapframe, kstack, usertrap as usize) };
+        unsafe {
+            TargetArch::user_trap_ret(
+                user_table,
+                trapframe,
+                trapframe_va,
+                kstack,
+                usertrap as usize,
+            )
+        };
     }
 }
 
@@ -174,7 +225,7 @@ impl KernelRef<'_, '_> {
             TrapTypes::Irq(irq_type) => unsafe {
                 self.handle_irq(irq_type);
             },
-            TrapTypes::BadTrap => {
+            TrapTypes::BadTrap | TrapTypes::PageFault { .. } => {
                 self.as_ref().write_str("kerneltrap(): ");
 
                 TargetArch::print_trap_status(|arg: fmt::Arguments<'_>| {
@@ -187,6 +238,9 @@ impl KernelRef<'_, '_> {
                     self.clock_intr();
                 }
             }
+            TrapTypes::Ipi => {
+                // do nothing
+            }
         }
 
         // SAFETY: It is coupled with `before_handling_trap` with same trap,
@@ -195,15 +249,24 @@ impl KernelRef<'_, '_> {
             TargetArch::after_handling_trap(&trap_type);
         }
 
-        // Give up the CPU if this is a timer interrupt.
+        // Give up the CPU if this is a timer interrupt, unless the interrupted
+        // kernel code disabled preemption. In that case, it yields as soon as it
+        // enables preemption again.
         if let TrapTypes::TimerInterrupt = trap_type {
-            // TODO(https://github.com/kaist-cp/rv6/issues/517): safety?
-            if let Some(ctx) = unsafe { self.get_ctx() } {
-                // SAFETY:
-                // Reading state without lock is safe because `proc_yield` and `sched`
-                // is called after we check if current process is `RUNNING`.
-                if unsafe { (*ctx.proc().info.get_mut_raw()).state } == Procstate::RUNNING {
-                    ctx.yield_cpu();
+            // SAFETY: interrupts are disabled.
+            let cpu = unsafe { hal().get_ref().cpus().current_unchecked() };
+            if cpu.get_preempt_count() > 0 {
+                cpu.miss_tick();
+            }
+            if cpu.is_preemptible() {
+                // TODO(https://github.com/kaist-cp/rv6/issues/517): safety?
+                if let Some(ctx) = unsafe { self.get_ctx() } {
+                    // SAFETY:
+                    // Reading state without lock is safe because `proc_yield` and `sched`
+                    // is called after we check if current process is `RUNNING`.
+                    if unsafe { (*ctx.proc().info.get_mut_raw()).state } == Procstate::RUNNING {
+                        ctx.yield_cpu();
+                    }
                 }
             }
         }
@@ -228,8 +291,8 @@ impl KernelRef<'_, '_> {
                 // SAFETY: it's unsafe only when ctrl+p is pressed.
                 unsafe { hal().console().intr(self) };
             }
-            IrqTypes::Virtio => {
-                hal().disk().pinned_lock().get_pin_mut().intr(self);
+            IrqTypes::Virtio(i) => {
+                hal().disk().intr(*i, self);
             }
             IrqTypes::Unknown(irq_num) => {
                 // Use `panic!` instead of `println` to prevent stack overflow.
@@ -246,5 +309,13 @@ impl KernelRef<'_, '_> {
         let mut ticks = self.ticks().lock();
         *ticks = ticks.wrapping_add(1);
         ticks.wakeup(self);
+        let now = *ticks;
+        drop(ticks);
+        self.procs().expire_alarms(now);
+        if now % BOOST_TICKS == 0 {
+            self.procs().boost();
+        }
+        self.futexes().wakeup_timed(self);
+        self.pollers().expire(now, self);
     }
 }
@@ -49,6 +49,14 @@ impl<T> StaticArc<T> {
         self.rc().load(Ordering::Acquire) > 0
     }
 
+    /// Returns the number of `Ref`s that refer to `self`, counting a `RefMut` as one.
+    pub fn refcnt(self: StrongPinMut<'_, Self>) -> usize {
+        match self.rc().load(Ordering::Acquire) {
+            BORROWED_MUT => 1,
+            r => r,
+        }
+    }
+
     #[allow(clippy::needless_lifetimes)]
     pub fn get_mut<'s>(mut self: StrongPinMut<'s, Self>) -> Option<&'s mut T> {
         if self.as_mut().is_borrowed() {
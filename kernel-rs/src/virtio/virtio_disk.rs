@@ -3,15 +3,18 @@
 /// qemu presents a "legacy" virtio interface.
 ///
 /// qemu ... -drive file=fs.img,if=none,format=raw,id=x0 -device virtio-blk-device,drive=x0,bus=virtio-mmio-bus.0
+///
+/// There are `NDISK` disks, on the mmio buses 0 to `NDISK - 1`. The disk on the
+/// bus `i` is the device `i + 1`, and only the disk on the bus 0 is required.
 use core::marker::PhantomPinned;
 use core::mem;
 use core::pin::Pin;
 use core::ptr;
 use core::sync::atomic::{fence, Ordering};
 
+use array_macro::array;
 use arrayvec::ArrayVec;
 use bitmaps::Bitmap;
-use cfg_if::cfg_if;
 use const_zero::const_zero;
 use pin_project::pin_project;
 
@@ -21,23 +24,32 @@ use super::{
 };
 use crate::{
     addr::{PGSHIFT, PGSIZE},
+    arch::interface::MemLayout,
     bio::Buf,
     kernel::KernelRef,
     lock::{SleepableLock, SleepableLockGuard},
-    param::BSIZE,
+    param::{BSIZE, NDISK, SEGSIZE},
     proc::KernelCtx,
 };
 
-cfg_if! {
-    if #[cfg(feature = "lfs")] {
-        use crate::param::SEGSIZE;
-        // Sequential write in a unit of one segment.
-        const MAX_SEQ_WRITE: usize = SEGSIZE;
-    } else {
-        // Do not support sequential write.
-        // Thus, `SleepableLock<VirtioDisk>::write_sequential` is the same as a normal write.
-        const MAX_SEQ_WRITE: usize = 0;
-    }
+// Sequential write in a unit of one segment of LFS.
+const MAX_SEQ_WRITE: usize = SEGSIZE;
+
+/// The virtio disks.
+///
+/// # Safety
+///
+/// `disks[i]` is initialized if `present[i]` is true.
+#[pin_project]
+pub struct VirtioDisks {
+    #[pin]
+    disks: [SleepableLock<VirtioDisk>; NDISK],
+
+    /// Whether there is a disk on each bus.
+    present: [bool; NDISK],
+
+    /// The number of blocks of the disk on each bus.
+    nblocks: [u32; NDISK],
 }
 
 // It must be page-aligned.
@@ -75,6 +87,9 @@ pub struct VirtioDisk {
     // TODO : We need this buffer to prevent stack overflow.
     // Remove this after resolving the stack overflow issue.
     write_buf: [u8; BSIZE * MAX_SEQ_WRITE],
+
+    /// The address of the mmio registers of the disk.
+    base: usize,
 }
 
 // It must be page-aligned because a virtqueue (desc + avail + used) occupies
@@ -124,7 +139,7 @@ impl VirtioDisk {
     /// # Safety
     ///
     /// It must be used only after initializing it with `VirtioDisk::init`.
-    pub const unsafe fn new() -> Self {
+    const unsafe fn new(base: usize) -> Self {
         Self {
             desc: [VirtqDesc::new(); NUM],
             avail: VirtqAvail::new(),
@@ -132,6 +147,7 @@ impl VirtioDisk {
             info: DiskInfo::new(),
             darray: ArrayVec::new_const(),
             write_buf: [0; BSIZE * MAX_SEQ_WRITE],
+            base,
         }
     }
 }
@@ -204,45 +220,103 @@ impl Drop for Descriptor {
     }
 }
 
-impl SleepableLock<VirtioDisk> {
+impl VirtioDisks {
+    /// # Safety
+    ///
+    /// It must be used only after initializing it with `VirtioDisks::init`.
+    pub const unsafe fn new<A: MemLayout>() -> Self {
+        Self {
+            disks: array![i => SleepableLock::new("DISK", unsafe { VirtioDisk::new(A::VIRTIO0 + i * A::VIRTIO_STRIDE) }); NDISK],
+            present: [false; NDISK],
+            nblocks: [0; NDISK],
+        }
+    }
+
+    /// Initializes the disks that are present.
+    pub fn init(self: Pin<&mut Self>) {
+        let this = self.project();
+        // SAFETY: the disks are never moved.
+        let disks = unsafe { this.disks.get_unchecked_mut() };
+        for (i, disk) in disks.iter_mut().enumerate() {
+            // SAFETY: the disks are never moved.
+            let disk = unsafe { Pin::new_unchecked(disk) }.get_pin_mut().into_ref();
+            // MMIO registers are located below KERNBASE, while kernel text and data
+            // are located above KERNBASE, so we can safely read/write MMIO registers.
+            this.present[i] = MmioRegs::check_virtio_disk(disk.base);
+            if this.present[i] {
+                disk.init();
+                this.nblocks[i] = MmioRegs::Capacity.read(disk.base) / (BSIZE / 512) as u32;
+            }
+        }
+        assert!(this.present[0], "could not find virtio disk");
+    }
+
+    /// Returns true if there is a disk of the device `dev`.
+    pub fn is_present(&self, dev: u32) -> bool {
+        dev >= 1 && self.present.get(dev as usize - 1) == Some(&true)
+    }
+
+    /// Returns the number of blocks of the disk of the device `dev`.
+    pub fn nblocks(&self, dev: u32) -> u32 {
+        assert!(self.is_present(dev), "no disk of device {}", dev);
+        self.nblocks[dev as usize - 1]
+    }
+
+    /// Returns the disk of the device `dev`.
+    fn disk(self: Pin<&Self>, dev: u32) -> Pin<&SleepableLock<VirtioDisk>> {
+        assert!(self.is_present(dev), "no disk of device {}", dev);
+        // SAFETY: the disks are never moved.
+        unsafe { Pin::new_unchecked(&self.get_ref().disks[dev as usize - 1]) }
+    }
+
     /// Return a locked Buf with the `latest` contents of the indicated block.
     // If buf.valid is true, we don't need to access Disk.
     pub fn read(self: Pin<&Self>, dev: u32, blockno: u32, ctx: &KernelCtx<'_, '_>) -> Buf {
         let mut buf = ctx.kernel().bcache().get_buf(dev, blockno).lock(ctx);
         if !buf.is_initialized() {
-            VirtioDisk::rw(&mut self.pinned_lock(), &mut buf, false, ctx);
+            VirtioDisk::rw(&mut self.disk(dev).pinned_lock(), &mut buf, false, ctx);
             buf.mark_initialized();
         }
         buf
     }
 
     pub fn write(self: Pin<&Self>, b: &mut Buf, ctx: &KernelCtx<'_, '_>) {
-        VirtioDisk::rw(&mut self.pinned_lock(), b, true, ctx)
+        VirtioDisk::rw(&mut self.disk(b.dev).pinned_lock(), b, true, ctx)
     }
 
+    /// The `Buf`s in `barray` must be of the same device.
     pub fn write_sequential(
         self: Pin<&Self>,
         barray: &mut ArrayVec<Buf, MAX_SEQ_WRITE>,
         ctx: &KernelCtx<'_, '_>,
     ) {
-        VirtioDisk::write_seq(&mut self.pinned_lock(), barray, ctx)
+        if let Some(b) = barray.first() {
+            let disk = self.disk(b.dev);
+            VirtioDisk::write_seq(&mut disk.pinned_lock(), barray, ctx)
+        }
+    }
+
+    /// Handles an interrupt from the disk on the bus `i`.
+    pub fn intr(self: Pin<&Self>, i: usize, kernel: KernelRef<'_, '_>) {
+        self.disk(i as u32 + 1)
+            .pinned_lock()
+            .get_pin_mut()
+            .intr(kernel);
     }
 }
 
 impl VirtioDisk {
-    pub fn init(self: Pin<&Self>) {
+    fn init(self: Pin<&Self>) {
+        let base = self.base;
         let mut status: VirtIOStatus = VirtIOStatus::empty();
 
-        // MMIO registers are located below KERNBASE, while kernel text and data
-        // are located above KERNBASE, so we can safely read/write MMIO registers.
-        MmioRegs::check_virtio_disk();
         status.insert(VirtIOStatus::ACKNOWLEDGE);
-        MmioRegs::set_status(&status);
+        MmioRegs::set_status(base, &status);
         status.insert(VirtIOStatus::DRIVER);
-        MmioRegs::set_status(&status);
+        MmioRegs::set_status(base, &status);
 
         // Negotiate features
-        let features = MmioRegs::get_features()
+        let features = MmioRegs::get_features(base)
             - (VirtIOFeatures::BLK_F_RO
                 | VirtIOFeatures::BLK_F_SCSI
                 | VirtIOFeatures::BLK_F_CONFIG_WCE
@@ -251,30 +325,31 @@ impl VirtioDisk {
                 | VirtIOFeatures::RING_F_EVENT_IDX
                 | VirtIOFeatures::RING_F_INDIRECT_DESC);
 
-        MmioRegs::set_features(&features);
+        MmioRegs::set_features(base, &features);
 
         // Tell device that feature negotiation is complete.
         status.insert(VirtIOStatus::FEATURES_OK);
-        MmioRegs::set_status(&status);
+        MmioRegs::set_status(base, &status);
 
         // Tell device we're completely ready.
         status.insert(VirtIOStatus::DRIVER_OK);
-        MmioRegs::set_status(&status);
+        MmioRegs::set_status(base, &status);
         // SAFETY: page size is `PGSIZE`.
         unsafe {
-            MmioRegs::set_pg_size(PGSIZE as _);
+            MmioRegs::set_pg_size(base, PGSIZE as _);
         }
 
         // Initialize queue 0.
         unsafe {
             MmioRegs::select_and_init_queue(
+                base,
                 0,
                 NUM as _,
                 (self.desc.as_ptr() as usize >> PGSHIFT) as _,
             );
         }
 
-        // plic.rs and trap.rs arrange for interrupts from VIRTIO0_IRQ.
+        // plic.rs and trap.rs arrange for interrupts from VIRTIO0_IRQ + i for the disk on the bus i.
     }
 
     /// Reads or writes a disk block, where the disk block number is given through `b`.
@@ -368,7 +443,7 @@ impl VirtioDisk {
         // the "used" ring, in which case we may process the new
         // completion entries in this interrupt, and have nothing to do
         // in the next interrupt, which is harmless.
-        MmioRegs::intr_ack_all();
+        MmioRegs::intr_ack_all(self.base);
 
         fence(Ordering::SeqCst);
 
@@ -516,7 +591,7 @@ impl VirtioDisk {
         // SAFETY: the all three descriptors' fields are well set.
         // Value is queue number.
         unsafe {
-            MmioRegs::notify_queue(0);
+            MmioRegs::notify_queue(*this.base, 0);
         }
 
         // Wait for virtio_disk_intr() to say request has finished.
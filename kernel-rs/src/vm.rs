@@ -1,19 +1,25 @@
-use core::{cmp, marker::PhantomData, mem, ops::DerefMut, pin::Pin, slice};
+use core::{cmp, fmt, marker::PhantomData, mem, ops::DerefMut, pin::Pin, slice};
 
+use arrayvec::ArrayVec;
 use bitflags::bitflags;
+use itertools::izip;
 use zerocopy::{AsBytes, FromBytes};
 
 use crate::{
     addr::{pgrounddown, pgroundup, Addr, KVAddr, PAddr, UVAddr, VAddr, MAXVA, PGSIZE},
     arch::interface::{Arch, IPageTableEntry, PageTableManager},
     arch::TargetArch,
-    fs::{DefaultFs, InodeGuard},
+    errno::Errno,
+    file::RcFile,
+    fs::{FileSystem, InodeGuard},
+    hal::hal,
     kalloc::Kmem,
     lock::SpinLock,
-    memlayout::{kstack, PHYSTOP, TRAMPOLINE, TRAPFRAME},
+    memlayout::{kstack, trapframe, PHYSTOP, TRAMPOLINE, TRAPFRAME, USERTOP},
     page::Page,
-    param::NPROC,
+    param::{NDISK, NPROC, NTHREAD, NVMA},
     proc::KernelCtx,
+    some_or,
     util::memmove,
 };
 
@@ -49,6 +55,38 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Memory protection of `mmap` and `mprotect`.
+    pub struct ProtFlags: i32 {
+        const READ = 0x1;
+        const WRITE = 0x2;
+        const EXEC = 0x4;
+    }
+}
+
+bitflags! {
+    /// Flags of `mmap`.
+    pub struct MapFlags: i32 {
+        const SHARED = 0x01;
+        const PRIVATE = 0x02;
+        const ANONYMOUS = 0x20;
+    }
+}
+
+impl From<ProtFlags> for AccessFlags {
+    /// The result is always readable and user-accessible.
+    fn from(item: ProtFlags) -> Self {
+        let mut ret = Self::RU;
+        if item.contains(ProtFlags::WRITE) {
+            ret |= Self::W;
+        }
+        if item.contains(ProtFlags::EXEC) {
+            ret |= Self::X;
+        }
+        ret
+    }
+}
+
 const PTE_PER_PT: usize = PGSIZE / mem::size_of::<PageTableEntry>();
 
 /// # Safety
@@ -203,8 +241,13 @@ impl<A: VAddr> PageTable<A> {
         Ok(())
     }
 
+    /// Unmap the page at `va`.
+    /// Return `Some(..)` with the physical address if `va` was mapped, `None` otherwise.
     fn remove(&mut self, va: A) -> Option<PAddr> {
         let pte = self.get_mut(va, None)?;
+        if !pte.is_valid() {
+            return None;
+        }
         assert!(pte.is_data(), "PageTable::remove");
         let pa = pte.get_pa();
         pte.invalidate();
@@ -231,8 +274,8 @@ impl<A: VAddr> Drop for PageTable<A> {
 }
 
 /// UserMemory manages the page table and allocated pages of a process. Its
-/// invariant guarantees that every PAddr mapped to VAddr except TRAMPOLINE and
-/// TRAPFRAME is from Page. This property is crucial for safety of methods that
+/// invariant guarantees that every PAddr mapped to VAddr below USERTOP is from
+/// Page. This property is crucial for safety of methods that
 /// read or write on memory, such as copy_in. Also, it is essential for safety
 /// of freeing a page created from each PAddr as well.
 ///
@@ -242,17 +285,102 @@ impl<A: VAddr> Drop for PageTable<A> {
 /// - If va ∈ dom(pt), va mod PGSIZE = 0 ∧ pt(va) mod PGSIZE = 0.
 /// - pt(TRAMPOLINE) = trampoline.
 /// - TRAPFRAME ∈ dom(pt).
-/// - If va ∈ dom(pt) ∧ va ∉ { TRAMPOLINE, TRAPFRAME },
-///   then Page::from_usize(pt(va)) succeeds without breaking the invariant of Page.
-/// - If va ∈ dom(pt) where va ∉ { 0, TRAMPOLINE, TRAPFRAME },
-///   then va - PGSIZE ∈ dom(pt).
-/// - pgroundup(size) ∉ dom(pt).
-/// - If size > 0, then pgroundup(size) - PGSIZE ∈ dom(pt).
+/// - If va ∈ dom(pt) ∧ va ≥ USERTOP, then va = TRAMPOLINE or va is the trap
+///   frame of a thread, which is owned by the thread.
+/// - If va ∈ dom(pt) ∧ va < USERTOP,
+///   then pt(va) is the address of a page allocated by Kmem, and pt holds one
+///   of its references. Page::from_usize(pt(va)) succeeds without breaking the
+///   invariant of Page if its reference count is 1.
+/// - If va ∈ dom(pt) and the reference count of pt(va) is larger than 1,
+///   then the PTE of va is not writable, unless va is in a shared area.
+/// - If va ∈ dom(pt) where va < USERTOP, then va < size,
+///   or va is in an area of areas.
+/// - Areas are page-aligned, do not overlap, and lie in [pgroundup(size), USERTOP).
+/// - If guard_page is Some(va), then va < size.
+///
+/// Pages in [0, size) and in areas need not be mapped. Such a page is lazily
+/// allocated when it is accessed for the first time. It is zeroed, or filled
+/// with the content of the file backing its area.
+///
+/// The pages of a shared area are shared with the children instead of being
+/// copied on write. Those of a shared file-backed area are written back to the
+/// file by `write_back`, and other mappings of the file do not see the writes
+/// until then.
+///
+/// The threads of a process share its UserMemory, so they may run on
+/// different CPUs with the same page table. Those CPUs may keep stale TLB
+/// entries, so a page removed from the page table is freed only after a TLB
+/// shootdown, and so is the old page of a copy-on-write page.
 pub struct UserMemory {
     /// Page table of process.
     page_table: PageTable<UVAddr>,
     /// Size of process memory (bytes).
     size: usize,
+    /// Memory-mapped areas, created by `mmap`.
+    areas: ArrayVec<MappedArea, NVMA>,
+    /// The stack guard page, which `clear` has made inaccessible by the user.
+    guard_page: Option<usize>,
+}
+
+/// The number of pages that `StalePages` holds before it frees them.
+const NSTALE: usize = 32;
+
+/// Pages removed from a user page table. Other CPUs may still have TLB entries
+/// that refer to them, so they are freed after a TLB shootdown by `flush`.
+struct StalePages {
+    /// Address of the page table.
+    page_table: usize,
+    pages: ArrayVec<Page, NSTALE>,
+}
+
+impl StalePages {
+    fn new(page_table: usize) -> Self {
+        Self {
+            page_table,
+            pages: ArrayVec::new(),
+        }
+    }
+
+    /// Adds `page`. Frees the pages added so far first if there is no room.
+    fn push(&mut self, page: Page, allocator: Pin<&SpinLock<Kmem>>) {
+        if self.pages.is_full() {
+            self.flush(allocator);
+        }
+        self.pages.push(page);
+    }
+
+    /// Shoots down the TLB entries of the other CPUs, and frees the pages.
+    fn flush(&mut self, allocator: Pin<&SpinLock<Kmem>>) {
+        if !self.pages.is_empty() {
+            hal().cpus().shootdown(self.page_table);
+        }
+        for page in self.pages.drain(..) {
+            allocator.free(page);
+        }
+    }
+}
+
+/// A memory-mapped area of a process.
+struct MappedArea {
+    /// Start address, page-aligned.
+    start: usize,
+    /// End address (exclusive), page-aligned.
+    end: usize,
+    /// Access permission. Always contains `AccessFlags::RU`.
+    perm: AccessFlags,
+    /// The file backing this area, or `None` for an anonymous area.
+    file: Option<RcFile>,
+    /// The file offset that `start` is mapped to.
+    offset: u64,
+    /// Whether the pages are shared with the children and the file, instead of
+    /// being private copies.
+    shared: bool,
+}
+
+impl MappedArea {
+    fn contains(&self, va: usize) -> bool {
+        self.start <= va && va < self.end
+    }
 }
 
 impl UserMemory {
@@ -300,6 +428,8 @@ impl UserMemory {
         let mut memory = Self {
             page_table: scopeguard::ScopeGuard::into_inner(page_table),
             size: 0,
+            areas: ArrayVec::new(),
+            guard_page: None,
         };
 
         if let Some(src) = src_opt {
@@ -318,72 +448,149 @@ impl UserMemory {
         Some(memory)
     }
 
-    /// Makes a new memory by copying a given memory. Copies both the page
-    /// table and the physical memory. Returns Some(memory) on success, None on
-    /// failure. Frees any allocated pages on failure.
+    /// Makes a new memory by copying a given memory. Instead of copying the
+    /// physical memory, shares every page between the two memories, and marks
+    /// writable pages outside shared areas as copy-on-write in both page tables.
+    /// Returns Some(memory) on success, None on failure. Frees any allocated
+    /// pages on failure.
     pub fn clone(&mut self, trap_frame: PAddr, allocator: Pin<&SpinLock<Kmem>>) -> Option<Self> {
         let new = Self::new(trap_frame, None, allocator)?;
-        let mut new = scopeguard::guard(new, |mut new| {
-            let _ = new.dealloc(0, allocator);
-        });
+        let mut new = scopeguard::guard(new, |new| new.free(allocator));
         for i in num_iter::range_step(0, self.size, PGSIZE) {
-            let pte = self
-                .page_table
-                .get_mut(i.into(), None)
-                .expect("clone_into: pte not found");
-            assert!(pte.is_valid(), "clone_into: invalid page");
-
-            let pa = pte.get_pa();
-            let flags = pte.get_flags();
-            let mut page = allocator.alloc(None)?;
-            // SAFETY: pa is an address in page_table,
-            // and thus it is the address of a page by the invariant.
-            let src = unsafe { slice::from_raw_parts(pa.into_usize() as *const u8, PGSIZE) };
-            memmove(page.deref_mut().deref_mut(), src);
-
-            new.push_page(page, flags, allocator)
-                .map_err(|page| allocator.free(page))
+            Self::share_page(
+                &mut self.page_table,
+                &mut new.page_table,
+                i,
+                true,
+                allocator,
+            )
+            .ok()?;
+            new.size = i + PGSIZE;
+        }
+        for area in &self.areas {
+            new.areas.push(MappedArea {
+                start: area.start,
+                end: area.end,
+                perm: area.perm,
+                file: None,
+                offset: area.offset,
+                shared: area.shared,
+            });
+            for va in num_iter::range_step(area.start, area.end, PGSIZE) {
+                Self::share_page(
+                    &mut self.page_table,
+                    &mut new.page_table,
+                    va,
+                    !area.shared,
+                    allocator,
+                )
                 .ok()?;
+            }
         }
+        // Writable pages of this page table have become copy-on-write.
+        hal().cpus().shootdown(self.page_table_addr());
         let mut new = scopeguard::ScopeGuard::into_inner(new);
         new.size = self.size;
+        new.guard_page = self.guard_page;
+        // Files are cloned last, since nothing can fail afterwards.
+        for (new_area, area) in izip!(new.areas.iter_mut(), self.areas.iter()) {
+            new_area.file = area.file.clone();
+        }
         Some(new)
     }
 
+    /// Map the page at `va` of `src` to `dst` as well, if it has been allocated.
+    /// Marks the page as copy-on-write if it is writable and `cow` is true.
+    /// Returns Ok(()) on success, Err(()) if an allocation has failed.
+    fn share_page(
+        src: &mut PageTable<UVAddr>,
+        dst: &mut PageTable<UVAddr>,
+        va: usize,
+        cow: bool,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<(), ()> {
+        let pte = some_or!(src.get_mut(va.into(), None), return Ok(()));
+        if !pte.is_valid() {
+            // Not allocated yet.
+            return Ok(());
+        }
+
+        if cow && pte.is_writable() {
+            pte.set_cow();
+        }
+        let pa = pte.get_pa();
+        let flags = pte.get_flags();
+        // SAFETY: pa is an address in page_table, and thus it is the
+        // address of a page by the invariant. The page is not writable
+        // in both page tables from now on.
+        let page = unsafe { allocator.share(pa) };
+        let pa = page.into_usize();
+
+        dst.insert(va.into(), pa.into(), flags, allocator)
+            // SAFETY: pa is the address of the shared page.
+            .map_err(|_| allocator.free(unsafe { Page::from_usize(pa) }))
+    }
+
     /// Get the size of this memory.
     pub fn size(&self) -> usize {
         self.size
     }
 
+    /// Writes the areas of this memory to `w`, one per line, as `/proc/<pid>/maps` shows
+    /// them: the range, the permission, whether it is shared or private, the file
+    /// offset, and the backing file.
+    /// The first line is the program image and the heap, which begin at address 0.
+    pub fn write_maps(&self, w: &mut impl fmt::Write) -> fmt::Result {
+        writeln!(w, "{:#x}-{:#x} rwxup 0 [image]", 0, pgroundup(self.size))?;
+        for area in &self.areas {
+            let perm = [
+                (AccessFlags::R, 'r'),
+                (AccessFlags::W, 'w'),
+                (AccessFlags::X, 'x'),
+                (AccessFlags::U, 'u'),
+            ];
+            write!(w, "{:#x}-{:#x} ", area.start, area.end)?;
+            for (flag, c) in perm {
+                w.write_char(if area.perm.contains(flag) { c } else { '-' })?;
+            }
+            w.write_char(if area.shared { 's' } else { 'p' })?;
+            match &area.file {
+                Some(file) => writeln!(w, " {} {}", area.offset, **file)?,
+                None => writeln!(w, " {} [anon]", area.offset)?,
+            }
+        }
+        Ok(())
+    }
+
     /// Load data from a file into memory at virtual address va. va must be
     /// page-aligned, and the pages from va to va + sz must already be mapped.
     ///
-    /// Returns Ok(()) on success, Err(()) on failure.
-    pub fn load_file(
+    /// Returns Ok(()) on success, Err(ENOEXEC) if the file is too short.
+    pub fn load_file<FS: FileSystem>(
         &mut self,
         va: UVAddr,
-        ip: &mut InodeGuard<'_, DefaultFs>,
-        offset: u32,
+        ip: &mut InodeGuard<'_, FS>,
+        offset: u64,
         sz: u32,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), Errno> {
         assert!(va.is_page_aligned(), "load_file: va must be page aligned");
         for i in num_iter::range_step(0, sz, PGSIZE as _) {
             let dst = self
-                .get_slice(va + i as usize)
+                .get_slice(va + i as usize, AccessFlags::W)
                 .expect("load_file: address should exist");
             let n = cmp::min((sz - i) as usize, PGSIZE);
-            let bytes_read = ip.read_bytes_kernel(&mut dst[..n], offset + i, ctx);
+            let bytes_read = ip.read_bytes_kernel(&mut dst[..n], offset + u64::from(i), ctx);
             if bytes_read != n {
-                return Err(());
+                return Err(Errno::ENOEXEC);
             }
         }
         Ok(())
     }
 
     /// Allocate PTEs and physical memory to grow process to newsz, which need
-    /// not be page aligned. Returns Ok(new size) or Err(()) on error.
-    pub fn alloc(&mut self, newsz: usize, allocator: Pin<&SpinLock<Kmem>>) -> Result<usize, ()> {
+    /// not be page aligned. Returns Ok(new size) or Err(ENOMEM) on error.
+    pub fn alloc(&mut self, newsz: usize, allocator: Pin<&SpinLock<Kmem>>) -> Result<usize, Errno> {
         if newsz <= self.size {
             return Ok(self.size);
         }
@@ -393,13 +600,16 @@ impl UserMemory {
             let _ = this.dealloc(oldsz, allocator);
         });
         while pgroundup(this.size) < pgroundup(newsz) {
-            let page = allocator.alloc(Some(0)).ok_or(())?;
+            let page = allocator.alloc(Some(0)).ok_or(Errno::ENOMEM)?;
             this.push_page(
                 page,
                 (AccessFlags::R | AccessFlags::W | AccessFlags::X | AccessFlags::U).into(),
                 allocator,
             )
-            .map_err(|page| allocator.free(page))?;
+            .map_err(|page| {
+                allocator.free(page);
+                Errno::ENOMEM
+            })?;
         }
         let this = scopeguard::ScopeGuard::into_inner(this);
         this.size = newsz;
@@ -413,23 +623,33 @@ impl UserMemory {
             return self.size;
         }
 
+        let mut stale = StalePages::new(self.page_table_addr());
         while pgroundup(newsz) < pgroundup(self.size) {
             if let Some(page) = self.pop_page() {
-                allocator.free(page);
+                stale.push(page, allocator);
             }
         }
+        stale.flush(allocator);
         self.size = newsz;
+        if self.guard_page.map_or(false, |va| va >= newsz) {
+            self.guard_page = None;
+        }
         newsz
     }
 
-    /// Grow or shrink process size by n bytes.
-    /// Return Ok(old size) on success, Err(()) on failure.
-    pub fn resize(&mut self, n: i32, allocator: Pin<&SpinLock<Kmem>>) -> Result<usize, ()> {
+    /// Grow or shrink process size by n bytes. Growing only reserves the
+    /// address range; each page is allocated when it is first accessed.
+    /// Return Ok(old size) on success, Err(ENOMEM) if there is no room to grow.
+    pub fn resize(&mut self, n: i32, allocator: Pin<&SpinLock<Kmem>>) -> Result<usize, Errno> {
         let size = self.size;
         match n.cmp(&0) {
             cmp::Ordering::Equal => (),
             cmp::Ordering::Greater => {
-                let _ = self.alloc(size + n as usize, allocator)?;
+                let newsz = size.checked_add(n as usize).ok_or(Errno::ENOMEM)?;
+                if newsz > self.mmap_bottom() {
+                    return Err(Errno::ENOMEM);
+                }
+                self.size = newsz;
             }
             cmp::Ordering::Less => {
                 let _ = self.dealloc(size - (-n as usize), allocator);
@@ -438,6 +658,417 @@ impl UserMemory {
         Ok(size)
     }
 
+    /// Handle a page fault at `va` caused by an access of kind `access`.
+    /// Returns Ok(None) if the fault has been resolved, and Err(()) if the
+    /// access is invalid. Returns Ok(Some((file, offset))) if the page belongs
+    /// to a file-backed area and has not been read yet. In this case, the
+    /// caller should read the page from `file` at `offset`, map it by
+    /// `map_file_page`, and free `file`.
+    pub fn handle_fault(
+        &mut self,
+        va: UVAddr,
+        access: AccessFlags,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<Option<(RcFile, u64)>, ()> {
+        if self.resolve_fault(va, access, allocator)? {
+            return Ok(None);
+        }
+        let va = pgrounddown(va.into_usize());
+        let area = self
+            .areas
+            .iter()
+            .find(|area| area.contains(va))
+            .expect("handle_fault");
+        let file = area.file.as_ref().expect("handle_fault").clone();
+        Ok(Some((file, area.offset + (va - area.start) as u64)))
+    }
+
+    /// Map a page read from a file at `va`, after `handle_fault` has returned
+    /// `Ok(Some(..))` for `va`. Returns Ok(()) on success, Err(()) on failure.
+    pub fn map_file_page(
+        &mut self,
+        va: UVAddr,
+        page: Page,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<(), ()> {
+        let va = pgrounddown(va.into_usize());
+        let area = some_or!(self.areas.iter().find(|area| area.contains(va)), {
+            // The area has been unmapped in the meantime.
+            allocator.free(page);
+            return Err(());
+        });
+        let perm = area.perm;
+        if self
+            .page_table
+            .get_mut(va.into(), None)
+            .map_or(false, |pte| pte.is_valid())
+        {
+            // Already mapped by someone else.
+            allocator.free(page);
+            return Ok(());
+        }
+        let pa = page.into_usize();
+        self.page_table
+            .insert(va.into(), pa.into(), perm.into(), allocator)
+            // SAFETY: pa is the address of the given page.
+            .map_err(|_| allocator.free(unsafe { Page::from_usize(pa) }))
+    }
+
+    /// Make the page at `va` accessible for an access of kind `access`,
+    /// allocating a zeroed page or copying a copy-on-write page if needed.
+    /// Returns Ok(true) if the page is accessible, Ok(false) if the page
+    /// belongs to a file-backed area and has not been read yet, and Err(()) if
+    /// the access is invalid or the allocation has failed.
+    fn resolve_fault(
+        &mut self,
+        va: UVAddr,
+        access: AccessFlags,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<bool, ()> {
+        let va = pgrounddown(va.into_usize());
+        let (perm, file_backed) = if va < self.size {
+            (AccessFlags::RWXU, false)
+        } else {
+            let area = self.areas.iter().find(|area| area.contains(va)).ok_or(())?;
+            (area.perm, area.file.is_some())
+        };
+        if !perm.contains(access) || self.guard_page == Some(va) {
+            return Err(());
+        }
+
+        let mapped = self
+            .page_table
+            .get_mut(va.into(), None)
+            .map_or(false, |pte| pte.is_valid());
+        if !mapped {
+            if file_backed {
+                return Ok(false);
+            }
+            // A lazily allocated page is accessed for the first time.
+            let page = allocator.alloc(Some(0)).ok_or(())?;
+            let pa = page.into_usize();
+            self.page_table
+                .insert(va.into(), pa.into(), perm.into(), allocator)
+                // SAFETY: pa is the address of the page allocated above.
+                .map_err(|_| allocator.free(unsafe { Page::from_usize(pa) }))?;
+            return Ok(true);
+        }
+
+        let pte = self.page_table.get_mut(va.into(), None).ok_or(())?;
+        if access.contains(AccessFlags::W) && !pte.is_writable() {
+            if !pte.is_cow() {
+                return Err(());
+            }
+            self.copy_on_write(va.into(), allocator)?;
+        }
+        Ok(true)
+    }
+
+    /// Map `len` bytes below the lowest area, with permission `perm`. If `file`
+    /// is `Some((f, offset))`, the area is backed by `f` from `offset`.
+    /// Otherwise, it is backed by zeroed pages. Pages are allocated when they
+    /// are accessed for the first time. The area is shared if `shared` is true,
+    /// and private otherwise.
+    /// Returns Ok(start address of the area) on success, Err(EINVAL) if `len` is
+    /// invalid, and Err(ENOMEM) if there is no room for the area.
+    pub fn mmap(
+        &mut self,
+        len: usize,
+        perm: AccessFlags,
+        file: Option<(&RcFile, u64)>,
+        shared: bool,
+    ) -> Result<usize, Errno> {
+        if len == 0 || len > USERTOP {
+            return Err(Errno::EINVAL);
+        }
+        if self.areas.is_full() {
+            return Err(Errno::ENOMEM);
+        }
+        let start = self
+            .mmap_bottom()
+            .checked_sub(pgroundup(len))
+            .ok_or(Errno::ENOMEM)?;
+        if start < pgroundup(self.size) {
+            return Err(Errno::ENOMEM);
+        }
+        self.areas.push(MappedArea {
+            start,
+            end: start + pgroundup(len),
+            perm: perm | AccessFlags::RU,
+            file: file.map(|(f, _)| f.clone()),
+            offset: file.map_or(0, |(_, offset)| offset),
+            shared,
+        });
+        Ok(start)
+    }
+
+    /// Unmap the pages in [va, va + len) that belong to areas.
+    /// Returns Ok(files that backed the removed areas) on success, Err(EINVAL) if
+    /// the range is invalid, and Err(ENOMEM) if there is no room to split an area.
+    /// The caller should free the returned files.
+    pub fn munmap(
+        &mut self,
+        va: UVAddr,
+        len: usize,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<ArrayVec<RcFile, NVMA>, Errno> {
+        let (start, end) = Self::page_range(va, len)?;
+        self.split_area(start)?;
+        self.split_area(end)?;
+
+        let mut files = ArrayVec::new();
+        let mut i = 0;
+        while i < self.areas.len() {
+            if start <= self.areas[i].start && self.areas[i].end <= end {
+                let area = self.areas.swap_remove(i);
+                self.unmap_pages(area.start, area.end, allocator);
+                if let Some(file) = area.file {
+                    files.push(file);
+                }
+            } else {
+                i += 1;
+            }
+        }
+        Ok(files)
+    }
+
+    /// Unmap every area. Returns the files that backed them, which the caller
+    /// should free.
+    pub fn munmap_all(&mut self, allocator: Pin<&SpinLock<Kmem>>) -> ArrayVec<RcFile, NVMA> {
+        let mut files = ArrayVec::new();
+        while let Some(area) = self.areas.pop() {
+            self.unmap_pages(area.start, area.end, allocator);
+            if let Some(file) = area.file {
+                files.push(file);
+            }
+        }
+        files
+    }
+
+    /// Change the permission of the pages in [va, va + len) to `perm`. Every
+    /// page in the range must belong to an area.
+    /// Returns Ok(()) on success, Err(EINVAL) if the range is invalid, Err(ENOMEM)
+    /// if a page in the range is not mapped or there is no room to split an area,
+    /// and Err(EACCES) if `perm` is writable but a shared area in the range is
+    /// backed by a file that is not writable.
+    pub fn mprotect(
+        &mut self,
+        va: UVAddr,
+        len: usize,
+        perm: AccessFlags,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<(), Errno> {
+        let (start, end) = Self::page_range(va, len)?;
+        // Areas do not overlap, so the range is covered iff the overlaps sum up to its length.
+        let covered: usize = self
+            .areas
+            .iter()
+            .map(|area| cmp::min(area.end, end).saturating_sub(cmp::max(area.start, start)))
+            .sum();
+        if covered != end - start {
+            return Err(Errno::ENOMEM);
+        }
+        if perm.contains(AccessFlags::W)
+            && self.areas.iter().any(|area| {
+                area.start < end
+                    && start < area.end
+                    && area.shared
+                    && area.file.as_ref().map_or(false, |f| !f.is_mappable(true))
+            })
+        {
+            return Err(Errno::EACCES);
+        }
+        self.split_area(start)?;
+        self.split_area(end)?;
+
+        let perm = perm | AccessFlags::RU;
+        for area in self
+            .areas
+            .iter_mut()
+            .filter(|area| start <= area.start && area.end <= end)
+        {
+            area.perm = perm;
+            for va in num_iter::range_step(area.start, area.end, PGSIZE) {
+                let pte = some_or!(self.page_table.get_mut(va.into(), None), continue);
+                if !pte.is_valid() {
+                    continue;
+                }
+                let pa = pte.get_pa();
+                pte.set_entry(pa, perm.into());
+                // A page shared by a private area must stay read-only until it is copied.
+                if !area.shared && perm.contains(AccessFlags::W) && allocator.refcount(pa) > 1 {
+                    pte.set_cow();
+                }
+            }
+        }
+        hal().cpus().shootdown(self.page_table_addr());
+        Ok(())
+    }
+
+    /// Returns the page-aligned range [va, va + len). va must be page-aligned.
+    fn page_range(va: UVAddr, len: usize) -> Result<(usize, usize), Errno> {
+        if !va.is_page_aligned() || len == 0 || len > USERTOP {
+            return Err(Errno::EINVAL);
+        }
+        let start = va.into_usize();
+        let end = start.checked_add(pgroundup(len)).ok_or(Errno::EINVAL)?;
+        if end > USERTOP {
+            return Err(Errno::EINVAL);
+        }
+        Ok((start, end))
+    }
+
+    /// If an area contains `va` in its middle, split it into two at `va`.
+    /// Returns Ok(()) on success, Err(ENOMEM) if there is no room for a new area.
+    fn split_area(&mut self, va: usize) -> Result<(), Errno> {
+        let i = some_or!(
+            self.areas
+                .iter()
+                .position(|area| area.start < va && va < area.end),
+            return Ok(())
+        );
+        if self.areas.is_full() {
+            return Err(Errno::ENOMEM);
+        }
+        let area = &mut self.areas[i];
+        let tail = MappedArea {
+            start: va,
+            end: area.end,
+            perm: area.perm,
+            file: area.file.clone(),
+            offset: area.offset + (va - area.start) as u64,
+            shared: area.shared,
+        };
+        area.end = va;
+        self.areas.push(tail);
+        Ok(())
+    }
+
+    /// Unmap and free the allocated pages in [start, end).
+    fn unmap_pages(&mut self, start: usize, end: usize, allocator: Pin<&SpinLock<Kmem>>) {
+        let mut stale = StalePages::new(self.page_table_addr());
+        for va in num_iter::range_step(start, end, PGSIZE) {
+            if let Some(pa) = self.page_table.remove(va.into()) {
+                // SAFETY: pa is an address in page_table,
+                // and, thus, it is the address of a page by the invariant.
+                stale.push(unsafe { Page::from_usize(pa.into_usize()) }, allocator);
+            }
+        }
+        stale.flush(allocator);
+    }
+
+    /// Returns the intersections of [start, end) and the areas that satisfy `pred`.
+    fn area_ranges(
+        &self,
+        start: usize,
+        end: usize,
+        pred: impl Fn(&MappedArea) -> bool,
+    ) -> ArrayVec<(usize, usize), NVMA> {
+        self.areas
+            .iter()
+            .filter(|area| pred(area))
+            .map(|area| (cmp::max(area.start, start), cmp::min(area.end, end)))
+            .filter(|(start, end)| start < end)
+            .collect()
+    }
+
+    /// If the page at `va` belongs to a shared area that can be written back to its
+    /// file and has been mapped, returns a new reference to the page, the file, and
+    /// the file offset of the page. The caller should free the page and the file.
+    fn shared_file_page(
+        &mut self,
+        va: usize,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Option<(Page, RcFile, u64)> {
+        let area = self.areas.iter().find(|area| area.contains(va))?;
+        let file = area
+            .file
+            .as_ref()
+            .filter(|f| area.shared && f.is_mappable(true))?;
+        let pa = self
+            .page_table
+            .get_mut(va.into(), None)
+            .filter(|pte| pte.is_valid())?
+            .get_pa();
+        // SAFETY: pa is an address in page_table, and thus it is the address of a page by the
+        // invariant.
+        let page = unsafe { allocator.share(pa) };
+        Some((page, file.clone(), area.offset + (va - area.start) as u64))
+    }
+
+    /// Returns the lowest address of the areas, or USERTOP if there is none.
+    fn mmap_bottom(&self) -> usize {
+        self.areas
+            .iter()
+            .map(|area| area.start)
+            .min()
+            .unwrap_or(USERTOP)
+    }
+
+    /// Make the copy-on-write page at `va` writable. If the page is still
+    /// shared, copies it into a new page first.
+    /// Returns Ok(()) on success, Err(()) if the allocation has failed.
+    fn copy_on_write(&mut self, va: UVAddr, allocator: Pin<&SpinLock<Kmem>>) -> Result<(), ()> {
+        let page_table = self.page_table_addr();
+        let pte = self.page_table.get_mut(va, None).ok_or(())?;
+        assert!(pte.is_cow(), "copy_on_write");
+        let pa = pte.get_pa();
+        // Only page tables hold references to a user page, so the count cannot
+        // grow behind our back. If this is the last reference, just reuse it.
+        if allocator.refcount(pa) > 1 {
+            let mut page = allocator.alloc(None).ok_or(())?;
+            // SAFETY: pa is an address in page_table,
+            // and thus it is the address of a page by the invariant.
+            let src = unsafe { slice::from_raw_parts(pa.into_usize() as *const u8, PGSIZE) };
+            memmove(page.deref_mut().deref_mut(), src);
+            let flags = pte.get_flags();
+            pte.set_entry(page.into_usize().into(), flags);
+            // Other threads must not keep reading the old page.
+            hal().cpus().shootdown(page_table);
+            // SAFETY: pa is the address of a page, and this page table does
+            // not refer to it anymore.
+            allocator.free(unsafe { Page::from_usize(pa.into_usize()) });
+        }
+        pte.clear_cow();
+        Ok(())
+    }
+
+    /// Map the trap frame of a new thread at a free slot beneath TRAPFRAME.
+    /// Returns Ok(virtual address of the trap frame) on success, Err(EAGAIN) if
+    /// there is no free slot, and Err(ENOMEM) if the allocation has failed.
+    pub fn map_trap_frame(
+        &mut self,
+        trap_frame: PAddr,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<usize, Errno> {
+        for va in (1..NTHREAD).map(trapframe) {
+            if self
+                .page_table
+                .get_mut(va.into(), None)
+                .map_or(false, |pte| pte.is_valid())
+            {
+                continue;
+            }
+            self.page_table
+                .insert(
+                    va.into(),
+                    trap_frame,
+                    (AccessFlags::R | AccessFlags::W).into(),
+                    allocator,
+                )
+                .map_err(|_| Errno::ENOMEM)?;
+            return Ok(va);
+        }
+        Err(Errno::EAGAIN)
+    }
+
+    /// Unmap the trap frame of a thread mapped by `map_trap_frame`. The trap
+    /// frame itself is not freed.
+    pub fn unmap_trap_frame(&mut self, va: usize) {
+        assert!((USERTOP..TRAPFRAME).contains(&va), "unmap_trap_frame");
+        let _ = self.page_table.remove(va.into());
+    }
+
     /// Mark a PTE invalid for user access.
     /// Used by exec for the user stack guard page.
     pub fn clear(&mut self, va: UVAddr) {
@@ -445,19 +1076,22 @@ impl UserMemory {
             .get_mut(va, None)
             .expect("clear")
             .clear_user();
+        self.guard_page = Some(va.into_usize());
     }
 
     /// Copy from kernel to user.
     /// Copy len bytes from src to virtual address dstva in a given page table.
-    /// Return Ok(()) on success, Err(()) on error.
-    pub fn copy_out_bytes(&mut self, dstva: UVAddr, src: &[u8]) -> Result<(), ()> {
+    /// Return Ok(()) on success, Err(EFAULT) on error.
+    pub fn copy_out_bytes(&mut self, dstva: UVAddr, src: &[u8]) -> Result<(), Errno> {
         let mut dst = dstva.into_usize();
         let mut len = src.len();
         let mut offset = 0;
         while len > 0 {
             let va = pgrounddown(dst);
             let poffset = dst - va;
-            let page = self.get_slice(va.into()).ok_or(())?;
+            let page = self
+                .get_slice(va.into(), AccessFlags::W)
+                .ok_or(Errno::EFAULT)?;
             let n = cmp::min(PGSIZE - poffset, len);
             memmove(&mut page[poffset..poffset + n], &src[offset..offset + n]);
             len -= n;
@@ -469,22 +1103,24 @@ impl UserMemory {
 
     /// Copy from kernel to user.
     /// Copy from src to virtual address dstva in a given page table.
-    /// Return Ok(()) on success, Err(()) on error.
-    pub fn copy_out<T: AsBytes>(&mut self, dstva: UVAddr, src: &T) -> Result<(), ()> {
+    /// Return Ok(()) on success, Err(EFAULT) on error.
+    pub fn copy_out<T: AsBytes>(&mut self, dstva: UVAddr, src: &T) -> Result<(), Errno> {
         self.copy_out_bytes(dstva, src.as_bytes())
     }
 
     /// Copy from user to kernel.
     /// Copy len bytes to dst from virtual address srcva in a given page table.
-    /// Return Ok(()) on success, Err(()) on error.
-    pub fn copy_in_bytes(&mut self, dst: &mut [u8], srcva: UVAddr) -> Result<(), ()> {
+    /// Return Ok(()) on success, Err(EFAULT) on error.
+    pub fn copy_in_bytes(&mut self, dst: &mut [u8], srcva: UVAddr) -> Result<(), Errno> {
         let mut src = srcva.into_usize();
         let mut len = dst.len();
         let mut offset = 0;
         while len > 0 {
             let va = pgrounddown(src);
             let poffset = src - va;
-            let page = self.get_slice(va.into()).ok_or(())?;
+            let page = self
+                .get_slice(va.into(), AccessFlags::R)
+                .ok_or(Errno::EFAULT)?;
             let n = cmp::min(PGSIZE - poffset, len);
             memmove(&mut dst[offset..offset + n], &page[poffset..poffset + n]);
             len -= n;
@@ -496,27 +1132,30 @@ impl UserMemory {
 
     /// Copy from user to kernel.
     /// Copy to dst from virtual address srcva in a given page table.
-    /// Return Ok(()) on success, Err(()) on error.
+    /// Return Ok(()) on success, Err(EFAULT) on error.
     pub unsafe fn copy_in<T: AsBytes + FromBytes>(
         &mut self,
         dst: &mut T,
         srcva: UVAddr,
-    ) -> Result<(), ()> {
+    ) -> Result<(), Errno> {
         self.copy_in_bytes(dst.as_bytes_mut(), srcva)
     }
 
     /// Copy a null-terminated string from user to kernel.
     /// Copy bytes to dst from virtual address srcva in a given page table,
     /// until a '\0', or max.
-    /// Return OK(()) on success, Err(()) on error.
-    pub fn copy_in_str(&mut self, dst: &mut [u8], srcva: UVAddr) -> Result<(), ()> {
+    /// Return OK(()) on success, Err(EFAULT) if srcva is invalid, and
+    /// Err(ENAMETOOLONG) if the string does not fit in dst.
+    pub fn copy_in_str(&mut self, dst: &mut [u8], srcva: UVAddr) -> Result<(), Errno> {
         let mut src = srcva.into_usize();
         let mut offset = 0;
         let mut max = dst.len();
         while max > 0 {
             let va = pgrounddown(src);
             let poffset = src - va;
-            let page = self.get_slice(va.into()).ok_or(())?;
+            let page = self
+                .get_slice(va.into(), AccessFlags::R)
+                .ok_or(Errno::EFAULT)?;
             let n = cmp::min(PGSIZE - poffset, max);
 
             let from = &page[poffset..poffset + n];
@@ -533,7 +1172,17 @@ impl UserMemory {
                 }
             }
         }
-        Err(())
+        Err(Errno::ENAMETOOLONG)
+    }
+
+    /// Return the physical address of the user word at `va`, which identifies a
+    /// futex. A lazily allocated or copy-on-write page is made writable first,
+    /// so that the address does not change while the page is mapped.
+    /// Some(address) on success, None on failure.
+    pub fn futex_addr(&mut self, va: UVAddr) -> Option<PAddr> {
+        let offset = va.into_usize() - pgrounddown(va.into_usize());
+        let page = self.get_slice(pgrounddown(va.into_usize()).into(), AccessFlags::W)?;
+        Some((page.as_ptr() as usize + offset).into())
     }
 
     /// Return the address of the page table
@@ -542,15 +1191,23 @@ impl UserMemory {
     }
 
     /// Return a page at va as a slice. Some(page) on success, None on failure.
-    fn get_slice(&mut self, va: UVAddr) -> Option<&mut [u8]> {
-        if va.into_usize() >= TRAPFRAME {
+    /// A lazily allocated page is allocated first, and if `access` contains
+    /// `AccessFlags::W`, a copy-on-write page is made writable first.
+    /// A file-backed page that has not been read yet is not accessible; the copy
+    /// functions of `KernelCtx` read such pages before calling this.
+    fn get_slice(&mut self, va: UVAddr, access: AccessFlags) -> Option<&mut [u8]> {
+        if va.into_usize() >= USERTOP {
+            return None;
+        }
+        if !self.resolve_fault(va, access, hal().kmem()).ok()? {
+            // A file can be read only with a `KernelCtx`. See `KernelCtx::fault_in`.
             return None;
         }
         let pte = self.page_table.get_mut(va, None)?;
         if !pte.is_user() {
             return None;
         }
-        // SAFETY: va < TRAPFRAME, so pte.get_pa() is the address of a page.
+        // SAFETY: va < USERTOP, so pte.get_pa() is the address of a page.
         Some(unsafe { slice::from_raw_parts_mut(pte.get_pa().into_usize() as _, PGSIZE) })
     }
 
@@ -573,31 +1230,163 @@ impl UserMemory {
         Ok(())
     }
 
-    /// Decrease the size by removing the most recently appended page.
-    /// Some(page) if size > 0, None if size = 0.
+    /// Decrease the size by removing the last page.
+    /// Some(page) if size > 0 and the last page has been allocated, None otherwise.
     fn pop_page(&mut self) -> Option<Page> {
         if self.size == 0 {
             return None;
         }
         self.size = pgroundup(self.size) - PGSIZE;
-        let pa = self
-            .page_table
-            .remove(self.size.into())
-            .expect("pop_page")
-            .into_usize();
+        let pa = self.page_table.remove(self.size.into())?.into_usize();
         // SAFETY: pa is an address in page_table,
         // and, thus, it is the address of a page by the invariant.
         Some(unsafe { Page::from_usize(pa) })
     }
 
+    /// Frees the memory. File-backed areas must have been unmapped by
+    /// `munmap_all` beforehand, since freeing their files needs a `KernelCtx`.
     pub fn free(mut self, allocator: Pin<&SpinLock<Kmem>>) {
         let _ = self.dealloc(0, allocator);
+        assert!(
+            self.munmap_all(allocator).is_empty(),
+            "UserMemory::free: file-backed area"
+        );
         // SAFETY: self will be dropped.
         unsafe { self.page_table.free(allocator) };
         mem::forget(self);
     }
 }
 
+impl KernelCtx<'_, '_> {
+    /// Handle a page fault of the current process at `va`, caused by an access
+    /// of kind `access`. Reads the page from its file if it is file-backed.
+    /// Returns Ok(()) on success, Err(()) if the access is invalid or the
+    /// page could not be allocated or read.
+    pub fn handle_page_fault(&self, va: UVAddr, access: AccessFlags) -> Result<(), ()> {
+        let allocator = hal().kmem();
+        let (file, offset) = some_or!(
+            self.proc().memory().handle_fault(va, access, allocator)?,
+            return Ok(())
+        );
+        let mut page = some_or!(allocator.alloc(Some(0)), {
+            file.free(self);
+            return Err(());
+        });
+        let res = file.read_kernel_at(&mut page[..], offset, self);
+        file.free(self);
+        if res.is_err() {
+            allocator.free(page);
+            return Err(());
+        }
+        self.proc().memory().map_file_page(va, page, allocator)
+    }
+
+    /// Read the unread file-backed pages in [va, va + len) of the current process.
+    /// The copy functions of `KernelCtx` call it before accessing user memory.
+    /// It should be called explicitly before accessing user memory through
+    /// `UserMemory` while holding a spinlock or an inode lock, since reading a
+    /// page may sleep and needs to lock the inode of the file.
+    pub fn fault_in(&self, va: UVAddr, len: usize, access: AccessFlags) {
+        let start = pgrounddown(va.into_usize());
+        let end = va.into_usize().saturating_add(len);
+        let ranges = self
+            .proc()
+            .memory()
+            .area_ranges(start, end, |area| area.file.is_some());
+        for (start, end) in ranges {
+            for va in num_iter::range_step(start, end, PGSIZE) {
+                // Errors are reported by the following access.
+                let _ = self.handle_page_fault(va.into(), access);
+            }
+        }
+    }
+
+    /// Fault in every page of the shared areas of the current process, so that
+    /// a child made by `UserMemory::clone` shares the same pages.
+    pub fn populate_shared(&self) {
+        let ranges = self
+            .proc()
+            .memory()
+            .area_ranges(0, USERTOP, |area| area.shared);
+        for (start, end) in ranges {
+            for va in num_iter::range_step(start, end, PGSIZE) {
+                // An unreadable page is faulted in again by the child.
+                let _ = self.handle_page_fault(va.into(), AccessFlags::empty());
+            }
+        }
+    }
+
+    /// Write the pages of the shared file-backed areas in [start, end) of the
+    /// current process back to their files. Pages that have never been mapped
+    /// are skipped.
+    pub fn write_back(&self, start: usize, end: usize) {
+        let allocator = hal().kmem();
+        let ranges = self.proc().memory().area_ranges(start, end, |area| {
+            area.shared && area.file.as_ref().map_or(false, |f| f.is_mappable(true))
+        });
+        for (start, end) in ranges {
+            for va in num_iter::range_step(pgrounddown(start), end, PGSIZE) {
+                let (page, file, offset) = some_or!(
+                    self.proc().memory().shared_file_page(va, allocator),
+                    continue
+                );
+                // Errors cannot be reported to anyone, as in close.
+                let _ = file.write_kernel_at(&page[..], offset, self);
+                file.free(self);
+                allocator.free(page);
+            }
+        }
+    }
+
+    /// Copy from kernel to user.
+    /// Copy len bytes from src to virtual address dstva of the current process,
+    /// reading the file-backed pages in the range first.
+    /// Return Ok(()) on success, Err(EFAULT) on error.
+    pub fn copy_out_bytes(&self, dstva: UVAddr, src: &[u8]) -> Result<(), Errno> {
+        self.fault_in(dstva, src.len(), AccessFlags::W);
+        self.proc().memory().copy_out_bytes(dstva, src)
+    }
+
+    /// Copy from kernel to user.
+    /// Copy from src to virtual address dstva of the current process,
+    /// reading the file-backed pages in the range first.
+    /// Return Ok(()) on success, Err(EFAULT) on error.
+    pub fn copy_out<T: AsBytes>(&self, dstva: UVAddr, src: &T) -> Result<(), Errno> {
+        self.copy_out_bytes(dstva, src.as_bytes())
+    }
+
+    /// Copy from user to kernel.
+    /// Copy len bytes to dst from virtual address srcva of the current process,
+    /// reading the file-backed pages in the range first.
+    /// Return Ok(()) on success, Err(EFAULT) on error.
+    pub fn copy_in_bytes(&self, dst: &mut [u8], srcva: UVAddr) -> Result<(), Errno> {
+        self.fault_in(srcva, dst.len(), AccessFlags::R);
+        self.proc().memory().copy_in_bytes(dst, srcva)
+    }
+
+    /// Copy from user to kernel.
+    /// Copy to dst from virtual address srcva of the current process,
+    /// reading the file-backed pages in the range first.
+    /// Return Ok(()) on success, Err(EFAULT) on error.
+    pub unsafe fn copy_in<T: AsBytes + FromBytes>(
+        &self,
+        dst: &mut T,
+        srcva: UVAddr,
+    ) -> Result<(), Errno> {
+        self.copy_in_bytes(dst.as_bytes_mut(), srcva)
+    }
+
+    /// Copy a null-terminated string from user to kernel.
+    /// Copy bytes to dst from virtual address srcva of the current process,
+    /// until a '\0', or max, reading the file-backed pages in the range first.
+    /// Return OK(()) on success, Err(EFAULT) if srcva is invalid, and
+    /// Err(ENAMETOOLONG) if the string does not fit in dst.
+    pub fn copy_in_str(&self, dst: &mut [u8], srcva: UVAddr) -> Result<(), Errno> {
+        self.fault_in(srcva, dst.len(), AccessFlags::R);
+        self.proc().memory().copy_in_str(dst, srcva)
+    }
+}
+
 impl Drop for UserMemory {
     fn drop(&mut self) {
         // HACK(@efenniht): we really need linear type here:
@@ -652,11 +1441,22 @@ impl<A: Arch> KernelMemory<A> {
             )
             .ok()?;
 
-        // Virtio mmio disk interface
+        // Real-time clock registers
         page_table
             .insert_range(
-                A::VIRTIO0.into(),
+                A::RTC0.into(),
                 PGSIZE,
+                A::RTC0.into(),
+                (AccessFlags::R | AccessFlags::W).into(),
+                allocator,
+            )
+            .ok()?;
+
+        // Virtio mmio disk interfaces
+        page_table
+            .insert_range(
+                A::VIRTIO0.into(),
+                pgroundup(NDISK * A::VIRTIO_STRIDE),
                 A::VIRTIO0.into(),
                 (AccessFlags::R | AccessFlags::W).into(),
                 allocator,
@@ -1,5 +1,32 @@
 #define O_RDONLY  0x000
 #define O_WRONLY  0x001
 #define O_RDWR    0x002
+#define O_NONBLOCK 0x004
+#define O_APPEND  0x008
 #define O_CREATE  0x200
 #define O_TRUNC   0x400
+#define O_NOFOLLOW 0x800
+#define O_EXCL    0x1000
+#define O_CLOEXEC 0x2000
+
+// fcntl()
+#define F_DUPFD   0
+#define F_GETFD   1
+#define F_SETFD   2
+#define F_GETFL   3
+#define F_SETFL   4
+
+#define FD_CLOEXEC 1
+
+// mmap() and mprotect()
+#define PROT_READ     0x1
+#define PROT_WRITE    0x2
+#define PROT_EXEC     0x4
+
+#define MAP_SHARED    0x01
+#define MAP_PRIVATE   0x02
+#define MAP_ANONYMOUS 0x20
+#define MAP_FAILED    ((void *) -1)
+
+// ioctl() on a disk
+#define DIOCGBLOCKS   1  // the number of blocks of the disk, as an int
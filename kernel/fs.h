@@ -20,13 +20,18 @@ struct superblock {
   uint logstart;     // Block number of first log block
   uint inodestart;   // Block number of first inode block
   uint bmapstart;    // Block number of first free map block
+  uint features;     // Optional features, such as FS_LONGNAMES
 };
 
 #define FSMAGIC 0x10203040
 
-#define NDIRECT 12
+// Directories have variable-length entries (struct ldirent).
+#define FS_LONGNAMES 0x1
+
+#define NDIRECT 10
 #define NINDIRECT (BSIZE / sizeof(uint))
-#define MAXFILE (NDIRECT + NINDIRECT)
+#define MAXFILE (NDIRECT + NINDIRECT + NINDIRECT * NINDIRECT + \
+                 NINDIRECT * NINDIRECT * NINDIRECT)
 
 // On-disk inode structure
 struct dinode {
@@ -34,8 +39,15 @@ struct dinode {
   ushort major;         // Major device number (T_DEVICE only)
   ushort minor;         // Minor device number (T_DEVICE only)
   short nlink;          // Number of links to inode in file system
-  uint size;            // Size of file (bytes)
-  uint addrs[NDIRECT+1];   // Data block addresses
+  uint64 size;          // Size of file (bytes)
+  struct timespec atime; // Time of last access
+  struct timespec mtime; // Time of last modification
+  struct timespec ctime; // Time of last status change
+  uint uid;             // Owner's user id
+  uint gid;             // Owner's group id
+  ushort mode;          // Permission bits
+  ushort pad;
+  uint addrs[NDIRECT+3];   // Data block addresses
 };
 
 // Inodes per block.
@@ -58,3 +70,16 @@ struct dirent {
   char name[DIRSIZ];
 };
 
+// If the superblock has FS_LONGNAMES, a directory is instead a file containing
+// a sequence of ldirent structures, each followed by its name without a NUL and
+// padded to a multiple of 4 bytes. An ldirent never crosses a block; its reclen
+// also covers the unused space after it, up to the end of the block.
+#define NAME_MAX 255
+
+struct ldirent {
+  uint inum;         // 0 if unused
+  ushort reclen;     // Length up to the next ldirent
+  uchar namelen;
+  uchar pad;
+};
+
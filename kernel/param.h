@@ -9,5 +9,5 @@
 #define MAXOPBLOCKS  10  // max # of blocks any FS op writes
 #define LOGSIZE      (MAXOPBLOCKS*3)  // max data blocks in on-disk log
 #define NBUF         (MAXOPBLOCKS*3)  // size of disk block cache
-#define FSSIZE       5000  // size of file system in blocks
-#define MAXPATH      128   // maximum file path name
+#define FSSIZE       20000 // size of file system in blocks
+#define MAXPATH      256   // maximum file path name
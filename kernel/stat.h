@@ -1,11 +1,28 @@
 #define T_DIR     1   // Directory
 #define T_FILE    2   // File
 #define T_DEVICE  3   // Device
+#define T_SYMLINK 4   // Symbolic link
 
 struct stat {
   int dev;     // File system's disk device
   uint ino;    // Inode number
   short type;  // Type of file
   short nlink; // Number of links to file
+  ushort mode; // Permission bits
+  uint uid;    // Owner's user id
+  uint gid;    // Owner's group id
   uint64 size; // Size of file in bytes
+  struct timespec atime; // Time of last access
+  struct timespec mtime; // Time of last modification
+  struct timespec ctime; // Time of last status change
+};
+
+// A directory entry of any file system, as getdents() returns.
+// Each record takes reclen bytes, a multiple of 4.
+struct direntry {
+  uint ino;        // Inode number
+  ushort reclen;   // Length of this record
+  uchar type;      // Type of file
+  uchar namelen;   // Length of name
+  char name[];     // NUL-terminated name
 };
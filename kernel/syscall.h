@@ -28,3 +28,37 @@
 #define SYS_lseek 27
 #define SYS_uptime_as_micro 28
 #define SYS_clock  29
+#define SYS_mmap   30
+#define SYS_munmap 31
+#define SYS_mprotect 32
+#define SYS_rt_sigaction 33
+#define SYS_sigprocmask 34
+#define SYS_sigreturn 35
+#define SYS_alarm 36
+#define SYS_clone 37
+#define SYS_join 38
+#define SYS_exit_group 39
+#define SYS_futex 40
+#define SYS_setpriority 41
+#define SYS_getpriority 42
+#define SYS_sched_setaffinity 43
+#define SYS_mount 44
+#define SYS_umount 45
+#define SYS_ioctl 46
+#define SYS_getdents 47
+#define SYS_rename 48
+#define SYS_symlink 49
+#define SYS_readlink 50
+#define SYS_clock_gettime 51
+#define SYS_utimens 52
+#define SYS_setuid 53
+#define SYS_getuid 54
+#define SYS_setgid 55
+#define SYS_getgid 56
+#define SYS_chmod 57
+#define SYS_chown 58
+#define SYS_poll 59
+#define SYS_epoll_create 60
+#define SYS_epoll_ctl 61
+#define SYS_epoll_wait 62
+#define SYS_fcntl 63
@@ -1,4 +1,5 @@
 #include <bits/types/struct_timeval.h>
+#include <bits/types/struct_timespec.h>
 #include <bits/types.h>
 #include <bits/types/sigset_t.h>
 
@@ -92,6 +93,55 @@ enum __itimer_which
 #define	SIGCHLD	20	/* to parent on child stop or exit */
 #define SIGUSR1 30	/* user defined signal 1 */
 
+#define SIG_BLOCK	0	/* Block signals.  */
+#define SIG_UNBLOCK	1	/* Unblock signals.  */
+#define SIG_SETMASK	2	/* Set the set of blocked signals.  */
+
+#define FUTEX_WAIT	0	/* Wait if the futex word has the value.  */
+#define FUTEX_WAKE	1	/* Wake up waiters of the futex.  */
+
+#define PRIO_PROCESS	0	/* WHO is a process ID.  */
+
+struct pollfd {
+	int fd;		/* File descriptor to poll.  */
+	short events;	/* Types of events poller cares about.  */
+	short revents;	/* Types of events that actually occurred.  */
+};
+
+#define POLLIN		0x001	/* There is data to read.  */
+#define POLLPRI		0x002	/* There is urgent data to read.  */
+#define POLLOUT		0x004	/* Writing now will not block.  */
+#define POLLNVAL	0x020	/* Invalid polling request.  */
+
+#define EPOLL_CTL_ADD	1	/* Add a file descriptor to the interface.  */
+#define EPOLL_CTL_DEL	2	/* Remove a file descriptor from the interface.  */
+#define EPOLL_CTL_MOD	3	/* Change file descriptor epoll_event structure.  */
+
+#define EPOLLIN		0x001
+#define EPOLLPRI	0x002
+#define EPOLLOUT	0x004
+
+typedef union epoll_data {
+	void *ptr;
+	int fd;
+	uint u32;
+	uint64 u64;
+} epoll_data_t;
+
+struct epoll_event {
+	uint events;		/* Epoll events.  */
+	epoll_data_t data;	/* User data variable.  */
+};
+
+#ifndef CLOCK_REALTIME
+#define CLOCK_REALTIME	0	/* Wall-clock time.  */
+#endif
+
+#ifndef UTIME_NOW
+#define UTIME_NOW	((1l << 30) - 1l)	/* Set the time to the current time.  */
+#define UTIME_OMIT	((1l << 30) - 2l)	/* Leave the time unchanged.  */
+#endif
+
 typedef void (*sighandler_t)(int);
 #define	SIG_ERR	 ((sighandler_t) -1)	/* Error return.  */
 #define	SIG_DFL	 ((sighandler_t)  0)	/* Default action.  */
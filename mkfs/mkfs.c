@@ -4,6 +4,7 @@
 #include <string.h>
 #include <fcntl.h>
 #include <assert.h>
+#include <time.h>
 
 #define stat xv6_stat  // avoid clash with host struct stat
 #include "kernel/types.h"
@@ -31,6 +32,9 @@ struct superblock sb;
 char zeroes[BSIZE];
 uint freeinode = 1;
 uint freeblock;
+char dirbuf[BSIZE];  // The block of root directory entries being built
+int dirlen;          // Bytes of dirbuf in use
+int dirlast;         // Offset of the last entry in dirbuf
 
 
 void balloc(int);
@@ -40,6 +44,8 @@ void rinode(uint inum, struct dinode *ip);
 void rsect(uint sec, void *buf);
 uint ialloc(ushort type);
 void iappend(uint inum, void *p, int n);
+void dappend(uint inum, char *name);
+void dflush(void);
 
 // convert to intel byte order
 ushort
@@ -68,10 +74,8 @@ int
 main(int argc, char *argv[])
 {
   int i, cc, fd;
-  uint rootino, inum, off;
-  struct dirent de;
+  uint rootino, inum;
   char buf[BSIZE];
-  struct dinode din;
 
 
   static_assert(sizeof(int) == 4, "Integers must be 4 bytes!");
@@ -102,6 +106,7 @@ main(int argc, char *argv[])
   sb.logstart = xint(2);
   sb.inodestart = xint(2+nlog);
   sb.bmapstart = xint(2+nlog+ninodeblocks);
+  sb.features = xint(FS_LONGNAMES);
 
   printf("nmeta %d (boot, super, log blocks %u inode blocks %u, bitmap blocks %u) blocks %d total %d\n",
          nmeta, nlog, ninodeblocks, nbitmap, nblocks, FSSIZE);
@@ -118,15 +123,8 @@ main(int argc, char *argv[])
   rootino = ialloc(T_DIR);
   assert(rootino == ROOTINO);
 
-  bzero(&de, sizeof(de));
-  de.inum = xshort(rootino);
-  strcpy(de.name, ".");
-  iappend(rootino, &de, sizeof(de));
-
-  bzero(&de, sizeof(de));
-  de.inum = xshort(rootino);
-  strcpy(de.name, "..");
-  iappend(rootino, &de, sizeof(de));
+  dappend(rootino, ".");
+  dappend(rootino, "..");
 
   for(i = 2; i < argc; i++){
     // get rid of "user/"
@@ -152,10 +150,7 @@ main(int argc, char *argv[])
 
     inum = ialloc(T_FILE);
 
-    bzero(&de, sizeof(de));
-    de.inum = xshort(inum);
-    strncpy(de.name, shortname, DIRSIZ);
-    iappend(rootino, &de, sizeof(de));
+    dappend(inum, shortname);
 
     while((cc = read(fd, buf, sizeof(buf))) > 0)
       iappend(inum, buf, cc);
@@ -163,12 +158,7 @@ main(int argc, char *argv[])
     close(fd);
   }
 
-  // fix size of root inode dir
-  rinode(rootino, &din);
-  off = xint(din.size);
-  off = ((off/BSIZE) + 1) * BSIZE;
-  din.size = xint(off);
-  winode(rootino, &din);
+  dflush();
 
   balloc(freeblock);
 
@@ -238,6 +228,11 @@ ialloc(ushort type)
   din.type = xshort(type);
   din.nlink = xshort(1);
   din.size = xint(0);
+  clock_gettime(CLOCK_REALTIME, &din.atime);
+  din.mtime = din.atime;
+  din.ctime = din.atime;
+  // Everyone may read and run the initial files.
+  din.mode = xshort(0755);
   winode(inum, &din);
   return inum;
 }
@@ -275,7 +270,8 @@ iappend(uint inum, void *xp, int n)
   // printf("append inum %d at off %d sz %d\n", inum, off, n);
   while(n > 0){
     fbn = off / BSIZE;
-    assert(fbn < MAXFILE);
+    // Only single indirect blocks are needed for the initial files.
+    assert(fbn < NDIRECT + NINDIRECT);
     if(fbn < NDIRECT){
       if(xint(din.addrs[fbn]) == 0){
         din.addrs[fbn] = xint(freeblock++);
@@ -303,3 +299,42 @@ iappend(uint inum, void *xp, int n)
   din.size = xint(off);
   winode(inum, &din);
 }
+
+// Add the entry (inum, name) to the root directory.
+void
+dappend(uint inum, char *name)
+{
+  struct ldirent *de;
+  int namelen, len;
+
+  namelen = strlen(name);
+  assert(namelen <= NAME_MAX);
+  len = (sizeof(*de) + namelen + 3) & ~3;
+  if(dirlen + len > BSIZE)
+    dflush();
+
+  de = (struct ldirent*)(dirbuf + dirlen);
+  de->inum = xint(inum);
+  de->reclen = xshort(len);
+  de->namelen = namelen;
+  de->pad = 0;
+  memmove(de + 1, name, namelen);
+  dirlast = dirlen;
+  dirlen += len;
+}
+
+// Write the root directory entries of dirbuf as a block,
+// whose last entry reaches the end of the block.
+void
+dflush(void)
+{
+  struct ldirent *de;
+
+  if(dirlen == 0)
+    return;
+  de = (struct ldirent*)(dirbuf + dirlast);
+  de->reclen = xshort(BSIZE - dirlast);
+  iappend(ROOTINO, dirbuf, BSIZE);
+  bzero(dirbuf, BSIZE);
+  dirlen = 0;
+}
@@ -4,6 +4,7 @@
 #include <string.h>
 #include <fcntl.h>
 #include <assert.h>
+#include <time.h>
 
 #define stat xv6_stat  // avoid clash with host struct stat
 #include "kernel/types.h"
@@ -16,7 +17,7 @@
 
 // Constants about "our" lfs. (Not to be universal over every lfs.)
 #define SEGSIZE 10  // segment size in blocks
-#define FSSIZE 5000 // size of file system in blocks
+#define FSSIZE 20000 // size of file system in blocks
 #define NINODES 200 // assumes inum : 0 ~ NINODES - 1
 #define NMETA 4
 
@@ -59,6 +60,9 @@ uint imp_block_no[NINODEMAP]; // the block number of each inode map block
 char zeroes[BSIZE];
 uint freeinode = 1;
 uint freeblock;
+char dirbuf[BSIZE];  // The block of root directory entries being built
+int dirlen;          // Bytes of dirbuf in use
+int dirlast;         // Offset of the last entry in dirbuf
 
 
 uint balloc(uint, uint, uint);
@@ -70,6 +74,8 @@ uint ialloc(ushort type);
 void wimap();
 void wchkpt(int chkpt_no);
 void iappend(uint inum, void *p, int n);
+void dappend(uint inum, char *name);
+void dflush(void);
 
 // convert to intel byte order
 ushort
@@ -98,10 +104,8 @@ int
 main(int argc, char *argv[])
 {
   int i, cc, fd;
-  uint rootino, inum, off;
-  struct dirent de;
+  uint rootino, inum;
   char buf[BSIZE];
-  struct dinode din;
 
 
   static_assert(sizeof(int) == 4, "Integers must be 4 bytes!");
@@ -130,6 +134,7 @@ main(int argc, char *argv[])
   sb.checkpoint1 = xint(2);
   sb.checkpoint2 = xint(3);
   sb.segstart = xint(NMETA);
+  sb.features = xint(FS_LONGNAMES);
 
   printf("nmeta %d (boot, super, checkpoint1, checkpoint2) blocks %d total %d\n",
          NMETA, nblocks, FSSIZE);
@@ -148,15 +153,8 @@ main(int argc, char *argv[])
   rootino = ialloc(T_DIR);
   assert(rootino == ROOTINO);
 
-  bzero(&de, sizeof(de));
-  de.inum = xshort(rootino);
-  strcpy(de.name, ".");
-  iappend(rootino, &de, sizeof(de));
-
-  bzero(&de, sizeof(de));
-  de.inum = xshort(rootino);
-  strcpy(de.name, "..");
-  iappend(rootino, &de, sizeof(de));
+  dappend(rootino, ".");
+  dappend(rootino, "..");
 
   for(i = 2; i < argc; i++){
     // get rid of "user/"
@@ -182,10 +180,7 @@ main(int argc, char *argv[])
 
     inum = ialloc(T_FILE);
 
-    bzero(&de, sizeof(de));
-    de.inum = xshort(inum);
-    strncpy(de.name, shortname, DIRSIZ);
-    iappend(rootino, &de, sizeof(de));
+    dappend(inum, shortname);
 
     while((cc = read(fd, buf, sizeof(buf))) > 0)
       iappend(inum, buf, cc);
@@ -193,12 +188,7 @@ main(int argc, char *argv[])
     close(fd);
   }
 
-  // fix size of root inode dir
-  rinode(rootino, &din);
-  off = xint(din.size);
-  off = ((off/BSIZE) + 1) * BSIZE;
-  din.size = xint(off);
-  winode(rootino, &din);
+  dflush();
 
   wimap();
   wchkpt(1);
@@ -299,6 +289,11 @@ ialloc(ushort type)
   din.type = xshort(type);
   din.nlink = xshort(1);
   din.size = xint(0);
+  clock_gettime(CLOCK_REALTIME, &din.atime);
+  din.mtime = din.atime;
+  din.ctime = din.atime;
+  // Everyone may read and run the initial files.
+  din.mode = xshort(0755);
   imp[inum] = balloc(SEGSUM_INODE, inum, 0);
   winode(inum, &din);
   return inum;
@@ -362,7 +357,8 @@ iappend(uint inum, void *xp, int n)
   // printf("append inum %d at off %d sz %d\n", inum, off, n);
   while(n > 0){
     fbn = off / BSIZE;
-    assert(fbn < MAXFILE);
+    // Only single indirect blocks are needed for the initial files.
+    assert(fbn < NDIRECT + NINDIRECT);
     if(fbn < NDIRECT){
       if(xint(din.addrs[fbn]) == 0){
         din.addrs[fbn] = xint(balloc(SEGSUM_DATA, inum, fbn));
@@ -390,3 +386,42 @@ iappend(uint inum, void *xp, int n)
   din.size = xint(off);
   winode(inum, &din);
 }
+
+// Add the entry (inum, name) to the root directory.
+void
+dappend(uint inum, char *name)
+{
+  struct ldirent *de;
+  int namelen, len;
+
+  namelen = strlen(name);
+  assert(namelen <= NAME_MAX);
+  len = (sizeof(*de) + namelen + 3) & ~3;
+  if(dirlen + len > BSIZE)
+    dflush();
+
+  de = (struct ldirent*)(dirbuf + dirlen);
+  de->inum = xint(inum);
+  de->reclen = xshort(len);
+  de->namelen = namelen;
+  de->pad = 0;
+  memmove(de + 1, name, namelen);
+  dirlast = dirlen;
+  dirlen += len;
+}
+
+// Write the root directory entries of dirbuf as a block,
+// whose last entry reaches the end of the block.
+void
+dflush(void)
+{
+  struct ldirent *de;
+
+  if(dirlen == 0)
+    return;
+  de = (struct ldirent*)(dirbuf + dirlast);
+  de->reclen = xshort(BSIZE - dirlast);
+  iappend(ROOTINO, dirbuf, BSIZE);
+  bzero(dirbuf, BSIZE);
+  dirlen = 0;
+}
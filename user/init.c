@@ -20,14 +20,27 @@ main(void)
 {
   // https://github.com/kaist-cp/rv6/commit/d12c1db8d9d7a7e5632e51ae712123d868087fe4
   // Add xstate to immediately run usertests and poweroff.
-  int pid, wpid, xstate;
+  int pid, wpid, xstate, devfs;
 
-  if(open("console", O_RDWR) < 0){
+  // Device files are in /dev, which the kernel fills in.
+  mkdir("/dev");
+  devfs = mount(0, "/dev", "devfs");
+  if(open("/dev/console", O_RDWR) < 0){
     mknod("console", CONSOLE, 0);
     open("console", O_RDWR);
   }
   dup(0);  // stdout
   dup(0);  // stderr
+  if(devfs < 0)
+    printf("init: mount /dev failed\n");
+
+  // Scratch files go to /tmp, which is in memory.
+  mkdir("/tmp");
+  if(mount(0, "/tmp", "tmpfs") < 0)
+    printf("init: mount /tmp failed\n");
+  mkdir("/proc");
+  if(mount(0, "/proc", "procfs") < 0)
+    printf("init: mount /proc failed\n");
 
   for(;;){
     printf("init: starting %s\n", argv[0]);
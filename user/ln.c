@@ -5,8 +5,13 @@
 int
 main(int argc, char *argv[])
 {
+  if(argc == 4 && strcmp(argv[1], "-s") == 0){
+    if(symlink(argv[2], argv[3]) < 0)
+      fprintf(2, "symlink %s %s: failed\n", argv[2], argv[3]);
+    exit(0);
+  }
   if(argc != 3){
-    fprintf(2, "Usage: ln old new\n");
+    fprintf(2, "Usage: ln [-s] old new\n");
     exit(1);
   }
   if(link(argv[1], argv[2]) < 0)
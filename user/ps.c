@@ -0,0 +1,78 @@
+// ps: list the processes, as /proc shows them.
+
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "user/user.h"
+#include "kernel/fcntl.h"
+
+#define NLINE 16
+
+char status[512];
+char *lines[NLINE];
+int nlines;
+
+// Split status into lines.
+void
+split(void)
+{
+  char *p, *q;
+
+  nlines = 0;
+  for(p = status; *p && nlines < NLINE; p = q + 1){
+    lines[nlines++] = p;
+    if((q = strchr(p, '\n')) == 0)
+      break;
+    *q = 0;
+  }
+}
+
+// Return the value of the "key:\tvalue" line of status.
+char*
+field(char *key)
+{
+  int i, n;
+
+  n = strlen(key);
+  for(i = 0; i < nlines; i++)
+    if(memcmp(lines[i], key, n) == 0 && lines[i][n] == ':')
+      return lines[i] + n + 2;
+  return "?";
+}
+
+int
+main(void)
+{
+  int fd, sfd, n, m, off;
+  uint dents[128];
+  struct direntry *de;
+  char path[32];
+
+  if((fd = open("/proc", O_RDONLY)) < 0){
+    fprintf(2, "ps: cannot open /proc\n");
+    exit(1);
+  }
+  printf("PID\tPPID\tSTATE\tSIZE\tNAME\n");
+  while((n = getdents(fd, dents, sizeof(dents))) > 0){
+    for(off = 0; off < n; off += de->reclen){
+      de = (struct direntry*)((char*)dents + off);
+      if(de->name[0] < '0' || de->name[0] > '9')
+        continue;
+      strcpy(path, "/proc/");
+      strcpy(path + strlen(path), de->name);
+      strcpy(path + strlen(path), "/status");
+      // The process may have exited.
+      if((sfd = open(path, O_RDONLY)) < 0)
+        continue;
+      m = read(sfd, status, sizeof(status) - 1);
+      close(sfd);
+      if(m <= 0)
+        continue;
+      status[m] = 0;
+      split();
+      printf("%s\t%s\t%s\t%s\t%s\n",
+             field("Pid"), field("PPid"), field("State"), field("Size"), field("Name"));
+    }
+  }
+  close(fd);
+  exit(0);
+}
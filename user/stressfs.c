@@ -23,6 +23,9 @@ main(int argc, char *argv[])
   printf("stressfs starting\n");
   memset(data, 'a', sizeof(data));
 
+  // Keep the files off the disk if possible.
+  chdir("/tmp");
+
   for(i = 0; i < 4; i++)
     if(fork() > 0)
       break;
@@ -5,6 +5,9 @@
 
 #define MICROSECS_PER_TICK 100000
 
+// Set by the syscall stubs in usys.S when a syscall fails.
+int errno;
+
 char*
 strcpy(char *s, const char *t)
 {
@@ -151,10 +154,16 @@ bcopy(const void *src, void *dest, size_t n)
 
 
 
-// ignore all signal function calls
 void (*signal(int sig, void (*func)(int)))(int)
 {
-  return 0;
+  struct sigaction act, oldact;
+
+  act.sa_handler = func;
+  sigemptyset(&act.sa_mask);
+  act.sa_flags = 0;
+  if(sigaction(sig, &act, &oldact) < 0)
+    return SIG_ERR;
+  return oldact.sa_handler;
 };
 
 int
@@ -286,7 +295,7 @@ int gettimeofday(struct timeval *__restrict__ tp,
 }
 
 int * __errno_location(void){
-  return 0;
+  return &errno;
 }
 
 // void *mmap(void *addr, size_t length, int prot, int flags,
@@ -316,15 +325,11 @@ int posix_select(int nfds, fd_set *restrict readfds,
             fd_set *restrict writefds, fd_set *restrict exceptfds,
             struct timeval* timeout)
 {
-  // doesn't support writefds and exceptfds now.
-  long ticks = (timeout->tv_sec * 1000000 + timeout->tv_usec) / MICROSECS_PER_TICK;
-  if(writefds) {
-    FD_ZERO(writefds);
-  }
-  if(exceptfds) {
-    FD_ZERO(exceptfds);
-  }
-  return select(nfds, readfds, writefds, 0, ticks);
+  // A NULL timeout waits without a limit.
+  long ticks = -1;
+  if(timeout)
+    ticks = (timeout->tv_sec * 1000000 + timeout->tv_usec) / MICROSECS_PER_TICK;
+  return select(nfds, readfds, writefds, exceptfds, ticks);
 }
 
 #define CHAR_BIT 8
@@ -547,13 +552,6 @@ execve(const char *pathname, char *const argv[], char *const envp[])
 //   // return exec((char*)file, arg);
 // }
 
-// nothing to do
-unsigned int
-alarm(unsigned int seconds)
-{
-  return 0;
-}
-
 // nothing to do
 int
 setitimer(int which, const struct itimerval *new_value,
@@ -562,21 +560,38 @@ setitimer(int which, const struct itimerval *new_value,
   return 0;
 }
 
+// handlers return to sigreturn(), which restores the interrupted context.
 int
 sigaction(int signum, const struct sigaction *restrict act,
                      struct sigaction *restrict oldact)
 {
-  return 0;
+  struct sigaction a;
+
+  if(act == 0)
+    return rt_sigaction(signum, 0, oldact);
+  a = *act;
+  a.sa_restorer = (void (*)(void))sigreturn;
+  return rt_sigaction(signum, &a, oldact);
 }
 
 int
 sigemptyset(sigset_t *set)
 {
+  memset(set, 0, sizeof(*set));
+  return 0;
+}
+
+int
+sigaddset(sigset_t *set, int signum)
+{
+  if(signum <= 0 || signum >= 8 * sizeof(set->__val[0]))
+    return -1;
+  set->__val[0] |= 1UL << signum;
   return 0;
 }
 
 char*
-strerror(int errno)
+strerror(int errnum)
 {
   return NULL;  
 }
@@ -588,10 +603,22 @@ execvp(const char * file, char * const argv[])
   // return execlp(file, argv);
 }
 
+// The getpriority syscall returns 20 - nice, so that it is never negative.
+int sys_getpriority(int which, int who);
+
+int
+getpriority(int which, int who)
+{
+  int r = sys_getpriority(which, who);
+  if(r < 0)
+    return r;
+  return 20 - r;
+}
+
 int
 posix_kill(pid_t pid, int sig)
 {
-  return kill(pid);
+  return kill(pid, sig);
 }
 
 int
@@ -11,7 +11,7 @@ int pipe(int*);
 int write(int, const void*, int);
 int read(int, void*, int);
 int close(int);
-int kill(int);
+int kill(int, int);
 int exec(char*, char**);
 int open(const char*, int);
 int mknod(const char*, short, short);
@@ -39,8 +39,42 @@ int uptime_as_micro();
 int gettimeofday(struct timeval *__restrict__ tp,
                 struct timezone *__restrict__ tzp);
 int clock(unsigned long*);
+void* mmap(void *addr, uint64 len, int prot, int flags, int fd, int offset);
+int munmap(void *addr, uint64 len);
+int mprotect(void *addr, uint64 len, int prot);
+int rt_sigaction(int signum, const struct sigaction *act, struct sigaction *oldact);
+int sigprocmask(int how, const sigset_t *set, sigset_t *oldset);
+int sigreturn(void);
+int clone(void (*fn)(void*), void *arg, void *stack);
+int join(int tid, int *status);
+int exit_group(int) __attribute__((noreturn));
+int futex(int *addr, int op, int val, int timeout);
+int setpriority(int which, int who, int prio);
+int getpriority(int which, int who);
+int sched_setaffinity(int pid, uint64 mask);
+int mount(int dev, char *target, char *fstype);
+int umount(char *target);
+int ioctl(int fd, int req, void *arg);
+int getdents(int fd, void *buf, int n);
+int rename(char *old, char *new);
+int symlink(char *target, char *path);
+int readlink(char *path, char *buf, int n);
+int clock_gettime(int clk, struct timespec *tp);
+int utimens(char *path, struct timespec times[2]);
+int setuid(uint uid);
+uint getuid(void);
+int setgid(uint gid);
+uint getgid(void);
+int chmod(char *path, int mode);
+int chown(char *path, int uid, int gid);
+int poll(struct pollfd *fds, int nfds, int timeout);
+int epoll_create(int size);
+int epoll_ctl(int epfd, int op, int fd, struct epoll_event *event);
+int epoll_wait(int epfd, struct epoll_event *events, int maxevents, int timeout);
+int fcntl(int fd, int cmd, int arg);
 
 // ulib.c
+extern int errno;
 int stat(const char*, struct stat*);
 char* strcpy(char*, const char*);
 void *memmove(void*, const void*, int);
@@ -127,6 +161,7 @@ int setitimer(int which, const struct itimerval *new_value,
 int sigaction(int signum, const struct sigaction *restrict act,
                      struct sigaction *restrict oldact);
 int sigemptyset(sigset_t *set);
+int sigaddset(sigset_t *set, int signum);
 
 
 
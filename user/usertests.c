@@ -7,6 +7,7 @@
 #include "kernel/syscall.h"
 #include "kernel/memlayout.h"
 #include "kernel/arch.h"
+#include "kernel/errno.h"
 
 //
 // Tests xv6 system calls.  usertests without arguments runs them all
@@ -587,7 +588,7 @@ writebig(char *s)
     exit(1);
   }
 
-  for(i = 0; i < MAXFILE; i++){
+  for(i = 0; i < NDIRECT + NINDIRECT; i++){
     ((int*)buf)[0] = i;
     if(write(fd, buf, BSIZE) != BSIZE){
       printf("%s: error: write big file failed\n", s, i);
@@ -607,7 +608,7 @@ writebig(char *s)
   for(;;){
     i = read(fd, buf, BSIZE);
     if(i == 0){
-      if(n == MAXFILE - 1){
+      if(n != NDIRECT + NINDIRECT){
         printf("%s: read only %d blocks from big", s, n);
         exit(1);
       }
@@ -806,7 +807,7 @@ killstatus(char *s)
       exit(0);
     }
     sleep(1);
-    kill(pid1);
+    kill(pid1, SIGKILL);
     wait(&xst);
     if(xst != -1) {
        printf("%s: status should be -1\n", s);
@@ -863,9 +864,9 @@ preempt(char *s)
   }
   close(pfds[0]);
   printf("kill... ");
-  kill(pid1);
-  kill(pid2);
-  kill(pid3);
+  kill(pid1, SIGKILL);
+  kill(pid2, SIGKILL);
+  kill(pid3, SIGKILL);
   printf("wait... ");
   wait(0);
   wait(0);
@@ -921,7 +922,7 @@ reparent(char *s)
     } else {
       int pid2 = fork();
       if(pid2 < 0){
-        kill(master_pid);
+        kill(master_pid, SIGKILL);
         exit(1);
       }
       exit(0);
@@ -1397,10 +1398,9 @@ concreate(char *s)
   char file[3];
   int i, pid, n, fd;
   char fa[N];
-  struct {
-    ushort inum;
-    char name[DIRSIZ];
-  } de;
+  uint dents[64];
+  struct direntry *de;
+  int m, off;
 
   file[0] = 'C';
   file[2] = '\0';
@@ -1433,21 +1433,22 @@ concreate(char *s)
   memset(fa, 0, sizeof(fa));
   fd = open(".", 0);
   n = 0;
-  while(read(fd, &de, sizeof(de)) > 0){
-    if(de.inum == 0)
-      continue;
-    if(de.name[0] == 'C' && de.name[2] == '\0'){
-      i = de.name[1] - '0';
-      if(i < 0 || i >= sizeof(fa)){
-        printf("%s: concreate weird file %s\n", s, de.name);
-        exit(1);
-      }
-      if(fa[i]){
-        printf("%s: concreate duplicate file %s\n", s, de.name);
-        exit(1);
+  while((m = getdents(fd, dents, sizeof(dents))) > 0){
+    for(off = 0; off < m; off += de->reclen){
+      de = (struct direntry*)((char*)dents + off);
+      if(de->name[0] == 'C' && de->name[2] == '\0'){
+        i = de->name[1] - '0';
+        if(i < 0 || i >= sizeof(fa)){
+          printf("%s: concreate weird file %s\n", s, de->name);
+          exit(1);
+        }
+        if(fa[i]){
+          printf("%s: concreate duplicate file %s\n", s, de->name);
+          exit(1);
+        }
+        fa[i] = 1;
+        n++;
       }
-      fa[i] = 1;
-      n++;
     }
   }
   close(fd);
@@ -1877,7 +1878,7 @@ fourteen(char *s)
 {
   int fd;
 
-  // DIRSIZ is 14.
+  // Names longer than DIRSIZ (14) are no longer truncated.
 
   if(mkdir("12345678901234") != 0){
     printf("%s: mkdir 12345678901234 failed\n", s);
@@ -1887,34 +1888,31 @@ fourteen(char *s)
     printf("%s: mkdir 12345678901234/123456789012345 failed\n", s);
     exit(1);
   }
-  fd = open("123456789012345/123456789012345/123456789012345", O_CREATE);
+  fd = open("12345678901234/123456789012345/123456789012345", O_CREATE);
   if(fd < 0){
-    printf("%s: create 123456789012345/123456789012345/123456789012345 failed\n", s);
+    printf("%s: create 12345678901234/123456789012345/123456789012345 failed\n", s);
     exit(1);
   }
   close(fd);
   fd = open("12345678901234/12345678901234/12345678901234", 0);
-  if(fd < 0){
-    printf("%s: open 12345678901234/12345678901234/12345678901234 failed\n", s);
+  if(fd >= 0){
+    printf("%s: open 12345678901234/12345678901234/12345678901234 succeeded!\n", s);
     exit(1);
   }
-  close(fd);
 
-  if(mkdir("12345678901234/12345678901234") == 0){
-    printf("%s: mkdir 12345678901234/12345678901234 succeeded!\n", s);
+  if(mkdir("12345678901234/12345678901234") != 0){
+    printf("%s: mkdir 12345678901234/12345678901234 failed\n", s);
     exit(1);
   }
-  if(mkdir("123456789012345/12345678901234") == 0){
+  if(mkdir("12345678901234/123456789012345") == 0){
     printf("%s: mkdir 12345678901234/123456789012345 succeeded!\n", s);
     exit(1);
   }
 
   // clean up
-  unlink("123456789012345/12345678901234");
-  unlink("12345678901234/12345678901234");
-  unlink("12345678901234/12345678901234/12345678901234");
-  unlink("123456789012345/123456789012345/123456789012345");
+  unlink("12345678901234/123456789012345/123456789012345");
   unlink("12345678901234/123456789012345");
+  unlink("12345678901234/12345678901234");
   unlink("12345678901234");
 }
 
@@ -2270,7 +2268,7 @@ sbrkfail(char *s)
   for(i = 0; i < sizeof(pids)/sizeof(pids[0]); i++){
     if(pids[i] == -1)
       continue;
-    kill(pids[i]);
+    kill(pids[i], SIGKILL);
     wait(0);
   }
   if(c == (char*)0xffffffffffffffffL){
@@ -2661,6 +2659,2656 @@ execout(char *s)
   exit(0);
 }
 
+int countfree();
+
+// fork() shares the pages copy-on-write: neither process may see
+// the writes of the other, and the pages of the child are freed
+// when it exits.
+void
+cowtest(char *s)
+{
+  enum { SZ=16*PGSIZE };
+  char *a, *p, c;
+  int fds[2], pid, xstatus, free0, free1;
+
+  a = sbrk(SZ);
+  if(a == (char*)0xffffffffffffffffL){
+    printf("%s: sbrk failed\n", s);
+    exit(1);
+  }
+  for(p = a; p < a + SZ; p += PGSIZE)
+    *p = 'p';
+  if(pipe(fds) != 0){
+    printf("%s: pipe() failed\n", s);
+    exit(1);
+  }
+
+  free0 = countfree();
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    for(p = a; p < a + SZ; p += PGSIZE){
+      if(*p != 'p'){
+        printf("%s: child does not see the memory of the parent\n", s);
+        exit(1);
+      }
+      *p = 'c';
+    }
+    // wait until the parent has written.
+    if(read(fds[0], &c, 1) != 1){
+      printf("%s: read failed\n", s);
+      exit(1);
+    }
+    for(p = a; p < a + SZ; p += PGSIZE){
+      if(*p != 'c'){
+        printf("%s: child sees a write of the parent\n", s);
+        exit(1);
+      }
+    }
+    exit(0);
+  }
+
+  // write the first half only, so that the second half stays shared
+  // until the child exits.
+  for(p = a; p < a + SZ / 2; p += PGSIZE)
+    *p = 'P';
+  if(write(fds[1], "x", 1) != 1){
+    printf("%s: write failed\n", s);
+    exit(1);
+  }
+  wait(&xstatus);
+  if(xstatus != 0)
+    exit(1);
+  for(p = a; p < a + SZ; p += PGSIZE){
+    if(*p != (p < a + SZ / 2 ? 'P' : 'p')){
+      printf("%s: parent sees a write of the child\n", s);
+      exit(1);
+    }
+  }
+
+  free1 = countfree();
+  if(free1 != free0){
+    printf("%s: free pages went from %d to %d\n", s, free0, free1);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+  sbrk(-SZ);
+}
+
+// sbrk() should only reserve memory; pages are allocated
+// and zeroed when they are first touched.
+void
+lazysbrk(char *s)
+{
+  enum { BIG=64*1024*1024 };
+  char *a, *p;
+  int pid, xstatus;
+
+  a = sbrk(BIG);
+  if(a == (char*)0xffffffffffffffffL){
+    printf("%s: sbrk of a big region failed\n", s);
+    exit(1);
+  }
+
+  // touch a few scattered pages only.
+  for(p = a; p < a + BIG; p += BIG / 8){
+    if(*p != 0){
+      printf("%s: lazily allocated page is not zeroed\n", s);
+      exit(1);
+    }
+    *p = 'x';
+  }
+  for(p = a; p < a + BIG; p += BIG / 8){
+    if(*p != 'x'){
+      printf("%s: lost a write to a lazily allocated page\n", s);
+      exit(1);
+    }
+  }
+
+  // the kernel must fault in pages that it writes on behalf of the user.
+  if(pipe((int*)(a + BIG / 2 + PGSIZE)) != 0){
+    printf("%s: pipe() into a lazy page failed\n", s);
+    exit(1);
+  }
+  close(((int*)(a + BIG / 2 + PGSIZE))[0]);
+  close(((int*)(a + BIG / 2 + PGSIZE))[1]);
+
+  // touching beyond the break must still kill the process.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    p = sbrk(0);
+    *(p + PGSIZE) = 'x';
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != -1){
+    printf("%s: touching beyond the break did not kill the process\n", s);
+    exit(1);
+  }
+
+  sbrk(-BIG);
+}
+
+// mmap() a file and anonymous memory, and check munmap() and mprotect().
+void
+mmaptest(char *s)
+{
+  enum { SZ=2*PGSIZE+100 };
+  char *f = "mmaptest.tmp";
+  char buf[32], *a, *b;
+  int fd, i, pid, xstatus;
+
+  fd = open(f, O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: open %s failed\n", s, f);
+    exit(1);
+  }
+  for(i = 0; i < SZ; i++){
+    buf[0] = 'a' + i % 26;
+    if(write(fd, buf, 1) != 1){
+      printf("%s: write %s failed\n", s, f);
+      exit(1);
+    }
+  }
+
+  a = mmap(0, SZ, PROT_READ|PROT_WRITE, MAP_PRIVATE, fd, 0);
+  if(a == MAP_FAILED){
+    printf("%s: mmap of a file failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < SZ; i++){
+    if(a[i] != 'a' + i % 26){
+      printf("%s: wrong content at %d\n", s, i);
+      exit(1);
+    }
+  }
+  // the rest of the last page is zeroed.
+  if(a[SZ] != 0){
+    printf("%s: page beyond the end of file is not zeroed\n", s);
+    exit(1);
+  }
+  // private writes do not reach the file.
+  a[0] = 'Z';
+  if(lseek(fd, 0, 0) != 0 || read(fd, buf, 1) != 1 || buf[0] != 'a'){
+    printf("%s: a private write reached the file\n", s);
+    exit(1);
+  }
+
+  // the kernel must read a mapped page that it accesses on behalf of the user.
+  b = mmap(0, PGSIZE, PROT_READ, MAP_PRIVATE, fd, PGSIZE);
+  if(b == MAP_FAILED){
+    printf("%s: mmap with an offset failed\n", s);
+    exit(1);
+  }
+  if(write(fd, b, 10) != 10){
+    printf("%s: write from a mapped page failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(b[0] != 'a' + PGSIZE % 26){
+    printf("%s: mmap with an offset has wrong content\n", s);
+    exit(1);
+  }
+
+  // a child sees and copies the mapping.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(a[0] != 'Z')
+      exit(1);
+    a[1] = 'Y';
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0 || a[1] != 'b'){
+    printf("%s: fork did not copy the mapping\n", s);
+    exit(1);
+  }
+
+  if(munmap(a, SZ) != 0 || munmap(b, PGSIZE) != 0){
+    printf("%s: munmap failed\n", s);
+    exit(1);
+  }
+  unlink(f);
+
+  // anonymous memory is zeroed, and becomes read-only by mprotect().
+  a = mmap(0, 2*PGSIZE, PROT_READ|PROT_WRITE, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0);
+  if(a == MAP_FAILED){
+    printf("%s: anonymous mmap failed\n", s);
+    exit(1);
+  }
+  if(a[0] != 0 || a[PGSIZE] != 0){
+    printf("%s: anonymous memory is not zeroed\n", s);
+    exit(1);
+  }
+  a[0] = 'x';
+  if(mprotect(a, PGSIZE, PROT_READ) != 0){
+    printf("%s: mprotect failed\n", s);
+    exit(1);
+  }
+  a[PGSIZE] = 'y';
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    a[0] = 'z';
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != -1 || a[0] != 'x'){
+    printf("%s: writing a read-only page did not kill the process\n", s);
+    exit(1);
+  }
+
+  // an unmapped page cannot be accessed anymore.
+  if(munmap(a, PGSIZE) != 0){
+    printf("%s: partial munmap failed\n", s);
+    exit(1);
+  }
+  if(a[PGSIZE] != 'y'){
+    printf("%s: partial munmap lost the rest\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    exit(a[0]);
+  }
+  wait(&xstatus);
+  if(xstatus != -1){
+    printf("%s: reading an unmapped page did not kill the process\n", s);
+    exit(1);
+  }
+  munmap(a + PGSIZE, PGSIZE);
+}
+
+volatile int signaltest_caught;
+
+void
+signaltest_handler(int sig)
+{
+  signaltest_caught = sig;
+}
+
+// deliver signals to user handlers, and check the default actions.
+void
+signaltest(char *s)
+{
+  sigset_t set;
+  int pid, xstatus;
+
+  signaltest_caught = 0;
+  if(signal(SIGTERM, signaltest_handler) == SIG_ERR){
+    printf("%s: signal failed\n", s);
+    exit(1);
+  }
+  if(kill(getpid(), SIGTERM) != 0 || signaltest_caught != SIGTERM){
+    printf("%s: SIGTERM was not caught\n", s);
+    exit(1);
+  }
+
+  // a blocked signal is delivered once it is unblocked.
+  signaltest_caught = 0;
+  sigemptyset(&set);
+  sigaddset(&set, SIGTERM);
+  sigprocmask(SIG_BLOCK, &set, 0);
+  kill(getpid(), SIGTERM);
+  if(signaltest_caught != 0){
+    printf("%s: a blocked signal was delivered\n", s);
+    exit(1);
+  }
+  sigprocmask(SIG_UNBLOCK, &set, 0);
+  if(signaltest_caught != SIGTERM){
+    printf("%s: an unblocked signal was not delivered\n", s);
+    exit(1);
+  }
+
+  // a parent is notified of the exit of its child.
+  signaltest_caught = 0;
+  signal(SIGCHLD, signaltest_handler);
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0)
+    exit(0);
+  wait(0);
+  if(signaltest_caught != SIGCHLD){
+    printf("%s: SIGCHLD was not caught\n", s);
+    exit(1);
+  }
+  signal(SIGCHLD, SIG_DFL);
+
+  // an alarm interrupts sleep().
+  signaltest_caught = 0;
+  signal(SIGALRM, signaltest_handler);
+  alarm(1);
+  while(signaltest_caught == 0)
+    sleep(100);
+  if(signaltest_caught != SIGALRM){
+    printf("%s: SIGALRM was not caught\n", s);
+    exit(1);
+  }
+
+  // SIGTERM terminates a process by default.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    signal(SIGTERM, SIG_DFL);
+    for(;;)
+      sleep(100);
+  }
+  sleep(1);
+  kill(pid, SIGTERM);
+  wait(&xstatus);
+  if(xstatus != -1){
+    printf("%s: SIGTERM did not terminate the process\n", s);
+    exit(1);
+  }
+  signal(SIGTERM, SIG_DFL);
+}
+
+#define NTHREADTEST 8
+#define THREADTEST_ROUNDS 10000
+
+volatile int threadtest_counter;
+int threadtest_fds[2];
+
+void
+threadtest_worker(void *arg)
+{
+  int i = (int)(uint64)arg;
+  char c = 'a' + i;
+
+  for(int j = 0; j < THREADTEST_ROUNDS; j++)
+    __sync_fetch_and_add(&threadtest_counter, 1);
+  if(write(threadtest_fds[1], &c, 1) != 1)
+    exit_group(1);
+  exit(i);
+}
+
+void
+threadtest_spinner(void *arg)
+{
+  for(;;)
+    ;
+}
+
+void
+threadtest_exiter(void *arg)
+{
+  sleep(1);
+  exit_group((int)(uint64)arg);
+}
+
+// threads share the memory and the open files of their process.
+// run with CPUS=8 to stress the threads on every cpu.
+void
+threadtest(char *s)
+{
+  int tids[NTHREADTEST], seen[NTHREADTEST];
+  int i, pid, tid, xstatus;
+  char *stacks, buf[NTHREADTEST];
+
+  if(pipe(threadtest_fds) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  stacks = sbrk(NTHREADTEST * 4096);
+  if(stacks == (char*)-1){
+    printf("%s: sbrk failed\n", s);
+    exit(1);
+  }
+
+  threadtest_counter = 0;
+  for(i = 0; i < NTHREADTEST; i++){
+    tids[i] = clone(threadtest_worker, (void*)(uint64)i, stacks + (i + 1) * 4096);
+    if(tids[i] < 0){
+      printf("%s: clone failed\n", s);
+      exit(1);
+    }
+  }
+  for(i = 0; i < NTHREADTEST; i++){
+    if(join(tids[i], &xstatus) != tids[i] || xstatus != i){
+      printf("%s: join failed\n", s);
+      exit(1);
+    }
+  }
+  if(join(0, 0) != -1){
+    printf("%s: join succeeded without threads\n", s);
+    exit(1);
+  }
+  if(threadtest_counter != NTHREADTEST * THREADTEST_ROUNDS){
+    printf("%s: lost updates to shared memory\n", s);
+    exit(1);
+  }
+
+  // every thread wrote to the shared pipe.
+  if(read(threadtest_fds[0], buf, NTHREADTEST) != NTHREADTEST){
+    printf("%s: read failed\n", s);
+    exit(1);
+  }
+  memset(seen, 0, sizeof(seen));
+  for(i = 0; i < NTHREADTEST; i++)
+    seen[buf[i] - 'a']++;
+  for(i = 0; i < NTHREADTEST; i++){
+    if(seen[i] != 1){
+      printf("%s: thread %d did not write to the pipe\n", s, i);
+      exit(1);
+    }
+  }
+  close(threadtest_fds[0]);
+  close(threadtest_fds[1]);
+
+  // exit_group in a thread terminates the whole process.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    for(i = 0; i < NTHREADTEST - 1; i++)
+      clone(threadtest_spinner, 0, stacks + (i + 1) * 4096);
+    tid = clone(threadtest_exiter, (void*)7, stacks + NTHREADTEST * 4096);
+    if(tid < 0)
+      exit(1);
+    for(;;)
+      sleep(100);
+  }
+  wait(&xstatus);
+  if(xstatus != 7){
+    printf("%s: exit_group did not terminate the process\n", s);
+    exit(1);
+  }
+
+  // exit in the main thread terminates the other threads.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    for(i = 0; i < NTHREADTEST; i++)
+      clone(threadtest_spinner, 0, stacks + (i + 1) * 4096);
+    exit(3);
+  }
+  wait(&xstatus);
+  if(xstatus != 3){
+    printf("%s: exit of the main thread failed\n", s);
+    exit(1);
+  }
+}
+
+#define FUTEXTEST_ROUNDS 1000
+
+int futextest_mutex;
+int futextest_counter;
+
+void
+futextest_lock(int *m)
+{
+  while(__sync_lock_test_and_set(m, 1))
+    futex(m, FUTEX_WAIT, 1, 0);
+}
+
+void
+futextest_unlock(int *m)
+{
+  __sync_lock_release(m);
+  futex(m, FUTEX_WAKE, 1, 0);
+}
+
+void
+futextest_worker(void *arg)
+{
+  for(int i = 0; i < FUTEXTEST_ROUNDS; i++){
+    futextest_lock(&futextest_mutex);
+    // a non-atomic update, which the mutex protects.
+    futextest_counter = futextest_counter + 1;
+    if(i % 100 == 0)
+      sleep(0);
+    futextest_unlock(&futextest_mutex);
+  }
+  exit(0);
+}
+
+// a mutex built on futexes.
+void
+futextest(char *s)
+{
+  int tids[NTHREADTEST];
+  int i, word, start;
+  char *stacks;
+
+  // waiting fails at once if the word has changed.
+  word = 0;
+  if(futex(&word, FUTEX_WAIT, 1, 0) != -1){
+    printf("%s: waited on a changed word\n", s);
+    exit(1);
+  }
+  if(futex(&word, FUTEX_WAKE, 1, 0) != 0){
+    printf("%s: woke up a process without waiters\n", s);
+    exit(1);
+  }
+
+  // waiting fails when the timeout expires.
+  start = uptime();
+  if(futex(&word, FUTEX_WAIT, 0, 2) != -1 || uptime() - start < 2){
+    printf("%s: timeout did not expire\n", s);
+    exit(1);
+  }
+
+  stacks = sbrk(NTHREADTEST * 4096);
+  if(stacks == (char*)-1){
+    printf("%s: sbrk failed\n", s);
+    exit(1);
+  }
+  futextest_mutex = 0;
+  futextest_counter = 0;
+  for(i = 0; i < NTHREADTEST; i++){
+    tids[i] = clone(futextest_worker, 0, stacks + (i + 1) * 4096);
+    if(tids[i] < 0){
+      printf("%s: clone failed\n", s);
+      exit(1);
+    }
+  }
+  for(i = 0; i < NTHREADTEST; i++){
+    if(join(tids[i], 0) != tids[i]){
+      printf("%s: join failed\n", s);
+      exit(1);
+    }
+  }
+  if(futextest_counter != NTHREADTEST * FUTEXTEST_ROUNDS){
+    printf("%s: mutex did not exclude the threads\n", s);
+    exit(1);
+  }
+}
+
+// nice values, which lower the priority of cpu-bound processes.
+void
+nicetest(char *s)
+{
+  int pid, pids[2], xstatus, i, start;
+  int ping[2], pong[2];
+  char c;
+
+  if(getpriority(PRIO_PROCESS, 0) != 0){
+    printf("%s: default nice value is not 0\n", s);
+    exit(1);
+  }
+  if(setpriority(PRIO_PROCESS, 0, 5) != 0 || getpriority(PRIO_PROCESS, getpid()) != 5){
+    printf("%s: setpriority failed\n", s);
+    exit(1);
+  }
+  if(setpriority(PRIO_PROCESS, 0, 100) != 0 || getpriority(PRIO_PROCESS, 0) != 19){
+    printf("%s: nice value was not clamped\n", s);
+    exit(1);
+  }
+  if(setpriority(PRIO_PROCESS + 1, 0, 0) != -1 || setpriority(PRIO_PROCESS, 1000000, 0) != -1){
+    printf("%s: setpriority succeeded on a bad target\n", s);
+    exit(1);
+  }
+
+  // a child inherits the nice value.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0)
+    exit(getpriority(PRIO_PROCESS, 0));
+  wait(&xstatus);
+  if(xstatus != 19){
+    printf("%s: nice value was not inherited\n", s);
+    exit(1);
+  }
+  setpriority(PRIO_PROCESS, 0, 0);
+
+  // an interactive process still runs soon while cpu-bound processes
+  // with a high nice value spin.
+  for(i = 0; i < 2; i++){
+    pids[i] = fork();
+    if(pids[i] < 0){
+      printf("%s: fork failed\n", s);
+      exit(1);
+    }
+    if(pids[i] == 0){
+      setpriority(PRIO_PROCESS, 0, 19);
+      for(;;)
+        ;
+    }
+  }
+  if(pipe(ping) < 0 || pipe(pong) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    for(i = 0; i < 10; i++){
+      if(read(ping[0], &c, 1) != 1 || write(pong[1], &c, 1) != 1)
+        exit(1);
+      sleep(1);
+    }
+    exit(0);
+  }
+  start = uptime();
+  for(i = 0; i < 10; i++){
+    c = i;
+    if(write(ping[1], &c, 1) != 1 || read(pong[0], &c, 1) != 1){
+      printf("%s: pipe failed\n", s);
+      exit(1);
+    }
+    sleep(1);
+  }
+  wait(&xstatus);
+  if(xstatus != 0 || uptime() - start > 100){
+    printf("%s: interactive process was starved\n", s);
+    exit(1);
+  }
+  for(i = 0; i < 2; i++){
+    kill(pids[i], SIGKILL);
+    wait(0);
+  }
+  close(ping[0]);
+  close(ping[1]);
+  close(pong[0]);
+  close(pong[1]);
+}
+
+#define PREEMPTTEST_SZ (16 * 1024)
+// every process is boosted to its highest level once per this many ticks.
+#define PREEMPTTEST_BOUND 50
+
+// a process keeps running while another process on the same cpu spends long
+// in the kernel writing a big file.
+void
+preempttest(char *s)
+{
+  volatile int *shared;
+  char *buf;
+  int fd, pid, xstatus, t0, start, last, now;
+
+  buf = sbrk(PREEMPTTEST_SZ);
+  shared = mmap(0, PGSIZE, PROT_READ|PROT_WRITE, MAP_SHARED|MAP_ANONYMOUS, -1, 0);
+  if(buf == (char*)-1 || shared == MAP_FAILED){
+    printf("%s: allocation failed\n", s);
+    exit(1);
+  }
+  if(sched_setaffinity(0, 1) != 0){
+    printf("%s: sched_setaffinity failed\n", s);
+    exit(1);
+  }
+
+  // the child counts its rounds in shared[0], and records the longest time
+  // it waited for the cpu in shared[1].
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    last = uptime();
+    for(;;){
+      now = uptime();
+      if(now - last > shared[1])
+        shared[1] = now - last;
+      last = now;
+      shared[0]++;
+    }
+  }
+  while(shared[0] == 0)
+    ;
+
+  fd = open("preempt.tmp", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: open failed\n", s);
+    exit(1);
+  }
+  start = shared[0];
+  t0 = uptime();
+  while(uptime() - t0 < 20){
+    if(lseek(fd, 0, 0) != 0 || write(fd, buf, PREEMPTTEST_SZ) != PREEMPTTEST_SZ){
+      printf("%s: write failed\n", s);
+      exit(1);
+    }
+  }
+  if(shared[0] == start){
+    printf("%s: writes starved a process on the same cpu\n", s);
+    exit(1);
+  }
+  if(shared[1] > PREEMPTTEST_BOUND){
+    printf("%s: a process waited %d ticks for the cpu\n", s, shared[1]);
+    exit(1);
+  }
+
+  kill(pid, SIGKILL);
+  wait(&xstatus);
+  close(fd);
+  unlink("preempt.tmp");
+  munmap((void*)shared, PGSIZE);
+}
+
+// spin for a while, so that the scheduler has to move processes around.
+static void
+spin(void)
+{
+  volatile int i;
+
+  for(i = 0; i < 10000000; i++)
+    ;
+}
+
+void
+affinitytest(char *s)
+{
+  int pids[4], xstatus, i;
+
+  if(sched_setaffinity(0, 0) != -1 || sched_setaffinity(0, (uint64)1 << 40) != -1){
+    printf("%s: sched_setaffinity accepted an empty mask\n", s);
+    exit(1);
+  }
+  if(sched_setaffinity(1000000, 1) != -1){
+    printf("%s: sched_setaffinity succeeded on a bad target\n", s);
+    exit(1);
+  }
+
+  // processes spread over the cpus finish.
+  for(i = 0; i < 4; i++){
+    pids[i] = fork();
+    if(pids[i] < 0){
+      printf("%s: fork failed\n", s);
+      exit(1);
+    }
+    if(pids[i] == 0){
+      spin();
+      exit(0);
+    }
+  }
+  for(i = 0; i < 4; i++){
+    wait(&xstatus);
+    if(xstatus != 0){
+      printf("%s: child failed\n", s);
+      exit(1);
+    }
+  }
+
+  // processes pinned to cpu 0, and children that inherit the pinning, finish.
+  pids[0] = fork();
+  if(pids[0] < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pids[0] == 0){
+    if(sched_setaffinity(0, 1) != 0)
+      exit(1);
+    for(i = 0; i < 3; i++){
+      pids[i] = fork();
+      if(pids[i] < 0)
+        exit(1);
+      if(pids[i] == 0){
+        spin();
+        exit(0);
+      }
+    }
+    spin();
+    for(i = 0; i < 3; i++){
+      wait(&xstatus);
+      if(xstatus != 0)
+        exit(1);
+    }
+    exit(0);
+  }
+  // move the pinned process to cpu 1 and back while it runs.
+  sched_setaffinity(pids[0], 2);
+  sched_setaffinity(pids[0], 1);
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: pinned processes failed\n", s);
+    exit(1);
+  }
+}
+
+// a failed system call returns -1 and tells why in errno.
+void
+errnotest(char *s)
+{
+  char buf[1];
+  int fd, fds[2];
+
+  errno = 0;
+  if(open("errnotest.missing", 0) != -1 || errno != ENOENT){
+    printf("%s: open of a missing file gave errno %d\n", s, errno);
+    exit(1);
+  }
+  if(mkdir("errnotest.dir") != 0){
+    printf("%s: mkdir failed\n", s);
+    exit(1);
+  }
+  if(mkdir("errnotest.dir") != -1 || errno != EEXIST){
+    printf("%s: mkdir of an existing dir gave errno %d\n", s, errno);
+    exit(1);
+  }
+  if(open("errnotest.dir", O_RDWR) != -1 || errno != EISDIR){
+    printf("%s: open of a dir for writing gave errno %d\n", s, errno);
+    exit(1);
+  }
+  fd = open("errnotest.dir/file", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(unlink("errnotest.dir") != -1 || errno != ENOTEMPTY){
+    printf("%s: unlink of a non-empty dir gave errno %d\n", s, errno);
+    exit(1);
+  }
+  if(chdir("errnotest.dir/file") != -1 || errno != ENOTDIR){
+    printf("%s: chdir to a file gave errno %d\n", s, errno);
+    exit(1);
+  }
+  unlink("errnotest.dir/file");
+  unlink("errnotest.dir");
+
+  if(read(-1, buf, 1) != -1 || errno != EBADF){
+    printf("%s: read of a bad fd gave errno %d\n", s, errno);
+    exit(1);
+  }
+  if(close(NOFILE) != -1 || errno != EBADF){
+    printf("%s: close of a bad fd gave errno %d\n", s, errno);
+    exit(1);
+  }
+  if(pipe(fds) != 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  if(write(fds[1], (char*)0xffffffffffULL, 1) != -1 || errno != EFAULT){
+    printf("%s: write from a bad address gave errno %d\n", s, errno);
+    exit(1);
+  }
+  close(fds[0]);
+  if(write(fds[1], buf, 1) != -1 || errno != EPIPE){
+    printf("%s: write to a closed pipe gave errno %d\n", s, errno);
+    exit(1);
+  }
+  close(fds[1]);
+  if(kill(1000000, SIGKILL) != -1 || errno != ESRCH){
+    printf("%s: kill of a missing process gave errno %d\n", s, errno);
+    exit(1);
+  }
+  if(wait(0) != -1 || errno != ECHILD){
+    printf("%s: wait without children gave errno %d\n", s, errno);
+    exit(1);
+  }
+}
+
+// mount the file system on the second disk, which is not the type of
+// the root file system, and check that it is a separate tree.
+void
+mounttest(char *s)
+{
+  char *fstype;
+  int fd;
+
+  if(mkdir("mountdir") != 0){
+    printf("%s: mkdir failed\n", s);
+    exit(1);
+  }
+  if(mount(2, "mountdir", "nofs") != -1 || errno != ENODEV){
+    printf("%s: mount of an unknown fs gave errno %d\n", s, errno);
+    exit(1);
+  }
+  if(mount(9, "mountdir", "ufs") != -1 || errno != ENXIO){
+    printf("%s: mount of a missing disk gave errno %d\n", s, errno);
+    exit(1);
+  }
+  fstype = "lfs";
+  if(mount(2, "mountdir", fstype) != 0){
+    fstype = "ufs";
+    if(mount(2, "mountdir", fstype) != 0){
+      printf("%s: mount failed with errno %d\n", s, errno);
+      exit(1);
+    }
+  }
+  if(mount(2, "mountdir", fstype) != -1 || errno != EBUSY){
+    printf("%s: second mount gave errno %d\n", s, errno);
+    exit(1);
+  }
+
+  fd = open("mountdir/README", 0);
+  if(fd < 0){
+    printf("%s: README of the mounted fs is missing\n", s);
+    exit(1);
+  }
+  close(fd);
+  fd = open("mountdir/file", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create failed\n", s);
+    exit(1);
+  }
+  if(write(fd, "x", 1) != 1){
+    printf("%s: write failed\n", s);
+    exit(1);
+  }
+  if(umount("mountdir") != -1 || errno != EBUSY){
+    printf("%s: umount with an open file gave errno %d\n", s, errno);
+    exit(1);
+  }
+  close(fd);
+  if(link("mountdir/file", "mountlink") != -1 || errno != EXDEV){
+    printf("%s: link across file systems gave errno %d\n", s, errno);
+    exit(1);
+  }
+  if(unlink("mountdir/file") != 0){
+    printf("%s: unlink failed\n", s);
+    exit(1);
+  }
+  if(chdir("mountdir") != 0){
+    printf("%s: chdir failed\n", s);
+    exit(1);
+  }
+  if(umount("/mountdir") != -1 || errno != EBUSY){
+    printf("%s: umount of the cwd gave errno %d\n", s, errno);
+    exit(1);
+  }
+  fd = open("../mountdir/README", 0);
+  if(fd < 0){
+    printf("%s: .. of the mounted root is wrong\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(chdir("..") != 0){
+    printf("%s: chdir .. failed\n", s);
+    exit(1);
+  }
+  if(unlink("mountdir") != -1 || errno != EBUSY){
+    printf("%s: unlink of a mount point gave errno %d\n", s, errno);
+    exit(1);
+  }
+  if(umount("mountdir") != 0){
+    printf("%s: umount failed with errno %d\n", s, errno);
+    exit(1);
+  }
+  if(umount("mountdir") != -1 || errno != EINVAL){
+    printf("%s: second umount gave errno %d\n", s, errno);
+    exit(1);
+  }
+  if(open("mountdir/README", 0) >= 0){
+    printf("%s: README is still there after umount\n", s);
+    exit(1);
+  }
+  if(unlink("mountdir") != 0){
+    printf("%s: unlink failed\n", s);
+    exit(1);
+  }
+}
+
+// fill the ufs disk, which is the root or the second disk, and check that
+// writes and creates fail with ENOSPC instead of crashing.
+void
+diskfull(char *s)
+{
+  static char buf[4096];
+  int fd, r, mounted;
+
+  if(mkdir("diskfulldir") != 0){
+    printf("%s: mkdir failed\n", s);
+    exit(1);
+  }
+  mounted = mount(2, "diskfulldir", "ufs") == 0;
+  if(chdir("diskfulldir") != 0){
+    printf("%s: chdir failed\n", s);
+    exit(1);
+  }
+
+  fd = open("big", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create failed\n", s);
+    exit(1);
+  }
+  while((r = write(fd, buf, sizeof(buf))) == sizeof(buf))
+    ;
+  // a short write returns the bytes that fit, and the next write fails.
+  if(r >= 0)
+    r = write(fd, buf, sizeof(buf));
+  if(r != -1 || errno != ENOSPC){
+    printf("%s: write to a full disk returned %d with errno %d\n", s, r, errno);
+    exit(1);
+  }
+  close(fd);
+  if(mkdir("dir") != -1 || errno != ENOSPC){
+    printf("%s: mkdir on a full disk gave errno %d\n", s, errno);
+    exit(1);
+  }
+  if(open("dir", 0) >= 0){
+    printf("%s: a failed mkdir left its directory\n", s);
+    exit(1);
+  }
+
+  // freeing the blocks makes room again.
+  if(unlink("big") != 0){
+    printf("%s: unlink failed\n", s);
+    exit(1);
+  }
+  if(mkdir("dir") != 0 || unlink("dir") != 0){
+    printf("%s: mkdir after freeing the disk failed\n", s);
+    exit(1);
+  }
+  fd = open("big", O_CREATE|O_RDWR);
+  if(fd < 0 || write(fd, buf, sizeof(buf)) != sizeof(buf)){
+    printf("%s: write after freeing the disk failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("big");
+
+  if(chdir("..") != 0 || (mounted && umount("diskfulldir") != 0) || unlink("diskfulldir") != 0){
+    printf("%s: cleanup failed\n", s);
+    exit(1);
+  }
+}
+
+// files in /tmp live in memory, on a file system of their own.
+void
+tmpfstest(char *s)
+{
+  struct stat st, rootst;
+  char buf[512];
+  int fd, i, n, total;
+
+  if(stat("/", &rootst) < 0 || stat("/tmp", &st) < 0){
+    printf("%s: stat failed\n", s);
+    exit(1);
+  }
+  if(st.dev == rootst.dev){
+    printf("%s: /tmp is not mounted\n", s);
+    exit(1);
+  }
+
+  fd = open("/tmp/tmpfstest", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < 20; i++){
+    memset(buf, i, sizeof(buf));
+    if(write(fd, buf, sizeof(buf)) != sizeof(buf)){
+      printf("%s: write failed\n", s);
+      exit(1);
+    }
+  }
+  close(fd);
+  fd = open("/tmp/tmpfstest", O_RDONLY);
+  for(i = 0; i < 20; i++){
+    if(read(fd, buf, sizeof(buf)) != sizeof(buf)){
+      printf("%s: read failed\n", s);
+      exit(1);
+    }
+    if(buf[0] != i || buf[sizeof(buf)-1] != i){
+      printf("%s: wrong data\n", s);
+      exit(1);
+    }
+  }
+  if(read(fd, buf, sizeof(buf)) != 0){
+    printf("%s: read past the end\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  // a file cannot grow without limit.
+  fd = open("/tmp/tmpfstest", O_RDWR|O_TRUNC);
+  total = 0;
+  while((n = write(fd, buf, sizeof(buf))) > 0)
+    total += n;
+  if(errno != EFBIG || total == 0){
+    printf("%s: filling a file gave errno %d after %d bytes\n", s, errno, total);
+    exit(1);
+  }
+  close(fd);
+
+  if(link("/tmp/tmpfstest", "tmpfstest.link") != -1 || errno != EXDEV){
+    printf("%s: link out of /tmp gave errno %d\n", s, errno);
+    exit(1);
+  }
+  if(mkdir("/tmp/tmpfstest.dir") != 0 || chdir("/tmp/tmpfstest.dir") != 0){
+    printf("%s: mkdir failed\n", s);
+    exit(1);
+  }
+  if(chdir("../..") != 0 || stat(".", &st) < 0 || st.dev != rootst.dev || st.ino != rootst.ino){
+    printf("%s: .. of /tmp is not /\n", s);
+    exit(1);
+  }
+  if(unlink("/tmp/tmpfstest.dir") != 0 || unlink("/tmp/tmpfstest") != 0){
+    printf("%s: unlink failed\n", s);
+    exit(1);
+  }
+  if(open("/tmp/tmpfstest", O_RDONLY) >= 0){
+    printf("%s: file is still there after unlink\n", s);
+    exit(1);
+  }
+}
+
+// write "/proc/<pid>" or "/proc/<pid>/<name>" to buf.
+void
+procpath(char *buf, int pid, char *name)
+{
+  char digits[16];
+  int n = 0;
+
+  do {
+    digits[n++] = '0' + pid % 10;
+    pid /= 10;
+  } while(pid > 0);
+  strcpy(buf, "/proc/");
+  buf += strlen(buf);
+  while(n > 0)
+    *buf++ = digits[--n];
+  *buf = 0;
+  if(*name){
+    *buf++ = '/';
+    strcpy(buf, name);
+  }
+}
+
+void
+procfstest(char *s)
+{
+  struct stat st, rootst;
+  char path[32], buf[512];
+  int fd, n, pid, fds[2];
+
+  if(stat("/", &rootst) < 0 || stat("/proc", &st) < 0){
+    printf("%s: stat failed\n", s);
+    exit(1);
+  }
+  if(st.dev == rootst.dev || st.type != T_DIR){
+    printf("%s: /proc is not mounted\n", s);
+    exit(1);
+  }
+
+  fd = open("/proc/meminfo", O_RDONLY);
+  if(fd < 0 || (n = read(fd, buf, sizeof(buf) - 1)) <= 0){
+    printf("%s: cannot read /proc/meminfo\n", s);
+    exit(1);
+  }
+  close(fd);
+  buf[n] = 0;
+  if(memcmp(buf, "PageSize:\t4096\n", 15) != 0){
+    printf("%s: wrong meminfo %s\n", s, buf);
+    exit(1);
+  }
+
+  procpath(path, getpid(), "status");
+  fd = open(path, O_RDONLY);
+  if(fd < 0 || (n = read(fd, buf, sizeof(buf) - 1)) <= 0){
+    printf("%s: cannot read %s\n", s, path);
+    exit(1);
+  }
+  buf[n] = 0;
+  if(memcmp(buf, "Name:\tusertests\n", 16) != 0){
+    printf("%s: wrong status %s\n", s, buf);
+    exit(1);
+  }
+  // the file is generated again from the start.
+  if(read(fd, buf, sizeof(buf)) != 0){
+    printf("%s: read past the end of status\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  // nothing can be changed.
+  fd = open("/proc/meminfo", O_WRONLY);
+  if(fd < 0){
+    printf("%s: cannot open /proc/meminfo for writing\n", s);
+    exit(1);
+  }
+  if(write(fd, "x", 1) != -1 || errno != EACCES){
+    printf("%s: write to /proc/meminfo gave errno %d\n", s, errno);
+    exit(1);
+  }
+  close(fd);
+  if(open("/proc/procfstest", O_CREATE|O_RDWR) != -1 || errno != EACCES){
+    printf("%s: create in /proc gave errno %d\n", s, errno);
+    exit(1);
+  }
+  if(unlink("/proc/meminfo") != -1 || errno != EACCES){
+    printf("%s: unlink in /proc gave errno %d\n", s, errno);
+    exit(1);
+  }
+
+  // a process shows up in /proc until it is waited for.
+  if(pipe(fds) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    close(fds[1]);
+    read(fds[0], buf, 1);
+    exit(0);
+  }
+  close(fds[0]);
+  procpath(path, pid, "fd");
+  fd = open(path, O_RDONLY);
+  if(fd < 0 || (n = read(fd, buf, sizeof(buf) - 1)) <= 0){
+    printf("%s: cannot read %s\n", s, path);
+    exit(1);
+  }
+  close(fd);
+  buf[n] = 0;
+  if(memcmp(buf, "0 ", 2) != 0){
+    printf("%s: wrong fd %s\n", s, buf);
+    exit(1);
+  }
+  close(fds[1]);
+  wait(0);
+  procpath(path, pid, "");
+  if(stat(path, &st) != -1 || errno != ENOENT){
+    printf("%s: %s is still there after wait\n", s, path);
+    exit(1);
+  }
+}
+
+// the kernel fills in /dev with the devices of its drivers.
+void
+devfstest(char *s)
+{
+  struct stat st;
+  char buf[64];
+  int fd, i, nblocks;
+
+  if(stat("/dev/console", &st) < 0 || st.type != T_DEVICE){
+    printf("%s: no /dev/console\n", s);
+    exit(1);
+  }
+
+  fd = open("/dev/null", O_RDWR);
+  if(fd < 0){
+    printf("%s: cannot open /dev/null\n", s);
+    exit(1);
+  }
+  if(write(fd, "x", 1) != 1 || read(fd, buf, sizeof(buf)) != 0){
+    printf("%s: /dev/null is not empty\n", s);
+    exit(1);
+  }
+  if(ioctl(fd, DIOCGBLOCKS, &nblocks) != -1 || errno != ENOTTY){
+    printf("%s: ioctl on /dev/null did not fail with ENOTTY\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  fd = open("/dev/zero", O_RDONLY);
+  memset(buf, 'x', sizeof(buf));
+  if(fd < 0 || read(fd, buf, sizeof(buf)) != sizeof(buf)){
+    printf("%s: cannot read /dev/zero\n", s);
+    exit(1);
+  }
+  close(fd);
+  for(i = 0; i < sizeof(buf); i++){
+    if(buf[i] != 0){
+      printf("%s: /dev/zero gave %d\n", s, buf[i]);
+      exit(1);
+    }
+  }
+
+  fd = open("/dev/disk1", O_RDWR);
+  if(fd < 0){
+    printf("%s: cannot open /dev/disk1\n", s);
+    exit(1);
+  }
+  if(ioctl(fd, DIOCGBLOCKS, &nblocks) != 0 || nblocks <= 0){
+    printf("%s: no size of /dev/disk1\n", s);
+    exit(1);
+  }
+  if(ioctl(fd, 99, 0) != -1 || errno != EINVAL){
+    printf("%s: unknown ioctl did not fail with EINVAL\n", s);
+    exit(1);
+  }
+  if(lseek(fd, (nblocks - 1) * BSIZE, 0) < 0 || read(fd, buf, sizeof(buf)) != sizeof(buf)){
+    printf("%s: cannot read the last block of /dev/disk1\n", s);
+    exit(1);
+  }
+  if(lseek(fd, nblocks * BSIZE, 0) < 0 || read(fd, buf, sizeof(buf)) != 0){
+    printf("%s: read past the end of /dev/disk1\n", s);
+    exit(1);
+  }
+  if(write(fd, buf, 1) != -1 || errno != EINVAL){
+    printf("%s: wrote to a read-only disk\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  if(open("/dev/new", O_CREATE | O_RDWR) != -1 || errno != EACCES
+     || unlink("/dev/null") != -1 || errno != EACCES){
+    printf("%s: /dev is writable\n", s);
+    exit(1);
+  }
+}
+
+// count the entries of dir that getdents() shows, checking the types of
+// the ones named in names[] against types[].
+int
+countdents(char *s, char *dir, char **names, int *types, int nnames)
+{
+  uint dents[64];
+  struct direntry *de;
+  int fd, n, off, i, count;
+
+  fd = open(dir, O_RDONLY);
+  if(fd < 0){
+    printf("%s: cannot open %s\n", s, dir);
+    exit(1);
+  }
+  count = 0;
+  while((n = getdents(fd, dents, sizeof(dents))) > 0){
+    for(off = 0; off < n; off += de->reclen){
+      de = (struct direntry*)((char*)dents + off);
+      if(de->reclen % 4 != 0 || de->namelen != strlen(de->name)){
+        printf("%s: bad record in %s\n", s, dir);
+        exit(1);
+      }
+      for(i = 0; i < nnames; i++){
+        if(strcmp(de->name, names[i]) == 0 && de->type != types[i]){
+          printf("%s: %s has type %d\n", s, de->name, de->type);
+          exit(1);
+        }
+      }
+      count++;
+    }
+  }
+  if(n < 0){
+    printf("%s: getdents %s failed\n", s, dir);
+    exit(1);
+  }
+  close(fd);
+  return count;
+}
+
+// getdents() lists directories of every file system.
+void
+getdentstest(char *s)
+{
+  char *names[] = { ".", "..", "file", "dir", "console" };
+  int types[] = { T_DIR, T_DIR, T_FILE, T_DIR, T_DEVICE };
+  char buf[4];
+  int fd, n;
+
+  unlink("dentsdir/file");
+  unlink("dentsdir/dir");
+  unlink("dentsdir");
+  if(mkdir("dentsdir") < 0 || mkdir("dentsdir/dir") < 0){
+    printf("%s: mkdir failed\n", s);
+    exit(1);
+  }
+  fd = open("dentsdir/file", O_CREATE | O_RDWR);
+  if(fd < 0){
+    printf("%s: create dentsdir/file failed\n", s);
+    exit(1);
+  }
+  if(getdents(fd, buf, sizeof(buf)) != -1 || errno != ENOTDIR){
+    printf("%s: getdents on a file did not fail with ENOTDIR\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  if((n = countdents(s, "dentsdir", names, types, 4)) != 4){
+    printf("%s: dentsdir has %d entries\n", s, n);
+    exit(1);
+  }
+  unlink("dentsdir/file");
+  if((n = countdents(s, "dentsdir", names, types, 4)) != 3){
+    printf("%s: dentsdir has %d entries after unlink\n", s, n);
+    exit(1);
+  }
+
+  fd = open("dentsdir", O_RDONLY);
+  if(getdents(fd, buf, sizeof(buf)) != -1 || errno != EINVAL){
+    printf("%s: getdents into a small buffer did not fail with EINVAL\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  unlink("dentsdir/dir");
+  unlink("dentsdir");
+
+  countdents(s, "/tmp", names, types, 2);
+  countdents(s, "/proc", names, types, 2);
+  if(countdents(s, "/dev", names, types, 5) < 3){
+    printf("%s: /dev is empty\n", s);
+    exit(1);
+  }
+}
+
+// rename() moves files and directories, replacing the target.
+void
+renametest(char *s)
+{
+  struct stat st, dot;
+  char buf[4];
+  int fd;
+
+  unlink("rnfile");
+  unlink("rnfile2");
+  unlink("rnsub/deep");
+  unlink("rnsub");
+  unlink("rndir");
+
+  fd = open("rnfile", O_CREATE | O_RDWR);
+  write(fd, "a", 1);
+  close(fd);
+  if(rename("rnfile", "rnfile2") != 0){
+    printf("%s: rename rnfile failed\n", s);
+    exit(1);
+  }
+  if(open("rnfile", O_RDONLY) >= 0){
+    printf("%s: rnfile exists after rename\n", s);
+    exit(1);
+  }
+
+  // replace rnfile2.
+  fd = open("rnfile", O_CREATE | O_RDWR);
+  write(fd, "b", 1);
+  close(fd);
+  if(rename("rnfile", "rnfile2") != 0){
+    printf("%s: rename over rnfile2 failed\n", s);
+    exit(1);
+  }
+  fd = open("rnfile2", O_RDONLY);
+  if(fd < 0 || read(fd, buf, sizeof(buf)) != 1 || buf[0] != 'b'){
+    printf("%s: rnfile2 was not replaced\n", s);
+    exit(1);
+  }
+  if(fstat(fd, &st) < 0 || st.nlink != 1){
+    printf("%s: rnfile2 has wrong nlink\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  // move a directory, and its "..".
+  if(mkdir("rndir") < 0 || mkdir("rndir/sub") < 0 || mkdir("rndir/sub/deep") < 0){
+    printf("%s: mkdir failed\n", s);
+    exit(1);
+  }
+  if(rename("rndir/sub", "rnsub") != 0){
+    printf("%s: rename rndir/sub failed\n", s);
+    exit(1);
+  }
+  if(stat(".", &dot) < 0 || stat("rnsub/..", &st) < 0 || st.ino != dot.ino){
+    printf("%s: rnsub/.. is not .\n", s);
+    exit(1);
+  }
+  if(rename("rnsub", "rnsub/deep/x") != -1 || errno != EINVAL){
+    printf("%s: moving rnsub into itself did not fail with EINVAL\n", s);
+    exit(1);
+  }
+
+  // replace the empty rndir.
+  if(rename("rnsub/deep", "rndir") != 0){
+    printf("%s: rename over rndir failed\n", s);
+    exit(1);
+  }
+  if(stat("rndir/..", &st) < 0 || st.ino != dot.ino){
+    printf("%s: rndir/.. is not .\n", s);
+    exit(1);
+  }
+
+  if(rename("rnfile2", "rndir") != -1 || errno != EISDIR){
+    printf("%s: replacing a directory with a file did not fail with EISDIR\n", s);
+    exit(1);
+  }
+  if(rename("rndir", "rnfile2") != -1 || errno != ENOTDIR){
+    printf("%s: replacing a file with a directory did not fail with ENOTDIR\n", s);
+    exit(1);
+  }
+  fd = open("rnsub/f", O_CREATE | O_RDWR);
+  close(fd);
+  if(rename("rndir", "rnsub") != -1 || errno != ENOTEMPTY){
+    printf("%s: replacing a full directory did not fail with ENOTEMPTY\n", s);
+    exit(1);
+  }
+  if(rename("rnfile2", "/tmp/rnfile2") != -1 || errno != EXDEV){
+    printf("%s: rename across file systems did not fail with EXDEV\n", s);
+    exit(1);
+  }
+
+  unlink("rnsub/f");
+  unlink("rnsub");
+  unlink("rndir");
+  unlink("rnfile2");
+}
+
+// symbolic links to files and directories, relative and absolute.
+void
+symlinktest(char *s)
+{
+  char *names[] = { "flink" };
+  int types[] = { T_SYMLINK };
+  struct stat st;
+  char buf[16];
+  int fd;
+
+  unlink("sldir/flink");
+  unlink("sldir/f");
+  unlink("sldir");
+  unlink("sldirlink");
+  unlink("sllink");
+  unlink("slfile");
+  unlink("sltmp");
+  unlink("slloop1");
+  unlink("slloop2");
+  unlink("sldangle");
+
+  fd = open("slfile", O_CREATE | O_RDWR);
+  write(fd, "x", 1);
+  close(fd);
+  if(symlink("slfile", "sllink") != 0){
+    printf("%s: symlink failed\n", s);
+    exit(1);
+  }
+  if(symlink("slfile", "sllink") != -1 || errno != EEXIST){
+    printf("%s: symlink over sllink did not fail with EEXIST\n", s);
+    exit(1);
+  }
+  fd = open("sllink", O_RDONLY);
+  if(fd < 0 || read(fd, buf, sizeof(buf)) != 1 || buf[0] != 'x'){
+    printf("%s: cannot read slfile through sllink\n", s);
+    exit(1);
+  }
+  if(fstat(fd, &st) < 0 || st.type != T_FILE){
+    printf("%s: sllink does not open a file\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(readlink("sllink", buf, sizeof(buf)) != 6 || memcmp(buf, "slfile", 6) != 0){
+    printf("%s: readlink sllink failed\n", s);
+    exit(1);
+  }
+  if(readlink("slfile", buf, sizeof(buf)) != -1 || errno != EINVAL){
+    printf("%s: readlink on a file did not fail with EINVAL\n", s);
+    exit(1);
+  }
+  if(open("sllink", O_RDONLY | O_NOFOLLOW) != -1 || errno != ELOOP){
+    printf("%s: O_NOFOLLOW open did not fail with ELOOP\n", s);
+    exit(1);
+  }
+
+  // a link to a directory, and a relative link in it.
+  if(mkdir("sldir") < 0 || symlink("sldir", "sldirlink") != 0){
+    printf("%s: cannot make sldirlink\n", s);
+    exit(1);
+  }
+  fd = open("sldirlink/f", O_CREATE | O_RDWR);
+  if(fd < 0){
+    printf("%s: create through sldirlink failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(symlink("f", "sldir/flink") != 0 || (fd = open("sldirlink/flink", O_RDONLY)) < 0){
+    printf("%s: cannot open sldirlink/flink\n", s);
+    exit(1);
+  }
+  close(fd);
+  countdents(s, "sldir", names, types, 1);
+
+  // an absolute link to another file system.
+  if(symlink("/tmp", "sltmp") != 0 || (fd = open("sltmp/slf", O_CREATE | O_RDWR)) < 0){
+    printf("%s: cannot create sltmp/slf\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(unlink("/tmp/slf") != 0){
+    printf("%s: sltmp/slf is not /tmp/slf\n", s);
+    exit(1);
+  }
+
+  if(symlink("slloop2", "slloop1") != 0 || symlink("slloop1", "slloop2") != 0){
+    printf("%s: cannot make a loop\n", s);
+    exit(1);
+  }
+  if(open("slloop1", O_RDONLY) != -1 || errno != ELOOP){
+    printf("%s: opening a loop did not fail with ELOOP\n", s);
+    exit(1);
+  }
+  if(symlink("slnone", "sldangle") != 0){
+    printf("%s: cannot make sldangle\n", s);
+    exit(1);
+  }
+  if(open("sldangle", O_RDONLY) != -1 || errno != ENOENT){
+    printf("%s: opening a dangling link did not fail with ENOENT\n", s);
+    exit(1);
+  }
+  if((fd = open("sldangle", O_CREATE | O_RDWR)) < 0){
+    printf("%s: cannot create through sldangle\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(unlink("slnone") != 0){
+    printf("%s: creating through sldangle did not create slnone\n", s);
+    exit(1);
+  }
+
+  // removing a link keeps its target.
+  unlink("sllink");
+  if((fd = open("slfile", O_RDONLY)) < 0){
+    printf("%s: unlink sllink removed slfile\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  unlink("sldir/flink");
+  unlink("sldir/f");
+  unlink("sldir");
+  unlink("sldirlink");
+  unlink("slfile");
+  unlink("sltmp");
+  unlink("slloop1");
+  unlink("slloop2");
+  unlink("sldangle");
+}
+
+#define NLNLENS 7
+int lnlens[NLNLENS] = { NAME_MAX, NAME_MAX - 1, 200, 100, 15, 14, 1 };
+
+// create the files named c repeated lnlens[i] times in the current
+// directory, each holding its c and i.
+void
+lncreate(char *s, char c)
+{
+  char name[NAME_MAX + 1];
+  int fd, i, v;
+
+  for(i = 0; i < NLNLENS; i++){
+    memset(name, c, lnlens[i]);
+    name[lnlens[i]] = 0;
+    fd = open(name, O_CREATE | O_RDWR);
+    v = c << 8 | i;
+    if(fd < 0 || write(fd, &v, sizeof(v)) != sizeof(v)){
+      printf("%s: cannot create a name of length %d\n", s, lnlens[i]);
+      exit(1);
+    }
+    close(fd);
+  }
+}
+
+// check that the files that lncreate(s, c) made hold the right values.
+void
+lncheck(char *s, char c)
+{
+  char name[NAME_MAX + 1];
+  int fd, i, v;
+
+  for(i = 0; i < NLNLENS; i++){
+    memset(name, c, lnlens[i]);
+    name[lnlens[i]] = 0;
+    fd = open(name, O_RDONLY);
+    if(fd < 0 || read(fd, &v, sizeof(v)) != sizeof(v) || v != (c << 8 | i)){
+      printf("%s: wrong file for a name of length %d\n", s, lnlens[i]);
+      exit(1);
+    }
+    close(fd);
+  }
+}
+
+void
+lnunlink(char *s, char c)
+{
+  char name[NAME_MAX + 1];
+  int i;
+
+  for(i = 0; i < NLNLENS; i++){
+    memset(name, c, lnlens[i]);
+    name[lnlens[i]] = 0;
+    if(unlink(name) != 0){
+      printf("%s: cannot unlink a name of length %d\n", s, lnlens[i]);
+      exit(1);
+    }
+  }
+}
+
+// names of up to NAME_MAX bytes, in a directory of more than one block.
+void
+longnametest(char *s)
+{
+  char name[NAME_MAX + 2], name2[NAME_MAX + 1];
+  int fd, n;
+
+  if(mkdir("lndir") != 0 || chdir("lndir") != 0){
+    printf("%s: cannot make lndir\n", s);
+    exit(1);
+  }
+
+  lncreate(s, 'a');
+  lncreate(s, 'b');
+  lncheck(s, 'a');
+  lncheck(s, 'b');
+  if((n = countdents(s, ".", 0, 0, 0)) != 2 + 2 * NLNLENS){
+    printf("%s: getdents shows %d entries\n", s, n);
+    exit(1);
+  }
+
+  // the freed space is used again.
+  lnunlink(s, 'a');
+  lncheck(s, 'b');
+  lncreate(s, 'c');
+  lncheck(s, 'b');
+  lncheck(s, 'c');
+  if((n = countdents(s, ".", 0, 0, 0)) != 2 + 2 * NLNLENS){
+    printf("%s: getdents shows %d entries after unlink\n", s, n);
+    exit(1);
+  }
+
+  memset(name, 'b', NAME_MAX);
+  name[NAME_MAX] = 0;
+  memset(name2, 'd', NAME_MAX);
+  name2[NAME_MAX] = 0;
+  if(rename(name, name2) != 0 || open(name, O_RDONLY) != -1){
+    printf("%s: cannot rename a long name\n", s);
+    exit(1);
+  }
+  if(rename(name2, name) != 0){
+    printf("%s: cannot rename a long name back\n", s);
+    exit(1);
+  }
+
+  // a name longer than NAME_MAX does not fit in a path.
+  memset(name, 'e', NAME_MAX + 1);
+  name[NAME_MAX + 1] = 0;
+  if((fd = open(name, O_CREATE | O_RDWR)) != -1 || errno != ENAMETOOLONG){
+    printf("%s: created a name longer than NAME_MAX\n", s);
+    exit(1);
+  }
+
+  lnunlink(s, 'b');
+  lnunlink(s, 'c');
+  if(chdir("..") != 0 || unlink("lndir") != 0){
+    printf("%s: cannot remove lndir\n", s);
+    exit(1);
+  }
+
+  // the tmpfs keeps whole names as well.
+  if(mkdir("/tmp/lndir") != 0 || chdir("/tmp/lndir") != 0){
+    printf("%s: cannot make /tmp/lndir\n", s);
+    exit(1);
+  }
+  lncreate(s, 'f');
+  lncheck(s, 'f');
+  lnunlink(s, 'f');
+  if(chdir("../..") != 0 || unlink("/tmp/lndir") != 0){
+    printf("%s: cannot remove /tmp/lndir\n", s);
+    exit(1);
+  }
+}
+
+// a file of several megabytes, which needs double indirect blocks.
+// writing it more times than the disk could hold checks that
+// truncating frees them.
+#define HUGEBLOCKS 4096
+#define HUGEROUNDS 5
+
+void
+hugefiletest(char *s)
+{
+  int fd, i, n, round;
+
+  for(round = 0; round < HUGEROUNDS; round++){
+    fd = open("huge", O_CREATE|O_TRUNC|O_RDWR);
+    if(fd < 0){
+      printf("%s: cannot create huge\n", s);
+      exit(1);
+    }
+    for(i = 0; i < HUGEBLOCKS; i++){
+      ((int*)buf)[0] = i;
+      ((int*)buf)[BSIZE / sizeof(int) - 1] = round;
+      if(write(fd, buf, BSIZE) != BSIZE){
+        printf("%s: write huge block %d failed\n", s, i);
+        exit(1);
+      }
+    }
+    close(fd);
+
+    fd = open("huge", O_RDONLY);
+    if(fd < 0){
+      printf("%s: cannot open huge\n", s);
+      exit(1);
+    }
+    for(n = 0; (i = read(fd, buf, BSIZE)) == BSIZE; n++){
+      if(((int*)buf)[0] != n || ((int*)buf)[BSIZE / sizeof(int) - 1] != round){
+        printf("%s: bad content of huge block %d\n", s, n);
+        exit(1);
+      }
+    }
+    close(fd);
+    if(i != 0 || n != HUGEBLOCKS){
+      printf("%s: read only %d blocks from huge\n", s, n);
+      exit(1);
+    }
+  }
+
+  if(unlink("huge") != 0){
+    printf("%s: unlink huge failed\n", s);
+    exit(1);
+  }
+}
+
+// file offsets beyond 4 GiB do not wrap around, and lseek
+// reports offsets that are negative or too large.
+void
+offsettest(char *s)
+{
+  int fd;
+  off_t big = 5L << 30;
+
+  fd = open("offset", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: cannot create offset\n", s);
+    exit(1);
+  }
+  if(write(fd, "x", 1) != 1){
+    printf("%s: write offset failed\n", s);
+    exit(1);
+  }
+  if(lseek(fd, big, SEEK_SET) != big || lseek(fd, 0, SEEK_CUR) != big){
+    printf("%s: lseek past 4 GiB failed\n", s);
+    exit(1);
+  }
+  if(read(fd, buf, 1) != 0){
+    printf("%s: read past the end of offset\n", s);
+    exit(1);
+  }
+  if(lseek(fd, -big, SEEK_CUR) != 0 || read(fd, buf, 1) != 1 || buf[0] != 'x'){
+    printf("%s: lseek back failed\n", s);
+    exit(1);
+  }
+  if(lseek(fd, -2, SEEK_END) != -1 || errno != EINVAL){
+    printf("%s: lseek to a negative offset did not fail with EINVAL\n", s);
+    exit(1);
+  }
+  if(lseek(fd, 0x7fffffffffffffffL, SEEK_SET) < 0 ||
+     lseek(fd, 1, SEEK_CUR) != -1 || errno != EOVERFLOW){
+    printf("%s: overflowing lseek did not fail with EOVERFLOW\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("offset");
+}
+
+// the real-time clock, and the times that files keep.
+void
+timetest(char *s)
+{
+  struct timespec now, times[2];
+  struct stat st;
+  int fd;
+
+  // 2020-01-01 00:00:00 UTC
+  if(clock_gettime(CLOCK_REALTIME, &now) < 0 || now.tv_sec < 1577836800L){
+    printf("%s: clock_gettime did not return the current time\n", s);
+    exit(1);
+  }
+  if(clock_gettime(-1, &now) != -1 || errno != EINVAL){
+    printf("%s: clock_gettime of a bad clock did not fail with EINVAL\n", s);
+    exit(1);
+  }
+
+  unlink("timefile");
+  fd = open("timefile", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: cannot create timefile\n", s);
+    exit(1);
+  }
+  if(fstat(fd, &st) < 0 || st.atime.tv_sec < now.tv_sec ||
+     st.mtime.tv_sec < now.tv_sec || st.ctime.tv_sec < now.tv_sec){
+    printf("%s: times of a new file are before its creation\n", s);
+    exit(1);
+  }
+
+  times[0].tv_sec = 1000;
+  times[0].tv_nsec = 1;
+  times[1].tv_sec = 2000;
+  times[1].tv_nsec = 2;
+  if(utimens("timefile", times) < 0 || fstat(fd, &st) < 0 ||
+     st.atime.tv_sec != 1000 || st.atime.tv_nsec != 1 ||
+     st.mtime.tv_sec != 2000 || st.mtime.tv_nsec != 2 || st.ctime.tv_sec < now.tv_sec){
+    printf("%s: utimens did not set the times\n", s);
+    exit(1);
+  }
+
+  if(write(fd, "x", 1) != 1 || fstat(fd, &st) < 0 ||
+     st.atime.tv_sec != 1000 || st.mtime.tv_sec < now.tv_sec){
+    printf("%s: write did not update the modification time\n", s);
+    exit(1);
+  }
+
+  times[0].tv_nsec = UTIME_OMIT;
+  times[1].tv_sec = 3000;
+  times[1].tv_nsec = 0;
+  if(utimens("timefile", times) < 0 || stat("timefile", &st) < 0 ||
+     st.atime.tv_sec != 1000 || st.mtime.tv_sec != 3000){
+    printf("%s: utimens with UTIME_OMIT failed\n", s);
+    exit(1);
+  }
+
+  times[1].tv_nsec = 1000000000L;
+  if(utimens("timefile", times) != -1 || errno != EINVAL){
+    printf("%s: utimens of bad nanoseconds did not fail with EINVAL\n", s);
+    exit(1);
+  }
+
+  if(utimens("timefile", 0) < 0 || stat("timefile", &st) < 0 ||
+     st.atime.tv_sec < now.tv_sec || st.mtime.tv_sec < now.tv_sec){
+    printf("%s: utimens did not set the times to now\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  if(unlink("timefile") < 0 || utimens("timefile", 0) != -1 || errno != ENOENT){
+    printf("%s: utimens of a removed file did not fail with ENOENT\n", s);
+    exit(1);
+  }
+}
+
+// only the superuser or the owner of a process may signal or reschedule it,
+// and only the superuser may lower a nice value.
+void
+procpermtest(char *s)
+{
+  int root, pid, child, xstatus;
+
+  root = fork();
+  if(root < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(root == 0){
+    for(;;)
+      sleep(1000);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setuid(1000) < 0){
+      printf("%s: setuid failed\n", s);
+      exit(1);
+    }
+    if(kill(root, SIGKILL) != -1 || errno != EPERM || kill(root, 0) != -1 || errno != EPERM){
+      printf("%s: could signal a process of another user\n", s);
+      exit(1);
+    }
+    if(setpriority(PRIO_PROCESS, root, 5) != -1 || errno != EPERM){
+      printf("%s: could renice a process of another user\n", s);
+      exit(1);
+    }
+    if(sched_setaffinity(root, 1) != -1 || errno != EPERM){
+      printf("%s: could set the affinity of a process of another user\n", s);
+      exit(1);
+    }
+
+    if(setpriority(PRIO_PROCESS, 0, 5) != 0 || sched_setaffinity(0, 1) != 0){
+      printf("%s: cannot reschedule an own process\n", s);
+      exit(1);
+    }
+    if(setpriority(PRIO_PROCESS, 0, 0) != -1 || errno != EPERM
+       || getpriority(PRIO_PROCESS, 0) != 5){
+      printf("%s: could lower the nice value without being the superuser\n", s);
+      exit(1);
+    }
+
+    child = fork();
+    if(child < 0){
+      printf("%s: fork failed\n", s);
+      exit(1);
+    }
+    if(child == 0){
+      for(;;)
+        sleep(1000);
+    }
+    if(kill(child, SIGKILL) != 0 || wait(&xstatus) != child){
+      printf("%s: cannot signal an own process\n", s);
+      exit(1);
+    }
+    exit(0);
+  }
+  wait(&xstatus);
+
+  // the superuser may do anything.
+  if(setpriority(PRIO_PROCESS, root, 5) != 0 || setpriority(PRIO_PROCESS, root, 0) != 0
+     || kill(root, SIGKILL) != 0){
+    printf("%s: the superuser cannot reschedule or signal a process\n", s);
+    exit(1);
+  }
+  wait(0);
+  exit(xstatus);
+}
+
+void
+permtest_setuid(void *arg)
+{
+  exit(setuid((uint)(uint64)arg) < 0);
+}
+
+// the permission bits of files keep other users away from them.
+void
+permtest(char *s)
+{
+  char *args[] = { "echo", 0 };
+  struct stat st;
+  int fd, pid, tid, xstatus;
+  char *stack;
+
+  fd = open("permfile", O_CREATE|O_WRONLY);
+  if(fd < 0 || write(fd, "x", 1) != 1){
+    printf("%s: cannot create permfile\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(stat("permfile", &st) < 0 || st.uid != 0 || st.gid != 0 || st.mode != 0644){
+    printf("%s: permfile has a wrong owner or mode\n", s);
+    exit(1);
+  }
+  if(mkdir("permdir") < 0 || chmod("permdir", 0777) < 0){
+    printf("%s: cannot create permdir\n", s);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    // the ids belong to the whole process.
+    stack = sbrk(4096);
+    if(stack == (char*)-1 || (tid = clone(permtest_setuid, (void*)1000, stack + 4096)) < 0){
+      printf("%s: clone failed\n", s);
+      exit(1);
+    }
+    if(join(tid, &xstatus) != tid || xstatus != 0 || getuid() != 1000){
+      printf("%s: setuid in a thread did not change the process\n", s);
+      exit(1);
+    }
+    if(setuid(1000) < 0 || getuid() != 1000 || setgid(1000) != -1 || errno != EPERM){
+      printf("%s: setuid failed\n", s);
+      exit(1);
+    }
+    if(setuid(0) != -1 || errno != EPERM){
+      printf("%s: setuid back to root did not fail with EPERM\n", s);
+      exit(1);
+    }
+
+    fd = open("permfile", O_RDONLY);
+    if(fd < 0){
+      printf("%s: cannot read permfile of another user\n", s);
+      exit(1);
+    }
+    close(fd);
+    if(open("permfile", O_RDWR) != -1 || errno != EACCES){
+      printf("%s: could write permfile of another user\n", s);
+      exit(1);
+    }
+    if(unlink("permfile") != -1 || errno != EACCES){
+      printf("%s: could unlink a file in a directory of another user\n", s);
+      exit(1);
+    }
+    if(chmod("permfile", 0777) != -1 || errno != EPERM){
+      printf("%s: could chmod permfile of another user\n", s);
+      exit(1);
+    }
+    if(chown("permfile", 1000, -1) != -1 || errno != EPERM){
+      printf("%s: could chown permfile\n", s);
+      exit(1);
+    }
+    if(open("/dev/disk1", O_RDONLY) != -1 || errno != EACCES){
+      printf("%s: could read a disk\n", s);
+      exit(1);
+    }
+
+    fd = open("permdir/mine", O_CREATE|O_RDWR);
+    if(fd < 0){
+      printf("%s: cannot create a file in permdir\n", s);
+      exit(1);
+    }
+    close(fd);
+    if(stat("permdir/mine", &st) < 0 || st.uid != 1000 || st.gid != 0){
+      printf("%s: a new file is not owned by its creator\n", s);
+      exit(1);
+    }
+    if(chmod("permdir/mine", 0) < 0){
+      printf("%s: cannot chmod an own file\n", s);
+      exit(1);
+    }
+    if(open("permdir/mine", O_RDONLY) != -1 || errno != EACCES){
+      printf("%s: could read a file without the read permission\n", s);
+      exit(1);
+    }
+    if(exec("permdir/mine", args) != -1 || errno != EACCES){
+      printf("%s: could exec a file without the execute permission\n", s);
+      exit(1);
+    }
+
+    if(mkdir("permdir/sub") < 0 || chmod("permdir/sub", 0600) < 0){
+      printf("%s: cannot create permdir/sub\n", s);
+      exit(1);
+    }
+    if(open("permdir/sub/file", O_CREATE|O_RDWR) != -1 || errno != EACCES){
+      printf("%s: could search a directory without the execute permission\n", s);
+      exit(1);
+    }
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0)
+    exit(xstatus);
+
+  if(stat("/dev/disk1", &st) < 0 || st.uid != 0 || st.mode != 0600){
+    printf("%s: /dev/disk1 has a wrong owner or mode\n", s);
+    exit(1);
+  }
+  // The superuser runs only a file that someone may execute.
+  if(exec("permfile", args) != -1 || errno != EACCES){
+    printf("%s: the superuser could exec a file without any execute permission\n", s);
+    exit(1);
+  }
+
+  // The superuser may do anything else.
+  if(unlink("permdir/sub") < 0 || unlink("permdir/mine") < 0 ||
+     unlink("permdir") < 0 || unlink("permfile") < 0){
+    printf("%s: cannot clean up permdir\n", s);
+    exit(1);
+  }
+}
+
+// select reports pipes, files, and the console as ready for writing,
+// polls with a timeout of 0, and stops waiting when the process is killed.
+void
+selecttest(char *s)
+{
+  fd_set rfds, wfds, efds;
+  int fds[2], fd, pid;
+
+  if(pipe(fds) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+
+  FD_ZERO(&rfds);
+  FD_ZERO(&wfds);
+  FD_ZERO(&efds);
+  FD_SET(fds[0], &rfds);
+  FD_SET(fds[1], &wfds);
+  FD_SET(fds[0], &efds);
+  if(select(fds[1] + 1, &rfds, &wfds, &efds, 0) != 1 ||
+     FD_ISSET(fds[0], &rfds) || !FD_ISSET(fds[1], &wfds) || FD_ISSET(fds[0], &efds)){
+    printf("%s: an empty pipe is not only ready for writing\n", s);
+    exit(1);
+  }
+
+  FD_ZERO(&rfds);
+  FD_SET(fds[0], &rfds);
+  if(select(fds[0] + 1, &rfds, 0, 0, 2) != 0 || FD_ISSET(fds[0], &rfds)){
+    printf("%s: select on an empty pipe did not time out\n", s);
+    exit(1);
+  }
+
+  if(write(fds[1], "x", 1) != 1){
+    printf("%s: write failed\n", s);
+    exit(1);
+  }
+  FD_SET(fds[0], &rfds);
+  if(select(fds[0] + 1, &rfds, 0, 0, -1) != 1 || !FD_ISSET(fds[0], &rfds)){
+    printf("%s: a pipe with data is not ready for reading\n", s);
+    exit(1);
+  }
+
+  fd = open("selectfile", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: cannot create selectfile\n", s);
+    exit(1);
+  }
+  FD_ZERO(&rfds);
+  FD_ZERO(&wfds);
+  FD_SET(fd, &rfds);
+  FD_SET(fd, &wfds);
+  FD_SET(1, &wfds);
+  if(select(fd + 1, &rfds, &wfds, 0, 0) != 3){
+    printf("%s: a file or the console is not ready\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("selectfile");
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    char c;
+    read(fds[0], &c, 1);
+    FD_ZERO(&rfds);
+    FD_SET(fds[0], &rfds);
+    select(fds[0] + 1, &rfds, 0, 0, -1);
+    exit(0);
+  }
+  sleep(2);
+  kill(pid, SIGKILL);
+  if(wait(0) != pid){
+    printf("%s: wait failed\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+}
+
+// poll and epoll report ready pipes, and wake up when a pipe becomes ready.
+void
+polltest(char *s)
+{
+  struct pollfd pfds[3];
+  struct epoll_event ev, evs[4];
+  int fds[2], pfd2[2], ep, pid;
+  char c;
+
+  if(pipe(fds) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+
+  pfds[0].fd = fds[0];
+  pfds[0].events = POLLIN;
+  pfds[1].fd = fds[1];
+  pfds[1].events = POLLOUT;
+  pfds[2].fd = -1;
+  pfds[2].events = POLLIN;
+  if(poll(pfds, 3, 0) != 1 || pfds[0].revents != 0 ||
+     pfds[1].revents != POLLOUT || pfds[2].revents != 0){
+    printf("%s: an empty pipe is not only ready for writing\n", s);
+    exit(1);
+  }
+  if(poll(pfds, 1, 200) != 0){
+    printf("%s: poll on an empty pipe did not time out\n", s);
+    exit(1);
+  }
+
+  ep = epoll_create(1);
+  if(ep < 0){
+    printf("%s: epoll_create failed\n", s);
+    exit(1);
+  }
+  ev.events = EPOLLIN;
+  ev.data.u64 = 42;
+  if(epoll_ctl(ep, EPOLL_CTL_ADD, fds[0], &ev) < 0){
+    printf("%s: epoll_ctl failed\n", s);
+    exit(1);
+  }
+  if(epoll_ctl(ep, EPOLL_CTL_ADD, fds[0], &ev) != -1 ||
+     epoll_ctl(ep, EPOLL_CTL_ADD, ep, &ev) != -1){
+    printf("%s: epoll_ctl added a watch twice or an epoll instance\n", s);
+    exit(1);
+  }
+  if(epoll_wait(ep, evs, 4, 0) != 0){
+    printf("%s: epoll_wait reported an empty pipe\n", s);
+    exit(1);
+  }
+
+  // The parent sleeps in epoll_wait until the child writes.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    sleep(2);
+    write(fds[1], "x", 1);
+    exit(0);
+  }
+  if(epoll_wait(ep, evs, 4, -1) != 1 || evs[0].events != EPOLLIN || evs[0].data.u64 != 42){
+    printf("%s: epoll_wait did not report the pipe\n", s);
+    exit(1);
+  }
+  wait(0);
+
+  pfds[0].revents = 0;
+  if(poll(pfds, 1, -1) != 1 || pfds[0].revents != POLLIN || read(fds[0], &c, 1) != 1){
+    printf("%s: a pipe with data is not ready for reading\n", s);
+    exit(1);
+  }
+
+  if(epoll_ctl(ep, EPOLL_CTL_DEL, fds[0], 0) < 0 ||
+     epoll_ctl(ep, EPOLL_CTL_DEL, fds[0], 0) != -1){
+    printf("%s: epoll_ctl did not remove the watch\n", s);
+    exit(1);
+  }
+
+  // A watch goes away with its file, and a new file with the same fd is not watched.
+  // The write end is ready for reading, as reading it does not block.
+  if(epoll_ctl(ep, EPOLL_CTL_ADD, fds[1], &ev) < 0 || epoll_wait(ep, evs, 4, 0) != 1){
+    printf("%s: epoll_wait did not report the write end\n", s);
+    exit(1);
+  }
+  close(fds[1]);
+  if(pipe(pfd2) < 0 || pfd2[0] != fds[1]){
+    printf("%s: pipe did not reuse the closed fd\n", s);
+    exit(1);
+  }
+  if(epoll_wait(ep, evs, 4, 0) != 0){
+    printf("%s: epoll_wait reported a closed file\n", s);
+    exit(1);
+  }
+  if(epoll_ctl(ep, EPOLL_CTL_DEL, fds[1], 0) != -1 ||
+     epoll_ctl(ep, EPOLL_CTL_ADD, fds[1], &ev) < 0){
+    printf("%s: the watch of a closed file is still there\n", s);
+    exit(1);
+  }
+  close(ep);
+  close(fds[0]);
+  close(pfd2[0]);
+  close(pfd2[1]);
+}
+
+// fcntl gets and sets descriptor and status flags, and O_NONBLOCK, O_APPEND, O_EXCL
+// and O_CLOEXEC take effect.
+void
+fcntltest(char *s)
+{
+  int fds[2], fd, fd2, pid, xstatus;
+  char buf[8];
+
+  if(pipe(fds) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  if(fcntl(fds[0], F_GETFL, 0) != O_RDONLY){
+    printf("%s: F_GETFL of a new pipe is wrong\n", s);
+    exit(1);
+  }
+  if(fcntl(fds[0], F_SETFL, O_NONBLOCK) < 0 ||
+     fcntl(fds[0], F_GETFL, 0) != (O_RDONLY | O_NONBLOCK)){
+    printf("%s: F_SETFL did not set O_NONBLOCK\n", s);
+    exit(1);
+  }
+  if(read(fds[0], buf, 1) != -1){
+    printf("%s: read on an empty non-blocking pipe did not fail\n", s);
+    exit(1);
+  }
+  if(write(fds[1], "x", 1) != 1 || read(fds[0], buf, sizeof(buf)) != 1){
+    printf("%s: non-blocking read did not return the data\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+
+  unlink("fcntlfile");
+  fd = open("fcntlfile", O_CREATE | O_EXCL | O_WRONLY);
+  if(fd < 0){
+    printf("%s: O_EXCL failed on a new file\n", s);
+    exit(1);
+  }
+  if(open("fcntlfile", O_CREATE | O_EXCL | O_WRONLY) != -1){
+    printf("%s: O_EXCL succeeded on an existing file\n", s);
+    exit(1);
+  }
+  if(write(fd, "aaaa", 4) != 4){
+    printf("%s: write failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  fd = open("fcntlfile", O_WRONLY | O_APPEND);
+  fd2 = open("fcntlfile", O_WRONLY);
+  if(fd < 0 || fd2 < 0){
+    printf("%s: open failed\n", s);
+    exit(1);
+  }
+  if(fcntl(fd, F_GETFL, 0) != (O_WRONLY | O_APPEND)){
+    printf("%s: F_GETFL did not report O_APPEND\n", s);
+    exit(1);
+  }
+  if(write(fd2, "bb", 2) != 2 || write(fd, "cc", 2) != 2){
+    printf("%s: write failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  close(fd2);
+  fd = open("fcntlfile", O_RDONLY);
+  if(read(fd, buf, sizeof(buf)) != 6 || memcmp(buf, "bbaacc", 6) != 0){
+    printf("%s: O_APPEND did not write at the end of the file\n", s);
+    exit(1);
+  }
+
+  fd2 = fcntl(fd, F_DUPFD, 10);
+  if(fd2 < 10){
+    printf("%s: F_DUPFD returned %d\n", s, fd2);
+    exit(1);
+  }
+  if(fcntl(fd2, F_GETFD, 0) != 0 || fcntl(fd2, F_SETFD, FD_CLOEXEC) < 0 ||
+     fcntl(fd2, F_GETFD, 0) != FD_CLOEXEC || fcntl(fd, F_GETFD, 0) != 0){
+    printf("%s: F_SETFD did not set FD_CLOEXEC of only one descriptor\n", s);
+    exit(1);
+  }
+  close(fd2);
+  close(fd);
+
+  fd = open("fcntlfile", O_RDONLY | O_CLOEXEC);
+  if(fd < 0 || fcntl(fd, F_GETFD, 0) != FD_CLOEXEC){
+    printf("%s: O_CLOEXEC did not set FD_CLOEXEC\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0)
+    exit(fcntl(fd, F_GETFD, 0) == FD_CLOEXEC ? 0 : 1);
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: fork did not keep FD_CLOEXEC\n", s);
+    exit(1);
+  }
+  if(fcntl(fd, 42, 0) != -1){
+    printf("%s: fcntl accepted an unknown command\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("fcntlfile");
+}
+
+// MAP_SHARED mappings reach the file and are shared with children, and
+// system calls can use untouched mapped pages.
+void
+mmapsharedtest(char *s)
+{
+  char *f = "mmapshared.tmp";
+  char buf[32], *a;
+  int fd, i, pid, xstatus, *fds;
+
+  fd = open(f, O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: open %s failed\n", s, f);
+    exit(1);
+  }
+  for(i = 0; i < PGSIZE; i++){
+    buf[0] = 'a' + i % 26;
+    if(write(fd, buf, 1) != 1){
+      printf("%s: write %s failed\n", s, f);
+      exit(1);
+    }
+  }
+
+  // shared writes reach the file once the mapping is unmapped.
+  a = mmap(0, PGSIZE, PROT_READ|PROT_WRITE, MAP_SHARED, fd, 0);
+  if(a == MAP_FAILED){
+    printf("%s: shared mmap of a file failed\n", s);
+    exit(1);
+  }
+  a[0] = 'Z';
+  if(munmap(a, PGSIZE) != 0){
+    printf("%s: munmap failed\n", s);
+    exit(1);
+  }
+  if(lseek(fd, 0, 0) != 0 || read(fd, buf, 2) != 2 || buf[0] != 'Z' || buf[1] != 'b'){
+    printf("%s: a shared write did not reach the file\n", s);
+    exit(1);
+  }
+
+  // a system call writes to a mapped page that has not been touched.
+  a = mmap(0, PGSIZE, PROT_READ|PROT_WRITE, MAP_PRIVATE, fd, 0);
+  if(a == MAP_FAILED){
+    printf("%s: private mmap of a file failed\n", s);
+    exit(1);
+  }
+  fds = (int*)(a + 8);
+  if(pipe(fds) != 0){
+    printf("%s: pipe into a mapped page failed\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+  if(a[0] != 'Z' || munmap(a, PGSIZE) != 0){
+    printf("%s: private mapping has wrong content\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  // a read-only file cannot be mapped shared and writable.
+  fd = open(f, O_RDONLY);
+  if(mmap(0, PGSIZE, PROT_READ|PROT_WRITE, MAP_SHARED, fd, 0) != MAP_FAILED){
+    printf("%s: writable shared mmap of a read-only file succeeded\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink(f);
+
+  // a child shares anonymous shared memory with its parent.
+  a = mmap(0, PGSIZE, PROT_READ|PROT_WRITE, MAP_SHARED|MAP_ANONYMOUS, -1, 0);
+  if(a == MAP_FAILED){
+    printf("%s: anonymous shared mmap failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    a[0] = 'x';
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0 || a[0] != 'x'){
+    printf("%s: a child's write to shared memory is not seen\n", s);
+    exit(1);
+  }
+  munmap(a, PGSIZE);
+}
+
+#define MUNMAPTHREAD_SZ (4 * PGSIZE)
+
+char *munmapthread_a;
+volatile int munmapthread_started;
+
+// writes to the region with system calls until it is unmapped.
+void
+munmapthread_syscaller(void *arg)
+{
+  struct stat *st = (struct stat*)(munmapthread_a + PGSIZE);
+
+  munmapthread_started = 1;
+  while(fstat((int)(uint64)arg, st) == 0)
+    ;
+  exit(0);
+}
+
+// writes to the region until it faults on the unmapped pages.
+void
+munmapthread_storer(void *arg)
+{
+  munmapthread_started = 1;
+  for(uint64 i = 0; ; i++)
+    munmapthread_a[i % MUNMAPTHREAD_SZ] = 'w';
+}
+
+// a thread writes to a region while another thread unmaps it.
+void
+munmapthreadtest(char *s)
+{
+  void (*writers[])(void*) = { munmapthread_syscaller, munmapthread_storer };
+  int expected[] = { 0, -1 };
+  int fd, i, tid, xstatus, free0;
+  char *stack;
+
+  fd = open("README", O_RDONLY);
+  stack = sbrk(4096);
+  if(fd < 0 || stack == (char*)-1){
+    printf("%s: setup failed\n", s);
+    exit(1);
+  }
+  free0 = countfree();
+
+  for(i = 0; i < 2; i++){
+    munmapthread_a = mmap(0, MUNMAPTHREAD_SZ, PROT_READ|PROT_WRITE, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0);
+    if(munmapthread_a == MAP_FAILED){
+      printf("%s: mmap failed\n", s);
+      exit(1);
+    }
+    munmapthread_started = 0;
+    tid = clone(writers[i], (void*)(uint64)fd, stack + 4096);
+    if(tid < 0){
+      printf("%s: clone failed\n", s);
+      exit(1);
+    }
+    while(munmapthread_started == 0)
+      ;
+    sleep(1);
+    if(munmap(munmapthread_a, MUNMAPTHREAD_SZ) != 0){
+      printf("%s: munmap failed\n", s);
+      exit(1);
+    }
+    if(join(tid, &xstatus) != tid || xstatus != expected[i]){
+      printf("%s: writer %d exited with %d\n", s, i, xstatus);
+      exit(1);
+    }
+  }
+
+  if(countfree() != free0){
+    printf("%s: the unmapped pages were not freed\n", s);
+    exit(1);
+  }
+  close(fd);
+}
+
 //
 // use sbrk() to count how many free physical memory pages there are.
 // touches the pages to force allocation.
@@ -2833,6 +5481,35 @@ main(int argc, char *argv[])
     {iref, "iref"},
     {forktest, "forktest"},
     {bigdir, "bigdir"}, // slow
+    {cowtest, "cowtest"},
+    {lazysbrk, "lazysbrk"},
+    {mmaptest, "mmaptest"},
+    {signaltest, "signaltest"},
+    {threadtest, "threadtest"},
+    {futextest, "futextest"},
+    {nicetest, "nicetest"},
+    {affinitytest, "affinitytest"},
+    {preempttest, "preempttest"},
+    {errnotest, "errnotest"},
+    {mounttest, "mounttest"},
+    {diskfull, "diskfull"}, // slow
+    {tmpfstest, "tmpfstest"},
+    {procfstest, "procfstest"},
+    {devfstest, "devfstest"},
+    {getdentstest, "getdentstest"},
+    {renametest, "renametest"},
+    {symlinktest, "symlinktest"},
+    {longnametest, "longnametest"},
+    {hugefiletest, "hugefiletest"},
+    {offsettest, "offsettest"},
+    {timetest, "timetest"},
+    {permtest, "permtest"},
+    {procpermtest, "procpermtest"},
+    {selecttest, "selecttest"},
+    {polltest, "polltest"},
+    {fcntltest, "fcntltest"},
+    {mmapsharedtest, "mmapsharedtest"},
+    {munmapthreadtest, "munmapthreadtest"},
     { 0, 0},
   };
 
@@ -8,25 +8,50 @@ print "#include \"kernel/syscall.h\"\n";
 
 $target = $ENV{'TARGET'};
 
+# A failed syscall returns -errno, which is in [-4095, -1].
+# The stub then stores errno and returns -1.
+# entry(name) defines name for SYS_name,
+# and entry(name, syscall) defines name for SYS_syscall.
 if($target eq "riscv") {
+    print "__syscall_error:\n";
+    print " neg a0, a0\n";
+    print " la t0, errno\n";
+    print " sw a0, 0(t0)\n";
+    print " li a0, -1\n";
+    print " ret\n";
+
     *entry = sub {
         my $name = shift;
+        my $syscall = @_ ? shift : $name;
         print ".global $name\n";
         print "${name}:\n";
-        print " li a7, SYS_${name}\n";
+        print " li a7, SYS_${syscall}\n";
         print " ecall\n";
+        print " li t0, -4096\n";
+        print " bgtu a0, t0, __syscall_error\n";
         print " ret\n";
     }
 }
 elsif($target eq "arm") {
+    print "__syscall_error:\n";
+    print " NEG x0, x0\n";
+    print " ADRP x1, errno\n";
+    print " ADD x1, x1, :lo12:errno\n";
+    print " STR w0, [x1]\n";
+    print " MOV x0, #-1\n";
+    print " br x30;	//lr = x30\n";
+
     *entry = sub {
         my $name = shift;
+        my $syscall = @_ ? shift : $name;
         print ".global $name\n";
         print "${name}:\n";
     	print " STR x7, [sp, #-0x08]!\n";
-        print " MOV x7, #SYS_${name}\n";
+        print " MOV x7, #SYS_${syscall}\n";
         print " SVC 0x00\n";
         print " LDR x7, [sp], #0x08\n";
+        print " CMN x0, #4095\n";
+        print " B.CS __syscall_error\n";
         print " br x30;	//lr = x30\n";
     }
 }
@@ -64,3 +89,37 @@ entry("getppid");
 entry("lseek");
 entry("uptime_as_micro");
 entry("clock");
+entry("mmap");
+entry("munmap");
+entry("mprotect");
+entry("rt_sigaction");
+entry("sigprocmask");
+entry("sigreturn");
+entry("alarm");
+entry("clone");
+entry("join");
+entry("exit_group");
+entry("futex");
+entry("setpriority");
+entry("sys_getpriority", "getpriority");
+entry("sched_setaffinity");
+entry("mount");
+entry("umount");
+entry("ioctl");
+entry("getdents");
+entry("rename");
+entry("symlink");
+entry("readlink");
+entry("clock_gettime");
+entry("utimens");
+entry("setuid");
+entry("getuid");
+entry("setgid");
+entry("getgid");
+entry("chmod");
+entry("chown");
+entry("poll");
+entry("epoll_create");
+entry("epoll_ctl");
+entry("epoll_wait");
+entry("fcntl");